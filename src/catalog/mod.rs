@@ -1,29 +1,141 @@
 pub mod schema;
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use self::schema::Schema;
-use crate::error::{Error, Result};
+use crate::{
+    datatypes::record::RecordId,
+    error::{Error, Result},
+    table::{
+        manifest::TxnId,
+        metadata::{TableMetadata, MAX_TABLE_NAME_LEN},
+        page::page_directory::{PageDirectory, METADATA_PARTITION},
+        Table,
+    },
+};
 
+/// The name of the table which stores the catalog.
+pub const METADATA_TABLE_NAME: &str = "_metadata.tables";
+
+/// Catalog keeps track of the metadata of all tables. Every table is persisted
+/// as a row of the `_metadata.tables` table in the metadata partition, and the
+/// rows are loaded into memory when the catalog is opened.
 pub struct Catalog {
-    tables: HashMap<String, Schema>,
+    /// The table of catalog rows.
+    metadata_table: Table,
+
+    /// The metadata of tables, with the id of their catalog rows.
+    tables: HashMap<String, (RecordId, TableMetadata)>,
 }
 
 impl Catalog {
-    pub fn get_table(&self, table_name: &str) -> Result<&Schema> {
+    /// Opens the catalog stored in the metadata partition of
+    /// `page_directory`.
+    pub async fn open(page_directory: Arc<PageDirectory>) -> Result<Self> {
+        page_directory
+            .alloc_part_with_num(METADATA_PARTITION)
+            .await?;
+        let metadata = TableMetadata::new(
+            METADATA_TABLE_NAME,
+            TableMetadata::row_schema(),
+            METADATA_PARTITION,
+            0,
+        );
+        let metadata_table = Table::create(metadata, page_directory).await?;
+
+        let mut tables = HashMap::new();
+        for (record_id, record) in metadata_table.scan().await? {
+            let metadata = TableMetadata::from_record(&record)?;
+            tables.insert(metadata.name().to_owned(), (record_id, metadata));
+        }
+
+        Ok(Self {
+            metadata_table,
+            tables,
+        })
+    }
+
+    pub fn get_table(&self, table_name: &str) -> Result<&TableMetadata> {
         self.tables
             .get(table_name)
+            .map(|(_, metadata)| metadata)
             .ok_or(Error::NotFound(format!("table {}", table_name)))
     }
 
-    pub fn create_table(&mut self) -> Result<()> {
-        todo!()
+    pub fn contains_table(&self, table_name: &str) -> bool {
+        self.tables.contains_key(table_name)
+    }
+
+    /// Returns the metadata of all tables.
+    pub fn tables(&self) -> impl Iterator<Item = &TableMetadata> {
+        self.tables.values().map(|(_, metadata)| metadata)
     }
 
-    pub fn drop_table(&mut self) -> Result<()> {
-        todo!()
+    /// Adds the table to catalog and persists its catalog row in transaction
+    /// `txn`.
+    pub async fn create_table(&mut self, txn: TxnId, metadata: TableMetadata) -> Result<()> {
+        if metadata.name().len() > MAX_TABLE_NAME_LEN as usize {
+            return Err(Error::Invalid(format!(
+                "table name {}: longer than {} bytes",
+                metadata.name(),
+                MAX_TABLE_NAME_LEN
+            )));
+        }
+        if self.contains_table(metadata.name()) {
+            return Err(Error::Invalid(format!(
+                "table name {}: already exist",
+                metadata.name()
+            )));
+        }
+
+        let record_id = self
+            .metadata_table
+            .insert(txn, metadata.to_record()?)
+            .await?;
+        self.tables
+            .insert(metadata.name().to_owned(), (record_id, metadata));
+        Ok(())
+    }
+
+    /// Removes the table from catalog and deletes its catalog row, returns the
+    /// metadata of the dropped table.
+    pub async fn drop_table(&mut self, txn: TxnId, table_name: &str) -> Result<TableMetadata> {
+        let (record_id, _) = self
+            .tables
+            .get(table_name)
+            .ok_or(Error::NotFound(format!("table {}", table_name)))?;
+
+        self.metadata_table.remove(txn, *record_id).await?;
+        let (_, metadata) = self.tables.remove(table_name).unwrap();
+        Ok(metadata)
     }
 
-    pub fn alter_table(&mut self) -> Result<()> {
-        todo!()
+    /// Rewrites the catalog row of table with its altered metadata (see
+    /// `TableMetadata::alter`).
+    pub async fn alter_table(&mut self, txn: TxnId, metadata: TableMetadata) -> Result<()> {
+        let table_name = metadata.name().to_owned();
+        let (record_id, _) = self
+            .tables
+            .get(&table_name)
+            .ok_or(Error::NotFound(format!("table {}", table_name)))?;
+        let record_id = *record_id;
+        let record = metadata.to_record()?;
+
+        let record_id = match self
+            .metadata_table
+            .update(txn, record_id, record.clone())
+            .await
+        {
+            Ok(_) => record_id,
+            // The row doesn't fit its page any more, so it's moved within the
+            // same transaction.
+            Err(Error::TooLargeSize) => {
+                let new_record_id = self.metadata_table.insert(txn, record).await?;
+                self.metadata_table.remove(txn, record_id).await?;
+                new_record_id
+            }
+            Err(e) => return Err(e),
+        };
+
+        self.tables.insert(table_name, (record_id, metadata));
+        Ok(())
     }
 }
@@ -1,22 +1,265 @@
-use crate::{datatypes::record::Record, error::Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    datatypes::{
+        array::scalar::ScalarImpl,
+        record::{null_bitmap_size, Record},
+    },
+    error::{Error, Result},
+    table::page::PageFormat,
+};
 
 pub type ColumnId = u16;
+
+/// The data type of a column, reuses the variants of `ArrayImpl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DataType {
+    Int16,
+    Int32,
+    Int64,
+    Bool,
+    /// String with the max length (in bytes).
+    String(u16),
+}
+
+impl DataType {
+    /// Get identifier of the current data type.
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            DataType::Int16 => "Int16",
+            DataType::Int32 => "Int32",
+            DataType::Int64 => "Int64",
+            DataType::Bool => "Bool",
+            DataType::String(_) => "String",
+        }
+    }
+
+    /// Returns the max size (in bytes) of an encoded value of this type,
+    /// excluding the type tag.
+    pub fn max_size(&self) -> usize {
+        match self {
+            DataType::Int16 => 2,
+            DataType::Int32 => 4,
+            DataType::Int64 => 8,
+            DataType::Bool => 1,
+            DataType::String(max_len) => 2 + *max_len as usize,
+        }
+    }
+
+    /// Casts the value to this type. Integers are widened (or narrowed if the
+    /// value fits), strings are truncated to the max length.
+    pub fn cast(&self, value: ScalarImpl) -> Result<ScalarImpl> {
+        let value = match (self, value) {
+            (DataType::Int16, ScalarImpl::Int16(v)) => ScalarImpl::Int16(v),
+            (DataType::Int16, ScalarImpl::Int32(v)) => ScalarImpl::Int16(narrow(v)?),
+            (DataType::Int16, ScalarImpl::Int64(v)) => ScalarImpl::Int16(narrow(v)?),
+            (DataType::Int32, ScalarImpl::Int16(v)) => ScalarImpl::Int32(v.into()),
+            (DataType::Int32, ScalarImpl::Int32(v)) => ScalarImpl::Int32(v),
+            (DataType::Int32, ScalarImpl::Int64(v)) => ScalarImpl::Int32(narrow(v)?),
+            (DataType::Int64, ScalarImpl::Int16(v)) => ScalarImpl::Int64(v.into()),
+            (DataType::Int64, ScalarImpl::Int32(v)) => ScalarImpl::Int64(v.into()),
+            (DataType::Int64, ScalarImpl::Int64(v)) => ScalarImpl::Int64(v),
+            (DataType::Bool, ScalarImpl::Bool(v)) => ScalarImpl::Bool(v),
+            (DataType::String(max_len), ScalarImpl::String(mut v)) => {
+                let mut len = (*max_len as usize).min(v.len());
+                while !v.is_char_boundary(len) {
+                    len -= 1;
+                }
+                v.truncate(len);
+                ScalarImpl::String(v)
+            }
+            (expected, other) => {
+                return Err(Error::Invalid(format!(
+                    "value type: expected {}, got {}",
+                    expected.identifier(),
+                    other.identifier()
+                )))
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[inline]
+fn narrow<T, U>(v: T) -> Result<U>
+where
+    T: std::fmt::Display + Copy,
+    U: TryFrom<T>,
+{
+    U::try_from(v).map_err(|_| Error::Invalid(format!("value {}: out of range", v)))
+}
+
+/// A column of the table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Column {
+    pub name: String,
+    pub data_type: DataType,
+    /// Whether the column accepts `NULL` value.
+    pub nullable: bool,
+    /// The value used when the column is missing from the inserted record.
+    pub default: Option<ScalarImpl>,
+}
+
+impl Column {
+    pub fn new(name: impl Into<String>, data_type: DataType) -> Self {
+        Self {
+            name: name.into(),
+            data_type,
+            nullable: true,
+            default: None,
+        }
+    }
+
+    pub fn not_null(mut self) -> Self {
+        self.nullable = false;
+        self
+    }
+
+    pub fn with_default(mut self, default: ScalarImpl) -> Self {
+        self.default = Some(default);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Schema {
+    columns: Vec<Column>,
     indices: Vec<TableIndex>,
+    /// The format of the data pages of table.
+    page_format: PageFormat,
 }
 
 impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
     /// Adds a new field to the schema. Returns the schema so that calls can be
     /// together.
-    pub fn add(&mut self) {}
+    ///
+    /// ```ignore
+    /// let schema = Schema::new()
+    ///     .add(Column::new("id", DataType::Int32).not_null())
+    ///     .add(Column::new("name", DataType::String(32)));
+    /// ```
+    pub fn add(mut self, column: Column) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    /// Adds a new index to the schema. Returns the schema so that calls can be
+    /// together.
+    pub fn add_index(mut self, index: TableIndex) -> Self {
+        self.indices.push(index);
+        self
+    }
+
+    /// Declares the primary key of the schema, which is a unique index named
+    /// [`PRIMARY_KEY`] on the columns, and the columns become not null. Fails
+    /// if the primary key is already declared.
+    ///
+    /// ```ignore
+    /// let schema = Schema::new()
+    ///     .add(Column::new("id", DataType::Int32))
+    ///     .add(Column::new("name", DataType::String(32)))
+    ///     .with_primary_key(vec![0])?;
+    /// ```
+    pub fn with_primary_key(mut self, cols: Vec<ColumnId>) -> Result<Self> {
+        if let Some(index) = self.primary_key() {
+            return Err(Error::Invalid(format!(
+                "primary key on columns {:?}, which is already declared on columns {:?}",
+                cols, index.cols
+            )));
+        }
+        for &col in &cols {
+            if let Some(column) = self.columns.get_mut(col as usize) {
+                column.nullable = false;
+            }
+        }
+        Ok(self.add_index(TableIndex::new(PRIMARY_KEY, cols).unique()))
+    }
+
+    /// Returns the index of primary key.
+    pub fn primary_key(&self) -> Option<&TableIndex> {
+        self.indices.iter().find(|index| index.name == PRIMARY_KEY)
+    }
+
+    /// Sets the format of the data pages. `PageFormat::SlotDirectory` stores
+    /// records with their actual size instead of `estimated_size`, which suits
+    /// tables with wide string columns.
+    pub fn with_page_format(mut self, page_format: PageFormat) -> Self {
+        self.page_format = page_format;
+        self
+    }
+
+    pub fn page_format(&self) -> PageFormat {
+        self.page_format
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// Returns the id of column `name`.
+    pub fn column_id(&self, name: &str) -> Option<ColumnId> {
+        self.columns
+            .iter()
+            .position(|c| c.name == name)
+            .map(|idx| idx as ColumnId)
+    }
 
     /// Verifies that a record matches the given schema. Performs the following
     /// implicit casts:
     /// - String's of the wrong size are cast to the expected size of the
-    ///   schame.
-    /// - Int's will be cast to floats if a float is expected.
+    ///   schame, longer strings are truncated and shorter ones are padded in
+    ///   the fixed-size slot (see `estimated_size`).
+    /// - Int's will be cast to the expected width of the schema (the schema has
+    ///   no float types yet).
+    ///
+    /// Missing trailing values are filled with the column default (or `NULL`).
     pub fn verify_record(&self, record: Record) -> Result<Record> {
-        todo!()
+        if record.len() > self.columns.len() {
+            return Err(Error::Invalid(format!(
+                "record: expected {} values, got {}",
+                self.columns.len(),
+                record.len()
+            )));
+        }
+
+        let provided = record.len();
+        let mut values = record.into_values();
+        values.resize(self.columns.len(), None);
+
+        let values = values
+            .into_iter()
+            .zip(self.columns.iter())
+            .enumerate()
+            .map(|(idx, (value, column))| {
+                let value = if idx < provided {
+                    value
+                } else {
+                    column.default.clone()
+                };
+                match value {
+                    Some(v) => column.data_type.cast(v).map(Some),
+                    None if column.nullable => Ok(None),
+                    None => Err(Error::Invalid(format!(
+                        "value of column {}: not null",
+                        column.name
+                    ))),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Record::new(values))
     }
 
     /// Returns all indicies associated table.
@@ -27,15 +270,348 @@ impl Schema {
         &self.indices
     }
 
+    /// Sets the page of the root node of index `name`.
+    pub(crate) fn set_index_root(&mut self, name: &str, root: u64) {
+        for index in self.indices.iter_mut().filter(|index| index.name == name) {
+            index.root = Some(root);
+        }
+    }
+
+    /// Returns the size (in bytes) of the widest encoded record of the schema,
+    /// which is the size of a record slot on a data page.
     pub fn estimated_size(&self) -> usize {
-        todo!()
+        2 + null_bitmap_size(self.columns.len())
+            + self
+                .columns
+                .iter()
+                .map(|c| 1 + c.data_type.max_size())
+                .sum::<usize>()
+    }
+
+    /// Returns the schema with the change applied.
+    pub fn apply_change(&self, change: &SchemaChange) -> Result<Schema> {
+        let mut schema = self.clone();
+        match change {
+            SchemaChange::AddColumn(column) => {
+                if self.column_id(&column.name).is_some() {
+                    return Err(Error::Invalid(format!(
+                        "column {}: already exist",
+                        column.name
+                    )));
+                }
+                if !column.nullable && column.default.is_none() {
+                    return Err(Error::Invalid(format!(
+                        "column {}: not null column requires a default value",
+                        column.name
+                    )));
+                }
+                if let Some(default) = &column.default {
+                    column.data_type.cast(default.clone())?;
+                }
+                schema.columns.push(column.clone());
+            }
+            SchemaChange::DropColumn(name) => {
+                let column_id = self.existing_column_id(name)?;
+                if self
+                    .indices
+                    .iter()
+                    .any(|index| index.cols.contains(&column_id))
+                {
+                    return Err(Error::Invalid(format!("column {}: indexed", name)));
+                }
+                if self.columns.len() == 1 {
+                    return Err(Error::Invalid(format!("column {}: the last column", name)));
+                }
+
+                schema.columns.remove(column_id as usize);
+                for col in schema
+                    .indices
+                    .iter_mut()
+                    .flat_map(|index| index.cols.iter_mut())
+                {
+                    if *col > column_id {
+                        *col -= 1;
+                    }
+                }
+            }
+            SchemaChange::RenameColumn(name, new_name) => {
+                let column_id = self.existing_column_id(name)?;
+                if self.column_id(new_name).is_some() {
+                    return Err(Error::Invalid(format!(
+                        "column {}: already exist",
+                        new_name
+                    )));
+                }
+                schema.columns[column_id as usize].name = new_name.clone();
+            }
+            SchemaChange::AddIndex(index) => {
+                if self.indices.iter().any(|i| i.name == index.name) {
+                    return Err(Error::Invalid(format!(
+                        "index {}: already exist",
+                        index.name
+                    )));
+                }
+                if index.cols.is_empty()
+                    || index
+                        .cols
+                        .iter()
+                        .any(|&col| col as usize >= self.columns.len())
+                {
+                    return Err(Error::Invalid(format!(
+                        "index {} on columns {:?}",
+                        index.name, index.cols
+                    )));
+                }
+                schema.indices.push(index.clone());
+            }
+        }
+        Ok(schema)
+    }
+
+    fn existing_column_id(&self, name: &str) -> Result<ColumnId> {
+        self.column_id(name)
+            .ok_or_else(|| Error::NotFound(format!("column {}", name)))
     }
 }
 
+/// A change of the columns (or indices) of a table, i.e. ALTER TABLE.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchemaChange {
+    /// Adds a column at the end, existing records take the default value of
+    /// the column (or `NULL`).
+    AddColumn(Column),
+    /// Drops the column with the name.
+    DropColumn(String),
+    /// Renames the column from the first name to the second name.
+    RenameColumn(String, String),
+    /// Adds an index, i.e. CREATE INDEX, which is built from the existing
+    /// records (see `Table::build_index`).
+    AddIndex(TableIndex),
+}
+
+impl SchemaChange {
+    /// Upgrades a record of `schema` (the schema which the change applies to)
+    /// to the changed schema.
+    pub fn upgrade_record(&self, schema: &Schema, record: Record) -> Result<Record> {
+        let mut values = record.into_values();
+        match self {
+            SchemaChange::AddColumn(column) => {
+                let default = column
+                    .default
+                    .clone()
+                    .map(|v| column.data_type.cast(v))
+                    .transpose()?;
+                values.push(default);
+            }
+            SchemaChange::DropColumn(name) => {
+                let column_id = schema.existing_column_id(name)? as usize;
+                if column_id < values.len() {
+                    values.remove(column_id);
+                }
+            }
+            SchemaChange::RenameColumn(..) | SchemaChange::AddIndex(_) => {}
+        }
+        Ok(Record::new(values))
+    }
+}
+
+/// The name of the index of primary key.
+pub const PRIMARY_KEY: &str = "PRIMARY";
+
+/// The access method of an index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IndexKind {
+    /// A B+ tree, which supports lookups by a prefix of the indexed columns
+    /// and range lookups.
+    #[default]
+    BTree,
+    /// An extendible hash index, which supports only equality lookups on all
+    /// indexed columns.
+    Hash,
+    /// A bloom filter of the indexed columns on every data page, which
+    /// supports equality lookups on all indexed columns by skipping the pages
+    /// that don't contain the values.
+    Bloom,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TableIndex {
     /// Index name.
     pub name: String,
 
     /// The column id corresponding to the index.
     pub cols: Vec<ColumnId>,
+
+    /// The page of the root node of index, which is allocated when the table
+    /// is created and never moves.
+    #[serde(default)]
+    pub root: Option<u64>,
+
+    /// Whether no two records have the same non-null values of the indexed
+    /// columns, i.e. UNIQUE constraint.
+    #[serde(default)]
+    pub unique: bool,
+
+    #[serde(default)]
+    pub kind: IndexKind,
+}
+
+impl TableIndex {
+    pub fn new(name: impl Into<String>, cols: Vec<ColumnId>) -> Self {
+        Self {
+            name: name.into(),
+            cols,
+            root: None,
+            unique: false,
+            kind: IndexKind::BTree,
+        }
+    }
+
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    pub fn with_kind(mut self, kind: IndexKind) -> Self {
+        self.kind = kind;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::new()
+            .add(Column::new("id", DataType::Int64).not_null())
+            .add(Column::new("name", DataType::String(4)))
+            .add(Column::new("active", DataType::Bool).with_default(ScalarImpl::Bool(true)))
+    }
+
+    #[test]
+    fn test_verify_record_casts() {
+        let schema = schema();
+        let record = Record::from(vec![
+            ScalarImpl::Int16(7),
+            ScalarImpl::String("rookiedb".to_owned()),
+        ]);
+
+        let record = schema.verify_record(record).unwrap();
+        assert_eq!(
+            record,
+            Record::from(vec![
+                ScalarImpl::Int64(7),
+                ScalarImpl::String("rook".to_owned()),
+                ScalarImpl::Bool(true),
+            ])
+        );
+        assert!(record.encoded_size() <= schema.estimated_size());
+    }
+
+    #[test]
+    fn test_verify_record_invalid() {
+        let schema = schema();
+
+        // Not null.
+        let record = Record::new(vec![None]);
+        assert!(schema.verify_record(record).is_err());
+
+        // Type mismatch.
+        let record = Record::from(vec![ScalarImpl::Bool(true)]);
+        assert!(schema.verify_record(record).is_err());
+
+        // Too many values.
+        let record = Record::from(vec![
+            ScalarImpl::Int64(1),
+            ScalarImpl::String("a".to_owned()),
+            ScalarImpl::Bool(true),
+            ScalarImpl::Bool(true),
+        ]);
+        assert!(schema.verify_record(record).is_err());
+    }
+
+    #[test]
+    fn test_apply_change() {
+        let schema = schema().add_index(TableIndex::new("idx_active", vec![2]));
+        let record = schema
+            .verify_record(Record::from(vec![ScalarImpl::Int64(1)]))
+            .unwrap();
+
+        let change = SchemaChange::AddColumn(
+            Column::new("age", DataType::Int32)
+                .not_null()
+                .with_default(ScalarImpl::Int16(18)),
+        );
+        let added = schema.apply_change(&change).unwrap();
+        let record = change.upgrade_record(&schema, record).unwrap();
+        assert_eq!(record.get(3), Some(&ScalarImpl::Int32(18)));
+
+        let change = SchemaChange::DropColumn("name".to_owned());
+        let dropped = added.apply_change(&change).unwrap();
+        let record = change.upgrade_record(&added, record).unwrap();
+        assert_eq!(dropped.column_id("age"), Some(2));
+        assert_eq!(dropped.get_indcies()[0].cols, vec![1]);
+        assert_eq!(dropped.verify_record(record.clone()).unwrap(), record);
+
+        let change = SchemaChange::RenameColumn("age".to_owned(), "years".to_owned());
+        let renamed = dropped.apply_change(&change).unwrap();
+        assert_eq!(renamed.column_id("years"), Some(2));
+        assert_eq!(
+            change.upgrade_record(&dropped, record.clone()).unwrap(),
+            record
+        );
+
+        let change = SchemaChange::AddIndex(TableIndex::new("idx_years", vec![2]));
+        let indexed = renamed.apply_change(&change).unwrap();
+        assert_eq!(indexed.get_indcies()[1].cols, vec![2]);
+
+        // Invalid changes.
+        let changes = [
+            SchemaChange::AddColumn(Column::new("id", DataType::Int32)),
+            SchemaChange::AddColumn(Column::new("score", DataType::Int32).not_null()),
+            SchemaChange::AddColumn(
+                Column::new("score", DataType::Int32).with_default(ScalarImpl::Bool(true)),
+            ),
+            SchemaChange::DropColumn("active".to_owned()),
+            SchemaChange::DropColumn("unknown".to_owned()),
+            SchemaChange::RenameColumn("id".to_owned(), "name".to_owned()),
+            SchemaChange::AddIndex(TableIndex::new("idx_active", vec![0])),
+            SchemaChange::AddIndex(TableIndex::new("idx_id", vec![3])),
+        ];
+        for change in changes {
+            assert!(schema.apply_change(&change).is_err(), "{:?}", change);
+        }
+    }
+
+    #[test]
+    fn test_estimated_size() {
+        // 2-byte len + 1-byte bitmap + (1 + 8) + (1 + 2 + 4) + (1 + 1).
+        assert_eq!(schema().estimated_size(), 21);
+        assert_eq!(schema().column_id("active"), Some(2));
+    }
+
+    #[test]
+    fn test_primary_key() {
+        let with_pk = schema().with_primary_key(vec![1]).unwrap();
+        let index = with_pk.primary_key().unwrap();
+        assert!(index.unique);
+        assert_eq!(index.cols, vec![1]);
+        assert!(!with_pk.columns()[1].nullable);
+        assert!(with_pk
+            .verify_record(Record::from(vec![ScalarImpl::Int64(1)]))
+            .is_err());
+
+        // Indices of schemas persisted before UNIQUE are not unique.
+        let json = r#"{"name":"idx","cols":[0],"root":3}"#;
+        let index: TableIndex = serde_json::from_str(json).unwrap();
+        assert!(!index.unique);
+        assert_eq!(index.kind, IndexKind::BTree);
+        assert!(schema().primary_key().is_none());
+        assert!(matches!(
+            with_pk.with_primary_key(vec![0]),
+            Err(Error::Invalid(_))
+        ));
+    }
 }
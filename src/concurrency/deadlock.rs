@@ -0,0 +1,149 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Weak},
+    thread,
+    time::Duration,
+};
+
+use super::lock_manager::LockManager;
+use crate::table::manifest::TxnId;
+
+/// The waits-for graph of transactions, i.e. the transactions each waiting
+/// transaction waits for.
+pub type WaitsForGraph = BTreeMap<TxnId, BTreeSet<TxnId>>;
+
+/// Returns a cycle of the graph, `None` if the graph is acyclic.
+pub fn find_cycle(graph: &WaitsForGraph) -> Option<Vec<TxnId>> {
+    // The transactions visited, and those on the path of the current search.
+    let mut visited = BTreeSet::new();
+    let mut path = vec![];
+    for &txn in graph.keys() {
+        if let Some(cycle) = search(graph, txn, &mut visited, &mut path) {
+            return Some(cycle);
+        }
+    }
+    None
+}
+
+fn search(
+    graph: &WaitsForGraph,
+    txn: TxnId,
+    visited: &mut BTreeSet<TxnId>,
+    path: &mut Vec<TxnId>,
+) -> Option<Vec<TxnId>> {
+    if let Some(pos) = path.iter().position(|&t| t == txn) {
+        return Some(path[pos..].to_vec());
+    }
+    if !visited.insert(txn) {
+        return None;
+    }
+
+    path.push(txn);
+    for &next in graph.get(&txn).into_iter().flatten() {
+        if let Some(cycle) = search(graph, next, visited, path) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    None
+}
+
+/// The background deadlock detector of a lock manager, which breaks the
+/// deadlocks every interval (see `LockManager::detect_deadlocks`). It stops
+/// once the lock manager is dropped.
+///
+/// The detector fails the lock request of victims with `Error::Deadlock`,
+/// whose owners abort them: a victim is rolled back, then its locks are
+/// released (see `Transaction`).
+pub struct DeadlockDetector {
+    lock_manager: Weak<LockManager>,
+    interval: Duration,
+}
+
+impl DeadlockDetector {
+    /// Starts the detector of lock manager in a new thread.
+    pub fn spawn(lock_manager: &Arc<LockManager>, interval: Duration) {
+        let detector = Self {
+            lock_manager: Arc::downgrade(lock_manager),
+            interval,
+        };
+        thread::Builder::new()
+            .name("deadlock-detector".to_owned())
+            .spawn(move || detector.run())
+            .expect("failed to spawn deadlock detector");
+    }
+
+    fn run(self) {
+        loop {
+            thread::sleep(self.interval);
+            match self.lock_manager.upgrade() {
+                Some(lock_manager) => {
+                    lock_manager.detect_deadlocks();
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{
+        concurrency::{
+            lock_manager::DEFAULT_ESCALATION_THRESHOLD, lock_type::LockType, resource::ResourceName,
+        },
+        error::Error,
+    };
+
+    fn graph(edges: &[(TxnId, TxnId)]) -> WaitsForGraph {
+        let mut graph = WaitsForGraph::new();
+        for &(from, to) in edges {
+            graph.entry(from).or_default().insert(to);
+        }
+        graph
+    }
+
+    #[test]
+    fn test_find_cycle() {
+        assert_eq!(find_cycle(&graph(&[])), None);
+        assert_eq!(find_cycle(&graph(&[(1, 2), (2, 3), (1, 3)])), None);
+        assert_eq!(find_cycle(&graph(&[(1, 1)])), Some(vec![1]));
+        assert_eq!(
+            find_cycle(&graph(&[(1, 2), (2, 3), (3, 4), (4, 2)])),
+            Some(vec![2, 3, 4])
+        );
+    }
+
+    #[test]
+    fn test_detector() {
+        let lock_manager = Arc::new(LockManager::new(DEFAULT_ESCALATION_THRESHOLD));
+        DeadlockDetector::spawn(&lock_manager, Duration::from_millis(10));
+
+        let barrier = Barrier::new(2);
+        let results = thread::scope(|s| {
+            let handles = [(1, 2), (2, 1)].map(|(txn, part_num)| {
+                let lock_manager = &lock_manager;
+                let barrier = &barrier;
+                s.spawn(move || {
+                    block_on(async {
+                        let own = ResourceName::Table(txn as usize);
+                        lock_manager.lock(txn, own, LockType::X).await.unwrap();
+                        barrier.wait();
+                        let other = ResourceName::Table(part_num);
+                        let result = lock_manager.lock(txn, other, LockType::X).await;
+                        lock_manager.release_all(txn);
+                        result
+                    })
+                })
+            });
+            handles.map(|handle| handle.join().unwrap())
+        });
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(Error::Deadlock(2))));
+    }
+}
@@ -0,0 +1,820 @@
+use std::collections::{HashMap, VecDeque};
+
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+
+use super::{
+    deadlock::{find_cycle, WaitsForGraph},
+    lock_type::LockType,
+    resource::ResourceName,
+};
+use crate::{
+    error::{Error, Result},
+    table::manifest::TxnId,
+};
+
+/// The default number of page locks of a transaction in a table, beyond which
+/// they're escalated to a lock on the table.
+pub const DEFAULT_ESCALATION_THRESHOLD: usize = 32;
+
+/// A lock manager of the resource hierarchy (see `ResourceName`), i.e.
+/// multigranularity locking.
+///
+/// Every resource has the locks granted to transactions and a FIFO queue of
+/// the requests which wait: a request is granted only if it's compatible with
+/// the locks of other transactions and no request is queued before it, and
+/// the queue is processed from the front once a lock is released until a
+/// request can't be granted. A request which changes a lock the transaction
+/// already holds (e.g. an upgrade) goes to the front of queue.
+///
+/// `acquire`, `promote` and `release` lock a single resource and only check
+/// that the locks of a transaction are valid for the hierarchy (see
+/// `LockType::can_be_parent_lock`). `lock` takes the intention locks on the
+/// ancestors of a resource as well, and escalates the page locks of a
+/// transaction in a table into a single table lock once there are more than
+/// the escalation threshold.
+///
+/// Locks are held until the transaction ends (see `release_all`), i.e. strict
+/// two-phase locking. Transactions waiting for each other are found from the
+/// queues by `detect_deadlocks`, which `DeadlockDetector` runs periodically.
+pub struct LockManager {
+    state: Mutex<LockState>,
+
+    /// The number of page locks of a transaction in a table beyond which
+    /// they're escalated.
+    escalation_threshold: usize,
+}
+
+#[derive(Default)]
+struct LockState {
+    resources: HashMap<ResourceName, ResourceEntry>,
+
+    /// The locks held by every transaction.
+    txn_locks: HashMap<TxnId, HashMap<ResourceName, LockType>>,
+}
+
+#[derive(Default)]
+struct ResourceEntry {
+    granted: Vec<(TxnId, LockType)>,
+    queue: VecDeque<Request>,
+}
+
+/// A request waiting in queue, which is woken up once granted.
+struct Request {
+    txn: TxnId,
+    lock_type: LockType,
+    /// The locks released once the request is granted.
+    release: Vec<ResourceName>,
+    waker: oneshot::Sender<Result<()>>,
+}
+
+impl LockState {
+    fn lock_type(&self, txn: TxnId, name: &ResourceName) -> LockType {
+        self.txn_locks
+            .get(&txn)
+            .and_then(|locks| locks.get(name))
+            .copied()
+            .unwrap_or(LockType::NL)
+    }
+
+    /// Returns the locks of transaction on the descendants of resource.
+    fn descendants(&self, txn: TxnId, name: &ResourceName) -> Vec<(ResourceName, LockType)> {
+        self.txn_locks
+            .get(&txn)
+            .map(|locks| {
+                locks
+                    .iter()
+                    .filter(|(resource, _)| resource.is_descendant_of(name))
+                    .map(|(resource, lock_type)| (*resource, *lock_type))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the lock is compatible with the locks of other
+    /// transactions on resource.
+    fn compatible(&self, txn: TxnId, name: &ResourceName, lock_type: LockType) -> bool {
+        self.resources.get(name).map_or(true, |entry| {
+            entry
+                .granted
+                .iter()
+                .all(|&(other, granted)| other == txn || granted.compatible(lock_type))
+        })
+    }
+
+    /// Grants the lock (which replaces the lock of transaction on resource if
+    /// any), then releases the locks of `release`.
+    fn grant(
+        &mut self,
+        txn: TxnId,
+        name: ResourceName,
+        lock_type: LockType,
+        release: &[ResourceName],
+    ) {
+        let entry = self.resources.entry(name).or_default();
+        match entry.granted.iter_mut().find(|(other, _)| *other == txn) {
+            Some(granted) => granted.1 = lock_type,
+            None => entry.granted.push((txn, lock_type)),
+        }
+        self.txn_locks
+            .entry(txn)
+            .or_default()
+            .insert(name, lock_type);
+
+        for resource in release.iter().filter(|&resource| resource != &name) {
+            self.release(txn, resource);
+        }
+    }
+
+    /// Releases the lock of transaction on resource, then grants the queued
+    /// requests.
+    fn release(&mut self, txn: TxnId, name: &ResourceName) {
+        if let Some(locks) = self.txn_locks.get_mut(&txn) {
+            locks.remove(name);
+            if locks.is_empty() {
+                self.txn_locks.remove(&txn);
+            }
+        }
+        if let Some(entry) = self.resources.get_mut(name) {
+            entry.granted.retain(|(other, _)| *other != txn);
+        }
+        self.process_queue(name);
+    }
+
+    /// Grants the requests from the front of queue until one can't be
+    /// granted.
+    fn process_queue(&mut self, name: &ResourceName) {
+        loop {
+            let entry = match self.resources.get_mut(name) {
+                Some(entry) => entry,
+                None => return,
+            };
+            let (txn, lock_type) = match entry.queue.front() {
+                Some(request) => (request.txn, request.lock_type),
+                None => {
+                    if entry.granted.is_empty() {
+                        self.resources.remove(name);
+                    }
+                    return;
+                }
+            };
+            if !self.compatible(txn, name, lock_type) {
+                return;
+            }
+
+            let request = self
+                .resources
+                .get_mut(name)
+                .and_then(|entry| entry.queue.pop_front())
+                .unwrap();
+            self.grant(txn, *name, lock_type, &request.release);
+            // The waiter may be gone, then the lock is released with the
+            // other locks of transaction.
+            let _ = request.waker.send(Ok(()));
+        }
+    }
+
+    /// Removes the waiting requests of transaction from queues.
+    fn cancel(&mut self, txn: TxnId) -> Vec<(ResourceName, Request)> {
+        let mut canceled = vec![];
+        for (name, entry) in self.resources.iter_mut() {
+            while let Some(pos) = entry.queue.iter().position(|request| request.txn == txn) {
+                canceled.push((*name, entry.queue.remove(pos).unwrap()));
+            }
+        }
+        canceled
+    }
+
+    /// Returns the waits-for graph: a waiting request waits for the other
+    /// transactions which hold incompatible locks on its resource, and those
+    /// queued before it.
+    fn waits_for(&self) -> WaitsForGraph {
+        let mut graph = WaitsForGraph::new();
+        for entry in self.resources.values() {
+            for (i, request) in entry.queue.iter().enumerate() {
+                let holders = entry
+                    .granted
+                    .iter()
+                    .filter(|(_, granted)| !granted.compatible(request.lock_type))
+                    .map(|(txn, _)| *txn);
+                let queued = entry.queue.iter().take(i).map(|queued| queued.txn);
+                graph
+                    .entry(request.txn)
+                    .or_default()
+                    .extend(holders.chain(queued).filter(|&txn| txn != request.txn));
+            }
+        }
+        graph
+    }
+
+    /// Checks that the transaction may hold `lock_type` on resource, given
+    /// the lock on its parent.
+    fn check_parent(&self, txn: TxnId, name: &ResourceName, lock_type: LockType) -> Result<()> {
+        if let Some(parent) = name.parent() {
+            let parent_lock = self.lock_type(txn, &parent);
+            if !parent_lock.can_be_parent_lock(lock_type) {
+                return Err(Error::Invalid(format!(
+                    "lock {} on {:?} of transaction {} holding {} on {:?}",
+                    lock_type, name, txn, parent_lock, parent
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LockManager {
+    pub fn new(escalation_threshold: usize) -> Self {
+        Self {
+            state: Mutex::new(LockState::default()),
+            escalation_threshold,
+        }
+    }
+
+    /// Requests the lock on resource and waits until it's granted, the locks
+    /// of `release` are released at the same time. The request goes to the
+    /// front of queue if `front`.
+    async fn request(
+        &self,
+        txn: TxnId,
+        name: ResourceName,
+        lock_type: LockType,
+        release: Vec<ResourceName>,
+        front: bool,
+    ) -> Result<()> {
+        let receiver = {
+            let mut state = self.state.lock();
+            let queued = state
+                .resources
+                .get(&name)
+                .map_or(false, |entry| !entry.queue.is_empty());
+            if state.compatible(txn, &name, lock_type) && (front || !queued) {
+                state.grant(txn, name, lock_type, &release);
+                return Ok(());
+            }
+
+            let (waker, receiver) = oneshot::channel();
+            let request = Request {
+                txn,
+                lock_type,
+                release,
+                waker,
+            };
+            let queue = &mut state.resources.entry(name).or_default().queue;
+            if front {
+                queue.push_front(request);
+            } else {
+                queue.push_back(request);
+            }
+            receiver
+        };
+
+        receiver.await.unwrap_or_else(|_| {
+            Err(Error::Invalid(format!(
+                "lock request of transaction {} on {:?}, which has ended",
+                txn, name
+            )))
+        })
+    }
+
+    /// Acquires a lock on resource, and waits until it's granted. The
+    /// transaction must not hold a lock on resource yet.
+    pub async fn acquire(&self, txn: TxnId, name: ResourceName, lock_type: LockType) -> Result<()> {
+        {
+            let state = self.state.lock();
+            let held = state.lock_type(txn, &name);
+            if held != LockType::NL {
+                return Err(Error::Invalid(format!(
+                    "transaction {} acquiring {} on {:?} already holds {}",
+                    txn, lock_type, name, held
+                )));
+            }
+            state.check_parent(txn, &name, lock_type)?;
+        }
+        self.request(txn, name, lock_type, vec![], false).await
+    }
+
+    /// Promotes the lock of transaction on resource to a stronger one, and
+    /// waits until it's granted. The S and IS locks on descendants are
+    /// released when promoted to SIX, as they're redundant.
+    pub async fn promote(&self, txn: TxnId, name: ResourceName, lock_type: LockType) -> Result<()> {
+        let release = {
+            let state = self.state.lock();
+            let held = state.lock_type(txn, &name);
+            if held == LockType::NL || held == lock_type || !lock_type.substitutable(held) {
+                return Err(Error::Invalid(format!(
+                    "transaction {} promoting {} on {:?} to {}",
+                    txn, held, name, lock_type
+                )));
+            }
+            state.check_parent(txn, &name, lock_type)?;
+
+            if lock_type == LockType::SIX {
+                state
+                    .descendants(txn, &name)
+                    .into_iter()
+                    .filter(|(_, held)| matches!(held, LockType::S | LockType::IS))
+                    .map(|(resource, _)| resource)
+                    .collect()
+            } else {
+                vec![]
+            }
+        };
+        self.request(txn, name, lock_type, release, true).await
+    }
+
+    /// Releases the lock of transaction on resource. The locks on its
+    /// descendants must be released first.
+    pub fn release(&self, txn: TxnId, name: ResourceName) -> Result<()> {
+        let mut state = self.state.lock();
+        if state.lock_type(txn, &name) == LockType::NL {
+            return Err(Error::NotFound(format!(
+                "lock on {:?} of transaction {}",
+                name, txn
+            )));
+        }
+        if let Some((child, _)) = state.descendants(txn, &name).first() {
+            return Err(Error::Invalid(format!(
+                "release lock on {:?} of transaction {} holding lock on {:?}",
+                name, txn, child
+            )));
+        }
+        state.release(txn, &name);
+        Ok(())
+    }
+
+    /// Releases all locks of transaction when it ends, and cancels its
+    /// waiting requests.
+    pub fn release_all(&self, txn: TxnId) {
+        let mut state = self.state.lock();
+        // The waiters see the requests are canceled once they're dropped.
+        let names = state
+            .cancel(txn)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        let locks = state.txn_locks.remove(&txn).unwrap_or_default();
+        for name in locks.keys() {
+            if let Some(entry) = state.resources.get_mut(name) {
+                entry.granted.retain(|(other, _)| *other != txn);
+            }
+        }
+        for name in locks.keys().chain(&names) {
+            state.process_queue(name);
+        }
+    }
+
+    /// Breaks the deadlocks of waiting transactions: while the waits-for graph
+    /// has a cycle, the youngest transaction of the cycle (whose id is the
+    /// largest) is the victim, whose waiting request fails with
+    /// `Error::Deadlock`. Returns the victims.
+    ///
+    /// A victim is aborted by its owner, which rolls it back, then releases its
+    /// locks by `release_all` (as `Transaction` and the operations of
+    /// `Database` do). Its locks aren't released here, since its changes are
+    /// undone physically and must stay protected until they're rolled back.
+    pub fn detect_deadlocks(&self) -> Vec<TxnId> {
+        let mut state = self.state.lock();
+        let mut victims = vec![];
+        while let Some(cycle) = find_cycle(&state.waits_for()) {
+            let victim = cycle.into_iter().max().unwrap();
+            for (name, request) in state.cancel(victim) {
+                let _ = request.waker.send(Err(Error::Deadlock(victim)));
+                state.process_queue(&name);
+            }
+            victims.push(victim);
+        }
+        victims
+    }
+
+    /// Returns the lock of transaction on resource, `LockType::NL` if none.
+    pub fn lock_type(&self, txn: TxnId, name: &ResourceName) -> LockType {
+        self.state.lock().lock_type(txn, name)
+    }
+
+    /// Returns the lock which transaction effectively holds on resource,
+    /// including the lock implied by the S, SIX or X locks on its ancestors.
+    pub fn effective_lock_type(&self, txn: TxnId, name: &ResourceName) -> LockType {
+        let state = self.state.lock();
+        let implied = name
+            .ancestors()
+            .iter()
+            .map(|ancestor| match state.lock_type(txn, ancestor) {
+                LockType::X => LockType::X,
+                LockType::S | LockType::SIX => LockType::S,
+                _ => LockType::NL,
+            })
+            .fold(LockType::NL, |implied, lock_type| {
+                if implied.substitutable(lock_type) {
+                    implied
+                } else {
+                    lock_type
+                }
+            });
+        match (state.lock_type(txn, name), implied) {
+            (held, implied) if held.substitutable(implied) => held,
+            (LockType::IX, LockType::S) => LockType::SIX,
+            (_, implied) => implied,
+        }
+    }
+
+    /// Returns the locks of transaction.
+    pub fn locks(&self, txn: TxnId) -> Vec<(ResourceName, LockType)> {
+        self.state
+            .lock()
+            .txn_locks
+            .get(&txn)
+            .map(|locks| locks.iter().map(|(name, held)| (*name, *held)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Makes transaction hold at least `lock_type` (S or X) on resource,
+    /// taking the intention locks on its ancestors from the database down,
+    /// and waits until all are granted. The page locks of transaction in the
+    /// table are escalated to a table lock once there are too many.
+    pub async fn lock(&self, txn: TxnId, name: ResourceName, lock_type: LockType) -> Result<()> {
+        assert!(
+            matches!(lock_type, LockType::S | LockType::X),
+            "lock {} is not S or X",
+            lock_type
+        );
+        if self
+            .effective_lock_type(txn, &name)
+            .substitutable(lock_type)
+        {
+            return Ok(());
+        }
+
+        for ancestor in name.ancestors() {
+            self.ensure(txn, ancestor, lock_type.parent_lock()).await?;
+        }
+        self.ensure(txn, name, lock_type).await?;
+
+        if let ResourceName::Page(part_num, _) | ResourceName::Record(part_num, _) = name {
+            let table = ResourceName::Table(part_num);
+            let num_pages = {
+                let state = self.state.lock();
+                state
+                    .descendants(txn, &table)
+                    .iter()
+                    .filter(|(resource, _)| matches!(resource, ResourceName::Page(..)))
+                    .count()
+            };
+            if num_pages > self.escalation_threshold {
+                self.escalate(txn, table).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes transaction hold at least `lock_type` (S or X) on resource like
+    /// `lock`, but only if the lock and the intention locks on its ancestors
+    /// are all granted at once, e.g. to skip a resource locked by others while
+    /// a latch is held. Returns false without taking any lock otherwise. The
+    /// page locks are not escalated.
+    pub fn try_lock(&self, txn: TxnId, name: ResourceName, lock_type: LockType) -> bool {
+        assert!(
+            matches!(lock_type, LockType::S | LockType::X),
+            "lock {} is not S or X",
+            lock_type
+        );
+        if self
+            .effective_lock_type(txn, &name)
+            .substitutable(lock_type)
+        {
+            return true;
+        }
+
+        let mut state = self.state.lock();
+        let requests = name
+            .ancestors()
+            .into_iter()
+            .map(|ancestor| (ancestor, lock_type.parent_lock()))
+            .chain(std::iter::once((name, lock_type)))
+            .filter_map(|(resource, lock_type)| {
+                let held = state.lock_type(txn, &resource);
+                let lock_type = match (held, lock_type) {
+                    (held, lock_type) if held.substitutable(lock_type) => return None,
+                    (LockType::S, LockType::IX) | (LockType::IX, LockType::S) => LockType::SIX,
+                    (_, lock_type) => lock_type,
+                };
+                Some((resource, held, lock_type))
+            })
+            .collect::<Vec<_>>();
+        let granted = requests.iter().all(|(resource, held, lock_type)| {
+            // Only a change of a held lock goes to the front of queue.
+            let queued = matches!(
+                state.resources.get(resource),
+                Some(entry) if !entry.queue.is_empty()
+            );
+            state.compatible(txn, resource, *lock_type) && (*held != LockType::NL || !queued)
+        });
+        if !granted {
+            return false;
+        }
+
+        for (resource, _, lock_type) in requests {
+            // The S and IS locks below SIX are redundant, as in `promote`.
+            let release = match lock_type {
+                LockType::SIX => state
+                    .descendants(txn, &resource)
+                    .into_iter()
+                    .filter(|(_, held)| matches!(held, LockType::S | LockType::IS))
+                    .map(|(resource, _)| resource)
+                    .collect(),
+                _ => vec![],
+            };
+            state.grant(txn, resource, lock_type, &release);
+        }
+        true
+    }
+
+    /// Makes transaction hold at least `lock_type` on resource by acquiring
+    /// or promoting its lock.
+    async fn ensure(&self, txn: TxnId, name: ResourceName, lock_type: LockType) -> Result<()> {
+        let held = self.lock_type(txn, &name);
+        if held.substitutable(lock_type) {
+            return Ok(());
+        }
+        match (held, lock_type) {
+            (LockType::NL, _) => self.acquire(txn, name, lock_type).await,
+            (LockType::S, LockType::IX) | (LockType::IX, LockType::S) => {
+                self.promote(txn, name, LockType::SIX).await
+            }
+            _ => self.promote(txn, name, lock_type).await,
+        }
+    }
+
+    /// Replaces the locks of transaction on resource and its descendants with
+    /// a single S lock on resource, or X if any of them is IX, SIX or X.
+    pub async fn escalate(&self, txn: TxnId, name: ResourceName) -> Result<()> {
+        let (lock_type, release) = {
+            let state = self.state.lock();
+            let held = state.lock_type(txn, &name);
+            let descendants = state.descendants(txn, &name);
+            if held == LockType::NL {
+                return Err(Error::NotFound(format!(
+                    "lock on {:?} of transaction {}",
+                    name, txn
+                )));
+            }
+
+            let write = std::iter::once(held)
+                .chain(descendants.iter().map(|(_, held)| *held))
+                .any(|held| matches!(held, LockType::IX | LockType::SIX | LockType::X));
+            let lock_type = if write { LockType::X } else { LockType::S };
+            if held == lock_type && descendants.is_empty() {
+                return Ok(());
+            }
+            state.check_parent(txn, &name, lock_type)?;
+            let release = descendants.into_iter().map(|(resource, _)| resource);
+            (lock_type, release.collect::<Vec<_>>())
+        };
+        self.request(txn, name, lock_type, release, true).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{executor::block_on, poll, task::Poll};
+
+    use super::{LockType::*, ResourceName::*, *};
+    use crate::datatypes::record::RecordId;
+
+    fn sorted_locks(manager: &LockManager, txn: TxnId) -> Vec<(ResourceName, LockType)> {
+        let mut locks = manager.locks(txn);
+        locks.sort_by_key(|(name, _)| format!("{:?}", name));
+        locks
+    }
+
+    #[test]
+    fn test_acquire_release() {
+        block_on(async {
+            let manager = LockManager::new(DEFAULT_ESCALATION_THRESHOLD);
+            manager.acquire(1, Database, IX).await.unwrap();
+            manager.acquire(2, Database, IS).await.unwrap();
+            assert!(manager.acquire(1, Database, S).await.is_err());
+            // The parent must allow the lock.
+            assert!(manager.acquire(2, Table(1), X).await.is_err());
+            assert!(manager.acquire(3, Table(1), S).await.is_err());
+            manager.acquire(1, Table(1), X).await.unwrap();
+            manager.acquire(2, Table(2), S).await.unwrap();
+            assert_eq!(manager.lock_type(1, &Table(1)), X);
+            assert_eq!(manager.lock_type(2, &Table(1)), NL);
+
+            // Children are released first.
+            assert!(manager.release(1, Database).is_err());
+            assert!(manager.release(1, Table(2)).is_err());
+            manager.release(1, Table(1)).unwrap();
+            manager.release(1, Database).unwrap();
+            assert!(manager.locks(1).is_empty());
+            manager.release_all(2);
+            assert!(manager.locks(2).is_empty());
+        })
+    }
+
+    #[test]
+    fn test_queue() {
+        block_on(async {
+            let manager = LockManager::new(DEFAULT_ESCALATION_THRESHOLD);
+            manager.acquire(1, Database, X).await.unwrap();
+
+            let mut s2 = Box::pin(manager.acquire(2, Database, S));
+            let mut x3 = Box::pin(manager.acquire(3, Database, X));
+            let mut s4 = Box::pin(manager.acquire(4, Database, S));
+            assert!(poll!(&mut s2).is_pending());
+            assert!(poll!(&mut x3).is_pending());
+            // Compatible with the granted lock of 2 later, but queued after 3.
+            assert!(poll!(&mut s4).is_pending());
+
+            manager.release(1, Database).unwrap();
+            assert!(matches!(poll!(&mut s2), Poll::Ready(Ok(()))));
+            assert!(poll!(&mut x3).is_pending());
+            assert!(poll!(&mut s4).is_pending());
+
+            manager.release_all(2);
+            assert!(matches!(poll!(&mut x3), Poll::Ready(Ok(()))));
+            assert!(poll!(&mut s4).is_pending());
+            // A waiting request is canceled when its transaction ends.
+            manager.release_all(4);
+            assert!(matches!(poll!(&mut s4), Poll::Ready(Err(_))));
+            assert!(manager.locks(4).is_empty());
+        })
+    }
+
+    #[test]
+    fn test_promote() {
+        block_on(async {
+            let manager = LockManager::new(DEFAULT_ESCALATION_THRESHOLD);
+            manager.acquire(1, Database, IS).await.unwrap();
+            manager.acquire(2, Database, IS).await.unwrap();
+            assert!(manager.promote(1, Database, IS).await.is_err());
+            assert!(manager.promote(1, Table(1), S).await.is_err());
+
+            manager.acquire(3, Database, IS).await.unwrap();
+            let mut x3 = Box::pin(manager.promote(3, Database, X));
+            assert!(poll!(&mut x3).is_pending());
+            let mut x4 = Box::pin(manager.acquire(4, Database, IX));
+            assert!(poll!(&mut x4).is_pending());
+            // Promotions don't wait behind the queue.
+            manager.promote(1, Database, S).await.unwrap();
+            assert_eq!(manager.lock_type(1, &Database), S);
+
+            manager.release_all(2);
+            assert!(poll!(&mut x3).is_pending());
+            manager.release_all(1);
+            assert!(matches!(poll!(&mut x3), Poll::Ready(Ok(()))));
+            assert_eq!(manager.lock_type(3, &Database), X);
+            manager.release_all(3);
+            assert!(matches!(poll!(&mut x4), Poll::Ready(Ok(()))));
+        })
+    }
+
+    #[test]
+    fn test_lock() {
+        block_on(async {
+            let manager = LockManager::new(DEFAULT_ESCALATION_THRESHOLD);
+            let record = Record(1, RecordId(3, 0));
+            manager.lock(1, record, S).await.unwrap();
+            assert_eq!(
+                sorted_locks(&manager, 1),
+                vec![
+                    (Database, IS),
+                    (Page(1, 3), IS),
+                    (record, S),
+                    (Table(1), IS)
+                ]
+            );
+
+            // Upgrades the intention locks on the way down.
+            manager.lock(1, Record(1, RecordId(3, 1)), X).await.unwrap();
+            assert_eq!(manager.lock_type(1, &Page(1, 3)), IX);
+            assert_eq!(manager.lock_type(1, &Database), IX);
+
+            // S over IX is SIX, the S and IS locks below are released.
+            manager.lock(1, Page(1, 3), S).await.unwrap();
+            assert_eq!(
+                sorted_locks(&manager, 1),
+                vec![
+                    (Database, IX),
+                    (Page(1, 3), SIX),
+                    (Record(1, RecordId(3, 1)), X),
+                    (Table(1), IX)
+                ]
+            );
+            assert_eq!(manager.effective_lock_type(1, &record), S);
+            // Implied by the lock on page.
+            manager.lock(1, record, S).await.unwrap();
+            assert_eq!(manager.lock_type(1, &record), NL);
+
+            manager.lock(1, Table(1), X).await.unwrap();
+            assert_eq!(manager.effective_lock_type(1, &Page(1, 7)), X);
+            manager.release_all(1);
+            assert!(manager.locks(1).is_empty());
+        })
+    }
+
+    #[test]
+    fn test_try_lock() {
+        block_on(async {
+            let manager = LockManager::new(DEFAULT_ESCALATION_THRESHOLD);
+            assert!(manager.try_lock(1, Page(1, 0), X));
+            assert_eq!(
+                sorted_locks(&manager, 1),
+                vec![(Database, IX), (Page(1, 0), X), (Table(1), IX)]
+            );
+            assert!(manager.try_lock(1, Record(1, RecordId(0, 1)), X));
+            assert_eq!(manager.locks(1).len(), 3);
+
+            // Nothing is taken if any lock must wait.
+            manager.lock(2, Record(1, RecordId(1, 0)), S).await.unwrap();
+            assert!(!manager.try_lock(1, Page(1, 1), X));
+            assert_eq!(manager.locks(1).len(), 3);
+            assert!(manager.try_lock(2, Page(1, 2), S));
+            assert!(!manager.try_lock(2, Table(1), S));
+            assert_eq!(manager.lock_type(2, &Table(1)), IS);
+
+            // Nor behind a queued request.
+            manager.release_all(2);
+            manager.lock(3, Page(1, 3), S).await.unwrap();
+            let mut x4 = Box::pin(manager.lock(4, Page(1, 3), X));
+            assert!(poll!(&mut x4).is_pending());
+            assert!(!manager.try_lock(5, Page(1, 3), S));
+            manager.release_all(3);
+            assert!(matches!(poll!(&mut x4), Poll::Ready(Ok(()))));
+            manager.release_all(4);
+            assert!(manager.try_lock(5, Page(1, 3), S));
+        })
+    }
+
+    #[test]
+    fn test_escalation() {
+        block_on(async {
+            let manager = LockManager::new(4);
+            for page in 0..4 {
+                manager.lock(1, Page(1, page), S).await.unwrap();
+            }
+            manager.lock(1, Page(2, 0), X).await.unwrap();
+            assert_eq!(manager.locks(1).len(), 8);
+
+            // The 5th page lock in table 1 escalates to a table lock.
+            manager.lock(1, Page(1, 4), S).await.unwrap();
+            assert_eq!(
+                sorted_locks(&manager, 1),
+                vec![
+                    (Database, IX),
+                    (Page(2, 0), X),
+                    (Table(1), S),
+                    (Table(2), IX)
+                ]
+            );
+
+            for page in 0..5 {
+                manager
+                    .lock(1, Record(2, RecordId(page, 0)), S)
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(manager.lock_type(1, &Table(2)), X);
+            assert_eq!(manager.locks(1).len(), 3);
+
+            // Others can't write the escalated tables.
+            let mut x2 = Box::pin(manager.lock(2, Page(1, 9), X));
+            assert!(poll!(&mut x2).is_pending());
+            manager.release_all(1);
+            assert!(matches!(poll!(&mut x2), Poll::Ready(Ok(()))));
+        })
+    }
+
+    #[test]
+    fn test_detect_deadlocks() {
+        block_on(async {
+            let manager = LockManager::new(DEFAULT_ESCALATION_THRESHOLD);
+            manager.lock(1, Table(1), X).await.unwrap();
+            manager.lock(2, Table(2), X).await.unwrap();
+            manager.lock(3, Table(3), S).await.unwrap();
+            let mut s1 = Box::pin(manager.lock(1, Table(2), S));
+            assert!(poll!(&mut s1).is_pending());
+            let mut x3 = Box::pin(manager.lock(3, Table(1), X));
+            assert!(poll!(&mut x3).is_pending());
+            assert!(manager.detect_deadlocks().is_empty());
+
+            let mut s2 = Box::pin(manager.lock(2, Table(1), S));
+            assert!(poll!(&mut s2).is_pending());
+            // 2 waits for 1 and 3 queued before it, 2 is the youngest of
+            // the cycle of 1 and 2.
+            assert_eq!(manager.detect_deadlocks(), vec![2]);
+            assert!(matches!(
+                poll!(&mut s2),
+                Poll::Ready(Err(Error::Deadlock(2)))
+            ));
+            assert!(poll!(&mut s1).is_pending());
+            assert!(poll!(&mut x3).is_pending());
+
+            manager.release_all(2);
+            assert!(matches!(poll!(&mut s1), Poll::Ready(Ok(()))));
+            manager.release_all(1);
+            assert!(matches!(poll!(&mut x3), Poll::Ready(Ok(()))));
+        })
+    }
+}
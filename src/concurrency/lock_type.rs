@@ -0,0 +1,135 @@
+use std::fmt;
+
+/// The mode of a lock on a resource of the hierarchy (see `ResourceName`).
+///
+/// The intention locks (IS, IX and SIX) are taken on the ancestors of a
+/// resource before the resource is locked in S or X, so that a lock on a
+/// resource conflicts with the locks on its descendants held by others.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockType {
+    /// No lock.
+    NL,
+    /// Intention shared, some descendants are locked in S.
+    IS,
+    /// Intention exclusive, some descendants are locked in S or X.
+    IX,
+    /// Shared, the resource and all its descendants are read.
+    S,
+    /// Shared and intention exclusive, i.e. S plus IX.
+    SIX,
+    /// Exclusive, the resource and all its descendants are written.
+    X,
+}
+
+impl LockType {
+    /// Returns whether locks of the two types on a resource can be held by
+    /// different transactions at the same time.
+    pub fn compatible(self, other: LockType) -> bool {
+        use LockType::*;
+        match (self, other) {
+            (NL, _) | (_, NL) => true,
+            (IS, X) | (X, IS) => false,
+            (IS, _) | (_, IS) => true,
+            (IX, IX) | (S, S) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the lock which the parent of a resource must hold at least to
+    /// lock the resource in this type.
+    pub fn parent_lock(self) -> LockType {
+        use LockType::*;
+        match self {
+            NL => NL,
+            IS | S => IS,
+            IX | SIX | X => IX,
+        }
+    }
+
+    /// Returns whether a resource locked in this type allows its child to be
+    /// locked in `child` by the same transaction.
+    pub fn can_be_parent_lock(self, child: LockType) -> bool {
+        use LockType::*;
+        match (self, child) {
+            (_, NL) => true,
+            (IS, IS | S) => true,
+            (IX, _) => true,
+            // The children are read by SIX already.
+            (SIX, IX | SIX | X) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether a lock of this type grants at least what a lock of
+    /// `required` does, so that it can be used instead.
+    pub fn substitutable(self, required: LockType) -> bool {
+        use LockType::*;
+        match required {
+            NL => true,
+            IS => self != NL,
+            IX => matches!(self, IX | SIX | X),
+            S => matches!(self, S | SIX | X),
+            SIX => matches!(self, SIX | X),
+            X => self == X,
+        }
+    }
+
+    /// Returns whether the lock is one of the intention locks.
+    pub fn is_intent(self) -> bool {
+        matches!(self, LockType::IS | LockType::IX | LockType::SIX)
+    }
+}
+
+impl fmt::Display for LockType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockType::*, *};
+
+    const ALL: [LockType; 6] = [NL, IS, IX, S, SIX, X];
+
+    #[test]
+    fn test_compatible() {
+        let compatible = |a: LockType| {
+            ALL.iter()
+                .filter(|&&b| a.compatible(b))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(compatible(NL), ALL.to_vec());
+        assert_eq!(compatible(IS), vec![NL, IS, IX, S, SIX]);
+        assert_eq!(compatible(IX), vec![NL, IS, IX]);
+        assert_eq!(compatible(S), vec![NL, IS, S]);
+        assert_eq!(compatible(SIX), vec![NL, IS]);
+        assert_eq!(compatible(X), vec![NL]);
+        for a in ALL {
+            for b in ALL {
+                assert_eq!(a.compatible(b), b.compatible(a));
+            }
+        }
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        for child in ALL {
+            assert!(child.parent_lock().can_be_parent_lock(child));
+            assert!(IX.can_be_parent_lock(child));
+        }
+        assert!(!IS.can_be_parent_lock(X));
+        assert!(!S.can_be_parent_lock(IS));
+        assert!(!SIX.can_be_parent_lock(S));
+        assert!(!X.can_be_parent_lock(X));
+
+        assert!(SIX.substitutable(S) && SIX.substitutable(IX));
+        assert!(!S.substitutable(IX) && !IX.substitutable(S));
+        assert!(X.substitutable(SIX));
+        for a in ALL {
+            assert!(a.substitutable(a) && a.substitutable(NL));
+        }
+    }
+}
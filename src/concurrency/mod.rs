@@ -0,0 +1,4 @@
+pub mod deadlock;
+pub mod lock_manager;
+pub mod lock_type;
+pub mod resource;
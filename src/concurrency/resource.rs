@@ -0,0 +1,51 @@
+use crate::datatypes::record::RecordId;
+
+/// A lockable resource, resources form a hierarchy: the database, then the
+/// tables (by partition), the data pages of a table, and the records of a
+/// page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceName {
+    Database,
+    /// The table of partition.
+    Table(usize),
+    /// The data page of the table of partition.
+    Page(usize, u64),
+    /// The record of the table of partition.
+    Record(usize, RecordId),
+}
+
+impl ResourceName {
+    /// Returns the parent of resource, `None` for the database.
+    pub fn parent(&self) -> Option<ResourceName> {
+        match *self {
+            ResourceName::Database => None,
+            ResourceName::Table(_) => Some(ResourceName::Database),
+            ResourceName::Page(part_num, _) => Some(ResourceName::Table(part_num)),
+            ResourceName::Record(part_num, rid) => Some(ResourceName::Page(part_num, rid.0)),
+        }
+    }
+
+    /// Returns the ancestors of resource from the database down.
+    pub fn ancestors(&self) -> Vec<ResourceName> {
+        let mut ancestors = vec![];
+        let mut resource = *self;
+        while let Some(parent) = resource.parent() {
+            ancestors.push(parent);
+            resource = parent;
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    /// Returns whether the resource is a descendant of `ancestor`.
+    pub fn is_descendant_of(&self, ancestor: &ResourceName) -> bool {
+        let mut resource = *self;
+        while let Some(parent) = resource.parent() {
+            if &parent == ancestor {
+                return true;
+            }
+            resource = parent;
+        }
+        false
+    }
+}
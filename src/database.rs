@@ -1,65 +1,716 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
+
+mod transaction;
+
+use parking_lot::Mutex;
+pub use transaction::Transaction;
 
 use crate::{
-    catalog::schema::Schema,
+    catalog::{
+        schema::{Schema, SchemaChange},
+        Catalog,
+    },
+    concurrency::{
+        deadlock::DeadlockDetector, lock_manager::LockManager, lock_type::LockType,
+        resource::ResourceName,
+    },
     datatypes::record::{Record, RecordId},
     error::{Error, Result},
     options::Options,
     table::{
-        page::{page_directory::PageDirectory, partition::PartitionHandle},
-        Table,
+        manifest::{ManifestEntry, TxnId},
+        metadata::TableMetadata,
+        page::page_directory::PageDirectory,
+        recover, Table,
     },
 };
 
 /// Database keeps track of transactions, tables and indices and delegates work
 /// to its disk manager, buffer manager, lock manager and recovery manager.
+///
+/// The changes to records across tables run in a `Transaction` (see `begin`),
+/// or each operation of `Database` runs in its own transaction, which is
+/// committed if the operation succeeds and rolled back otherwise.
+/// The data pages (or tables) changed by a transaction are locked in X until
+/// it ends (see `LockManager` and `Table`).
 pub struct Database {
     options: Options,
-    page_directory: PageDirectory,
+    page_directory: Arc<PageDirectory>,
+    lock_manager: Arc<LockManager>,
+    catalog: Catalog,
     tables: HashMap<String, Table>,
+
+    /// The transactions dropped without ending them whose rollback failed,
+    /// with the errors.
+    rollback_failures: Mutex<Vec<(TxnId, Error)>>,
 }
 
 impl Database {
-    pub fn open(options: Options) -> Self {
-        let page_directory = PageDirectory::new(options.path.clone());
+    /// Opens the database at `options.path`, the database is recovered from
+    /// the write-ahead log first, then tables recorded in the catalog are
+    /// loaded again.
+    pub async fn open(options: Options) -> Result<Self> {
+        let page_directory = Arc::new(PageDirectory::open(&options).await?);
+        recover::recover(&page_directory).await?;
+        let catalog = Catalog::open(page_directory.clone()).await?;
+        let lock_manager = Arc::new(LockManager::new(options.lock_escalation_threshold));
+
+        let mut tables = HashMap::new();
+        for metadata in catalog.tables() {
+            page_directory
+                .alloc_part_with_num(metadata.part_num())
+                .await?;
+            let table = Table::create(metadata.clone(), page_directory.clone())
+                .await?
+                .with_lock_manager(lock_manager.clone());
+            tables.insert(metadata.name().to_owned(), table);
+        }
 
-        Self {
+        DeadlockDetector::spawn(&lock_manager, options.deadlock_detection_interval);
+
+        Ok(Self {
+            lock_manager,
             options,
-            tables: HashMap::new(),
+            tables,
+            catalog,
             page_directory,
+            rollback_failures: Mutex::new(vec![]),
+        })
+    }
+
+    /// Takes the errors of rolling back the transactions which were dropped
+    /// without `commit` or `rollback` (see `Transaction`), with their ids.
+    /// Such a transaction isn't ended in the log, so it's rolled back by
+    /// recovery when the database is opened again.
+    pub fn take_rollback_failures(&self) -> Vec<(TxnId, Error)> {
+        std::mem::take(&mut *self.rollback_failures.lock())
+    }
+
+    /// Begins a transaction, whose changes are committed or rolled back
+    /// together.
+    pub async fn begin(&self) -> Result<Transaction<'_>> {
+        let txn = self.page_directory.begin_txn().await;
+        self.page_directory
+            .log(txn, ManifestEntry::BeginTxn)
+            .await?;
+        Ok(Transaction::new(self, txn))
+    }
+
+    /// Commits the transaction if `result` is ok, otherwise rolls it back.
+    /// The locks of transaction are released either way.
+    async fn finish_txn<T>(&self, txn: TxnId, result: Result<T>) -> Result<T> {
+        let result = self.end_txn(txn, result).await;
+        self.lock_manager.release_all(txn);
+        result
+    }
+
+    /// Commits or rolls back the transaction, see `finish_txn`.
+    async fn end_txn<T>(&self, txn: TxnId, result: Result<T>) -> Result<T> {
+        match result {
+            Ok(v) => {
+                self.page_directory.commit_txn(txn).await?;
+                self.maybe_checkpoint().await?;
+                Ok(v)
+            }
+            Err(e) => {
+                recover::rollback(&self.page_directory, txn).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Takes a checkpoint, which bounds the work of recovery and lets the log
+    /// be truncated.
+    pub async fn checkpoint(&self) -> Result<()> {
+        recover::checkpoint(&self.page_directory).await
+    }
+
+    /// Takes a checkpoint if enough log is written since the last one.
+    async fn maybe_checkpoint(&self) -> Result<()> {
+        let pages = self
+            .page_directory
+            .manifest()
+            .await
+            .pages_since_checkpoint();
+        if pages >= self.options.checkpoint_interval {
+            self.checkpoint().await?;
         }
+        Ok(())
     }
 
     pub async fn create_table(&mut self, table_name: String, schema: Schema) -> Result<()> {
-        if self.tables.contains_key(&table_name) {
+        if self.catalog.contains_table(&table_name) {
             return Err(Error::Corrupted(format!(
                 "table name {} already exist.",
                 table_name
             )));
         }
 
+        let txn = self.page_directory.begin_txn().await;
+        let result = self.create_table_in_txn(txn, table_name, schema).await;
+        let table = self.finish_txn(txn, result).await?;
+        self.tables.insert(table.name().to_owned(), table);
+        Ok(())
+    }
+
+    /// Allocates the partition of table and adds the table to catalog. The
+    /// partition is deleted if the transaction rolls back.
+    async fn create_table_in_txn(
+        &mut self,
+        txn: TxnId,
+        table_name: String,
+        schema: Schema,
+    ) -> Result<Table> {
         let part_num = self.page_directory.alloc_part().await?;
+        self.page_directory
+            .log(txn, ManifestEntry::AllocParition(part_num))
+            .await?;
 
-        // todo optimize.
-        let ph = unsafe {
-            let ptr = self.page_directory.get_partition(part_num)?.value() as *const PartitionHandle
-                as *mut PartitionHandle;
-            Box::from_raw(ptr)
-        };
+        let metadata = TableMetadata::new(
+            table_name,
+            schema,
+            part_num,
+            self.options.num_records_per_page,
+        );
+        let table = Table::create(metadata, self.page_directory.clone())
+            .await?
+            .with_lock_manager(self.lock_manager.clone());
+        self.catalog
+            .create_table(txn, table.metadata().clone())
+            .await?;
+        Ok(table)
+    }
 
-        let table = Table::create(schema, ph, self.options.num_records_per_page).await?;
+    /// Drops the table, its catalog row is removed, then its indices are
+    /// dropped and its partition is deleted once the transaction commits. The
+    /// locks of transaction are released even if the deletion fails, then the
+    /// partition is deleted by recovery.
+    pub async fn drop_table(&mut self, table_name: &str) -> Result<()> {
+        let part_num = self.get_table(table_name)?.get_part_num();
+
+        let txn = self.page_directory.begin_txn().await;
+        let result = self.drop_table_in_txn(txn, table_name, part_num).await;
+        if let Err(e) = result {
+            return self.finish_txn(txn, Err(e)).await;
+        }
 
-        self.tables.insert(table_name, table);
+        // The table is dropped once the transaction commits, the partition is
+        // deleted by recovery if we crash from here on.
+        if let Err(e) = self.page_directory.manifest().await.commit(txn).await {
+            self.lock_manager.release_all(txn);
+            return Err(e);
+        }
+        let table = self.tables.remove(table_name).unwrap();
+        let result = match table.destroy().await {
+            Ok(()) => self.page_directory.manifest().await.end(txn).await,
+            // The transaction isn't ended, so the partition is deleted by
+            // recovery.
+            Err(e) => Err(e),
+        };
+        self.lock_manager.release_all(txn);
+        result?;
+        self.maybe_checkpoint().await
+    }
+
+    /// Locks the table in X, then logs that its partition is freed and removes
+    /// it from catalog.
+    async fn drop_table_in_txn(
+        &mut self,
+        txn: TxnId,
+        table_name: &str,
+        part_num: usize,
+    ) -> Result<()> {
+        self.lock_manager
+            .lock(txn, ResourceName::Table(part_num), LockType::X)
+            .await?;
+        self.page_directory
+            .log(txn, ManifestEntry::FreePartition(part_num))
+            .await?;
+        self.catalog.drop_table(txn, table_name).await?;
         Ok(())
     }
 
+    /// Alters the columns (or indices) of table. Existing records are not
+    /// rewritten, they're upgraded to the new schema when they're read or
+    /// updated.
+    pub async fn alter_table(&mut self, table_name: &str, change: SchemaChange) -> Result<()> {
+        if !self.tables.contains_key(table_name) {
+            return Err(Error::NotFound(format!("table {}", table_name)));
+        }
+
+        let txn = self.page_directory.begin_txn().await;
+        let result = self.alter_table_in_txn(txn, table_name, change).await;
+        let metadata = self.finish_txn(txn, result).await?;
+        self.tables
+            .get_mut(table_name)
+            .unwrap()
+            .alter(metadata)
+            .await
+    }
+
+    /// Locks the table in X, builds the index added by the change (i.e.
+    /// CREATE INDEX) from its records, then rewrites its catalog row with the
+    /// root of index.
+    async fn alter_table_in_txn(
+        &mut self,
+        txn: TxnId,
+        table_name: &str,
+        change: SchemaChange,
+    ) -> Result<TableMetadata> {
+        let table = self.get_table(table_name)?;
+        self.lock_manager
+            .lock(txn, ResourceName::Table(table.get_part_num()), LockType::X)
+            .await?;
+        let mut metadata = self.catalog.get_table(table_name)?.alter(change.clone())?;
+        if let SchemaChange::AddIndex(index) = &change {
+            let root = table.build_index(txn, index).await?;
+            metadata.set_index_root(&index.name, root);
+        }
+        self.catalog.alter_table(txn, metadata.clone()).await?;
+        Ok(metadata)
+    }
+
     // todo(improve): batchRecord instead of record.
     pub async fn insert(&self, table_name: &str, record: Record) -> Result<RecordId> {
-        let table = self
-            .tables
+        let table = self.get_table(table_name)?;
+        let txn = self.page_directory.begin_txn().await;
+        let result = table.insert(txn, record).await;
+        self.finish_txn(txn, result).await
+    }
+
+    pub async fn get(&self, table_name: &str, id: RecordId) -> Result<Record> {
+        self.get_table(table_name)?.get(id).await
+    }
+
+    pub async fn update(&self, table_name: &str, id: RecordId, record: Record) -> Result<Record> {
+        let table = self.get_table(table_name)?;
+        let txn = self.page_directory.begin_txn().await;
+        let result = table.update(txn, id, record).await;
+        self.finish_txn(txn, result).await
+    }
+
+    pub async fn remove(&self, table_name: &str, id: RecordId) -> Result<Record> {
+        let table = self.get_table(table_name)?;
+        let txn = self.page_directory.begin_txn().await;
+        let result = table.remove(txn, id).await;
+        self.finish_txn(txn, result).await
+    }
+
+    /// Locks the record in S or X for transaction, along with the intention
+    /// locks on its page, table and the database.
+    async fn lock_record(
+        &self,
+        txn: TxnId,
+        table: &Table,
+        id: RecordId,
+        lock_type: LockType,
+    ) -> Result<()> {
+        let name = ResourceName::Record(table.get_part_num(), id);
+        self.lock_manager.lock(txn, name, lock_type).await
+    }
+
+    fn get_table(&self, table_name: &str) -> Result<&Table> {
+        self.tables
             .get(table_name)
-            .ok_or(Error::NotFound(format!("table {}", table_name)))?;
+            .ok_or(Error::NotFound(format!("table {}", table_name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        catalog::schema::{Column, DataType, TableIndex},
+        datatypes::array::scalar::ScalarImpl,
+        table::page::{
+            page_directory::{virtual_page_num, DEFAULT_PAGE_SIZE},
+            PageFormat,
+        },
+    };
+
+    fn schema() -> Schema {
+        Schema::new()
+            .add(Column::new("id", DataType::Int32).not_null())
+            .add(Column::new("name", DataType::String(32)))
+    }
+
+    fn part_path(dir: &TempDir, part_num: usize) -> std::path::PathBuf {
+        dir.path().join(format!("db.{}", part_num))
+    }
+
+    fn options(dir: &TempDir) -> Options {
+        Options {
+            path: dir.path().join("db").to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reopen() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let users = Schema::new()
+                .add(Column::new("id", DataType::Int32).not_null())
+                .add(Column::new("name", DataType::String(32)));
+            let posts = Schema::new()
+                .add(Column::new("body", DataType::String(u16::MAX)))
+                .with_page_format(PageFormat::SlotDirectory);
+            let user = Record::from(vec![
+                ScalarImpl::Int32(1),
+                ScalarImpl::String("rookie".to_owned()),
+            ]);
+            let post = Record::from(vec![ScalarImpl::String("hello".to_owned())]);
+
+            let (user_id, post_id) = {
+                let mut db = Database::open(options(&dir)).await.unwrap();
+                db.create_table("users".to_owned(), users.clone())
+                    .await
+                    .unwrap();
+                db.create_table("posts".to_owned(), posts.clone())
+                    .await
+                    .unwrap();
+                assert!(db
+                    .create_table("users".to_owned(), users.clone())
+                    .await
+                    .is_err());
+                (
+                    db.insert("users", user.clone()).await.unwrap(),
+                    db.insert("posts", post.clone()).await.unwrap(),
+                )
+            };
+
+            let mut db = Database::open(options(&dir)).await.unwrap();
+            assert_eq!(db.catalog.get_table("users").unwrap().get_schema(), &users);
+            assert_eq!(db.catalog.get_table("posts").unwrap().get_schema(), &posts);
+            assert_eq!(db.get("users", user_id).await.unwrap(), user);
+            assert_eq!(db.get("posts", post_id).await.unwrap(), post);
+
+            // New partitions don't collide with the loaded ones.
+            db.create_table("comments".to_owned(), posts).await.unwrap();
+            let parts = db
+                .catalog
+                .tables()
+                .map(|t| t.part_num())
+                .collect::<std::collections::HashSet<_>>();
+            assert_eq!(parts.len(), 3);
+        })
+    }
+
+    #[test]
+    fn test_drop_table() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let mut db = Database::open(options(&dir)).await.unwrap();
+            db.create_table("users".to_owned(), schema()).await.unwrap();
+            let id = db
+                .insert("users", Record::from(vec![ScalarImpl::Int32(1)]))
+                .await
+                .unwrap();
+            let part_num = db.get_table("users").unwrap().get_part_num();
+            assert!(part_path(&dir, part_num).exists());
+
+            db.drop_table("users").await.unwrap();
+            assert!(!part_path(&dir, part_num).exists());
+            assert!(db.get("users", id).await.is_err());
+            assert!(db.drop_table("users").await.is_err());
+            drop(db);
+
+            let mut db = Database::open(options(&dir)).await.unwrap();
+            assert!(db.catalog.get_table("users").is_err());
+            db.create_table("users".to_owned(), schema()).await.unwrap();
+            assert!(db.get("users", id).await.is_err());
+        })
+    }
+
+    #[test]
+    fn test_drop_table_recovery() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let mut db = Database::open(options(&dir)).await.unwrap();
+            db.create_table("users".to_owned(), schema()).await.unwrap();
+            db.create_table("posts".to_owned(), schema()).await.unwrap();
+            let users = db.get_table("users").unwrap().get_part_num();
+            let posts = db.get_table("posts").unwrap().get_part_num();
+
+            // Crash after dropping `users` commits.
+            let txn = db.page_directory.begin_txn().await;
+            db.page_directory
+                .log(txn, ManifestEntry::FreePartition(users))
+                .await
+                .unwrap();
+            db.catalog.drop_table(txn, "users").await.unwrap();
+            db.page_directory
+                .manifest()
+                .await
+                .commit(txn)
+                .await
+                .unwrap();
+            // Crash before dropping `posts` commits.
+            let txn = db.page_directory.begin_txn().await;
+            db.page_directory
+                .log(txn, ManifestEntry::FreePartition(posts))
+                .await
+                .unwrap();
+            db.catalog.drop_table(txn, "posts").await.unwrap();
+            drop(db);
+            assert!(part_path(&dir, users).exists());
+
+            let db = Database::open(options(&dir)).await.unwrap();
+            assert!(!part_path(&dir, users).exists());
+            assert!(db.catalog.get_table("users").is_err());
+            assert!(part_path(&dir, posts).exists());
+            assert!(db.catalog.get_table("posts").is_ok());
+            assert!(db.get_table("posts").is_ok());
+        })
+    }
+
+    #[test]
+    fn test_rollback() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let mut db = Database::open(options(&dir)).await.unwrap();
+            db.create_table("users".to_owned(), schema()).await.unwrap();
+            let user = Record::from(vec![
+                ScalarImpl::Int32(1),
+                ScalarImpl::String("rookie".to_owned()),
+            ]);
+            let id = db.insert("users", user.clone()).await.unwrap();
+
+            // The record doesn't fit the schema.
+            let invalid = Record::from(vec![ScalarImpl::Bool(true)]);
+            assert!(db.update("users", id, invalid).await.is_err());
+            assert_eq!(db.get("users", id).await.unwrap(), user);
+
+            // The partition of a table which fails to be created is deleted.
+            let part_num = db.get_table("users").unwrap().get_part_num() + 1;
+            let wide = Schema::new().add(Column::new("body", DataType::String(u16::MAX)));
+            assert!(db.create_table("posts".to_owned(), wide).await.is_err());
+            assert!(!part_path(&dir, part_num).exists());
+            assert!(db.catalog.get_table("posts").is_err());
+        })
+    }
+
+    #[test]
+    fn test_recovery() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let user = |id: i32, name: &str| {
+                Record::from(vec![
+                    ScalarImpl::Int32(id),
+                    ScalarImpl::String(name.to_owned()),
+                ])
+            };
+
+            let mut db = Database::open(options(&dir)).await.unwrap();
+            db.create_table("users".to_owned(), schema()).await.unwrap();
+            let committed = db.insert("users", user(1, "rookie")).await.unwrap();
+
+            // Crash with a running transaction, its changes are undone.
+            let table = db.get_table("users").unwrap();
+            let txn = db.page_directory.begin_txn().await;
+            let uncommitted = table.insert(txn, user(2, "db")).await.unwrap();
+            table
+                .update(txn, committed, user(1, "updated"))
+                .await
+                .unwrap();
+            drop(db);
+
+            let db = Database::open(options(&dir)).await.unwrap();
+            assert_eq!(db.get("users", committed).await.unwrap(), user(1, "rookie"));
+            assert!(db.get("users", uncommitted).await.is_err());
+            let part_num = db.get_table("users").unwrap().get_part_num();
+            let page_num = virtual_page_num(part_num, committed.0 as usize);
+            let page = db.page_directory.read_page(page_num).await.unwrap();
+
+            // Crash before the cached page is written back, committed changes
+            // are redone.
+            let id = db.insert("users", user(3, "wal")).await.unwrap();
+            assert_eq!(id.0, committed.0);
+            assert_eq!(db.page_directory.read_page(page_num).await.unwrap(), page);
+            drop(db);
+
+            let db = Database::open(options(&dir)).await.unwrap();
+            assert_eq!(db.get("users", committed).await.unwrap(), user(1, "rookie"));
+            assert_eq!(db.get("users", id).await.unwrap(), user(3, "wal"));
+        })
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let options = || Options {
+                checkpoint_interval: 1,
+                ..options(&dir)
+            };
+            let posts = Schema::new()
+                .add(Column::new("body", DataType::String(u16::MAX)))
+                .with_page_format(PageFormat::SlotDirectory);
+            let post =
+                |i: usize| Record::from(vec![ScalarImpl::String(format!("{:04}", i).repeat(300))]);
+
+            let mut db = Database::open(options()).await.unwrap();
+            db.create_table("posts".to_owned(), posts).await.unwrap();
+            let mut ids = vec![];
+            for i in 0..200 {
+                ids.push(db.insert("posts", post(i)).await.unwrap());
+            }
+            // The log is truncated and its pages are reused.
+            let log_size = std::fs::metadata(part_path(&dir, 0)).unwrap().len();
+            assert!(log_size < 16 * DEFAULT_PAGE_SIZE as u64);
+
+            // A transaction running across a checkpoint is undone.
+            let txn = db.page_directory.begin_txn().await;
+            let uncommitted = db
+                .get_table("posts")
+                .unwrap()
+                .insert(txn, post(200))
+                .await
+                .unwrap();
+            db.checkpoint().await.unwrap();
+            ids.push(db.insert("posts", post(201)).await.unwrap());
+            drop(db);
+
+            let db = Database::open(options()).await.unwrap();
+            assert!(db.get("posts", uncommitted).await.is_err());
+            for (i, id) in ids.into_iter().enumerate() {
+                let i = if i == 200 { 201 } else { i };
+                assert_eq!(db.get("posts", id).await.unwrap(), post(i));
+            }
+        })
+    }
+
+    #[test]
+    fn test_alter_table() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let mut db = Database::open(options(&dir)).await.unwrap();
+            db.create_table("users".to_owned(), schema()).await.unwrap();
+            let old = db
+                .insert(
+                    "users",
+                    Record::from(vec![
+                        ScalarImpl::Int32(1),
+                        ScalarImpl::String("rookie".to_owned()),
+                    ]),
+                )
+                .await
+                .unwrap();
+
+            db.alter_table("users", SchemaChange::DropColumn("name".to_owned()))
+                .await
+                .unwrap();
+            db.alter_table(
+                "users",
+                SchemaChange::AddColumn(
+                    Column::new("active", DataType::Bool).with_default(ScalarImpl::Bool(true)),
+                ),
+            )
+            .await
+            .unwrap();
+            db.alter_table(
+                "users",
+                SchemaChange::RenameColumn("id".to_owned(), "user_id".to_owned()),
+            )
+            .await
+            .unwrap();
+            assert!(db
+                .alter_table("users", SchemaChange::DropColumn("name".to_owned()))
+                .await
+                .is_err());
+
+            let new = db
+                .insert(
+                    "users",
+                    Record::from(vec![ScalarImpl::Int32(2), ScalarImpl::Bool(false)]),
+                )
+                .await
+                .unwrap();
+            drop(db);
+
+            let db = Database::open(options(&dir)).await.unwrap();
+            let schema = db.catalog.get_table("users").unwrap().get_schema();
+            assert_eq!(schema.column_id("user_id"), Some(0));
+            assert_eq!(schema.column_id("active"), Some(1));
+
+            // The old record is upgraded on read, and written with the current
+            // version on update.
+            let upgraded = Record::from(vec![ScalarImpl::Int32(1), ScalarImpl::Bool(true)]);
+            assert_eq!(db.get("users", old).await.unwrap(), upgraded);
+            let updated = Record::from(vec![ScalarImpl::Int32(1), ScalarImpl::Bool(false)]);
+            assert_eq!(
+                db.update("users", old, updated.clone()).await.unwrap(),
+                upgraded
+            );
+            assert_eq!(db.get("users", old).await.unwrap(), updated);
+            assert_eq!(
+                db.get("users", new).await.unwrap(),
+                Record::from(vec![ScalarImpl::Int32(2), ScalarImpl::Bool(false)])
+            );
+        })
+    }
+
+    #[test]
+    fn test_create_index() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let mut db = Database::open(options(&dir)).await.unwrap();
+            db.create_table("users".to_owned(), schema()).await.unwrap();
+            let mut ids = vec![];
+            for i in 0..500 {
+                let name = format!("user{}", i % 250);
+                let record = Record::from(vec![ScalarImpl::Int32(i), ScalarImpl::String(name)]);
+                ids.push(db.insert("users", record).await.unwrap());
+            }
+
+            // The names are not unique, nothing is added.
+            let unique = TableIndex::new("idx_name", vec![1]).unique();
+            assert!(matches!(
+                db.alter_table("users", SchemaChange::AddIndex(unique))
+                    .await,
+                Err(Error::ConstraintViolation(_))
+            ));
+            assert!(db
+                .get_table("users")
+                .unwrap()
+                .lookup("idx_name", &[])
+                .await
+                .is_err());
+
+            let index = TableIndex::new("idx_name", vec![1]);
+            db.alter_table("users", SchemaChange::AddIndex(index))
+                .await
+                .unwrap();
+            let id = db
+                .insert(
+                    "users",
+                    Record::from(vec![
+                        ScalarImpl::Int32(500),
+                        ScalarImpl::String("user7".to_owned()),
+                    ]),
+                )
+                .await
+                .unwrap();
+            drop(db);
 
-        table.insert(record).await
+            let db = Database::open(options(&dir)).await.unwrap();
+            let root = db
+                .catalog
+                .get_table("users")
+                .unwrap()
+                .get_schema()
+                .get_indcies()[0]
+                .root;
+            assert!(root.is_some());
+            let table = db.get_table("users").unwrap();
+            let name = ScalarImpl::String("user7".to_owned());
+            assert_eq!(
+                table.lookup("idx_name", &[name]).await.unwrap(),
+                vec![ids[7], ids[257], id]
+            );
+        })
     }
 }
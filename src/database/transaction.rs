@@ -0,0 +1,450 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use futures::executor::block_on;
+
+use super::Database;
+use crate::{
+    concurrency::{lock_type::LockType, resource::ResourceName},
+    datatypes::record::{Record, RecordId},
+    error::{Error, Result},
+    table::{
+        manifest::{Lsn, TxnId},
+        recover,
+    },
+};
+
+/// A transaction of database (see `Database::begin`), whose changes across
+/// tables are committed or rolled back together.
+///
+/// The records read are locked in S and the data pages changed are locked in X
+/// (see `Table`) until the transaction ends, a scan locks the whole table in S
+/// (see `LockManager::lock`). An operation which fails is rolled back alone and
+/// the transaction keeps running, but one which fails with `Error::Deadlock`
+/// aborts the transaction to break a deadlock: the transaction is rolled back
+/// and its locks are released at once, then its operations and `commit` fail
+/// with `Error::Deadlock`. It may be retried by a new transaction.
+///
+/// Savepoints mark the log of transaction, the changes after a savepoint can
+/// be rolled back without ending the transaction. The locks taken since are
+/// kept until the transaction ends.
+///
+/// A transaction should end with `commit` or `rollback`, which return the
+/// failure of ending it. A transaction which is dropped without them is rolled
+/// back on drop, and its locks are released even if the rollback fails, whose
+/// error is kept by the database (see `Database::take_rollback_failures`).
+/// The transaction is rolled back by recovery then.
+///
+/// ```ignore
+/// let txn = db.begin().await?;
+/// let id = txn.insert("users", user).await?;
+/// txn.update("users", id, updated).await?;
+/// txn.commit().await?;
+/// ```
+pub struct Transaction<'a> {
+    db: &'a Database,
+    txn: TxnId,
+
+    /// The savepoints in the order they're set, with the last LSN of
+    /// transaction at the time.
+    savepoints: Vec<(String, Lsn)>,
+
+    /// Whether the transaction is committed or rolled back.
+    ended: bool,
+
+    /// Whether the transaction is aborted by a deadlock, which rolls it back.
+    aborted: AtomicBool,
+}
+
+/// private methods.
+impl<'a> Transaction<'a> {
+    pub(super) fn new(db: &'a Database, txn: TxnId) -> Self {
+        Self {
+            db,
+            txn,
+            savepoints: vec![],
+            ended: false,
+            aborted: AtomicBool::new(false),
+        }
+    }
+
+    async fn last_lsn(&self) -> Lsn {
+        let manifest = self.db.page_directory.manifest().await;
+        manifest.last_lsn_of(self.txn).unwrap_or(0)
+    }
+
+    /// Fails if the transaction is aborted by a deadlock.
+    fn check_aborted(&self) -> Result<()> {
+        if self.aborted.load(Ordering::SeqCst) {
+            return Err(Error::Deadlock(self.txn));
+        }
+        Ok(())
+    }
+
+    /// Rolls back the changes of a failed operation, which are logged after
+    /// `lsn`. An operation which fails with `Error::Deadlock` aborts the
+    /// transaction instead.
+    async fn finish_op<T>(&self, lsn: Lsn, result: Result<T>) -> Result<T> {
+        match result {
+            Err(Error::Deadlock(_)) => self.abort().await?,
+            Err(_) => recover::rollback_to(&self.db.page_directory, self.txn, lsn).await?,
+            Ok(_) => {}
+        }
+        result
+    }
+
+    /// Rolls back the transaction chosen as the victim of a deadlock, then
+    /// releases its locks, which the other transactions of the cycle wait
+    /// for. The locks are released even if the rollback fails, then the
+    /// transaction is rolled back by recovery.
+    async fn abort(&self) -> Result<()> {
+        self.aborted.store(true, Ordering::SeqCst);
+        let result = recover::rollback(&self.db.page_directory, self.txn).await;
+        self.db.lock_manager.release_all(self.txn);
+        result
+    }
+
+    fn savepoint_pos(&self, name: &str) -> Result<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|(savepoint, _)| savepoint == name)
+            .ok_or(Error::NotFound(format!("savepoint {}", name)))
+    }
+}
+
+impl Transaction<'_> {
+    /// Returns the id of transaction.
+    pub fn id(&self) -> TxnId {
+        self.txn
+    }
+
+    pub async fn insert(&self, table_name: &str, record: Record) -> Result<RecordId> {
+        self.check_aborted()?;
+        let table = self.db.get_table(table_name)?;
+        let lsn = self.last_lsn().await;
+        let result = table.insert(self.txn, record).await;
+        self.finish_op(lsn, result).await
+    }
+
+    pub async fn get(&self, table_name: &str, id: RecordId) -> Result<Record> {
+        self.check_aborted()?;
+        let table = self.db.get_table(table_name)?;
+        let lsn = self.last_lsn().await;
+        let result = self.db.lock_record(self.txn, table, id, LockType::S).await;
+        self.finish_op(lsn, result).await?;
+        table.get(id).await
+    }
+
+    pub async fn update(&self, table_name: &str, id: RecordId, record: Record) -> Result<Record> {
+        self.check_aborted()?;
+        let table = self.db.get_table(table_name)?;
+        let lsn = self.last_lsn().await;
+        let result = table.update(self.txn, id, record).await;
+        self.finish_op(lsn, result).await
+    }
+
+    pub async fn remove(&self, table_name: &str, id: RecordId) -> Result<Record> {
+        self.check_aborted()?;
+        let table = self.db.get_table(table_name)?;
+        let lsn = self.last_lsn().await;
+        let result = table.remove(self.txn, id).await;
+        self.finish_op(lsn, result).await
+    }
+
+    /// Returns all records of table with their record ids, ordered by record
+    /// id. The table is locked in S.
+    pub async fn scan(&self, table_name: &str) -> Result<Vec<(RecordId, Record)>> {
+        self.check_aborted()?;
+        let table = self.db.get_table(table_name)?;
+        let name = ResourceName::Table(table.get_part_num());
+        let lsn = self.last_lsn().await;
+        let result = self.db.lock_manager.lock(self.txn, name, LockType::S).await;
+        self.finish_op(lsn, result).await?;
+        table.scan().await
+    }
+
+    /// Sets a savepoint, which replaces the savepoint of the same name if any.
+    pub async fn savepoint(&mut self, name: &str) {
+        let lsn = self.last_lsn().await;
+        self.savepoints.retain(|(savepoint, _)| savepoint != name);
+        self.savepoints.push((name.to_owned(), lsn));
+    }
+
+    /// Rolls back the changes since savepoint, the savepoint is kept and the
+    /// savepoints set after it are released.
+    pub async fn rollback_to_savepoint(&mut self, name: &str) -> Result<()> {
+        self.check_aborted()?;
+        let pos = self.savepoint_pos(name)?;
+        self.savepoints.truncate(pos + 1);
+        let lsn = self.savepoints[pos].1;
+        recover::rollback_to(&self.db.page_directory, self.txn, lsn).await
+    }
+
+    /// Releases the savepoint and those set after it, the changes are kept.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        let pos = self.savepoint_pos(name)?;
+        self.savepoints.truncate(pos);
+        Ok(())
+    }
+
+    /// Commits the transaction, then releases its locks. A transaction aborted
+    /// by a deadlock is rolled back already and fails to commit.
+    pub async fn commit(mut self) -> Result<()> {
+        self.ended = true;
+        self.check_aborted()?;
+        self.db.finish_txn(self.txn, Ok(())).await
+    }
+
+    /// Rolls back the transaction, then releases its locks. The locks are
+    /// released even if the rollback fails, then the transaction is rolled
+    /// back by recovery.
+    pub async fn rollback(mut self) -> Result<()> {
+        self.ended = true;
+        if self.aborted.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let result = recover::rollback(&self.db.page_directory, self.txn).await;
+        self.db.lock_manager.release_all(self.txn);
+        result
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.ended || self.aborted.load(Ordering::SeqCst) {
+            return;
+        }
+
+        // The drop may be in an executor already, which can't block on
+        // another one, so the rollback is run to completion on another
+        // thread.
+        let result = thread::scope(|s| {
+            s.spawn(|| block_on(recover::rollback(&self.db.page_directory, self.txn)))
+                .join()
+                .unwrap_or_else(|_| {
+                    Err(Error::Invalid(format!(
+                        "rollback of transaction {}, which panicked",
+                        self.txn
+                    )))
+                })
+        });
+        self.db.lock_manager.release_all(self.txn);
+        if let Err(e) = result {
+            self.db.rollback_failures.lock().push((self.txn, e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::poll;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        catalog::schema::{Column, DataType, Schema},
+        datatypes::array::scalar::ScalarImpl,
+        options::Options,
+    };
+
+    fn user(id: i32, name: &str) -> Record {
+        Record::from(vec![
+            ScalarImpl::Int32(id),
+            ScalarImpl::String(name.to_owned()),
+        ])
+    }
+
+    async fn open(dir: &TempDir) -> Database {
+        Database::open(Options {
+            path: dir.path().join("db").to_string_lossy().to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Opens a new database with tables users and admins.
+    async fn create(dir: &TempDir) -> Database {
+        let mut db = open(dir).await;
+        for table in ["users", "admins"] {
+            let schema = Schema::new()
+                .add(Column::new("id", DataType::Int32).not_null())
+                .add(Column::new("name", DataType::String(32)));
+            db.create_table(table.to_owned(), schema).await.unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_commit_rollback() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let db = create(&dir).await;
+
+            let txn = db.begin().await.unwrap();
+            let id = txn.insert("users", user(1, "rookie")).await.unwrap();
+            let admin = txn.insert("admins", user(1, "rookie")).await.unwrap();
+            txn.update("users", id, user(1, "updated")).await.unwrap();
+            assert_eq!(txn.get("users", id).await.unwrap(), user(1, "updated"));
+            txn.commit().await.unwrap();
+
+            let txn = db.begin().await.unwrap();
+            txn.remove("admins", admin).await.unwrap();
+            txn.insert("users", user(2, "db")).await.unwrap();
+            txn.rollback().await.unwrap();
+
+            // Dropped without commit.
+            let txn = db.begin().await.unwrap();
+            txn.update("users", id, user(1, "dropped")).await.unwrap();
+            drop(txn);
+            assert!(db.take_rollback_failures().is_empty());
+
+            let txn = db.begin().await.unwrap();
+            let users = txn.scan("users").await.unwrap();
+            assert_eq!(users, vec![(id, user(1, "updated"))]);
+            assert_eq!(txn.get("admins", admin).await.unwrap(), user(1, "rookie"));
+            txn.commit().await.unwrap();
+
+            // Committed changes survive a restart.
+            drop(db);
+            let db = open(&dir).await;
+            assert_eq!(db.get("users", id).await.unwrap(), user(1, "updated"));
+        })
+    }
+
+    #[test]
+    fn test_savepoint() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let db = create(&dir).await;
+
+            let mut txn = db.begin().await.unwrap();
+            let id = txn.insert("users", user(1, "rookie")).await.unwrap();
+            txn.savepoint("a").await;
+            txn.update("users", id, user(1, "a")).await.unwrap();
+            txn.savepoint("b").await;
+            let other = txn.insert("users", user(2, "b")).await.unwrap();
+
+            txn.rollback_to_savepoint("b").await.unwrap();
+            assert_eq!(txn.scan("users").await.unwrap().len(), 1);
+            txn.update("users", id, user(1, "b")).await.unwrap();
+            txn.rollback_to_savepoint("a").await.unwrap();
+            assert_eq!(txn.get("users", id).await.unwrap(), user(1, "rookie"));
+            // Savepoints after the one rolled back to are released.
+            assert!(txn.rollback_to_savepoint("b").await.is_err());
+            txn.release_savepoint("a").unwrap();
+            assert!(txn.release_savepoint("a").is_err());
+
+            // A failed operation is rolled back alone.
+            let invalid = Record::from(vec![ScalarImpl::Bool(true)]);
+            assert!(txn.update("users", id, invalid).await.is_err());
+            txn.update("users", id, user(1, "final")).await.unwrap();
+            txn.commit().await.unwrap();
+            assert!(db.get("users", other).await.is_err());
+
+            // The undone changes stay undone when the whole transaction rolls
+            // back.
+            let mut txn = db.begin().await.unwrap();
+            txn.savepoint("a").await;
+            txn.update("users", id, user(1, "a")).await.unwrap();
+            txn.rollback_to_savepoint("a").await.unwrap();
+            txn.update("users", id, user(1, "b")).await.unwrap();
+            txn.rollback().await.unwrap();
+            assert_eq!(db.get("users", id).await.unwrap(), user(1, "final"));
+        })
+    }
+
+    #[test]
+    fn test_isolation() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let db = create(&dir).await;
+            let id = db.insert("users", user(1, "rookie")).await.unwrap();
+
+            let writer = db.begin().await.unwrap();
+            writer
+                .update("users", id, user(1, "updated"))
+                .await
+                .unwrap();
+
+            // The reader waits for the lock of writer until it commits.
+            let reader = db.begin().await.unwrap();
+            let mut get = Box::pin(reader.get("users", id));
+            assert!(poll!(&mut get).is_pending());
+            writer.commit().await.unwrap();
+            assert_eq!(get.await.unwrap(), user(1, "updated"));
+            reader.commit().await.unwrap();
+        })
+    }
+
+    #[test]
+    fn test_page_locks() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let db = create(&dir).await;
+            let first = db.insert("users", user(1, "rookie")).await.unwrap();
+            let second = db.insert("users", user(2, "db")).await.unwrap();
+            assert_eq!(first.0, second.0);
+
+            // An insert skips the page locked by another writer.
+            let writer = db.begin().await.unwrap();
+            writer
+                .update("users", first, user(1, "rolled back"))
+                .await
+                .unwrap();
+            let other = db.begin().await.unwrap();
+            let third = other.insert("users", user(3, "other")).await.unwrap();
+            assert_ne!(third.0, first.0);
+
+            // A write to another record of the page waits for the writer, so
+            // the rollback which restores the page doesn't undo it.
+            let mut update = Box::pin(other.update("users", second, user(2, "updated")));
+            assert!(poll!(&mut update).is_pending());
+            writer.rollback().await.unwrap();
+            update.await.unwrap();
+            other.commit().await.unwrap();
+
+            assert_eq!(db.get("users", first).await.unwrap(), user(1, "rookie"));
+            assert_eq!(db.get("users", second).await.unwrap(), user(2, "updated"));
+            assert_eq!(db.get("users", third).await.unwrap(), user(3, "other"));
+        })
+    }
+
+    #[test]
+    fn test_deadlock() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let db = create(&dir).await;
+            let id = db.insert("users", user(1, "rookie")).await.unwrap();
+            let admin = db.insert("admins", user(1, "rookie")).await.unwrap();
+
+            let first = db.begin().await.unwrap();
+            let second = db.begin().await.unwrap();
+            first.update("users", id, user(1, "first")).await.unwrap();
+            second
+                .update("admins", admin, user(1, "second"))
+                .await
+                .unwrap();
+
+            // The younger transaction is aborted, which rolls it back and
+            // releases its locks for the older one.
+            let mut update = Box::pin(first.update("admins", admin, user(1, "first")));
+            assert!(poll!(&mut update).is_pending());
+            let result = second.update("users", id, user(1, "second")).await;
+            assert!(matches!(result, Err(Error::Deadlock(txn)) if txn == second.id()));
+            update.await.unwrap();
+            assert!(matches!(
+                second.get("users", id).await,
+                Err(Error::Deadlock(_))
+            ));
+            assert!(matches!(second.commit().await, Err(Error::Deadlock(_))));
+            first.commit().await.unwrap();
+
+            assert_eq!(db.get("users", id).await.unwrap(), user(1, "first"));
+            assert_eq!(db.get("admins", admin).await.unwrap(), user(1, "first"));
+            assert!(db.take_rollback_failures().is_empty());
+        })
+    }
+}
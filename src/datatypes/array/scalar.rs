@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 use super::Array;
 
 mod impls;
@@ -26,6 +28,7 @@ pub trait ScalarRef<'a>:
     fn to_owned_scalar(&self) -> Self::ScalarType;
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum ScalarImpl {
     Int16(i16),
     Int32(i32),
@@ -1,3 +1,3 @@
-mod array;
+pub mod array;
 mod chunk;
 pub mod record;
@@ -1,7 +1,292 @@
+use bytes::{Buf, BufMut};
+
+use super::array::scalar::ScalarImpl;
+use crate::error::{Error, Result};
+
+/// The type tags of encoded values, follow the declaration order of
+/// [`ScalarImpl`].
+const TAG_INT16: u8 = 0;
+const TAG_INT32: u8 = 1;
+const TAG_INT64: u8 = 2;
+const TAG_BOOL: u8 = 3;
+const TAG_STRING: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RecordId(pub u64, pub usize);
 
 pub fn new_record_id(page_num: u64, entry_num: usize) -> RecordId {
     RecordId(page_num, entry_num)
 }
 
-pub struct Record {}
+/// A Record represents a row of table, it carries one value per column, `None`
+/// represent a `NULL` value.
+///
+/// Records are encoded as follows:
+///  - 2 bytes: the number of values.
+///  - ceil(n / 8) bytes: null bitmap, the bit is set if the value is `NULL`.
+///  - for every non-null value, 1-byte type tag followed by the value, integers
+///    are encoded with fixed-width big-endian, bool takes 1 byte and string is
+///    prefixed with 2-byte length.
+///
+/// The type tag makes the record self-describing, so a data page can be decoded
+/// without consulting the catalog.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    values: Vec<Option<ScalarImpl>>,
+}
+
+impl Record {
+    pub fn new(values: Vec<Option<ScalarImpl>>) -> Self {
+        Self { values }
+    }
+
+    pub fn values(&self) -> &[Option<ScalarImpl>] {
+        &self.values
+    }
+
+    pub fn into_values(self) -> Vec<Option<ScalarImpl>> {
+        self.values
+    }
+
+    /// Returns the value of column `idx`, `None` if the value is `NULL`.
+    pub fn get(&self, idx: usize) -> Option<&ScalarImpl> {
+        self.values.get(idx).and_then(|v| v.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the size (in bytes) of the encoded record.
+    pub fn encoded_size(&self) -> usize {
+        let mut size = 2 + null_bitmap_size(self.values.len());
+        for value in self.values.iter().flatten() {
+            size += 1 + encoded_value_size(value);
+        }
+        size
+    }
+
+    /// Encodes the record into `buf`, nothing is written if the record can't
+    /// be encoded or `buf` is too small.
+    pub fn encode<B: BufMut>(&self, buf: &mut B) -> Result<()> {
+        if self.values.len() > u16::MAX as usize {
+            return Err(Error::TooLargeSize);
+        }
+        for value in self.values.iter().flatten() {
+            if let ScalarImpl::String(v) = value {
+                if v.len() > u16::MAX as usize {
+                    return Err(Error::TooLargeSize);
+                }
+            }
+        }
+        if self.encoded_size() > buf.remaining_mut() {
+            return Err(Error::TooLargeSize);
+        }
+        buf.put_u16(self.values.len() as u16);
+
+        let mut bitmap = vec![0u8; null_bitmap_size(self.values.len())];
+        for (idx, value) in self.values.iter().enumerate() {
+            if value.is_none() {
+                bitmap[idx / 8] |= 1 << (idx % 8);
+            }
+        }
+        buf.put_slice(&bitmap);
+
+        for value in self.values.iter().flatten() {
+            encode_value(value, buf);
+        }
+        Ok(())
+    }
+
+    /// Encodes the record into a new buffer.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(self.encoded_size());
+        self.encode(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decodes a record from `buf`, advances `buf` past the record.
+    pub fn decode<B: Buf>(buf: &mut B) -> Result<Record> {
+        ensure_remaining(buf, 2)?;
+        let len = buf.get_u16() as usize;
+
+        let bitmap_size = null_bitmap_size(len);
+        ensure_remaining(buf, bitmap_size)?;
+        let mut bitmap = vec![0u8; bitmap_size];
+        buf.copy_to_slice(&mut bitmap);
+
+        let mut values = Vec::with_capacity(len);
+        for idx in 0..len {
+            if bitmap[idx / 8] & (1 << (idx % 8)) != 0 {
+                values.push(None);
+                continue;
+            }
+
+            values.push(Some(decode_value(buf)?));
+        }
+
+        Ok(Record { values })
+    }
+}
+
+impl From<Vec<ScalarImpl>> for Record {
+    fn from(values: Vec<ScalarImpl>) -> Self {
+        Self {
+            values: values.into_iter().map(Some).collect(),
+        }
+    }
+}
+
+/// Encodes a value with its type tag, a string value must be shorter than
+/// 64KB.
+pub fn encode_value<B: BufMut>(value: &ScalarImpl, buf: &mut B) {
+    match value {
+        ScalarImpl::Int16(v) => {
+            buf.put_u8(TAG_INT16);
+            buf.put_i16(*v);
+        }
+        ScalarImpl::Int32(v) => {
+            buf.put_u8(TAG_INT32);
+            buf.put_i32(*v);
+        }
+        ScalarImpl::Int64(v) => {
+            buf.put_u8(TAG_INT64);
+            buf.put_i64(*v);
+        }
+        ScalarImpl::Bool(v) => {
+            buf.put_u8(TAG_BOOL);
+            buf.put_u8(*v as u8);
+        }
+        ScalarImpl::String(v) => {
+            buf.put_u8(TAG_STRING);
+            buf.put_u16(v.len() as u16);
+            buf.put_slice(v.as_bytes());
+        }
+    }
+}
+
+/// Decodes a value with its type tag, advances `buf` past the value.
+pub fn decode_value<B: Buf>(buf: &mut B) -> Result<ScalarImpl> {
+    ensure_remaining(buf, 1)?;
+    let value =
+        match buf.get_u8() {
+            TAG_INT16 => {
+                ensure_remaining(buf, 2)?;
+                ScalarImpl::Int16(buf.get_i16())
+            }
+            TAG_INT32 => {
+                ensure_remaining(buf, 4)?;
+                ScalarImpl::Int32(buf.get_i32())
+            }
+            TAG_INT64 => {
+                ensure_remaining(buf, 8)?;
+                ScalarImpl::Int64(buf.get_i64())
+            }
+            TAG_BOOL => {
+                ensure_remaining(buf, 1)?;
+                ScalarImpl::Bool(buf.get_u8() != 0)
+            }
+            TAG_STRING => {
+                ensure_remaining(buf, 2)?;
+                let str_len = buf.get_u16() as usize;
+                ensure_remaining(buf, str_len)?;
+                let mut bytes = vec![0u8; str_len];
+                buf.copy_to_slice(&mut bytes);
+                ScalarImpl::String(String::from_utf8(bytes).map_err(|_| {
+                    Error::Corrupted("record: string value is not utf-8".to_owned())
+                })?)
+            }
+            tag => {
+                return Err(Error::Corrupted(format!(
+                    "record: unknown type tag {}",
+                    tag
+                )))
+            }
+        };
+    Ok(value)
+}
+
+/// Returns the size (in bytes) of null bitmap for `n` values.
+#[inline]
+pub fn null_bitmap_size(n: usize) -> usize {
+    (n + 7) / 8
+}
+
+/// Returns the size (in bytes) of an encoded value, excluding the type tag.
+#[inline]
+pub fn encoded_value_size(value: &ScalarImpl) -> usize {
+    match value {
+        ScalarImpl::Int16(_) => 2,
+        ScalarImpl::Int32(_) => 4,
+        ScalarImpl::Int64(_) => 8,
+        ScalarImpl::Bool(_) => 1,
+        ScalarImpl::String(v) => 2 + v.len(),
+    }
+}
+
+#[inline]
+fn ensure_remaining<B: Buf>(buf: &B, n: usize) -> Result<()> {
+    if buf.remaining() < n {
+        return Err(Error::Corrupted(
+            "record: unexpected end of buffer".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = Record::new(vec![
+            Some(ScalarImpl::Int16(-3)),
+            Some(ScalarImpl::Int32(42)),
+            None,
+            Some(ScalarImpl::Int64(i64::MAX)),
+            Some(ScalarImpl::Bool(true)),
+            Some(ScalarImpl::String("rookiedb".to_owned())),
+            None,
+            Some(ScalarImpl::String("".to_owned())),
+            None,
+        ]);
+
+        let bytes = record.to_bytes().unwrap();
+        assert_eq!(bytes.len(), record.encoded_size());
+
+        let decoded = Record::decode(&mut bytes.as_slice()).unwrap();
+        assert_eq!(decoded, record);
+        assert_eq!(decoded.get(1), Some(&ScalarImpl::Int32(42)));
+        assert_eq!(decoded.get(2), None);
+    }
+
+    #[test]
+    fn test_record_decode_from_slot() {
+        // A record is decoded from a fixed-size slot, trailing bytes are ignored.
+        let record = Record::from(vec![ScalarImpl::Int32(7), ScalarImpl::Bool(false)]);
+        let mut slot = [0u8; 64];
+        record.encode(&mut &mut slot[..]).unwrap();
+
+        let decoded = Record::decode(&mut &slot[..]).unwrap();
+        assert_eq!(decoded, record);
+
+        // Nothing is written into a slot which is too small.
+        let mut slot = [0u8; 8];
+        assert!(record.encode(&mut &mut slot[..]).is_err());
+        assert_eq!(slot, [0u8; 8]);
+    }
+
+    #[test]
+    fn test_record_decode_corrupted() {
+        let record = Record::from(vec![ScalarImpl::String("hello".to_owned())]);
+        let bytes = record.to_bytes().unwrap();
+
+        assert!(Record::decode(&mut &bytes[..bytes.len() - 1]).is_err());
+        assert!(Record::decode(&mut &[0u8][..]).is_err());
+    }
+}
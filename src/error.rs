@@ -12,6 +12,14 @@ pub enum Error {
     TooLargeSize,
     #[error("{0} not found.")]
     NotFound(String),
+    #[error("constraint violation: {0}.")]
+    ConstraintViolation(String),
+    /// The transaction is aborted to break a deadlock, it may be retried. Its
+    /// owner must roll it back, then release its locks, which the other
+    /// transactions of the deadlock wait for. `Transaction` and the
+    /// operations of `Database` do so before returning the error.
+    #[error("transaction {0} is aborted by deadlock.")]
+    Deadlock(u64),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -6,6 +6,7 @@
 #![feature(box_into_inner)]
 mod catalog;
 mod common;
+mod concurrency;
 mod database;
 pub mod datatypes;
 pub mod error;
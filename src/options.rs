@@ -1,4 +1,8 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, time::Duration};
+
+use crate::{
+    concurrency::lock_manager::DEFAULT_ESCALATION_THRESHOLD, table::cache::EvictionPolicy,
+};
 
 pub struct Options {
     pub path: String,
@@ -10,6 +14,24 @@ pub struct Options {
     /// Represent the data of statistic whether persistent to disk.
     pub stats_persistent: bool,
     pub num_records_per_page: usize,
+    /// A checkpoint is taken once this many log pages are written since the
+    /// last one.
+    pub checkpoint_interval: usize,
+    /// The number of page frames of the buffer pool shared by all partitions.
+    pub cache_frames: usize,
+    /// The policy choosing which page to evict when the buffer pool is full,
+    /// `EvictionPolicy::LruK` and `EvictionPolicy::TwoQueue` keep frequently
+    /// used pages cached during large scans.
+    pub eviction_policy: EvictionPolicy,
+    /// The number of pages read ahead into the private ring of a full table
+    /// scan, which bypasses the buffer pool.
+    pub scan_ring_pages: usize,
+    /// The page locks of a transaction in a table are escalated to a single
+    /// table lock once it holds more than this many.
+    pub lock_escalation_threshold: usize,
+    /// The interval at which the deadlock detector looks for transactions
+    /// waiting for each other, and aborts one of them.
+    pub deadlock_detection_interval: Duration,
 }
 
 impl Default for Options {
@@ -17,6 +39,12 @@ impl Default for Options {
         Self {
             path: "".to_owned(),
             num_records_per_page: 8,
+            checkpoint_interval: 64,
+            cache_frames: 1024,
+            eviction_policy: EvictionPolicy::Lru,
+            scan_ring_pages: 32,
+            lock_escalation_threshold: DEFAULT_ESCALATION_THRESHOLD,
+            deadlock_detection_interval: Duration::from_millis(100),
             stats_null_method: "nulls_equal".to_owned(),
             stats_persistent: true,
         }
@@ -0,0 +1,70 @@
+use super::Evictor;
+
+/// Approximates LRU with a reference bit per frame. The clock hand sweeps the
+/// frames, clearing the bits which are set, and evicts the first frame whose
+/// bit is already cleared.
+pub struct ClockEvictor {
+    /// The reference bit of every frame, `None` if the frame is not tracked.
+    referenced: Vec<Option<bool>>,
+    hand: usize,
+}
+
+impl ClockEvictor {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            referenced: vec![None; capacity],
+            hand: 0,
+        }
+    }
+}
+
+impl Evictor for ClockEvictor {
+    fn record_access(&mut self, frame: usize, _page: u64) {
+        self.referenced[frame] = Some(true);
+    }
+
+    fn remove(&mut self, frame: usize) {
+        self.referenced[frame] = None;
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        // All bits are cleared within the first sweep.
+        for _ in 0..2 * self.referenced.len() {
+            let frame = self.hand;
+            self.hand = (self.hand + 1) % self.referenced.len();
+            match self.referenced[frame] {
+                Some(true) if evictable(frame) => self.referenced[frame] = Some(false),
+                Some(false) if evictable(frame) => return Some(frame),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_evictor() {
+        let mut evictor = ClockEvictor::new(4);
+        for frame in 0..4 {
+            evictor.record_access(frame, frame as u64);
+        }
+        // Every bit is cleared, then the sweep starts over from frame 0.
+        assert_eq!(evictor.victim(&|_| true), Some(0));
+
+        // Frame 1 gets a second chance.
+        evictor.record_access(1, 1);
+        assert_eq!(evictor.victim(&|_| true), Some(2));
+        assert_eq!(evictor.victim(&|frame| frame != 3), Some(0));
+
+        evictor.remove(0);
+        evictor.remove(3);
+        assert_eq!(evictor.victim(&|_| true), Some(1));
+        evictor.remove(1);
+        evictor.remove(2);
+        assert_eq!(evictor.victim(&|_| true), None);
+    }
+}
@@ -1 +1,62 @@
-pub struct LruEvictor {}
+use std::collections::{BTreeMap, HashMap};
+
+use super::Evictor;
+
+/// Evicts the least recently used frame. Every access stamps the frame with
+/// a tick, and frames are visited from the oldest tick.
+#[derive(Default)]
+pub struct LruEvictor {
+    tick: u64,
+    /// The frames ordered by their last access.
+    order: BTreeMap<u64, usize>,
+    /// The tick of last access of every frame.
+    ticks: HashMap<usize, u64>,
+}
+
+impl LruEvictor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Evictor for LruEvictor {
+    fn record_access(&mut self, frame: usize, _page: u64) {
+        self.remove(frame);
+        self.tick += 1;
+        self.order.insert(self.tick, frame);
+        self.ticks.insert(frame, self.tick);
+    }
+
+    fn remove(&mut self, frame: usize) {
+        if let Some(tick) = self.ticks.remove(&frame) {
+            self.order.remove(&tick);
+        }
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        self.order.values().copied().find(|&frame| evictable(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_evictor() {
+        let mut evictor = LruEvictor::new();
+        for frame in 0..4 {
+            evictor.record_access(frame, frame as u64);
+        }
+        assert_eq!(evictor.victim(&|_| true), Some(0));
+
+        evictor.record_access(0, 0);
+        assert_eq!(evictor.victim(&|_| true), Some(1));
+        assert_eq!(evictor.victim(&|frame| frame != 1), Some(2));
+
+        evictor.remove(2);
+        evictor.remove(1);
+        assert_eq!(evictor.victim(&|_| true), Some(3));
+        assert_eq!(evictor.victim(&|frame| frame == 2), None);
+    }
+}
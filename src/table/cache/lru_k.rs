@@ -0,0 +1,132 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::Evictor;
+
+/// Evicts the frame whose K-th most recent access is the oldest (the largest
+/// backward K-distance). Frames accessed less than K times have an infinite
+/// distance and are evicted first, the least recently used of them first, so
+/// that pages read once by a scan don't push out pages used repeatedly.
+///
+/// The history is kept per page, and outlives the eviction of page for as many
+/// evicted pages as frames, so that a page read in again soon counts its
+/// accesses before the eviction.
+pub struct LruKEvictor {
+    k: usize,
+    tick: u64,
+    /// The max number of evicted pages whose history is kept.
+    retained: usize,
+    /// The page in every tracked frame.
+    frames: HashMap<usize, u64>,
+    /// The ticks of the last K accesses of every page, the oldest first.
+    history: HashMap<u64, VecDeque<u64>>,
+    /// The evicted pages whose history is kept, the first evicted first.
+    evicted: VecDeque<u64>,
+}
+
+impl LruKEvictor {
+    pub fn new(k: usize, capacity: usize) -> Self {
+        assert!(k > 0, "LRU-K needs k greater than 0.");
+        Self {
+            k,
+            tick: 0,
+            retained: capacity,
+            frames: HashMap::new(),
+            history: HashMap::new(),
+            evicted: VecDeque::new(),
+        }
+    }
+}
+
+impl Evictor for LruKEvictor {
+    fn record_access(&mut self, frame: usize, page: u64) {
+        if self.frames.insert(frame, page) != Some(page) {
+            self.evicted.retain(|&evicted| evicted != page);
+        }
+
+        self.tick += 1;
+        let history = self.history.entry(page).or_default();
+        if history.len() == self.k {
+            history.pop_front();
+        }
+        history.push_back(self.tick);
+    }
+
+    fn remove(&mut self, frame: usize) {
+        if let Some(page) = self.frames.remove(&frame) {
+            self.evicted.push_back(page);
+            if self.evicted.len() > self.retained {
+                let page = self.evicted.pop_front().unwrap();
+                self.history.remove(&page);
+            }
+        }
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        // Frames with less than K accesses come first, then by the K-th most
+        // recent access, or the last access for those with less than K.
+        self.frames
+            .iter()
+            .filter(|(&frame, _)| evictable(frame))
+            .min_by_key(|(_, page)| {
+                let history = &self.history[page];
+                let tick = if history.len() < self.k {
+                    *history.back().unwrap()
+                } else {
+                    *history.front().unwrap()
+                };
+                (history.len() >= self.k, tick)
+            })
+            .map(|(&frame, _)| frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_k_evictor() {
+        let mut evictor = LruKEvictor::new(2, 4);
+        // Frame 0 and 1 are hot.
+        for frame in [0, 1, 0, 1] {
+            evictor.record_access(frame, frame as u64);
+        }
+        // Frame 2 and 3 are read once by a scan.
+        evictor.record_access(2, 2);
+        evictor.record_access(3, 3);
+        assert_eq!(evictor.victim(&|_| true), Some(2));
+        evictor.remove(2);
+        assert_eq!(evictor.victim(&|_| true), Some(3));
+        evictor.remove(3);
+
+        // The hot frame with the oldest second last access.
+        assert_eq!(evictor.victim(&|_| true), Some(0));
+        evictor.record_access(0, 0);
+        evictor.record_access(0, 0);
+        assert_eq!(evictor.victim(&|_| true), Some(1));
+        assert_eq!(evictor.victim(&|frame| frame != 1), Some(0));
+        assert_eq!(evictor.victim(&|_| false), None);
+    }
+
+    #[test]
+    fn test_lru_k_evicted_history() {
+        let mut evictor = LruKEvictor::new(2, 2);
+        // Page 10 is evicted after its first access, and read in again.
+        evictor.record_access(0, 10);
+        evictor.remove(0);
+        evictor.record_access(1, 11);
+        evictor.record_access(0, 10);
+        // Page 10 has two accesses, page 11 only one.
+        assert_eq!(evictor.victim(&|_| true), Some(1));
+
+        // The history of page 10 is dropped once two more pages are evicted
+        // after it, but page 11 keeps its history.
+        evictor.remove(0);
+        evictor.remove(1);
+        evictor.record_access(0, 12);
+        evictor.remove(0);
+        evictor.record_access(0, 10);
+        evictor.record_access(1, 11);
+        assert_eq!(evictor.victim(&|_| true), Some(0));
+    }
+}
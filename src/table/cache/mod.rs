@@ -1,55 +1,626 @@
-use std::collections::LinkedList;
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
 
-use self::lru::LruEvictor;
-use super::page::{marker, PageRef};
+use futures::lock::{Mutex, MutexGuard};
 
+use self::{clock::ClockEvictor, lru::LruEvictor, lru_k::LruKEvictor, two_queue::TwoQueueEvictor};
+use super::page::{marker, page_directory::DEFAULT_PAGE_SIZE, PageRef};
+use crate::error::{Error, Result};
+
+pub mod clock;
 pub mod lru;
+pub mod lru_k;
+pub mod two_queue;
+
+/// The policy choosing which frame to evict when the cache is full. A frame is
+/// tracked from the first access of its page until the page is evicted, a
+/// policy may remember the pages evicted recently.
+pub trait Evictor: Send {
+    /// Records an access of the page (virtual page number) in frame.
+    fn record_access(&mut self, frame: usize, page: u64);
 
-pub enum Evictor {
-    Lru(LruEvictor),
+    /// Forgets the frame, e.g. its page is evicted or discarded.
+    fn remove(&mut self, frame: usize);
+
+    /// Chooses a tracked frame which is `evictable` to evict.
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize>;
 }
 
-pub struct CacheEntry {
-    num: u64,
-    page: PageRef<marker::HeaderOrData>,
+/// The eviction policies of the page cache (see `Options::eviction_policy`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Least recently used.
+    #[default]
+    Lru,
+    /// Second chance with a reference bit per frame, cheaper than LRU.
+    Clock,
+    /// The largest backward distance of the K-th most recent access, which
+    /// resists scans.
+    LruK(usize),
+    /// A FIFO queue of pages accessed once and an LRU queue of pages accessed
+    /// again, which resists scans.
+    TwoQueue,
 }
 
-impl CacheEntry {
-    pub fn new(num: u64, page: PageRef<marker::HeaderOrData>) -> Self {
-        Self { num, page }
+impl EvictionPolicy {
+    /// Creates the evictor of cache with `capacity` frames.
+    pub fn evictor(self, capacity: usize) -> Box<dyn Evictor> {
+        match self {
+            EvictionPolicy::Lru => Box::new(LruEvictor::new()),
+            EvictionPolicy::Clock => Box::new(ClockEvictor::new(capacity)),
+            EvictionPolicy::LruK(k) => Box::new(LruKEvictor::new(k, capacity)),
+            EvictionPolicy::TwoQueue => Box::new(TwoQueueEvictor::new(capacity)),
+        }
     }
+}
 
-    pub fn num(&self) -> u64 {
-        self.num
-    }
+/// The counters of page lookups of the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of lookups which found the page in cache.
+    pub hits: u64,
+    /// The number of lookups which read the page in from disk.
+    pub misses: u64,
+}
 
-    pub fn page(&self) -> PageRef<marker::HeaderOrData> {
-        self.page
-    }
+/// The page number of a frame which holds no page.
+const NO_PAGE: u64 = u64::MAX;
+
+/// A buffer frame, which holds a page read in from a partition.
+struct Frame {
+    /// The index of frame in cache.
+    index: usize,
+
+    page: PageRef<marker::Data>,
+
+    /// The virtual page number of the content of frame, changed only while
+    /// the frame is latched, `NO_PAGE` if the content is not valid.
+    page_num: AtomicU64,
+
+    /// The number of guards of the page, a pinned page is never evicted.
+    pin_count: AtomicUsize,
+
+    /// Whether the page is changed since it's read in or written back.
+    dirty: AtomicBool,
+
+    /// The latch of the content of page, held by the guard.
+    latch: Mutex<()>,
+}
+
+// Safety: the page of frame is only accessed through `PageGuard`, which holds
+// the latch of frame.
+unsafe impl Send for Frame {}
+unsafe impl Sync for Frame {}
+
+/// The mapping between pages and frames, guarded by the cache lock.
+pub struct CacheState {
+    /// The frame of every cached page, keyed by virtual page number. A page
+    /// being read in is mapped to its frame, and so is a dirty page being
+    /// written back before its frame is reused.
+    page_table: HashMap<u64, usize>,
+
+    /// The virtual page number of the page in every frame, or being read into
+    /// it.
+    pages: Vec<Option<u64>>,
+
+    /// The frames which hold no page.
+    free_frames: Vec<usize>,
+
+    evictor: Box<dyn Evictor>,
 }
 
+/// A buffer pool of a fixed number of frames shared by all partitions of the
+/// page directory. The pages are read in and written back by the page
+/// directory (see `PageDirectory::fetch_page`), the cache only keeps track of
+/// which page is in which frame:
+///  - a page is pinned by every `PageGuard` of it, and is evicted only when
+///    it's unpinned.
+///  - a page is dirty once changed, a dirty page must be written back before
+///    its frame is reused, after the log is flushed to the page LSN.
+///  - a frame is reserved for a page to read in under the cache lock, but the
+///    I/O is done with only the frame latched, so that other pages are looked
+///    up meanwhile. Those who wait for the latch find the guard stale if the
+///    frame ends up holding another page (see `PageGuard::is_stale`).
 pub struct PageCache {
-    list: LinkedList<CacheEntry>,
-    capacity: usize,
+    frames: Vec<Frame>,
+
+    state: Mutex<CacheState>,
+
+    hits: AtomicU64,
+    misses: AtomicU64,
 }
 
 impl PageCache {
-    pub fn with_capacity(capacity: usize) -> Self {
+    /// Creates a cache of `capacity` frames, which evicts pages by `policy`.
+    pub fn new(capacity: usize, policy: EvictionPolicy) -> Self {
+        assert!(capacity > 0, "page cache needs at least one frame.");
+        let frames = (0..capacity)
+            .map(|index| {
+                let mut page = PageRef::new_data_page();
+                page.as_data_page_mut().fill(&[0u8; DEFAULT_PAGE_SIZE]);
+                Frame {
+                    index,
+                    page,
+                    page_num: AtomicU64::new(NO_PAGE),
+                    pin_count: AtomicUsize::new(0),
+                    dirty: AtomicBool::new(false),
+                    latch: Mutex::new(()),
+                }
+            })
+            .collect();
+
         Self {
-            list: LinkedList::new(),
-            capacity,
+            frames,
+            state: Mutex::new(CacheState {
+                page_table: HashMap::new(),
+                pages: vec![None; capacity],
+                free_frames: (0..capacity).rev().collect(),
+                evictor: policy.evictor(capacity),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of frames.
+    pub fn capacity(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Locks the mapping between pages and frames.
+    pub async fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().await
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
         }
     }
 
-    pub fn lookup(&self, _page_num: u64) -> Option<CacheEntry> {
-        todo!()
+    /// Pins the page if it's cached, returns its frame.
+    pub fn pin(&self, state: &mut CacheState, page: u64) -> Option<usize> {
+        let frame = *state.page_table.get(&page)?;
+        self.frames[frame].pin_count.fetch_add(1, Ordering::AcqRel);
+        Some(frame)
     }
 
-    pub fn add(&mut self, entry: CacheEntry) {
-        let len = self.list.len();
-        if len + 1 >= self.capacity {
-            self.list.pop_front();
+    /// Records a lookup of the page in frame, `hit` tells whether the page was
+    /// cached.
+    pub fn record_access(&self, state: &mut CacheState, frame: usize, hit: bool) {
+        if let Some(page) = state.pages[frame] {
+            state.evictor.record_access(frame, page);
         }
-        self.list.push_back(entry);
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn is_pinned(&self, frame: usize) -> bool {
+        self.frames[frame].pin_count.load(Ordering::Acquire) > 0
+    }
+
+    /// Returns a frame to read a page in, which is either free or holds an
+    /// unpinned page (see `reserve`).
+    pub fn victim(&self, state: &mut CacheState) -> Result<usize> {
+        // A free frame is still pinned by those who found the page failed to
+        // read in.
+        if let Some(pos) = state
+            .free_frames
+            .iter()
+            .rposition(|&frame| !self.is_pinned(frame))
+        {
+            return Ok(state.free_frames.remove(pos));
+        }
+        state
+            .evictor
+            .victim(&|frame| !self.is_pinned(frame))
+            .ok_or_else(|| {
+                Error::Invalid(format!(
+                    "page cache: all {} frames are pinned",
+                    self.capacity()
+                ))
+            })
+    }
+
+    /// Returns the virtual page number of the page in frame if it's dirty.
+    pub fn dirty_page(&self, state: &CacheState, frame: usize) -> Option<u64> {
+        state.pages[frame].filter(|_| self.frames[frame].dirty.load(Ordering::Acquire))
+    }
+
+    /// Returns the virtual page numbers of all dirty pages.
+    pub fn dirty_pages(&self, state: &CacheState) -> Vec<u64> {
+        (0..self.capacity())
+            .filter_map(|frame| self.dirty_page(state, frame))
+            .collect()
+    }
+
+    /// Unmaps the page from frame if it's mapped to the frame.
+    fn unmap(state: &mut CacheState, page: u64, frame: usize) {
+        if state.page_table.get(&page) == Some(&frame) {
+            state.page_table.remove(&page);
+        }
+    }
+
+    /// Reserves the frame got from `victim` for the page to read in, returns
+    /// the latched frame and the page evicted from it if the evicted page is
+    /// dirty. The dirty page stays mapped until it's written back (see
+    /// `finish_write_back`), so that it's not read from disk meanwhile.
+    pub fn reserve(
+        &self,
+        state: &mut CacheState,
+        frame: usize,
+        page: u64,
+    ) -> (PageGuard<'_>, Option<u64>) {
+        let evicted = state.pages[frame].replace(page);
+        state.page_table.insert(page, frame);
+        state.evictor.remove(frame);
+
+        let dirty = evicted.filter(|_| self.frames[frame].dirty.load(Ordering::Acquire));
+        if let (Some(evicted), None) = (evicted, dirty) {
+            Self::unmap(state, evicted, frame);
+        }
+
+        // An unpinned frame is not latched.
+        self.frames[frame].pin_count.store(1, Ordering::Release);
+        let guard = self
+            .try_guard(frame, page)
+            .expect("unpinned frame is latched");
+        (guard, dirty)
+    }
+
+    /// Unmaps the page evicted from the reserved frame once it's written back.
+    pub fn finish_write_back(&self, state: &mut CacheState, frame: usize, evicted: u64) {
+        self.frames[frame].dirty.store(false, Ordering::Release);
+        Self::unmap(state, evicted, frame);
+    }
+
+    /// Gives the reserved frame back to the evicted page which failed to write
+    /// back, the page to read in is unmapped.
+    pub fn cancel_write_back(&self, state: &mut CacheState, guard: PageGuard<'_>, evicted: u64) {
+        let frame = guard.frame.index;
+        Self::unmap(state, guard.virtual_page_num, frame);
+        state.pages[frame] = Some(evicted);
+        state.evictor.record_access(frame, evicted);
+    }
+
+    /// Marks the page read into the reserved frame, which is cached from now
+    /// on.
+    pub fn finish_read(&self, state: &mut CacheState, guard: &PageGuard<'_>) {
+        let frame = guard.frame.index;
+        guard
+            .frame
+            .page_num
+            .store(guard.virtual_page_num, Ordering::Release);
+        self.record_access(state, frame, false);
+    }
+
+    /// Frees the reserved frame of the page which failed to read in.
+    pub fn cancel_read(&self, state: &mut CacheState, guard: PageGuard<'_>) {
+        let frame = guard.frame.index;
+        guard.frame.page_num.store(NO_PAGE, Ordering::Release);
+        Self::unmap(state, guard.virtual_page_num, frame);
+        state.pages[frame] = None;
+        state.free_frames.push(frame);
+    }
+
+    /// Drops the page from cache without writing it back, e.g. the page is
+    /// released. The page must not be pinned.
+    pub fn discard(&self, state: &mut CacheState, page: u64) -> Result<()> {
+        if let Some(&frame) = state.page_table.get(&page) {
+            if self.is_pinned(frame) {
+                return Err(Error::Invalid(format!(
+                    "page cache: discard pinned page {}",
+                    page
+                )));
+            }
+            state.page_table.remove(&page);
+            state.pages[frame] = None;
+            state.evictor.remove(frame);
+            state.free_frames.push(frame);
+            self.frames[frame]
+                .page_num
+                .store(NO_PAGE, Ordering::Release);
+            self.frames[frame].dirty.store(false, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    /// Returns the cached pages which satisfy `f`.
+    pub fn cached_pages(&self, state: &CacheState, f: impl Fn(u64) -> bool) -> Vec<u64> {
+        state
+            .page_table
+            .keys()
+            .copied()
+            .filter(|&page| f(page))
+            .collect()
+    }
+
+    /// Latches the page in frame pinned by `pin`, the guard unpins the page
+    /// when dropped. The guard is stale if the frame no longer holds the page
+    /// once latched.
+    pub async fn guard(&self, frame: usize, page: u64) -> PageGuard<'_> {
+        let frame = &self.frames[frame];
+        let latch = frame.latch.lock().await;
+        PageGuard {
+            page: frame.page,
+            virtual_page_num: page,
+            frame,
+            latch: Some(latch),
+        }
+    }
+
+    /// Latches the page in frame like `guard` if the latch is not held,
+    /// otherwise returns `None` and the page stays pinned.
+    pub fn try_guard(&self, frame: usize, page: u64) -> Option<PageGuard<'_>> {
+        let frame = &self.frames[frame];
+        let latch = frame.latch.try_lock()?;
+        Some(PageGuard {
+            page: frame.page,
+            virtual_page_num: page,
+            frame,
+            latch: Some(latch),
+        })
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        for frame in &self.frames {
+            unsafe { frame.page.forget_type().dealloc() };
+        }
+    }
+}
+
+/// A pinned and latched page in cache. Changes to the page must be marked by
+/// `PageDirectory::mark_dirty` (or logged by `PageDirectory::log_page_update`),
+/// otherwise they may be lost when the page is evicted.
+pub struct PageGuard<'a> {
+    page: PageRef<marker::Data>,
+    virtual_page_num: u64,
+    frame: &'a Frame,
+    latch: Option<MutexGuard<'a, ()>>,
+}
+
+impl<'a> PageGuard<'a> {
+    pub fn virtual_page_num(&self) -> u64 {
+        self.virtual_page_num
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.frame.dirty.load(Ordering::Acquire)
+    }
+
+    /// Whether the frame doesn't hold the page, e.g. the page failed to read
+    /// in or the frame is reused, while waiting for the latch. A stale guard
+    /// must be dropped and the page looked up again.
+    pub fn is_stale(&self) -> bool {
+        self.frame.page_num.load(Ordering::Acquire) != self.virtual_page_num
+    }
+
+    pub(crate) fn set_dirty(&self) {
+        self.frame.dirty.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn set_clean(&self) {
+        self.frame.dirty.store(false, Ordering::Release);
+    }
+}
+
+impl<'a> Deref for PageGuard<'a> {
+    type Target = PageRef<marker::Data>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.page
+    }
+}
+
+impl<'a> DerefMut for PageGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.page
+    }
+}
+
+impl<'a> Drop for PageGuard<'a> {
+    fn drop(&mut self) {
+        // The latch is released before the page can be evicted.
+        self.latch.take();
+        self.frame.pin_count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        options::Options,
+        table::page::page_directory::{virtual_page_num, PageDirectory},
+    };
+
+    async fn open(dir: &TempDir, cache_frames: usize, policy: EvictionPolicy) -> PageDirectory {
+        let options = Options {
+            path: dir.path().join("db").to_string_lossy().to_string(),
+            cache_frames,
+            eviction_policy: policy,
+            ..Default::default()
+        };
+        PageDirectory::open(&options).await.unwrap()
+    }
+
+    #[test]
+    fn test_page_cache() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open(&dir, 2, EvictionPolicy::Lru).await;
+            let part_num = page_directory.alloc_part().await.unwrap();
+            let pages = (0..3)
+                .map(|page_num| virtual_page_num(part_num, page_num))
+                .collect::<Vec<_>>();
+            for &page in &pages {
+                page_directory.alloc_page(page).await.unwrap();
+            }
+
+            let mut page = page_directory.fetch_page(pages[0]).await.unwrap();
+            page.as_data_page_mut().vals_mut()[100] = 7;
+            page_directory.mark_dirty(&page).unwrap();
+            drop(page);
+            assert!(page_directory
+                .fetch_page(pages[0])
+                .await
+                .unwrap()
+                .is_dirty());
+            let stats = page_directory.cache_stats();
+            assert_eq!((stats.hits, stats.misses), (1, 1));
+
+            // The dirty page is written back when evicted.
+            drop(page_directory.fetch_page(pages[1]).await.unwrap());
+            assert_eq!(page_directory.read_page(pages[0]).await.unwrap()[100], 0);
+            drop(page_directory.fetch_page(pages[2]).await.unwrap());
+            assert_eq!(page_directory.read_page(pages[0]).await.unwrap()[100], 7);
+            let page = page_directory.fetch_page(pages[0]).await.unwrap();
+            assert_eq!(page.as_data_page().vals()[100], 7);
+            assert!(!page.is_dirty());
+
+            // Pinned pages are never evicted.
+            let other = page_directory.fetch_page(pages[1]).await.unwrap();
+            assert!(page_directory.fetch_page(pages[2]).await.is_err());
+            assert!(page_directory.release_page(pages[1]).await.is_err());
+            drop(other);
+            drop(page);
+            drop(page_directory.fetch_page(pages[2]).await.unwrap());
+            let stats = page_directory.cache_stats();
+            assert_eq!((stats.hits, stats.misses), (1, 6));
+
+            // Released pages are dropped from cache.
+            page_directory.release_page(pages[2]).await.unwrap();
+            assert!(page_directory.fetch_page(pages[2]).await.is_err());
+        })
+    }
+
+    #[test]
+    fn test_flush_pages() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open(&dir, 8, EvictionPolicy::Lru).await;
+            let part_num = page_directory.alloc_part().await.unwrap();
+            let pages = (0..6)
+                .map(|page_num| virtual_page_num(part_num, page_num))
+                .collect::<Vec<_>>();
+            for &page in &pages {
+                page_directory.alloc_page(page).await.unwrap();
+            }
+
+            // A run of adjacent dirty pages and a single one.
+            for &i in &[2, 0, 1, 4] {
+                let mut page = page_directory.fetch_page(pages[i]).await.unwrap();
+                page.as_data_page_mut().vals_mut()[100] = i as u8 + 1;
+                page_directory.mark_dirty(&page).unwrap();
+            }
+            drop(page_directory.fetch_page(pages[3]).await.unwrap());
+
+            page_directory.flush_pages().await.unwrap();
+            for (i, &page) in pages.iter().enumerate() {
+                let expected = if [0, 1, 2, 4].contains(&i) {
+                    i as u8 + 1
+                } else {
+                    0
+                };
+                assert_eq!(page_directory.read_page(page).await.unwrap()[100], expected);
+                assert!(!page_directory.fetch_page(page).await.unwrap().is_dirty());
+            }
+        })
+    }
+
+    #[test]
+    fn test_concurrent_fetch() {
+        let dir = TempDir::new().unwrap();
+        let page_directory = block_on(open(&dir, 4, EvictionPolicy::Lru));
+        let part_num = block_on(page_directory.alloc_part()).unwrap();
+        let pages = (0..16)
+            .map(|page_num| virtual_page_num(part_num, page_num))
+            .collect::<Vec<_>>();
+        for &page in &pages {
+            block_on(page_directory.alloc_page(page)).unwrap();
+        }
+
+        // Every thread counts its fetches in the pages, which are evicted and
+        // read in by the others meanwhile.
+        std::thread::scope(|s| {
+            for thread in 0..3 {
+                let page_directory = &page_directory;
+                let pages = &pages;
+                s.spawn(move || {
+                    block_on(async {
+                        for round in 0..100 {
+                            let page = pages[(thread * 5 + round * 3) % pages.len()];
+                            let mut page = page_directory.fetch_page(page).await.unwrap();
+                            page.as_data_page_mut().vals_mut()[100 + thread] += 1;
+                            page_directory.mark_dirty(&page).unwrap();
+                        }
+                    })
+                });
+            }
+        });
+
+        block_on(page_directory.flush_pages()).unwrap();
+        for thread in 0..3 {
+            let count: usize = pages
+                .iter()
+                .map(|&page| {
+                    block_on(page_directory.read_page(page)).unwrap()[100 + thread] as usize
+                })
+                .sum();
+            assert_eq!(count, 100);
+        }
+    }
+
+    #[test]
+    fn test_scan_resistance() {
+        block_on(async {
+            for (policy, hot_cached) in [
+                (EvictionPolicy::Lru, false),
+                (EvictionPolicy::Clock, false),
+                (EvictionPolicy::LruK(2), true),
+                (EvictionPolicy::TwoQueue, true),
+            ] {
+                let dir = TempDir::new().unwrap();
+                let page_directory = open(&dir, 8, policy).await;
+                let part_num = page_directory.alloc_part().await.unwrap();
+                let mut pages = vec![];
+                for _ in 0..20 {
+                    let page_num = page_directory
+                        .get_partition(part_num)
+                        .unwrap()
+                        .alloc_page()
+                        .await
+                        .unwrap();
+                    pages.push(virtual_page_num(part_num, page_num));
+                }
+
+                // Two hot pages are used repeatedly, then the other pages are
+                // scanned once.
+                for _ in 0..3 {
+                    for &page in &pages[..2] {
+                        drop(page_directory.fetch_page(page).await.unwrap());
+                    }
+                }
+                for &page in &pages[2..] {
+                    drop(page_directory.fetch_page(page).await.unwrap());
+                }
+
+                let hits = page_directory.cache_stats().hits;
+                for &page in &pages[..2] {
+                    drop(page_directory.fetch_page(page).await.unwrap());
+                }
+                let hot_hits = page_directory.cache_stats().hits - hits;
+                assert_eq!(hot_hits == 2, hot_cached, "{:?}", policy);
+            }
+        })
     }
 }
@@ -0,0 +1,132 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::Evictor;
+
+/// 2Q: frames read in are kept in a FIFO queue (A1in), and are moved to an LRU
+/// queue (Am) when accessed again. A1in holds at most a quarter of the frames,
+/// beyond which frames are evicted from A1in first, so a scan only cycles
+/// through A1in and leaves the pages in Am cached.
+///
+/// The pages evicted from A1in are remembered in a FIFO queue of page numbers
+/// (A1out) of half the frames, a page of A1out which is read in again goes to
+/// Am directly, as it's accessed again though not while it's cached.
+pub struct TwoQueueEvictor {
+    /// The max number of frames in A1in before they're evicted first.
+    a1_capacity: usize,
+    /// The max number of pages remembered in A1out.
+    a1out_capacity: usize,
+    /// The page in every tracked frame.
+    pages: HashMap<usize, u64>,
+    /// The frames accessed once, the oldest first.
+    a1: VecDeque<usize>,
+    /// The pages evicted from A1in, the first evicted first.
+    a1out: VecDeque<u64>,
+    /// The frames accessed more than once, the least recently used first.
+    am: VecDeque<usize>,
+}
+
+impl TwoQueueEvictor {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            a1_capacity: (capacity / 4).max(1),
+            a1out_capacity: (capacity / 2).max(1),
+            pages: HashMap::new(),
+            a1: VecDeque::new(),
+            a1out: VecDeque::new(),
+            am: VecDeque::new(),
+        }
+    }
+}
+
+impl Evictor for TwoQueueEvictor {
+    fn record_access(&mut self, frame: usize, page: u64) {
+        self.pages.insert(frame, page);
+        if let Some(pos) = self.am.iter().position(|&f| f == frame) {
+            self.am.remove(pos);
+            self.am.push_back(frame);
+        } else if let Some(pos) = self.a1.iter().position(|&f| f == frame) {
+            self.a1.remove(pos);
+            self.am.push_back(frame);
+        } else if let Some(pos) = self.a1out.iter().position(|&p| p == page) {
+            self.a1out.remove(pos);
+            self.am.push_back(frame);
+        } else {
+            self.a1.push_back(frame);
+        }
+    }
+
+    fn remove(&mut self, frame: usize) {
+        let page = self.pages.remove(&frame);
+        if let Some(pos) = self.a1.iter().position(|&f| f == frame) {
+            self.a1.remove(pos);
+            self.a1out.extend(page);
+            if self.a1out.len() > self.a1out_capacity {
+                self.a1out.pop_front();
+            }
+        }
+        self.am.retain(|&f| f != frame);
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(usize) -> bool) -> Option<usize> {
+        let from_a1 = self.a1.iter().copied().find(|&frame| evictable(frame));
+        let from_am = self.am.iter().copied().find(|&frame| evictable(frame));
+        if self.a1.len() > self.a1_capacity {
+            from_a1.or(from_am)
+        } else {
+            from_am.or(from_a1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_two_queue_evictor() {
+        let mut evictor = TwoQueueEvictor::new(4);
+        // Frame 0 is hot, frame 1 and 2 are read once by a scan.
+        for frame in [0, 0, 1, 2] {
+            evictor.record_access(frame, frame as u64);
+        }
+        assert_eq!(evictor.victim(&|_| true), Some(1));
+        evictor.remove(1);
+
+        // A1in is within its capacity, Am is evicted first.
+        assert_eq!(evictor.victim(&|_| true), Some(0));
+        assert_eq!(evictor.victim(&|frame| frame != 0), Some(2));
+
+        evictor.record_access(2, 2);
+        evictor.record_access(3, 3);
+        assert_eq!(evictor.victim(&|_| true), Some(0));
+        evictor.record_access(0, 0);
+        assert_eq!(evictor.victim(&|_| true), Some(2));
+    }
+
+    #[test]
+    fn test_two_queue_a1out() {
+        let mut evictor = TwoQueueEvictor::new(4);
+        // Page 10 is evicted from A1in, and read in again into Am.
+        for (frame, page) in [(0, 10), (1, 11), (2, 12)] {
+            evictor.record_access(frame, page);
+        }
+        assert_eq!(evictor.victim(&|_| true), Some(0));
+        evictor.remove(0);
+        evictor.record_access(0, 13);
+        evictor.remove(1);
+        evictor.record_access(1, 10);
+        // A1in holds frames 2 and 0 beyond its capacity, page 10 is in Am.
+        assert_eq!(evictor.victim(&|_| true), Some(2));
+        assert_eq!(evictor.victim(&|frame| frame != 2 && frame != 0), Some(1));
+
+        // A1out only remembers the last two pages evicted from A1in, page 12
+        // and 13, so page 11 goes to A1in.
+        evictor.remove(2);
+        evictor.remove(0);
+        evictor.record_access(2, 11);
+        evictor.record_access(0, 12);
+        evictor.record_access(3, 14);
+        assert_eq!(evictor.victim(&|_| true), Some(2));
+        assert_eq!(evictor.victim(&|frame| frame != 2 && frame != 3), Some(1));
+    }
+}
@@ -1,190 +1,995 @@
-use std::{
-    borrow::Borrow, collections::BTreeMap, fmt::Debug, marker::PhantomData, ops::RangeBounds,
-    ptr::NonNull,
+use std::{borrow::Borrow, marker::PhantomData, ops::RangeBounds, sync::Arc};
+
+use super::{
+    cursor::Cursor,
+    node::{check_entry_size, internal::InternalNode, leaf::LeafNode, Node},
+    Codec, EntryLog,
+};
+use crate::{
+    error::{Error, Result},
+    table::{
+        cache::PageGuard,
+        manifest::{IndexChange, TxnId},
+        page::page_directory::{PageDirectory, INDEX_PARTITION},
+    },
 };
 
-use super::node::{leaf::LeafNode, Node, NodeRef, Root};
-use crate::error::{Error, Result};
 /// A persistent B+ tree.
 ///
-/// ```rust
-/// let mut tree = BPlusTree::new();
-/// tree.insert(1, 1);
-/// tree.insert(2, 2);
+/// Every node takes a page of `INDEX_PARTITION`, nodes are read and changed
+/// through the page cache, so a tree can be larger than the memory. Changes to
+/// nodes are logged like changes to data pages (see
+/// `PageDirectory::log_page_update`), so the tree is recovered with the tables.
+///
+/// Every insert or removal is a nested top action (see `EntryLog`), so a
+/// rolled back transaction removes the entries it inserted (and inserts the
+/// entries it removed) wherever they are now, and the splits and merges of
+/// nodes are kept, as other transactions may have changed the same nodes
+/// since.
+///
+/// The root never moves: when the root splits, its two halves are moved into
+/// new pages and the root becomes their parent, and when the root is left with
+/// a single child, the child is moved into the root. So a tree is found by the
+/// page of root alone (see `TableIndex::root`). Nodes are split by their size
+/// in bytes, and a node less than a quarter full is merged with a sibling, or
+/// takes entries from it if both don't fit in a page.
+///
+/// A tree is read and changed concurrently through `&self`, the pages of
+/// nodes are latched by `PageGuard` (i.e. latch crabbing):
+///  - readers latch a child before they release its parent.
+///  - writers keep the latches of the ancestors which may split (or merge), and
+///    release them once a node is safe (see `Node::is_safe` and
+///    `Node::is_safe_for_remove`). The latches of changed nodes are kept until
+///    the change is logged.
+///  - siblings are latched from left to right only, so a cursor moves to
+///    another leaf by descending from root again (see `Cursor`).
+///
+/// ```ignore
+/// let mut tree = BTree::create(page_directory).await?;
+/// tree.insert(txn, 1, 1).await?;
+/// tree.insert(txn, 2, 2).await?;
 /// ```
 pub struct BTree<K, V> {
-    root: Option<Root<K, V>>,
-    length: usize,
+    page_directory: Arc<PageDirectory>,
+
+    /// The page of root node.
+    root: u64,
+
+    phantom: PhantomData<(K, V)>,
 }
 
-// impl<K, V> Drop for BTree<K, V> {
-//     fn drop(&mut self) {
-//         drop(unsafe { std::ptr::read(self) }.into_iter())
-//     }
-// }
+/// The latched internal nodes from the root (or the lowest node which doesn't
+/// split or merge) to a leaf, with the index of the child followed.
+type Path<'a, K> = Vec<(PageGuard<'a>, InternalNode<K>, usize)>;
+
+/// The pages changed by an operation, which are latched until the operation
+/// ends, and the pages freed by merges, which are released then.
+struct Changed<'a> {
+    pages: Vec<PageGuard<'a>>,
+    freed: Vec<u64>,
+}
 
 /// private methods.
-impl<K, V> BTree<K, V> {}
+impl<K, V> BTree<K, V>
+where
+    K: Codec + Ord + Clone,
+    V: Codec + Clone,
+{
+    pub(super) async fn read_node(&self, page: u64) -> Result<Node<K, V>> {
+        let page = self.page_directory.fetch_page(page).await?;
+        Node::decode(page.as_data_page())
+    }
 
-impl<K, V> BTree<K, V> {
-    pub fn new() -> Self {
-        Self {
-            root: None,
-            length: 0,
-        }
+    /// Writes the node into the latched page, the change is logged by
+    /// transaction.
+    async fn write_node(
+        &self,
+        txn: TxnId,
+        page: &mut PageGuard<'_>,
+        node: &Node<K, V>,
+    ) -> Result<()> {
+        let before = page.as_data_page().vals().to_vec();
+        node.encode(page.as_data_page_mut());
+        self.page_directory
+            .log_page_update(txn, page, &before)
+            .await
     }
 
-    /// Returns the value associated with `key`.
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    /// Allocates a page for a new node, returns the latched page.
+    async fn alloc_node(&self) -> Result<PageGuard<'_>> {
+        let page = self
+            .page_directory
+            .alloc_page_with_part(INDEX_PARTITION)
+            .await?;
+        self.page_directory.fetch_page(page).await
+    }
+
+    /// Latches the page of node and decodes it.
+    async fn fetch_node(&self, page: u64) -> Result<(PageGuard<'_>, Node<K, V>)> {
+        let page = self.page_directory.fetch_page(page).await?;
+        let node = Node::decode(page.as_data_page())?;
+        Ok((page, node))
+    }
+
+    /// Descends from root to a leaf, following the child chosen by `f` in
+    /// every internal node, returns the latched page of leaf. The latch of a
+    /// child is taken before the latch of its parent is released.
+    async fn descend<F>(&self, mut f: F) -> Result<(PageGuard<'_>, LeafNode<K, V>)>
     where
-        K: Borrow<Q> + Ord,
-        Q: Ord,
+        F: FnMut(&InternalNode<K>) -> usize,
     {
-        let node = self.root.as_ref()?;
-        node.search_node(key)
+        let mut page = self.page_directory.fetch_page(self.root).await?;
+        loop {
+            match Node::decode(page.as_data_page())? {
+                Node::Leaf(leaf) => return Ok((page, leaf)),
+                Node::Internal(internal) => {
+                    let child = internal.children[f(&internal)];
+                    page = self.page_directory.fetch_page(child).await?;
+                }
+            }
+        }
     }
 
-    pub fn scan<T: ?Sized, R>(&self, range: R) -> Option<Vec<V>>
+    /// Descends like `descend`, returns the leaf with the separator keys of
+    /// its ancestors which bound it, i.e. the keys of leaf are at least the
+    /// lower bound and less than the upper bound.
+    pub(super) async fn descend_with_bounds<F>(
+        &self,
+        f: F,
+    ) -> Result<(LeafNode<K, V>, Option<K>, Option<K>)>
     where
-        T: Ord,
-        K: Borrow<T> + Ord,
-        R: RangeBounds<T>,
+        F: Fn(&InternalNode<K>) -> usize,
     {
-        // todo(project4_integration)
-
-        // todo(project2)
+        let (mut lower, mut upper) = (None, None);
+        let (_, leaf) = self
+            .descend(|internal| {
+                let idx = f(internal);
+                if idx > 0 {
+                    lower = Some(internal.keys[idx - 1].clone());
+                }
+                if idx < internal.len() {
+                    upper = Some(internal.keys[idx].clone());
+                }
+                idx
+            })
+            .await?;
+        Ok((leaf, lower, upper))
+    }
 
-        None
+    /// Returns whether the node of page never splits or merges when an entry
+    /// is removed from its subtree. The root leaf may be empty, and the root
+    /// is only merged with its last sibling when it has a single key left.
+    fn is_safe_for_remove(&self, page: &PageGuard<'_>, node: &Node<K, V>) -> bool {
+        match node {
+            _ if page.virtual_page_num() != self.root => node.is_safe_for_remove(),
+            Node::Leaf(_) => true,
+            Node::Internal(internal) => internal.len() >= 2 && node.is_safe(),
+        }
     }
 
-    /// Returns btree whether contains this key.
-    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord,
-    {
-        self.get(key).is_some()
+    /// Inserts a (key, value) pair, if key exists then the value is replaced
+    /// only if `replace`, returns the existing value. The operation is logged
+    /// by `log`.
+    ///
+    /// The nodes are latched from root down (i.e. latch crabbing): the
+    /// latches of ancestors are released once a node is safe (see
+    /// `Node::is_safe`), as a split doesn't go beyond it.
+    pub(super) async fn insert_entry(
+        &self,
+        mut log: EntryLog<'_>,
+        key: K,
+        value: V,
+        replace: bool,
+    ) -> Result<Option<V>> {
+        check_entry_size(&key, &value)?;
+
+        let mut path: Path<'_, K> = vec![];
+        let mut page = self.page_directory.fetch_page(self.root).await?;
+        let mut leaf = loop {
+            let node: Node<K, V> = Node::decode(page.as_data_page())?;
+            if node.is_safe() {
+                path.clear();
+            }
+            match node {
+                Node::Leaf(leaf) => break leaf,
+                Node::Internal(internal) => {
+                    let idx = internal.search_internal(&key);
+                    let child = self
+                        .page_directory
+                        .fetch_page(internal.children[idx])
+                        .await?;
+                    path.push((std::mem::replace(&mut page, child), internal, idx));
+                }
+            }
+        };
+
+        if !replace {
+            if let Some(existing) = leaf.search_leaf(&key).cloned() {
+                log.end(self.root).await?;
+                return Ok(Some(existing));
+            }
+        }
+        // The replaced value is logged first, so that the new one is removed
+        // before it's inserted back.
+        if let Some(existing) = leaf.search_leaf(&key) {
+            log.change(IndexChange::BTreeRemove, &key, existing);
+        }
+        log.change(IndexChange::BTreeInsert, &key, &value);
+        let replaced = leaf.insert_leaf(key, value);
+
+        self.write_back(log, path, page, Node::Leaf(leaf)).await?;
+        Ok(replaced)
     }
 
-    /// Inserts a (key, rid) pair into a B+ tree. If the key already exists
-    /// in the B+ tree, then the pair is not inserted and an exception is
-    /// raised.
-    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    /// Removes the key, returns the value removed. The operation is logged by
+    /// `log`.
+    ///
+    /// The nodes are latched from root down like `insert_entry`, the latches
+    /// of ancestors are released once a node is safe for removal (see
+    /// `Node::is_safe_for_remove`), as a merge doesn't go beyond it.
+    pub(super) async fn remove_entry<Q>(&self, mut log: EntryLog<'_>, key: &Q) -> Result<Option<V>>
     where
-        K: Ord + Clone,
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
     {
-        match self.root.as_mut() {
+        let mut path: Path<'_, K> = vec![];
+        let mut page = self.page_directory.fetch_page(self.root).await?;
+        let mut leaf = loop {
+            let node: Node<K, V> = Node::decode(page.as_data_page())?;
+            if self.is_safe_for_remove(&page, &node) {
+                path.clear();
+            }
+            match node {
+                Node::Leaf(leaf) => break leaf,
+                Node::Internal(internal) => {
+                    let idx = internal.search_internal(key);
+                    let child = self
+                        .page_directory
+                        .fetch_page(internal.children[idx])
+                        .await?;
+                    path.push((std::mem::replace(&mut page, child), internal, idx));
+                }
+            }
+        };
+
+        let (key, removed) = match leaf.remove_leaf(key) {
+            Some(entry) => entry,
             None => {
-                let mut leaf = Node::new_leaf_boxed();
-                leaf.as_mut().insert_leaf(key, value);
+                log.end(self.root).await?;
+                return Ok(None);
+            }
+        };
+        log.change(IndexChange::BTreeRemove, &key, &removed);
+        self.write_back(log, path, page, Node::Leaf(leaf)).await?;
+        Ok(Some(removed))
+    }
 
-                self.root = Some(NodeRef::from_node(leaf.downcast()));
-                self.length += 1;
-                None
+    /// Writes the changed node into its latched page, and the ancestors of
+    /// `path` as long as the changed node overflows (then it splits) or
+    /// underflows (then it's rebalanced with a sibling), from the node up.
+    /// The operation ends while the changed pages are still latched, then the
+    /// pages freed by merges are released.
+    async fn write_back(
+        &self,
+        log: EntryLog<'_>,
+        mut path: Path<'_, K>,
+        mut page: PageGuard<'_>,
+        mut node: Node<K, V>,
+    ) -> Result<()> {
+        let txn = log.txn();
+        let mut changed = Changed {
+            pages: vec![],
+            freed: vec![],
+        };
+        loop {
+            if page.virtual_page_num() == self.root {
+                if node.is_overflow() {
+                    self.split_root(txn, &mut page, node).await?;
+                } else {
+                    self.write_node(txn, &mut page, &node).await?;
+                }
+                break;
             }
-            Some(node) => {
-                let replaced = node.insert_node(key, value);
-                self.length += 1;
-                replaced
+            // A node left underflowing by a bulk load isn't rebalanced until
+            // an entry is removed from it.
+            let parent = if node.is_overflow() || node.is_underflow() {
+                path.pop()
+            } else {
+                None
+            };
+            let (mut parent_page, mut parent, idx) = match parent {
+                Some(parent) => parent,
+                None => {
+                    self.write_node(txn, &mut page, &node).await?;
+                    break;
+                }
+            };
+            if node.is_overflow() {
+                let (key, right) = self.split(txn, &mut page, node, &mut changed).await?;
+                parent.insert_internal(idx, key, right);
+                changed.pages.push(page);
+            } else if self
+                .rebalance(
+                    txn,
+                    &mut parent_page,
+                    &mut parent,
+                    idx,
+                    page,
+                    node,
+                    &mut changed,
+                )
+                .await?
+            {
+                page = parent_page;
+                break;
             }
+            (page, node) = (parent_page, Node::Internal(parent));
         }
+
+        log.end(self.root).await?;
+        drop((path, page, changed.pages));
+        for freed in changed.freed {
+            self.page_directory.release_page(freed).await?;
+        }
+        Ok(())
     }
 
-    /// Deletes a (key, rid) pair from a B+ tree.
-    pub fn remove<Q: ?Sized>(&self, key: &Q) -> Option<V>
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord,
-    {
-        // todo(project4_integration)
+    /// Splits the node (which isn't root) of page into a new right sibling,
+    /// returns the separator key and the page of sibling. The next leaf is
+    /// latched after the split leaf, so leaves are latched from left to right.
+    async fn split<'b>(
+        &'b self,
+        txn: TxnId,
+        page: &mut PageGuard<'b>,
+        node: Node<K, V>,
+        changed: &mut Changed<'b>,
+    ) -> Result<(K, u64)> {
+        let mut right_page = self.alloc_node().await?;
+        let right_page_num = right_page.virtual_page_num();
+        let (key, left, right) = match node {
+            Node::Leaf(mut left) => {
+                let mut right = left.split_leaf();
+                right.prev = Some(page.virtual_page_num());
+                right.next = left.next.replace(right_page_num);
+                if let Some(next) = right.next {
+                    changed
+                        .pages
+                        .push(self.link_prev(txn, next, right_page_num).await?);
+                }
+                (right.keys[0].clone(), Node::Leaf(left), Node::Leaf(right))
+            }
+            Node::Internal(mut left) => {
+                let (key, right) = left.split_internal();
+                (key, Node::Internal(left), Node::Internal(right))
+            }
+        };
 
-        // todo(project2)
+        self.write_node(txn, &mut right_page, &right).await?;
+        self.write_node(txn, page, &left).await?;
+        changed.pages.push(right_page);
+        Ok((key, right_page_num))
+    }
 
-        todo!()
+    /// Sets the previous leaf of leaf `page` to `prev`, returns the latched
+    /// page of leaf.
+    async fn link_prev(&self, txn: TxnId, page: u64, prev: u64) -> Result<PageGuard<'_>> {
+        let (mut page_guard, mut node) = self.fetch_node(page).await?;
+        node.get_leaf_mut()
+            .ok_or_else(|| Error::Corrupted(format!("index leaf {}", page)))?
+            .prev = Some(prev);
+        self.write_node(txn, &mut page_guard, &node).await?;
+        Ok(page_guard)
     }
-}
 
-pub struct IntoIter<K, V> {
-    phantom: PhantomData<(K, V)>,
-}
+    /// Splits the root node into two new nodes, the root becomes their parent.
+    async fn split_root(
+        &self,
+        txn: TxnId,
+        page: &mut PageGuard<'_>,
+        node: Node<K, V>,
+    ) -> Result<()> {
+        debug_assert_eq!(page.virtual_page_num(), self.root);
+        let (mut left_page, mut right_page) = (self.alloc_node().await?, self.alloc_node().await?);
+        let (left_page_num, right_page_num) =
+            (left_page.virtual_page_num(), right_page.virtual_page_num());
+        let (key, left, right) = match node {
+            Node::Leaf(mut left) => {
+                let mut right = left.split_leaf();
+                left.next = Some(right_page_num);
+                right.prev = Some(left_page_num);
+                (right.keys[0].clone(), Node::Leaf(left), Node::Leaf(right))
+            }
+            Node::Internal(mut left) => {
+                let (key, right) = left.split_internal();
+                (key, Node::Internal(left), Node::Internal(right))
+            }
+        };
 
-impl<K, V> IntoIter<K, V> {
-    fn dying_next(&mut self) -> Option<Node<K, V>> {
-        None
+        self.write_node(txn, &mut left_page, &left).await?;
+        self.write_node(txn, &mut right_page, &right).await?;
+        let root = InternalNode::new(vec![key], vec![left_page_num, right_page_num]);
+        self.write_node(txn, page, &Node::Internal(root)).await
     }
-}
 
-impl<K, V> Drop for IntoIter<K, V> {
-    fn drop(&mut self) {
-        struct DropGuard<'a, K, V>(&'a mut IntoIter<K, V>);
-
-        impl<'a, K, V> Drop for DropGuard<'a, K, V> {
-            fn drop(&mut self) {
-                while let Some(kv) = self.0.dying_next() {
-                    unsafe { kv.drop_key_val() };
-                }
+    /// Rebalances the underflowing node of page, the child `idx` of parent,
+    /// with its next sibling (or the previous one if it's the last child):
+    /// both are merged into the left one if they fit in a page, otherwise their
+    /// entries are split again evenly. Changes the parent but doesn't write it,
+    /// unless the parent is the root which is left with a single child, then
+    /// the merged node is moved into the root and returns true.
+    #[allow(clippy::too_many_arguments)]
+    async fn rebalance<'b>(
+        &'b self,
+        txn: TxnId,
+        parent_page: &mut PageGuard<'b>,
+        parent: &mut InternalNode<K>,
+        idx: usize,
+        page: PageGuard<'b>,
+        node: Node<K, V>,
+        changed: &mut Changed<'b>,
+    ) -> Result<bool> {
+        // Siblings are latched from left to right, so the node is released
+        // before its previous sibling is latched, nobody else reaches it in
+        // the meantime as the parent is latched.
+        let (sep, (mut left_page, left), (mut right_page, right)) =
+            if idx + 1 < parent.children.len() {
+                let right = self.fetch_node(parent.children[idx + 1]).await?;
+                (idx, (page, node), right)
+            } else {
+                let page_num = page.virtual_page_num();
+                drop(page);
+                let left = self.fetch_node(parent.children[idx - 1]).await?;
+                let page = self.page_directory.fetch_page(page_num).await?;
+                (idx - 1, left, (page, node))
+            };
+        let (left_page_num, right_page_num) =
+            (left_page.virtual_page_num(), right_page.virtual_page_num());
+
+        let mut merged = match (left, right) {
+            (Node::Leaf(mut left), Node::Leaf(right)) => {
+                left.merge_leaf(right);
+                Node::Leaf(left)
             }
+            (Node::Internal(mut left), Node::Internal(right)) => {
+                left.merge_internal(parent.keys[sep].clone(), right);
+                Node::Internal(left)
+            }
+            _ => return Err(Error::Corrupted(format!("index node {}", right_page_num))),
+        };
+
+        if merged.is_overflow() {
+            // Splits the entries of both again, the right sibling keeps its
+            // page.
+            let (key, right) = match &mut merged {
+                Node::Leaf(left) => {
+                    let mut right = left.split_leaf();
+                    right.prev = Some(left_page_num);
+                    right.next = left.next.replace(right_page_num);
+                    (right.keys[0].clone(), Node::Leaf(right))
+                }
+                Node::Internal(left) => {
+                    let (key, right) = left.split_internal();
+                    (key, Node::Internal(right))
+                }
+            };
+            parent.keys[sep] = key;
+            self.write_node(txn, &mut left_page, &merged).await?;
+            self.write_node(txn, &mut right_page, &right).await?;
+            changed.pages.extend([left_page, right_page]);
+            return Ok(false);
+        }
+
+        parent.remove_internal(sep);
+        drop(right_page);
+        changed.freed.push(right_page_num);
+        if parent_page.virtual_page_num() == self.root && parent.is_empty() {
+            // The merged node is the only leaf if it's a leaf, so it has no
+            // siblings.
+            self.write_node(txn, parent_page, &merged).await?;
+            drop(left_page);
+            changed.freed.push(left_page_num);
+            return Ok(true);
         }
+        if let Node::Leaf(LeafNode {
+            next: Some(next), ..
+        }) = merged
+        {
+            changed
+                .pages
+                .push(self.link_prev(txn, next, left_page_num).await?);
+        }
+        self.write_node(txn, &mut left_page, &merged).await?;
+        changed.pages.push(left_page);
+        Ok(false)
+    }
+}
+
+impl<K, V> BTree<K, V>
+where
+    K: Codec + Ord + Clone,
+    V: Codec + Clone,
+{
+    /// Creates an empty tree, the root is a newly allocated page, which is an
+    /// empty leaf.
+    pub async fn create(page_directory: Arc<PageDirectory>) -> Result<Self> {
+        let root = super::alloc_root(&page_directory).await?;
+        Self::open(page_directory, root).await
+    }
+
+    /// Opens the tree whose root node is at page `root`.
+    pub async fn open(page_directory: Arc<PageDirectory>, root: u64) -> Result<Self> {
+        super::open_root(&page_directory, root).await?;
 
-        while let Some(kv) = self.dying_next() {
-            let guard = DropGuard(self);
+        Ok(Self {
+            page_directory,
+            root,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Returns the page of root node.
+    pub fn root(&self) -> u64 {
+        self.root
+    }
 
-            unsafe { kv.drop_key_val() };
+    /// Returns the value associated with `key`.
+    pub async fn get<Q>(&self, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        let (_, leaf) = self
+            .descend(|internal| internal.search_internal(key))
+            .await?;
+        Ok(leaf.search_leaf(key).cloned())
+    }
 
-            std::mem::forget(guard);
+    /// Returns a cursor before the first entry in `range`.
+    pub async fn cursor<R>(&self, range: R) -> Result<Cursor<'_, K, V>>
+    where
+        R: RangeBounds<K>,
+    {
+        Cursor::new(self, range).await
+    }
+
+    /// Returns the values of keys in `range`, in key order.
+    pub async fn scan<R>(&self, range: R) -> Result<Vec<V>>
+    where
+        R: RangeBounds<K>,
+    {
+        let mut cursor = self.cursor(range).await?;
+        let mut vals = vec![];
+        while let Some((_, val)) = cursor.next().await? {
+            vals.push(val);
         }
+        Ok(vals)
     }
-}
 
-impl<K, V> Iterator for IntoIter<K, V> {
-    type Item = (K, V);
+    /// Returns btree whether contains this key.
+    pub async fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        Ok(self.get(key).await?.is_some())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+    /// Inserts a (key, value) pair into a B+ tree, the change is logged by
+    /// transaction. If the key already exists in the B+ tree, the value is
+    /// replaced and the old value is returned. Returns `Error::TooLargeSize` if
+    /// the entry is larger than `MAX_ENTRY_SIZE`.
+    pub async fn insert(&self, txn: TxnId, key: K, value: V) -> Result<Option<V>> {
+        let log = EntryLog::begin(&self.page_directory, txn).await;
+        self.insert_entry(log, key, value, true).await
     }
-}
 
-impl<K, V> IntoIterator for BTree<K, V> {
-    type IntoIter = IntoIter<K, V>;
-    type Item = (K, V);
+    /// Inserts a (key, value) pair like `insert` only if the key doesn't
+    /// exist, otherwise returns the existing value. The check and the insert
+    /// are atomic.
+    pub async fn try_insert(&self, txn: TxnId, key: K, value: V) -> Result<Option<V>> {
+        let log = EntryLog::begin(&self.page_directory, txn).await;
+        self.insert_entry(log, key, value, false).await
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        todo!()
+    /// Deletes a (key, value) pair from a B+ tree, the change is logged by
+    /// transaction. Returns the removed value.
+    pub async fn remove<Q>(&self, txn: TxnId, key: &Q) -> Result<Option<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
+    {
+        let log = EntryLog::begin(&self.page_directory, txn).await;
+        self.remove_entry(log, key).await
+    }
+
+    /// Releases all pages of the tree.
+    pub async fn destroy(self) -> Result<()> {
+        let mut pages = vec![self.root];
+        while let Some(page) = pages.pop() {
+            if let Node::Internal(internal) = self.read_node(page).await? {
+                pages.extend(internal.children);
+            }
+            self.page_directory.release_page(page).await?;
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::ops::Bound;
+
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
     use super::*;
+    use crate::{
+        datatypes::{array::scalar::ScalarImpl, record::RecordId},
+        options::Options,
+        table::{
+            index::{
+                key::IndexKey,
+                tests::{open_page_directory, WideKey},
+            },
+            recover,
+        },
+    };
+
+    /// Returns 0..n in a shuffled order.
+    fn shuffled(n: u32) -> impl Iterator<Item = u32> {
+        (0..n).map(move |i| i * 7919 % n)
+    }
+
+    /// Returns the number of levels of tree.
+    async fn depth<K: Codec + Ord + Clone, V: Codec + Clone>(btree: &BTree<K, V>) -> usize {
+        let mut depth = 1;
+        let mut node = btree.read_node(btree.root()).await.unwrap();
+        while let Node::Internal(internal) = node {
+            node = btree.read_node(internal.children[0]).await.unwrap();
+            depth += 1;
+        }
+        depth
+    }
+
+    /// Returns the keys of leaves from the first one along the next leaves,
+    /// checks the leaves are linked both ways.
+    async fn leaf_keys<V: Codec + Clone>(btree: &BTree<WideKey, V>) -> Vec<u32> {
+        let mut page = btree.root();
+        let mut node = btree.read_node(page).await.unwrap();
+        while let Node::Internal(internal) = node {
+            page = internal.children[0];
+            node = btree.read_node(page).await.unwrap();
+        }
+        let mut leaf = match node {
+            Node::Leaf(leaf) => leaf,
+            Node::Internal(_) => unreachable!(),
+        };
+        assert!(leaf.prev.is_none());
+        let mut keys = vec![];
+        while let Some(next) = leaf.next {
+            keys.extend(leaf.keys.iter().map(|key| key.0));
+            leaf = match btree.read_node(next).await.unwrap() {
+                Node::Leaf(leaf) => leaf,
+                Node::Internal(_) => panic!("page {} is not a leaf", next),
+            };
+            assert_eq!(leaf.prev, Some(page));
+            page = next;
+        }
+        keys.extend(leaf.keys.iter().map(|key| key.0));
+        keys
+    }
+
+    /// Returns the key of an index entry, which is padded to about 250 bytes
+    /// like `WideKey`.
+    fn index_key(i: u32) -> IndexKey {
+        let value = ScalarImpl::String(format!("{:0>240}", i));
+        IndexKey::new(&[Some(&value)], RecordId(0, i as usize))
+    }
 
     #[test]
     fn test_sample_get() {
-        let mut btree = BTree::new();
-        btree.insert(1, 1);
-        btree.insert(2, 2);
-        btree.insert(3, 3);
-        btree.insert(4, 4);
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let btree = BTree::create(open_page_directory(&dir).await)
+                .await
+                .unwrap();
+            for i in 1..=4 {
+                btree.insert(1, i, i).await.unwrap();
+            }
 
-        assert_eq!(btree.get(&1), Some(&1));
-        assert_eq!(btree.get(&2), Some(&2));
-        assert_eq!(btree.get(&3), Some(&3));
-        assert_eq!(btree.get(&4), Some(&4));
+            assert_eq!(btree.get(&1).await.unwrap(), Some(1));
+            assert_eq!(btree.get(&2).await.unwrap(), Some(2));
+            assert_eq!(btree.get(&3).await.unwrap(), Some(3));
+            assert_eq!(btree.get(&4).await.unwrap(), Some(4));
 
-        assert_eq!(btree.get(&5), None);
+            assert_eq!(btree.get(&5).await.unwrap(), None);
+        })
     }
 
     #[test]
     fn test_sample_remove() {
-        let mut btree = BTree::new();
-        btree.insert(1, 1);
-        assert_eq!(btree.get(&1), Some(&1));
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let btree = BTree::create(open_page_directory(&dir).await)
+                .await
+                .unwrap();
+            btree.insert(1, 1, 1).await.unwrap();
+            assert_eq!(btree.get(&1).await.unwrap(), Some(1));
+
+            let removed = btree.remove(1, &1).await.unwrap();
+            assert_eq!(removed, Some(1));
+
+            assert_eq!(btree.get(&1).await.unwrap(), None);
+        })
+    }
+
+    #[test]
+    fn test_sample_scan() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let btree = BTree::create(open_page_directory(&dir).await)
+                .await
+                .unwrap();
+            for i in shuffled(1000) {
+                btree.insert(1, WideKey(i), i).await.unwrap();
+            }
 
-        let removed = btree.remove(&1);
-        assert_eq!(removed, Some(1));
+            let all = btree.scan(..).await.unwrap();
+            assert_eq!(all, (0..1000).collect::<Vec<_>>());
+            let range = btree.scan(WideKey(100)..WideKey(300)).await.unwrap();
+            assert_eq!(range, (100..300).collect::<Vec<_>>());
+            let range = btree
+                .scan((Bound::Excluded(WideKey(997)), Bound::Unbounded))
+                .await
+                .unwrap();
+            assert_eq!(range, vec![998, 999]);
+            let range = btree.scan(..=WideKey(0)).await.unwrap();
+            assert_eq!(range, vec![0]);
+        })
+    }
 
-        assert_eq!(btree.get(&1), None);
+    #[test]
+    fn test_split() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let btree = BTree::create(page_directory.clone()).await.unwrap();
+            // The tree is much larger than the 8 cached pages.
+            for i in shuffled(2000) {
+                assert_eq!(btree.insert(1, WideKey(i), i).await.unwrap(), None);
+            }
+            assert_eq!(btree.insert(1, WideKey(7), 70).await.unwrap(), Some(7));
+            for i in (0..2000).step_by(2) {
+                assert_eq!(btree.remove(1, &WideKey(i)).await.unwrap(), Some(i));
+            }
+            assert_eq!(btree.remove(1, &WideKey(0)).await.unwrap(), None);
+
+            for i in 0..2000 {
+                let expected = match i {
+                    7 => Some(70),
+                    i if i % 2 == 0 => None,
+                    i => Some(i),
+                };
+                assert_eq!(btree.get(&WideKey(i)).await.unwrap(), expected);
+            }
+
+            // The tree has at least 3 levels.
+            assert!(depth(&btree).await >= 3);
+            assert_eq!(
+                leaf_keys(&btree).await,
+                (1..2000).step_by(2).collect::<Vec<_>>()
+            );
+
+            btree.destroy().await.unwrap();
+            assert!(page_directory
+                .get_partition(INDEX_PARTITION)
+                .unwrap()
+                .data_page_nums()
+                .is_empty());
+        })
+    }
+
+    #[test]
+    fn test_merge() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let btree = BTree::create(page_directory.clone()).await.unwrap();
+            let pages = || {
+                page_directory
+                    .get_partition(INDEX_PARTITION)
+                    .unwrap()
+                    .data_page_nums()
+                    .len()
+            };
+            for i in shuffled(2000) {
+                btree.insert(1, WideKey(i), i).await.unwrap();
+            }
+            let (full_depth, full_pages) = (depth(&btree).await, pages());
+            assert!(full_depth >= 3);
+
+            // Nodes are merged (or take entries from siblings) as they
+            // underflow, the pages of merged nodes are freed.
+            for i in shuffled(2000).filter(|i| i % 50 != 0) {
+                assert_eq!(btree.remove(1, &WideKey(i)).await.unwrap(), Some(i));
+            }
+            let expected = (0..2000).step_by(50).collect::<Vec<_>>();
+            assert_eq!(leaf_keys(&btree).await, expected);
+            assert_eq!(btree.scan(..).await.unwrap(), expected);
+            assert!(depth(&btree).await < full_depth);
+            assert!(pages() * 10 < full_pages);
+
+            // The root takes the last leaf back.
+            for i in expected {
+                assert_eq!(btree.remove(1, &WideKey(i)).await.unwrap(), Some(i));
+            }
+            assert_eq!(depth(&btree).await, 1);
+            assert_eq!(pages(), 1);
+            assert!(btree.scan(..).await.unwrap().is_empty());
+        })
     }
 
     #[test]
-    fn test_sample_scan() {}
+    fn test_rollback() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let btree = BTree::create(page_directory.clone()).await.unwrap();
+            let txn = page_directory.begin_txn().await;
+            for i in shuffled(600).filter(|i| i % 3 == 0) {
+                btree
+                    .insert(txn, index_key(i), RecordId(0, i as usize))
+                    .await
+                    .unwrap();
+            }
+            page_directory.commit_txn(txn).await.unwrap();
+
+            // Two transactions change the same leaves, which split and merge
+            // in turn.
+            let (txn1, txn2) = (
+                page_directory.begin_txn().await,
+                page_directory.begin_txn().await,
+            );
+            for i in shuffled(600).filter(|i| i % 3 != 0) {
+                let txn = if i % 3 == 1 { txn1 } else { txn2 };
+                btree
+                    .insert(txn, index_key(i), RecordId(0, i as usize))
+                    .await
+                    .unwrap();
+            }
+            for i in (0..600).step_by(6) {
+                btree.remove(txn1, &index_key(i)).await.unwrap();
+            }
+            for i in (2..600).step_by(6) {
+                btree.remove(txn2, &index_key(i)).await.unwrap();
+            }
+            recover::rollback(&page_directory, txn1).await.unwrap();
+            page_directory.commit_txn(txn2).await.unwrap();
+
+            // The entries of the other transaction and the splits are kept.
+            assert!(depth(&btree).await > 1);
+            let expected = (0..600)
+                .filter(|i| i % 3 == 0 || i % 6 == 5)
+                .map(|i| RecordId(0, i))
+                .collect::<Vec<_>>();
+            assert_eq!(btree.scan(..).await.unwrap(), expected);
+        })
+    }
+
+    #[test]
+    fn test_reopen() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let root = {
+                let page_directory = open_page_directory(&dir).await;
+                let btree = BTree::create(page_directory.clone()).await.unwrap();
+                let txn = page_directory.begin_txn().await;
+                for i in shuffled(500) {
+                    btree
+                        .insert(txn, index_key(i), RecordId(0, i as usize))
+                        .await
+                        .unwrap();
+                }
+                page_directory.commit_txn(txn).await.unwrap();
+
+                // A transaction which isn't committed at the crash.
+                let txn = page_directory.begin_txn().await;
+                for i in 500..600 {
+                    btree
+                        .insert(txn, index_key(i), RecordId(0, i as usize))
+                        .await
+                        .unwrap();
+                }
+                for i in (0..500).step_by(5) {
+                    btree.remove(txn, &index_key(i)).await.unwrap();
+                }
+                page_directory
+                    .manifest()
+                    .await
+                    .flush_to(u64::MAX)
+                    .await
+                    .unwrap();
+                btree.root()
+                // Crashes without a checkpoint, the cached pages are lost.
+            };
+
+            let btree = BTree::<IndexKey, RecordId>::open(open_page_directory(&dir).await, root)
+                .await
+                .unwrap();
+            let all = btree.scan(..).await.unwrap();
+            assert_eq!(all, (0..500).map(|i| RecordId(0, i)).collect::<Vec<_>>());
+        })
+    }
+
+    #[test]
+    fn test_concurrent() {
+        const THREADS: u32 = 4;
+        const KEYS_PER_THREAD: u32 = 300;
+
+        let dir = TempDir::new().unwrap();
+        let page_directory = block_on(PageDirectory::open(&Options {
+            path: dir.path().join("db").to_string_lossy().to_string(),
+            // Every thread latches a few pages at a time.
+            cache_frames: 64,
+            ..Default::default()
+        }))
+        .unwrap();
+        let page_directory = Arc::new(page_directory);
+        let btree = block_on(BTree::create(page_directory.clone())).unwrap();
+
+        std::thread::scope(|s| {
+            // Every writer inserts its own keys, then removes a third of them.
+            for t in 0..THREADS {
+                let (btree, page_directory) = (&btree, &page_directory);
+                s.spawn(move || {
+                    block_on(async move {
+                        let txn = page_directory.begin_txn().await;
+                        let keys = shuffled(KEYS_PER_THREAD)
+                            .map(|i| i * THREADS + t)
+                            .collect::<Vec<_>>();
+                        for &key in &keys {
+                            let inserted = btree.insert(txn, WideKey(key), key).await.unwrap();
+                            assert_eq!(inserted, None);
+                        }
+                        for key in keys.into_iter().filter(|key| key % 3 == 0) {
+                            let removed = btree.remove(txn, &WideKey(key)).await.unwrap();
+                            assert_eq!(removed, Some(key));
+                        }
+                        page_directory.commit_txn(txn).await.unwrap();
+                    })
+                });
+            }
+
+            // Readers see the keys in order both ways while nodes split.
+            for _ in 0..2 {
+                let btree = &btree;
+                s.spawn(move || {
+                    block_on(async move {
+                        for _ in 0..10 {
+                            let mut cursor = btree.cursor(..).await.unwrap();
+                            let mut last = None;
+                            while let Some((key, val)) = cursor.next().await.unwrap() {
+                                assert_eq!(key.0, val);
+                                assert!(last < Some(key.0));
+                                last = Some(key.0);
+                            }
+                            while let Some((key, _)) = cursor.prev().await.unwrap() {
+                                assert!(last >= Some(key.0));
+                                last = Some(key.0);
+                            }
+                        }
+                    })
+                });
+            }
+        });
+
+        let expected = (0..THREADS * KEYS_PER_THREAD)
+            .filter(|key| key % 3 != 0)
+            .collect::<Vec<_>>();
+        assert_eq!(block_on(btree.scan(..)).unwrap(), expected);
+        for key in 0..THREADS * KEYS_PER_THREAD {
+            let expected = (key % 3 != 0).then_some(key);
+            assert_eq!(block_on(btree.get(&WideKey(key))).unwrap(), expected);
+        }
+    }
 }
@@ -1,25 +1,449 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 
-use super::btree::BTree;
-use crate::catalog::schema::TableIndex;
+use super::{
+    btree::BTree,
+    node::{
+        check_entry_size, internal::InternalNode, leaf::LeafNode, Node, NODE_SIZE, POINTER_SIZE,
+    },
+    sorter::{ExternalSorter, SortedEntries, DEFAULT_SORT_BUFFER_SIZE},
+    Codec,
+};
+use crate::{
+    catalog::schema::TableIndex,
+    error::{Error, Result},
+    table::page::{
+        page_directory::{
+            is_next_page, PageDirectory, DEFAULT_PAGE_SIZE, INDEX_PARTITION, MAX_WRITE_PAGES,
+        },
+        DataPage,
+    },
+};
 
-/// A B+ tree builder.
+/// The default fraction of a node filled by bulk loading.
+pub const DEFAULT_FILL_FACTOR: f64 = 0.9;
+
+/// A B+ tree builder, which opens the tree of index, or creates the tree if
+/// the index has none yet (see `TableIndex::root`).
+///
+/// A new tree is bulk loaded from the entries added to the builder, e.g. when
+/// an index is created on an existing table: the entries are sorted (see
+/// `ExternalSorter`), then the tree is built bottom-up, the leaves are packed
+/// in key order up to the fill factor, followed by each level of internal
+/// nodes. The nodes are written to disk directly instead of through the cache
+/// and the log, and the index partition is synced before the tree is returned.
 ///
 /// # Example
-/// ```rust
-/// let mut builder = TreeBuilder::new();
-/// builder.build();
+/// ```ignore
+/// let mut builder = BTreeBuilder::<i32, i32>::new(page_directory, &index);
+/// builder.add(1, 1).await?;
+/// let btree = builder.finish().await?;
 /// ```
 pub struct BTreeBuilder<K, V> {
+    page_directory: Arc<PageDirectory>,
+    root: Option<u64>,
+    fill_factor: f64,
+    sorter: ExternalSorter<K, V>,
     phantom: PhantomData<(K, V)>,
 }
 
-impl<K, V> BTreeBuilder<K, V> {
-    pub fn new(index: &TableIndex) -> Self {
-        unimplemented!()
+impl<K, V> BTreeBuilder<K, V>
+where
+    K: Codec + Ord + Clone,
+    V: Codec + Clone,
+{
+    pub fn new(page_directory: Arc<PageDirectory>, index: &TableIndex) -> Self {
+        Self {
+            sorter: ExternalSorter::new(page_directory.path(), DEFAULT_SORT_BUFFER_SIZE),
+            page_directory,
+            root: index.root,
+            fill_factor: DEFAULT_FILL_FACTOR,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Sets the fraction of a node filled by bulk loading, in `(0, 1]`. A lower
+    /// fill factor leaves room for the entries inserted later without
+    /// splitting nodes.
+    pub fn with_fill_factor(mut self, fill_factor: f64) -> Self {
+        assert!(
+            fill_factor > 0.0 && fill_factor <= 1.0,
+            "fill factor {} out of (0, 1]",
+            fill_factor
+        );
+        self.fill_factor = fill_factor;
+        self
+    }
+
+    /// Sets the amount (in bytes) of entries sorted in memory, beyond which
+    /// the entries are sorted externally.
+    pub fn with_sort_buffer_size(mut self, sort_buffer_size: usize) -> Self {
+        self.sorter = ExternalSorter::new(self.page_directory.path(), sort_buffer_size);
+        self
+    }
+
+    /// Adds an entry to be bulk loaded, in any order. Returns
+    /// `Error::TooLargeSize` if the entry doesn't fit in a node.
+    pub async fn add(&mut self, key: K, value: V) -> Result<()> {
+        check_entry_size(&key, &value)?;
+        self.sorter.push(key, value).await
+    }
+
+    /// Opens or creates the tree, returns `Error::Invalid` if entries are
+    /// added but the index has a tree already, or
+    /// `Error::ConstraintViolation` if a key is added twice, then the pages of
+    /// the tree are released.
+    pub async fn finish(self) -> Result<BTree<K, V>> {
+        match self.root {
+            Some(root) if self.sorter.is_empty() => BTree::open(self.page_directory, root).await,
+            Some(_) => Err(Error::Invalid("bulk load: index is not empty".to_owned())),
+            None if self.sorter.is_empty() => BTree::create(self.page_directory).await,
+            None => {
+                let entries = self.sorter.finish().await?;
+                let root = bulk_load(&self.page_directory, entries, self.fill_factor).await?;
+                BTree::open(self.page_directory, root).await
+            }
+        }
+    }
+}
+
+/// Builds a tree from the sorted entries, returns the page of root. The pages
+/// allocated are released if the tree can't be built.
+async fn bulk_load<K, V>(
+    page_directory: &PageDirectory,
+    entries: SortedEntries<K, V>,
+    fill_factor: f64,
+) -> Result<u64>
+where
+    K: Codec + Ord + Clone,
+    V: Codec + Clone,
+{
+    let root = super::alloc_root(page_directory).await?;
+    let mut writer = NodeWriter::new(page_directory).await;
+    match write_tree(&mut writer, root, entries, fill_factor).await {
+        Ok(()) => Ok(root),
+        Err(e) => {
+            for page in writer.allocated.into_iter().chain([root]) {
+                page_directory.release_page(page).await?;
+            }
+            Err(e)
+        }
     }
+}
+
+/// Writes the nodes of a tree whose root is at page `root`, see `bulk_load`.
+async fn write_tree<K, V>(
+    writer: &mut NodeWriter<'_>,
+    root: u64,
+    mut entries: SortedEntries<K, V>,
+    fill_factor: f64,
+) -> Result<()>
+where
+    K: Codec + Ord + Clone,
+    V: Codec + Clone,
+{
+    let limit = (fill_factor * NODE_SIZE as f64) as usize;
+
+    // The leaves, the page of a leaf is allocated once it's full, as the only
+    // leaf is the root.
+    let mut children: Vec<(K, u64)> = vec![];
+    let mut leaf = LeafNode::new();
+    let mut leaf_page = None;
+    let mut size = Node::Leaf(LeafNode::<K, V>::new()).encoded_size();
+    while let Some((key, val)) = entries.next().await? {
+        if leaf.keys.last() == Some(&key) {
+            return Err(Error::ConstraintViolation(
+                "bulk load: duplicate key".to_owned(),
+            ));
+        }
+
+        let entry_size = key.encoded_size() + val.encoded_size();
+        if !leaf.is_empty() && size + entry_size > limit {
+            let page = match leaf_page {
+                Some(page) => page,
+                None => writer.alloc().await?,
+            };
+            let next = writer.alloc().await?;
+            leaf.next = Some(next);
+            children.push((leaf.keys[0].clone(), page));
+            writer
+                .write(page, &Node::Leaf(std::mem::take(&mut leaf)))
+                .await?;
+
+            leaf.prev = Some(page);
+            leaf_page = Some(next);
+            size = Node::Leaf(LeafNode::<K, V>::new()).encoded_size();
+        }
+        size += entry_size;
+        leaf.keys.push(key);
+        leaf.vals.push(val);
+    }
+    match leaf_page {
+        Some(page) => {
+            children.push((leaf.keys[0].clone(), page));
+            writer.write(page, &Node::Leaf(leaf)).await?;
+        }
+        None => {
+            writer.write(root, &Node::Leaf(leaf)).await?;
+            return writer.finish().await;
+        }
+    }
+
+    // The internal nodes, level by level, until the level fits in the root.
+    loop {
+        let groups = group_children(children, limit);
+        if groups.len() == 1 {
+            let node = internal_node(groups.into_iter().next().unwrap()).1;
+            writer.write(root, &Node::<K, V>::Internal(node)).await?;
+            break;
+        }
+
+        children = Vec::with_capacity(groups.len());
+        for group in groups {
+            let (key, node) = internal_node(group);
+            let page = writer.alloc().await?;
+            writer.write(page, &Node::<K, V>::Internal(node)).await?;
+            children.push((key, page));
+        }
+    }
+    writer.finish().await
+}
+
+/// Groups the children (the first key of each subtree and its page) of a level
+/// into internal nodes filled up to `limit` bytes, every node has at least two
+/// children.
+fn group_children<K: Codec>(children: Vec<(K, u64)>, limit: usize) -> Vec<Vec<(K, u64)>> {
+    let empty_size = 1 + 2 + POINTER_SIZE;
+    let mut groups: Vec<Vec<(K, u64)>> = vec![];
+    let mut group = vec![];
+    let mut size = empty_size;
+    for (key, page) in children {
+        let entry_size = key.encoded_size() + POINTER_SIZE;
+        if group.len() >= 2 && size + entry_size > limit {
+            groups.push(std::mem::take(&mut group));
+            size = empty_size;
+        }
+        // The key of the first child is not stored in the node.
+        if !group.is_empty() {
+            size += entry_size;
+        }
+        group.push((key, page));
+    }
+
+    // The last node takes a child from the previous one, or is merged into it.
+    if group.len() == 1 {
+        if let Some(prev) = groups.last_mut() {
+            if prev.len() > 2 {
+                group.insert(0, prev.pop().unwrap());
+            } else {
+                prev.append(&mut group);
+            }
+        }
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+    groups
+}
+
+/// Returns the internal node of children, and the first key of its subtree.
+fn internal_node<K>(children: Vec<(K, u64)>) -> (K, InternalNode<K>) {
+    let (mut keys, pages): (Vec<_>, Vec<_>) = children.into_iter().unzip();
+    let first = keys.remove(0);
+    (first, InternalNode::new(keys, pages))
+}
+
+/// Writes the nodes of a bulk load to disk, consecutive pages are written at
+/// once. The page LSN of nodes is the last LSN at the start, so that the
+/// changes logged before to the pages are not redone by recovery.
+struct NodeWriter<'a> {
+    page_directory: &'a PageDirectory,
+    page_lsn: u64,
+    /// The pages allocated for nodes, but the root.
+    allocated: Vec<u64>,
+    /// The pages not written yet, which are consecutive.
+    run: Vec<(u64, Box<DataPage>)>,
+}
+
+impl<'a> NodeWriter<'a> {
+    async fn new(page_directory: &'a PageDirectory) -> NodeWriter<'a> {
+        let page_lsn = page_directory.manifest().await.last_lsn();
+        Self {
+            page_directory,
+            page_lsn,
+            allocated: vec![],
+            run: vec![],
+        }
+    }
+
+    async fn alloc(&mut self) -> Result<u64> {
+        let page = self
+            .page_directory
+            .alloc_page_with_part(INDEX_PARTITION)
+            .await?;
+        self.allocated.push(page);
+        Ok(page)
+    }
+
+    async fn write<K: Codec, V: Codec>(&mut self, page: u64, node: &Node<K, V>) -> Result<()> {
+        let mut data_page = DataPage::new();
+        data_page.fill(&[0u8; DEFAULT_PAGE_SIZE]);
+        node.encode(&mut data_page);
+        data_page.set_page_lsn(self.page_lsn);
+
+        if let Some((last, _)) = self.run.last() {
+            if !is_next_page(*last, page) || self.run.len() == MAX_WRITE_PAGES {
+                self.flush().await?;
+            }
+        }
+        self.run.push((page, data_page));
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if let Some((first, _)) = self.run.first() {
+            let bufs = self
+                .run
+                .iter()
+                .map(|(_, data_page)| data_page.vals())
+                .collect::<Vec<_>>();
+            self.page_directory.write_pages(*first, &bufs).await?;
+        }
+        self.run.clear();
+        Ok(())
+    }
+
+    /// Writes the remaining nodes and syncs the index partition.
+    async fn finish(&mut self) -> Result<()> {
+        self.flush().await?;
+        self.page_directory
+            .get_partition(INDEX_PARTITION)?
+            .sync()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{table::index::tests::open_page_directory, utils::fs};
+
+    fn index_pages(page_directory: &PageDirectory) -> usize {
+        page_directory
+            .get_partition(INDEX_PARTITION)
+            .unwrap()
+            .data_page_nums()
+            .len()
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let index = TableIndex::new("idx", vec![0]);
+            let root = {
+                let page_directory = open_page_directory(&dir).await;
+                // Sorted in 20 runs.
+                let mut builder = BTreeBuilder::<u64, u64>::new(page_directory.clone(), &index)
+                    .with_fill_factor(0.5)
+                    .with_sort_buffer_size(1000 * 16);
+                for i in 0..20000u64 {
+                    builder.add(i * 7919 % 20000, i).await.unwrap();
+                }
+                let btree = builder.finish().await.unwrap();
+                assert!(!fs::read_dir(dir.path())
+                    .await
+                    .unwrap()
+                    .iter()
+                    .any(|file| file.contains(".sort.")));
+
+                // Half filled leaves hold 125 entries, which are 160 leaves
+                // under 2 internal nodes and the root.
+                assert_eq!(index_pages(&page_directory), 160 + 2 + 1);
+
+                let txn = page_directory.begin_txn().await;
+                for i in 20000..20100u64 {
+                    btree.insert(txn, i, i).await.unwrap();
+                }
+                page_directory.commit_txn(txn).await.unwrap();
+                assert_eq!(btree.get(&20050).await.unwrap(), Some(20050));
+                btree.root()
+                // Crashes without a checkpoint.
+            };
+
+            let btree = BTreeBuilder::<u64, u64>::new(
+                open_page_directory(&dir).await,
+                &TableIndex {
+                    root: Some(root),
+                    ..index
+                },
+            )
+            .finish()
+            .await
+            .unwrap();
+            for i in (0..20000u64).step_by(97) {
+                assert_eq!(btree.get(&(i * 7919 % 20000)).await.unwrap(), Some(i));
+            }
+            let keys = btree.scan(19990..).await.unwrap();
+            assert_eq!(keys.len(), 110);
+            assert_eq!(btree.scan(..).await.unwrap().len(), 20100);
+        })
+    }
+
+    #[test]
+    fn test_bulk_load_small() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let index = TableIndex::new("idx", vec![0]);
+
+            // A single leaf is the root.
+            let mut builder = BTreeBuilder::<i32, i32>::new(page_directory.clone(), &index);
+            for i in (0..10).rev() {
+                builder.add(i, i * 10).await.unwrap();
+            }
+            let btree = builder.finish().await.unwrap();
+            assert_eq!(index_pages(&page_directory), 1);
+            assert_eq!(btree.scan(3..6).await.unwrap(), vec![30, 40, 50]);
+
+            // The pages of a tree with duplicate keys are released.
+            let mut builder = BTreeBuilder::<i32, i32>::new(page_directory.clone(), &index);
+            for i in 0..1000 {
+                builder.add(i, i).await.unwrap();
+            }
+            builder.add(999, 0).await.unwrap();
+            assert!(matches!(
+                builder.finish().await,
+                Err(Error::ConstraintViolation(_))
+            ));
+            assert_eq!(index_pages(&page_directory), 1);
+
+            let index = TableIndex {
+                root: Some(btree.root()),
+                ..index
+            };
+            let mut builder = BTreeBuilder::<i32, i32>::new(page_directory, &index);
+            builder.add(1, 1).await.unwrap();
+            assert!(matches!(builder.finish().await, Err(Error::Invalid(_))));
+        })
+    }
+
+    #[test]
+    fn test_group_children() {
+        let children = (0..10u64).map(|i| (i, i)).collect::<Vec<_>>();
+        // Nodes of 11 bytes plus 16 bytes of every child but the first.
+        let sizes = group_children(children.clone(), 11 + 16 * 3)
+            .iter()
+            .map(|group| group.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![4, 4, 2]);
 
-    pub fn finish(self) -> BTree<K, V> {
-        unimplemented!()
+        let sizes = group_children(children, 11 + 16 * 2)
+            .iter()
+            .map(|group| group.len())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![3, 3, 2, 2]);
     }
 }
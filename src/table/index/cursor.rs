@@ -0,0 +1,285 @@
+use std::ops::{Bound, RangeBounds};
+
+use super::{
+    btree::BTree,
+    node::{internal::InternalNode, leaf::LeafNode},
+    Codec,
+};
+use crate::error::Result;
+
+/// A cursor over the entries of [`BTree`] in a range of keys, which moves
+/// forward and backward from leaf to leaf.
+///
+/// The cursor sits between two entries (or before the first and after the
+/// last). It holds a decoded copy of the leaf it's in rather than the page, so
+/// the pages of tree can be evicted (or changed by other writers) while the
+/// cursor is alive, and the next leaf is read only when the cursor moves past
+/// the end of current one.
+///
+/// The cursor doesn't see the changes to the copy of current leaf. As leaves
+/// split, merge and are freed in the meantime, the cursor doesn't follow the
+/// sibling pointers: it keeps the separator keys which bound the copy, and
+/// finds the next (or previous) leaf by descending from root to the upper (or
+/// lower) bound, then the entries on the other side of the bound are skipped.
+///
+/// ```ignore
+/// let mut cursor = tree.cursor(10..20).await?;
+/// cursor.seek_to_last().await?;
+/// while let Some((key, val)) = cursor.prev().await? {
+///     ...
+/// }
+/// ```
+pub struct Cursor<'a, K, V> {
+    btree: &'a BTree<K, V>,
+
+    start: Bound<K>,
+    end: Bound<K>,
+
+    /// The leaf which the cursor is in, and the separator keys which bound
+    /// it, `None` at the ends of tree.
+    leaf: LeafNode<K, V>,
+    lower: Option<K>,
+    upper: Option<K>,
+    /// The index of the entry after the cursor.
+    idx: usize,
+}
+
+impl<'a, K, V> Cursor<'a, K, V>
+where
+    K: Codec + Ord + Clone,
+    V: Codec + Clone,
+{
+    /// Returns a cursor before the first entry of range.
+    pub(super) async fn new<R>(btree: &'a BTree<K, V>, range: R) -> Result<Cursor<'a, K, V>>
+    where
+        R: RangeBounds<K>,
+    {
+        let mut cursor = Self {
+            btree,
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+            leaf: LeafNode::new(),
+            lower: None,
+            upper: None,
+            idx: 0,
+        };
+        cursor.seek_to_first().await?;
+        Ok(cursor)
+    }
+
+    /// Moves the cursor before the first entry of range.
+    pub async fn seek_to_first(&mut self) -> Result<()> {
+        match self.start.clone() {
+            Bound::Included(key) => self.seek_to(&key, false).await,
+            Bound::Excluded(key) => self.seek_to(&key, true).await,
+            Bound::Unbounded => self.descend(|_| 0, |_| 0).await,
+        }
+    }
+
+    /// Moves the cursor after the last entry of range.
+    pub async fn seek_to_last(&mut self) -> Result<()> {
+        match self.end.clone() {
+            Bound::Included(key) => self.seek_to(&key, true).await,
+            Bound::Excluded(key) => self.seek_to(&key, false).await,
+            Bound::Unbounded => {
+                self.descend(|internal| internal.len(), |leaf| leaf.len())
+                    .await
+            }
+        }
+    }
+
+    /// Moves the cursor before the first entry whose key is at least `key`,
+    /// the cursor stays in the range if `key` is out of it.
+    pub async fn seek(&mut self, key: &K) -> Result<()> {
+        if self.before_start(key) {
+            self.seek_to_first().await
+        } else if self.past_end(key) {
+            self.seek_to_last().await
+        } else {
+            self.seek_to(key, false).await
+        }
+    }
+
+    /// Returns the entry after the cursor and moves the cursor past it, `None`
+    /// at the end of range.
+    pub async fn next(&mut self) -> Result<Option<(K, V)>> {
+        while self.idx == self.leaf.len() {
+            // The upper bound of the next leaf is greater.
+            let upper = match self.upper.take() {
+                Some(upper) => upper,
+                None => return Ok(None),
+            };
+            self.descend(
+                |internal| internal.search_internal(&upper),
+                |leaf| leaf.keys.partition_point(|k| k < &upper),
+            )
+            .await?;
+        }
+
+        if self.past_end(&self.leaf.keys[self.idx]) {
+            return Ok(None);
+        }
+        self.idx += 1;
+        Ok(Some((
+            self.leaf.keys[self.idx - 1].clone(),
+            self.leaf.vals[self.idx - 1].clone(),
+        )))
+    }
+
+    /// Returns the entry before the cursor and moves the cursor before it,
+    /// `None` at the start of range.
+    pub async fn prev(&mut self) -> Result<Option<(K, V)>> {
+        while self.idx == 0 {
+            // The lower bound of the previous leaf is less.
+            let lower = match self.lower.take() {
+                Some(lower) => lower,
+                None => return Ok(None),
+            };
+            let before = |k: &K| k < &lower;
+            self.descend(
+                |internal| internal.keys.partition_point(before),
+                |leaf| leaf.keys.partition_point(before),
+            )
+            .await?;
+        }
+
+        if self.before_start(&self.leaf.keys[self.idx - 1]) {
+            return Ok(None);
+        }
+        self.idx -= 1;
+        Ok(Some((
+            self.leaf.keys[self.idx].clone(),
+            self.leaf.vals[self.idx].clone(),
+        )))
+    }
+
+    fn before_start(&self, key: &K) -> bool {
+        match &self.start {
+            Bound::Included(start) => key < start,
+            Bound::Excluded(start) => key <= start,
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn past_end(&self, key: &K) -> bool {
+        match &self.end {
+            Bound::Included(end) => key > end,
+            Bound::Excluded(end) => key >= end,
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// Moves the cursor before the first entry whose key is greater than (or
+    /// equal to, if not `after`) `key`.
+    async fn seek_to(&mut self, key: &K, after: bool) -> Result<()> {
+        self.descend(
+            |internal| internal.search_internal(key),
+            |leaf| {
+                leaf.keys
+                    .partition_point(|k| if after { k <= key } else { k < key })
+            },
+        )
+        .await
+    }
+
+    /// Moves the cursor into the leaf found by following the child chosen by
+    /// `f` from root, before the entry chosen by `idx`.
+    async fn descend<F, I>(&mut self, f: F, idx: I) -> Result<()>
+    where
+        F: Fn(&InternalNode<K>) -> usize,
+        I: FnOnce(&LeafNode<K, V>) -> usize,
+    {
+        let (leaf, lower, upper) = self.btree.descend_with_bounds(f).await?;
+        self.idx = idx(&leaf);
+        (self.leaf, self.lower, self.upper) = (leaf, lower, upper);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::table::index::tests::open_page_directory;
+
+    /// Returns a tree of the even keys in 0..4000, which spans more leaves
+    /// than the 8 cached pages.
+    async fn tree(dir: &TempDir) -> BTree<u64, u32> {
+        let btree = BTree::create(open_page_directory(dir).await).await.unwrap();
+        for i in 0..4000u64 {
+            btree.insert(1, i * 7919 % 4000, 0).await.unwrap();
+        }
+        for i in (1..4000).step_by(2) {
+            btree.remove(1, &i).await.unwrap();
+        }
+        btree
+    }
+
+    async fn collect(cursor: &mut Cursor<'_, u64, u32>, forward: bool) -> Vec<u64> {
+        let mut keys = vec![];
+        loop {
+            let entry = if forward {
+                cursor.next().await.unwrap()
+            } else {
+                cursor.prev().await.unwrap()
+            };
+            match entry {
+                Some((key, _)) => keys.push(key),
+                None => return keys,
+            }
+        }
+    }
+
+    #[test]
+    fn test_cursor() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let btree = tree(&dir).await;
+
+            let mut cursor = btree.cursor(..).await.unwrap();
+            let all = collect(&mut cursor, true).await;
+            assert_eq!(all, (0..4000).step_by(2).collect::<Vec<_>>());
+            // Back from the end.
+            let mut rev = collect(&mut cursor, false).await;
+            rev.reverse();
+            assert_eq!(rev, all);
+
+            let mut cursor = btree.cursor(1001..=3000).await.unwrap();
+            let keys = collect(&mut cursor, true).await;
+            assert_eq!(keys, (1002..=3000).step_by(2).collect::<Vec<_>>());
+            cursor.seek_to_last().await.unwrap();
+            assert_eq!(cursor.prev().await.unwrap().unwrap().0, 3000);
+            assert_eq!(cursor.next().await.unwrap().unwrap().0, 3000);
+            assert!(cursor.next().await.unwrap().is_none());
+
+            let mut cursor = btree
+                .cursor((Bound::Excluded(1000), Bound::Excluded(3000)))
+                .await
+                .unwrap();
+            assert!(cursor.prev().await.unwrap().is_none());
+            assert_eq!(cursor.next().await.unwrap().unwrap().0, 1002);
+            cursor.seek_to_last().await.unwrap();
+            assert_eq!(cursor.prev().await.unwrap().unwrap().0, 2998);
+
+            // Seeking goes to the first key at least the given key, but not
+            // before the range.
+            cursor.seek(&2001).await.unwrap();
+            assert_eq!(cursor.next().await.unwrap().unwrap().0, 2002);
+            cursor.seek(&2000).await.unwrap();
+            assert_eq!(cursor.next().await.unwrap().unwrap().0, 2000);
+            assert_eq!(cursor.prev().await.unwrap().unwrap().0, 2000);
+            assert_eq!(cursor.prev().await.unwrap().unwrap().0, 1998);
+            cursor.seek(&0).await.unwrap();
+            assert_eq!(cursor.next().await.unwrap().unwrap().0, 1002);
+            cursor.seek(&5000).await.unwrap();
+            assert!(cursor.next().await.unwrap().is_none());
+            assert_eq!(cursor.prev().await.unwrap().unwrap().0, 2998);
+
+            let mut cursor = btree.cursor(20000..).await.unwrap();
+            assert!(cursor.next().await.unwrap().is_none());
+            assert!(collect(&mut cursor, false).await.is_empty());
+        })
+    }
+}
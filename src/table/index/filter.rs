@@ -0,0 +1,306 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use bytes::BufMut;
+
+use super::{
+    key_hash,
+    node::{NODE_SIZE, POINTER_SIZE},
+    Codec, EntryLog,
+};
+use crate::{
+    error::Result,
+    table::{
+        cache::PageGuard,
+        manifest::TxnId,
+        page::page_directory::{PageDirectory, INDEX_PARTITION},
+    },
+    utils::bloom::Bloom,
+};
+
+/// The size (in bytes) of the bloom filter of a data page, i.e. the filter
+/// bits followed by the number of hash functions (see `Bloom`).
+pub const FILTER_SIZE: usize = 128;
+
+/// The number of filters in a filter page.
+pub const FILTERS_PER_PAGE: usize = NODE_SIZE / FILTER_SIZE;
+
+/// The max number of filter pages, whose pointers fit in the root page. The
+/// data pages beyond `MAX_FILTER_PAGES * FILTERS_PER_PAGE` are not filtered.
+pub const MAX_FILTER_PAGES: usize = NODE_SIZE / POINTER_SIZE;
+
+/// The bloom filters of the data pages of a table, which tell the data pages
+/// which definitely don't contain a key, so that an equality lookup without
+/// an index (or a join probe) can skip them.
+///
+/// The pages of filters (see `alloc_root`) are laid out as follows:
+///  - The root page (which never moves) is the pointers to filter pages, the
+///    filter of data page n is in the filter page `n / FILTERS_PER_PAGE`, which
+///    is allocated when a key is first added to one of its data pages.
+///  - A filter page is `FILTERS_PER_PAGE` filters of `FILTER_SIZE` bytes.
+///
+/// The filter pages are stored in `INDEX_PARTITION` beside the other index
+/// pages rather than alongside the header pages of the table: a header page is
+/// filled with the entries of the data pages it covers (see
+/// `header_entry_count`) and the data pages follow it at fixed offsets (see
+/// `virtual_data_page_offset`), so there's no room for filters there. The
+/// filter pages are latched, cached and logged as index pages instead, so that
+/// the adds are redone by recovery like the other index changes.
+///
+/// A zeroed filter has no hash functions, so a data page without filter may
+/// contain any key. Keys are only added to filters: the keys of removed (or
+/// updated) records stay in the filter of their page, which makes the filter
+/// less selective, but never wrong.
+///
+/// So keys are never removed from filters either when a transaction rolls
+/// back: an add is a nested top action without any change of entries (see
+/// `EntryLog`), which is redone but never undone, as other transactions may
+/// have added keys to the same filter since.
+///
+/// The root page is latched before a filter page, and released once the
+/// filter page is latched (or once the add ends if it allocated the filter
+/// page).
+///
+/// ```ignore
+/// let filters = PageFilters::create(page_directory, keys_per_page).await?;
+/// filters.add(txn, 3, &key).await?;
+/// assert!(filters.may_contain(3, &key).await?);
+/// ```
+pub struct PageFilters<K> {
+    page_directory: Arc<PageDirectory>,
+
+    /// The page of root.
+    root: u64,
+
+    /// The number of hash functions of a new filter.
+    k: u8,
+
+    phantom: PhantomData<K>,
+}
+
+/// private methods.
+impl<K: Codec> PageFilters<K> {
+    /// Returns the pointers to filter pages in root.
+    fn decode_root(page: &PageGuard<'_>) -> Result<Vec<Option<u64>>> {
+        let mut buf = page.as_data_page().data_area();
+        (0..MAX_FILTER_PAGES)
+            .map(|_| u64::decode(&mut buf).map(|page| (page != 0).then_some(page)))
+            .collect()
+    }
+
+    /// Returns the latched filter page of data page, `None` if the data page
+    /// isn't filtered. The filter page is allocated if `alloc_txn` is given,
+    /// then the changed root is returned latched too.
+    async fn filter_page(
+        &self,
+        page_num: usize,
+        alloc_txn: Option<TxnId>,
+    ) -> Result<Option<(PageGuard<'_>, Option<PageGuard<'_>>)>> {
+        let idx = page_num / FILTERS_PER_PAGE;
+        if idx >= MAX_FILTER_PAGES {
+            return Ok(None);
+        }
+
+        let mut root = self.page_directory.fetch_page(self.root).await?;
+        match (Self::decode_root(&root)?[idx], alloc_txn) {
+            (Some(page), _) => Ok(Some((self.page_directory.fetch_page(page).await?, None))),
+            (None, None) => Ok(None),
+            (None, Some(txn)) => {
+                let page = self
+                    .page_directory
+                    .alloc_page_with_part(INDEX_PARTITION)
+                    .await?;
+                let before = root.as_data_page().vals().to_vec();
+                let mut buf = &mut root.as_data_page_mut().data_area_mut()[idx * POINTER_SIZE..];
+                buf.put_u64(page);
+                self.page_directory
+                    .log_page_update(txn, &mut root, &before)
+                    .await?;
+                let page = self.page_directory.fetch_page(page).await?;
+                Ok(Some((page, Some(root))))
+            }
+        }
+    }
+
+    /// Returns the filter of data page in the filter page.
+    fn filter<'a>(page: &'a PageGuard<'_>, page_num: usize) -> &'a [u8] {
+        let offset = page_num % FILTERS_PER_PAGE * FILTER_SIZE;
+        &page.as_data_page().data_area()[offset..offset + FILTER_SIZE]
+    }
+}
+
+impl<K: Codec> PageFilters<K> {
+    /// Creates empty filters, the root is a newly allocated page. The filters
+    /// are sized for `keys_per_page` keys in a data page.
+    pub async fn create(page_directory: Arc<PageDirectory>, keys_per_page: usize) -> Result<Self> {
+        let root = super::alloc_root(&page_directory).await?;
+        Self::open(page_directory, root, keys_per_page).await
+    }
+
+    /// Opens the filters whose root is at page `root`.
+    pub async fn open(
+        page_directory: Arc<PageDirectory>,
+        root: u64,
+        keys_per_page: usize,
+    ) -> Result<Self> {
+        super::open_root(&page_directory, root).await?;
+
+        let bits_per_key = (FILTER_SIZE - 1) * 8 / keys_per_page.max(1);
+        Ok(Self {
+            page_directory,
+            root,
+            k: Bloom::num_hashes(bits_per_key),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Returns the page of root.
+    pub fn root(&self) -> u64 {
+        self.root
+    }
+
+    /// Adds key to the filter of data page `page_num`, the change is logged
+    /// by transaction, and it's kept if the transaction rolls back.
+    pub async fn add(&self, txn: TxnId, page_num: usize, key: &K) -> Result<()> {
+        let log = EntryLog::begin(&self.page_directory, txn).await;
+        let (mut page, _root) = match self.filter_page(page_num, Some(txn)).await? {
+            Some(pages) => pages,
+            None => return Ok(()),
+        };
+
+        let before = page.as_data_page().vals().to_vec();
+        let offset = page_num % FILTERS_PER_PAGE * FILTER_SIZE;
+        let filter = &mut page.as_data_page_mut().data_area_mut()[offset..offset + FILTER_SIZE];
+        if filter[FILTER_SIZE - 1] == 0 {
+            filter[FILTER_SIZE - 1] = self.k;
+        }
+        Bloom::add_key_hash(filter, key_hash(key));
+        self.page_directory
+            .log_page_update(txn, &mut page, &before)
+            .await?;
+        log.end(self.root).await
+    }
+
+    /// Returns whether data page `page_num` may contain key, false if it
+    /// definitely doesn't.
+    pub async fn may_contain(&self, page_num: usize, key: &K) -> Result<bool> {
+        Ok(!self.filter_pages(&[page_num], key).await?.is_empty())
+    }
+
+    /// Returns the data pages of `page_nums` which may contain key, in order.
+    /// A filter page is read once for a run of its data pages.
+    pub async fn filter_pages(&self, page_nums: &[usize], key: &K) -> Result<Vec<usize>> {
+        let hash = key_hash(key);
+        let mut ans = vec![];
+        let mut filter_page: Option<(usize, Option<PageGuard<'_>>)> = None;
+        for &page_num in page_nums {
+            let idx = page_num / FILTERS_PER_PAGE;
+            if filter_page.as_ref().map(|(i, _)| *i) != Some(idx) {
+                // Release the last filter page before latching the next one.
+                drop(filter_page.take());
+                let page = self.filter_page(page_num, None).await?;
+                filter_page = Some((idx, page.map(|(page, _)| page)));
+            }
+            let may_contain = match &filter_page {
+                Some((_, Some(page))) => Bloom::new(Self::filter(page, page_num)).may_contain(hash),
+                _ => true,
+            };
+            if may_contain {
+                ans.push(page_num);
+            }
+        }
+        Ok(ans)
+    }
+
+    /// Releases all pages of the filters.
+    pub async fn destroy(self) -> Result<()> {
+        let pages = Self::decode_root(&self.page_directory.fetch_page(self.root).await?)?;
+        for page in pages.into_iter().flatten().chain(Some(self.root)) {
+            self.page_directory.release_page(page).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::table::{index::tests::open_page_directory, recover};
+
+    #[test]
+    fn test_page_filters() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let filters = PageFilters::<u64>::create(page_directory.clone(), 20)
+                .await
+                .unwrap();
+            // Pages without filter may contain any key.
+            assert!(filters.may_contain(0, &1).await.unwrap());
+
+            // 20 keys on each of 100 pages, over 4 filter pages.
+            let pages = (0..100).collect::<Vec<_>>();
+            for &page in &pages {
+                for i in 0..20 {
+                    filters.add(1, page, &(page as u64 * 20 + i)).await.unwrap();
+                }
+            }
+            for &page in &pages {
+                assert!(filters
+                    .may_contain(page, &(page as u64 * 20 + 7))
+                    .await
+                    .unwrap());
+            }
+            let mut skipped = 0;
+            for key in 0..2000 {
+                let candidates = filters.filter_pages(&pages, &key).await.unwrap();
+                assert!(candidates.contains(&(key as usize / 20)));
+                skipped += pages.len() - candidates.len();
+            }
+            // A filter of 50 bits per key has a tiny false positive rate.
+            assert!(skipped > 2000 * 99 * 99 / 100, "{}", skipped);
+
+            let far = MAX_FILTER_PAGES * FILTERS_PER_PAGE;
+            filters.add(1, far, &1).await.unwrap();
+            assert!(filters.may_contain(far, &2).await.unwrap());
+
+            let root = filters.root();
+            let filters = PageFilters::<u64>::open(page_directory.clone(), root, 20)
+                .await
+                .unwrap();
+            assert!(!filters.may_contain(1, &0).await.unwrap());
+            filters.destroy().await.unwrap();
+            assert!(!page_directory.is_page_allocated(root).await);
+        })
+    }
+
+    #[test]
+    fn test_rollback() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let filters = PageFilters::<u64>::create(page_directory.clone(), 20)
+                .await
+                .unwrap();
+            let txn = page_directory.begin_txn().await;
+            filters.add(txn, 0, &1).await.unwrap();
+            page_directory.commit_txn(txn).await.unwrap();
+
+            // Another transaction adds a key to the same filter, then a key to
+            // a new filter page, and rolls back.
+            let txn = page_directory.begin_txn().await;
+            filters.add(txn, 0, &2).await.unwrap();
+            filters.add(txn, FILTERS_PER_PAGE, &3).await.unwrap();
+            recover::rollback(&page_directory, txn).await.unwrap();
+
+            // The keys are kept, so a key added by another transaction in the
+            // meantime is never lost.
+            assert!(filters.may_contain(0, &1).await.unwrap());
+            assert!(filters.may_contain(0, &2).await.unwrap());
+            assert!(filters.may_contain(FILTERS_PER_PAGE, &3).await.unwrap());
+            assert!(!filters.may_contain(FILTERS_PER_PAGE, &1).await.unwrap());
+        })
+    }
+}
@@ -0,0 +1,805 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use bytes::{Buf, BufMut};
+
+use super::{
+    key_hash,
+    node::{check_entry_size, NODE_SIZE, POINTER_SIZE},
+    Codec, EntryLog,
+};
+use crate::{
+    error::{Error, Result},
+    table::{
+        cache::PageGuard,
+        manifest::{IndexChange, TxnId},
+        page::page_directory::{PageDirectory, INDEX_PARTITION},
+    },
+};
+
+/// The number of low bits of a slot within a page of directory, i.e. a page
+/// of directory holds `2^DIR_PAGE_BITS` bucket pointers.
+const DIR_PAGE_BITS: u8 = 8;
+
+/// The max global depth of directory, so that the pointers to the
+/// `2^(MAX_DEPTH - DIR_PAGE_BITS)` pages of directory fit in the page of root.
+/// A full bucket of this depth is extended by overflow pages instead of split.
+pub const MAX_DEPTH: u8 = 2 * DIR_PAGE_BITS;
+
+/// The size (in bytes) of the header of a bucket page: 1-byte local depth,
+/// 8-byte overflow page and 2-byte number of entries.
+const BUCKET_HEADER_SIZE: usize = 1 + POINTER_SIZE + 2;
+
+/// A persistent extendible hash index, which maps a key to any number of
+/// values and only supports equality lookups.
+///
+/// The pages of index (see `alloc_root`) are laid out as follows:
+///  - The directory maps `2^d` slots to the pages of buckets, where d is the
+///    global depth, a key is in the bucket at the low d bits of its hash (see
+///    `key_hash`). The page of root (which never moves) is the 1-byte d,
+///    followed by the `2^d` bucket pointers if d is at most `DIR_PAGE_BITS`,
+///    otherwise by the pointers to `2^(d - DIR_PAGE_BITS)` pages of directory,
+///    the slot s is the `s % 2^DIR_PAGE_BITS`-th pointer of the `s /
+///    2^DIR_PAGE_BITS`-th page.
+///  - A bucket page is the 1-byte local depth l (the buckets at the slots with
+///    the same low l bits are the same), the 8-byte overflow page, 2-byte
+///    number of entries, followed by the entries (key then value).
+///
+/// A full bucket splits into two buckets of depth l + 1, and the directory
+/// doubles if l is the global depth. A full bucket of depth `MAX_DEPTH`, or
+/// whose keys all have the same hash as the key inserted (a split would leave
+/// them in the same bucket), is extended by a chain of overflow pages
+/// instead. Buckets are not merged.
+///
+/// Every insert or removal is a nested top action (see `EntryLog`), so a
+/// rolled back transaction removes the entries it inserted (and inserts the
+/// entries it removed) wherever they are now, and the splits of buckets are
+/// kept.
+///
+/// The page of root is latched before the pages of directory and buckets, and
+/// an insert keeps it latched as the bucket may split (until the insert ends
+/// if the directory changed), lookups and removes release it once the first
+/// page of bucket is latched, which guards the rest of its chain.
+///
+/// ```ignore
+/// let index = HashIndex::create(page_directory).await?;
+/// index.insert(txn, 1, 1).await?;
+/// index.insert(txn, 1, 2).await?;
+/// assert_eq!(index.get(&1).await?, vec![1, 2]);
+/// ```
+pub struct HashIndex<K, V> {
+    page_directory: Arc<PageDirectory>,
+
+    /// The page of directory.
+    root: u64,
+
+    phantom: PhantomData<(K, V)>,
+}
+
+/// The whole directory of buckets, a zeroed page of root is an empty
+/// directory of depth 0 whose bucket isn't allocated yet.
+struct Directory {
+    depth: u8,
+    /// The pages of directory, empty if the bucket pointers are in the page
+    /// of root.
+    pages: Vec<u64>,
+    buckets: Vec<Option<u64>>,
+}
+
+/// Decodes the global depth at the start of the page of root.
+fn decode_depth(buf: &mut &[u8]) -> Result<u8> {
+    let depth = buf.get_u8();
+    if depth > MAX_DEPTH {
+        return Err(Error::Corrupted(format!("hash index: depth {}", depth)));
+    }
+    Ok(depth)
+}
+
+/// Decodes `n` page pointers, `None` if the page isn't allocated yet.
+fn decode_pointers(buf: &mut &[u8], n: usize) -> Result<Vec<Option<u64>>> {
+    (0..n)
+        .map(|_| u64::decode(buf).map(|page| (page != 0).then_some(page)))
+        .collect()
+}
+
+fn encode_pointers(buf: &mut Vec<u8>, pointers: impl Iterator<Item = Option<u64>>) {
+    for pointer in pointers {
+        buf.put_u64(pointer.unwrap_or(0));
+    }
+}
+
+/// A page of bucket.
+struct Bucket<K, V> {
+    depth: u8,
+    /// The next page of bucket.
+    next: Option<u64>,
+    keys: Vec<K>,
+    vals: Vec<V>,
+}
+
+impl<K: Codec, V: Codec> Bucket<K, V> {
+    fn new(depth: u8) -> Self {
+        Self {
+            depth,
+            next: None,
+            keys: vec![],
+            vals: vec![],
+        }
+    }
+
+    fn decode(page: &PageGuard<'_>) -> Result<Self> {
+        let mut buf = page.as_data_page().data_area();
+        let depth = buf.get_u8();
+        let next = u64::decode(&mut buf)?;
+        let len = u16::decode(&mut buf)? as usize;
+        let mut bucket = Self::new(depth);
+        bucket.next = (next != 0).then_some(next);
+        for _ in 0..len {
+            bucket.keys.push(K::decode(&mut buf)?);
+            bucket.vals.push(V::decode(&mut buf)?);
+        }
+        Ok(bucket)
+    }
+
+    fn encode(&self, page: &mut PageGuard<'_>) {
+        assert!(self.encoded_size() <= NODE_SIZE);
+        let mut buf = page.as_data_page_mut().data_area_mut();
+        buf.put_u8(self.depth);
+        buf.put_u64(self.next.unwrap_or(0));
+        buf.put_u16(self.keys.len() as u16);
+        for (key, val) in self.keys.iter().zip(&self.vals) {
+            key.encode(&mut buf);
+            val.encode(&mut buf);
+        }
+    }
+
+    fn encoded_size(&self) -> usize {
+        BUCKET_HEADER_SIZE
+            + self
+                .keys
+                .iter()
+                .zip(&self.vals)
+                .map(|(key, val)| key.encoded_size() + val.encoded_size())
+                .sum::<usize>()
+    }
+
+    /// Returns whether the entry fits in the page.
+    fn has_room(&self, key: &K, val: &V) -> bool {
+        self.encoded_size() + key.encoded_size() + val.encoded_size() <= NODE_SIZE
+    }
+}
+
+/// private methods.
+impl<K, V> HashIndex<K, V>
+where
+    K: Codec + Eq + Clone,
+    V: Codec + Eq + Clone,
+{
+    /// Writes the page, the change is logged by transaction.
+    async fn write_page<F>(&self, txn: TxnId, page: &mut PageGuard<'_>, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut PageGuard<'_>),
+    {
+        let before = page.as_data_page().vals().to_vec();
+        f(page);
+        self.page_directory
+            .log_page_update(txn, page, &before)
+            .await
+    }
+
+    async fn alloc_page(&self) -> Result<PageGuard<'_>> {
+        let page = self
+            .page_directory
+            .alloc_page_with_part(INDEX_PARTITION)
+            .await?;
+        self.page_directory.fetch_page(page).await
+    }
+
+    /// Returns the slot of hash in the directory whose root page is latched,
+    /// and the page of its bucket. Only the page of directory holding the
+    /// slot is read.
+    async fn slot_of(&self, dir_page: &PageGuard<'_>, hash: u32) -> Result<(usize, Option<u64>)> {
+        let mut buf = dir_page.as_data_page().data_area();
+        let depth = decode_depth(&mut buf)?;
+        let slot = hash as usize & ((1 << depth) - 1);
+        if depth <= DIR_PAGE_BITS {
+            buf.advance(slot * POINTER_SIZE);
+            return Ok((slot, decode_pointers(&mut buf, 1)?[0]));
+        }
+
+        buf.advance((slot >> DIR_PAGE_BITS) * POINTER_SIZE);
+        let page = u64::decode(&mut buf)?;
+        let page = self.page_directory.fetch_page(page).await?;
+        let mut buf = page.as_data_page().data_area();
+        buf.advance((slot & ((1 << DIR_PAGE_BITS) - 1)) * POINTER_SIZE);
+        Ok((slot, decode_pointers(&mut buf, 1)?[0]))
+    }
+
+    /// Reads the whole directory whose root page is latched.
+    async fn load_dir(&self, dir_page: &PageGuard<'_>) -> Result<Directory> {
+        let mut buf = dir_page.as_data_page().data_area();
+        let depth = decode_depth(&mut buf)?;
+        if depth <= DIR_PAGE_BITS {
+            let buckets = decode_pointers(&mut buf, 1 << depth)?;
+            return Ok(Directory {
+                depth,
+                pages: vec![],
+                buckets,
+            });
+        }
+
+        let pages = (0..1 << (depth - DIR_PAGE_BITS))
+            .map(|_| u64::decode(&mut buf))
+            .collect::<Result<Vec<_>>>()?;
+        let mut buckets = Vec::with_capacity(1 << depth);
+        for &page in &pages {
+            let page = self.page_directory.fetch_page(page).await?;
+            let mut buf = page.as_data_page().data_area();
+            buckets.extend(decode_pointers(&mut buf, 1 << DIR_PAGE_BITS)?);
+        }
+        Ok(Directory {
+            depth,
+            pages,
+            buckets,
+        })
+    }
+
+    /// Writes the directory whose root page is latched, only the pages
+    /// changed are written.
+    async fn store_dir(
+        &self,
+        txn: TxnId,
+        dir_page: &mut PageGuard<'_>,
+        dir: &Directory,
+    ) -> Result<()> {
+        let mut root = vec![dir.depth];
+        if dir.pages.is_empty() {
+            encode_pointers(&mut root, dir.buckets.iter().copied());
+        } else {
+            encode_pointers(&mut root, dir.pages.iter().copied().map(Some));
+        }
+        self.write_if_changed(txn, dir_page, &root).await?;
+
+        for (&page, buckets) in dir.pages.iter().zip(dir.buckets.chunks(1 << DIR_PAGE_BITS)) {
+            let mut content = vec![];
+            encode_pointers(&mut content, buckets.iter().copied());
+            let mut page = self.page_directory.fetch_page(page).await?;
+            self.write_if_changed(txn, &mut page, &content).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes `content` at the start of the data area of page, unless it's
+    /// there already.
+    async fn write_if_changed(
+        &self,
+        txn: TxnId,
+        page: &mut PageGuard<'_>,
+        content: &[u8],
+    ) -> Result<()> {
+        if page.as_data_page().data_area().starts_with(content) {
+            return Ok(());
+        }
+        self.write_page(txn, page, |page| {
+            page.as_data_page_mut().data_area_mut()[..content.len()].copy_from_slice(content)
+        })
+        .await
+    }
+
+    /// Returns the latched first page of the bucket of key, `None` if the
+    /// index is empty. The directory is released.
+    async fn bucket_of(&self, key: &K) -> Result<Option<PageGuard<'_>>> {
+        let dir_page = self.page_directory.fetch_page(self.root).await?;
+        match self.slot_of(&dir_page, key_hash(key)).await?.1 {
+            Some(bucket) => Ok(Some(self.page_directory.fetch_page(bucket).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Calls `f` on every page of the bucket whose first page is latched,
+    /// until it returns true. The pages after the first are latched one at a
+    /// time.
+    async fn find<F>(&self, head: &PageGuard<'_>, mut f: F) -> Result<()>
+    where
+        F: FnMut(&Bucket<K, V>) -> bool,
+    {
+        let mut bucket = Bucket::decode(head)?;
+        while !f(&bucket) {
+            match bucket.next {
+                Some(next) => {
+                    bucket = Bucket::decode(&self.page_directory.fetch_page(next).await?)?
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls `f` on every page of the bucket like `find`, until it changes a
+    /// page and returns true, then the page is written. Returns whether a
+    /// page is changed.
+    async fn update<F>(&self, txn: TxnId, head: &mut PageGuard<'_>, mut f: F) -> Result<bool>
+    where
+        F: FnMut(&mut Bucket<K, V>) -> bool,
+    {
+        let mut bucket = Bucket::decode(head)?;
+        if f(&mut bucket) {
+            self.write_page(txn, head, |page| bucket.encode(page))
+                .await?;
+            return Ok(true);
+        }
+        let mut next = bucket.next;
+        while let Some(page_num) = next {
+            let mut page = self.page_directory.fetch_page(page_num).await?;
+            let mut bucket = Bucket::decode(&page)?;
+            if f(&mut bucket) {
+                self.write_page(txn, &mut page, |page| bucket.encode(page))
+                    .await?;
+                return Ok(true);
+            }
+            next = bucket.next;
+        }
+        Ok(false)
+    }
+
+    /// Splits the (single page) bucket at `slot` of the directory into two
+    /// buckets of a greater depth, doubles the directory if necessary. The
+    /// pages of a directory which outgrows the page of root are allocated.
+    async fn split(
+        &self,
+        txn: TxnId,
+        dir_page: &mut PageGuard<'_>,
+        slot: usize,
+        mut page: PageGuard<'_>,
+        bucket: Bucket<K, V>,
+    ) -> Result<()> {
+        debug_assert!(bucket.next.is_none() && bucket.depth < MAX_DEPTH);
+        let mut dir = self.load_dir(dir_page).await?;
+        let depth = bucket.depth;
+        if depth == dir.depth {
+            dir.buckets.extend_from_within(..);
+            dir.depth += 1;
+            while dir.depth > DIR_PAGE_BITS && dir.pages.len() < 1 << (dir.depth - DIR_PAGE_BITS) {
+                let page = self
+                    .page_directory
+                    .alloc_page_with_part(INDEX_PARTITION)
+                    .await?;
+                dir.pages.push(page);
+            }
+        }
+
+        let mut new_page = self.alloc_page().await?;
+        let new_page_num = new_page.virtual_page_num();
+        let (mut left, mut right) = (Bucket::new(depth + 1), Bucket::new(depth + 1));
+        for (key, val) in bucket.keys.into_iter().zip(bucket.vals) {
+            let half = if key_hash(&key) & (1 << depth) == 0 {
+                &mut left
+            } else {
+                &mut right
+            };
+            half.keys.push(key);
+            half.vals.push(val);
+        }
+
+        let low = slot & ((1 << depth) - 1);
+        for (i, bucket) in dir.buckets.iter_mut().enumerate() {
+            if i & ((1 << depth) - 1) == low && i & (1 << depth) != 0 {
+                *bucket = Some(new_page_num);
+            }
+        }
+
+        self.write_page(txn, &mut new_page, |page| right.encode(page))
+            .await?;
+        self.write_page(txn, &mut page, |page| left.encode(page))
+            .await?;
+        self.store_dir(txn, dir_page, &dir).await
+    }
+
+    /// Adds a (key, value) pair, if `unique` then only if the key doesn't
+    /// exist, otherwise returns a value of the key. The operation is logged by
+    /// `log`.
+    pub(super) async fn insert_entry(
+        &self,
+        mut log: EntryLog<'_>,
+        key: K,
+        val: V,
+        unique: bool,
+    ) -> Result<Option<V>> {
+        check_entry_size(&key, &val)?;
+        let txn = log.txn();
+        let hash = key_hash(&key);
+
+        let mut dir_page = self.page_directory.fetch_page(self.root).await?;
+        let mut dir_changed = false;
+        loop {
+            let (slot, bucket) = self.slot_of(&dir_page, hash).await?;
+            let mut page = match bucket {
+                Some(bucket) => self.page_directory.fetch_page(bucket).await?,
+                None => {
+                    // The first insert allocates the bucket of empty index.
+                    let page = self.alloc_page().await?;
+                    let mut dir = self.load_dir(&dir_page).await?;
+                    dir.buckets[slot] = Some(page.virtual_page_num());
+                    self.store_dir(txn, &mut dir_page, &dir).await?;
+                    dir_changed = true;
+                    page
+                }
+            };
+
+            if unique {
+                let mut existing = None;
+                self.find(&page, |bucket| {
+                    existing = bucket
+                        .keys
+                        .iter()
+                        .position(|k| k == &key)
+                        .map(|idx| bucket.vals[idx].clone());
+                    existing.is_some()
+                })
+                .await?;
+                if existing.is_some() {
+                    log.end(self.root).await?;
+                    return Ok(existing);
+                }
+            }
+
+            let bucket = Bucket::decode(&page)?;
+            if bucket.depth < MAX_DEPTH
+                && bucket.next.is_none()
+                && !bucket.has_room(&key, &val)
+                && bucket.keys.iter().any(|k| key_hash(k) != hash)
+            {
+                self.split(txn, &mut dir_page, slot, page, bucket).await?;
+                dir_changed = true;
+                continue;
+            }
+            // The changed directory is latched until the operation ends.
+            let _dir_page = dir_changed.then_some(dir_page);
+
+            log.change(IndexChange::HashInsert, &key, &val);
+            let inserted = self
+                .update(txn, &mut page, |bucket| {
+                    if !bucket.has_room(&key, &val) {
+                        return false;
+                    }
+                    bucket.keys.push(key.clone());
+                    bucket.vals.push(val.clone());
+                    true
+                })
+                .await?;
+            if inserted {
+                log.end(self.root).await?;
+                return Ok(None);
+            }
+
+            // Every page is full, append an overflow page to the bucket.
+            let mut overflow = Bucket::new(bucket.depth);
+            overflow.keys.push(key);
+            overflow.vals.push(val);
+            let mut new_page = self.alloc_page().await?;
+            let new_page_num = new_page.virtual_page_num();
+            self.write_page(txn, &mut new_page, |page| overflow.encode(page))
+                .await?;
+            self.update(txn, &mut page, |bucket| {
+                if bucket.next.is_some() {
+                    return false;
+                }
+                bucket.next = Some(new_page_num);
+                true
+            })
+            .await?;
+            log.end(self.root).await?;
+            return Ok(None);
+        }
+    }
+
+    /// Removes the (key, value) pair, returns whether the pair existed. The
+    /// operation is logged by `log`.
+    pub(super) async fn remove_entry(
+        &self,
+        mut log: EntryLog<'_>,
+        key: &K,
+        val: &V,
+    ) -> Result<bool> {
+        let mut page = match self.bucket_of(key).await? {
+            Some(page) => page,
+            None => {
+                log.end(self.root).await?;
+                return Ok(false);
+            }
+        };
+        let removed = self
+            .update(log.txn(), &mut page, |bucket| {
+                let idx = bucket
+                    .keys
+                    .iter()
+                    .zip(&bucket.vals)
+                    .position(|(k, v)| k == key && v == val);
+                match idx {
+                    Some(idx) => {
+                        bucket.keys.remove(idx);
+                        bucket.vals.remove(idx);
+                        true
+                    }
+                    None => false,
+                }
+            })
+            .await?;
+        if removed {
+            log.change(IndexChange::HashRemove, key, val);
+        }
+        log.end(self.root).await?;
+        Ok(removed)
+    }
+}
+
+impl<K, V> HashIndex<K, V>
+where
+    K: Codec + Eq + Clone,
+    V: Codec + Eq + Clone,
+{
+    /// Creates an empty index, the directory is a newly allocated page.
+    pub async fn create(page_directory: Arc<PageDirectory>) -> Result<Self> {
+        let root = super::alloc_root(&page_directory).await?;
+        Self::open(page_directory, root).await
+    }
+
+    /// Opens the index whose directory is at page `root`.
+    pub async fn open(page_directory: Arc<PageDirectory>, root: u64) -> Result<Self> {
+        super::open_root(&page_directory, root).await?;
+
+        Ok(Self {
+            page_directory,
+            root,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Returns the page of directory.
+    pub fn root(&self) -> u64 {
+        self.root
+    }
+
+    /// Returns the values associated with `key`, in insertion order within a
+    /// page of bucket.
+    pub async fn get(&self, key: &K) -> Result<Vec<V>> {
+        let page = match self.bucket_of(key).await? {
+            Some(page) => page,
+            None => return Ok(vec![]),
+        };
+        let mut vals = vec![];
+        self.find(&page, |bucket| {
+            for (k, v) in bucket.keys.iter().zip(&bucket.vals) {
+                if k == key {
+                    vals.push(v.clone());
+                }
+            }
+            false
+        })
+        .await?;
+        Ok(vals)
+    }
+
+    /// Returns whether the index contains `key`.
+    pub async fn contains_key(&self, key: &K) -> Result<bool> {
+        Ok(!self.get(key).await?.is_empty())
+    }
+
+    /// Adds a (key, value) pair, the change is logged by transaction. A key
+    /// may have several values. Returns `Error::TooLargeSize` if the entry is
+    /// larger than `MAX_ENTRY_SIZE`.
+    pub async fn insert(&self, txn: TxnId, key: K, val: V) -> Result<()> {
+        let log = EntryLog::begin(&self.page_directory, txn).await;
+        self.insert_entry(log, key, val, false).await?;
+        Ok(())
+    }
+
+    /// Adds a (key, value) pair like `insert` only if the key doesn't exist,
+    /// otherwise returns a value of the key. The check and the insert are
+    /// atomic.
+    pub async fn try_insert(&self, txn: TxnId, key: K, val: V) -> Result<Option<V>> {
+        let log = EntryLog::begin(&self.page_directory, txn).await;
+        self.insert_entry(log, key, val, true).await
+    }
+
+    /// Removes the (key, value) pair, the change is logged by transaction.
+    /// Returns whether the pair existed.
+    pub async fn remove(&self, txn: TxnId, key: &K, val: &V) -> Result<bool> {
+        let log = EntryLog::begin(&self.page_directory, txn).await;
+        self.remove_entry(log, key, val).await
+    }
+
+    /// Releases all pages of the index.
+    pub async fn destroy(self) -> Result<()> {
+        let dir = self
+            .load_dir(&self.page_directory.fetch_page(self.root).await?)
+            .await?;
+        let mut pages = dir.buckets.into_iter().flatten().collect::<Vec<_>>();
+        pages.sort_unstable();
+        pages.dedup();
+        let mut i = 0;
+        while i < pages.len() {
+            let bucket = Bucket::<K, V>::decode(&self.page_directory.fetch_page(pages[i]).await?)?;
+            pages.extend(bucket.next);
+            i += 1;
+        }
+        pages.extend(dir.pages);
+        pages.push(self.root);
+        for page in pages {
+            self.page_directory.release_page(page).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        datatypes::{array::scalar::ScalarImpl, record::RecordId},
+        table::{
+            index::{
+                key::IndexKey,
+                tests::{open_page_directory, WideKey},
+            },
+            recover,
+        },
+    };
+
+    async fn depth<K, V>(index: &HashIndex<K, V>) -> u8 {
+        let page = index.page_directory.fetch_page(index.root).await.unwrap();
+        page.as_data_page().data_area()[0]
+    }
+
+    #[test]
+    fn test_hash_index() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let index = HashIndex::<u64, u64>::create(page_directory.clone())
+                .await
+                .unwrap();
+            assert!(index.get(&1).await.unwrap().is_empty());
+            assert!(!index.remove(1, &1, &1).await.unwrap());
+
+            // About 250 entries fit in a bucket.
+            for i in 0..2000 {
+                index.insert(1, i, i * 2).await.unwrap();
+            }
+            assert!(depth(&index).await >= 3);
+            for i in 0..2000 {
+                assert_eq!(index.get(&i).await.unwrap(), vec![i * 2]);
+            }
+            assert!(index.get(&2000).await.unwrap().is_empty());
+
+            index.insert(1, 5, 11).await.unwrap();
+            assert_eq!(index.get(&5).await.unwrap(), vec![10, 11]);
+            assert_eq!(index.try_insert(1, 5, 12).await.unwrap(), Some(10));
+            assert_eq!(index.try_insert(1, 2000, 12).await.unwrap(), None);
+            assert_eq!(index.get(&2000).await.unwrap(), vec![12]);
+
+            assert!(index.remove(1, &5, &10).await.unwrap());
+            assert!(!index.remove(1, &5, &10).await.unwrap());
+            assert_eq!(index.get(&5).await.unwrap(), vec![11]);
+
+            let root = index.root();
+            let index = HashIndex::<u64, u64>::open(page_directory.clone(), root)
+                .await
+                .unwrap();
+            assert_eq!(index.get(&1999).await.unwrap(), vec![3998]);
+            index.destroy().await.unwrap();
+            assert!(!page_directory.is_page_allocated(root).await);
+        })
+    }
+
+    #[test]
+    fn test_overflow() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let index = HashIndex::<u64, u64>::create(open_page_directory(&dir).await)
+                .await
+                .unwrap();
+            // The values of a key don't fit in a bucket, which overflows
+            // instead of splitting, as the keys all have the same hash.
+            for i in 0..1000 {
+                index.insert(1, 7, i).await.unwrap();
+            }
+            index.insert(1, 8, 0).await.unwrap();
+            assert_eq!(depth(&index).await, 0);
+            assert_eq!(index.get(&7).await.unwrap(), (0..1000).collect::<Vec<_>>());
+
+            for i in (0..1000).step_by(2) {
+                assert!(index.remove(1, &7, &i).await.unwrap());
+            }
+            // The room left in the chain is reused.
+            index.insert(1, 7, 1000).await.unwrap();
+            let mut vals = index.get(&7).await.unwrap();
+            vals.sort_unstable();
+            let mut expected = (1..1000).step_by(2).collect::<Vec<_>>();
+            expected.push(1000);
+            assert_eq!(vals, expected);
+            assert_eq!(index.get(&8).await.unwrap(), vec![0]);
+        })
+    }
+
+    #[test]
+    fn test_rollback() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let index = HashIndex::<IndexKey, RecordId>::create(page_directory.clone())
+                .await
+                .unwrap();
+            let key = |i: usize| IndexKey::unique(&[Some(&ScalarImpl::Int64(i as i64 % 100))]);
+            let txn = page_directory.begin_txn().await;
+            for i in (0..1000).step_by(2) {
+                index.insert(txn, key(i), RecordId(0, i)).await.unwrap();
+            }
+            page_directory.commit_txn(txn).await.unwrap();
+
+            // Two transactions change the same buckets, which split in turn.
+            let (txn1, txn2) = (
+                page_directory.begin_txn().await,
+                page_directory.begin_txn().await,
+            );
+            for i in (1..4000).step_by(2) {
+                let txn = if i % 4 == 1 { txn1 } else { txn2 };
+                index.insert(txn, key(i), RecordId(0, i)).await.unwrap();
+            }
+            for i in (0..1000).step_by(4) {
+                assert!(index.remove(txn1, &key(i), &RecordId(0, i)).await.unwrap());
+            }
+            let split_depth = depth(&index).await;
+            recover::rollback(&page_directory, txn1).await.unwrap();
+            page_directory.commit_txn(txn2).await.unwrap();
+
+            // The entries of the other transaction and the splits are kept.
+            assert_eq!(depth(&index).await, split_depth);
+            for k in 0..100 {
+                let mut vals = index.get(&key(k)).await.unwrap();
+                vals.sort_unstable();
+                let expected = (k..4000)
+                    .step_by(100)
+                    .filter(|&i| (i % 2 == 0 && i < 1000) || i % 4 == 3)
+                    .map(|i| RecordId(0, i))
+                    .collect::<Vec<_>>();
+                assert_eq!(vals, expected);
+            }
+        })
+    }
+
+    #[test]
+    fn test_directory_pages() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open_page_directory(&dir).await;
+            let index = HashIndex::<WideKey, u32>::create(page_directory.clone())
+                .await
+                .unwrap();
+            // About 15 entries fit in a bucket, the directory of more than
+            // `2^DIR_PAGE_BITS` buckets spans several pages.
+            for i in 0..4000 {
+                index.insert(1, WideKey(i), i).await.unwrap();
+            }
+            assert!(depth(&index).await > DIR_PAGE_BITS);
+            for i in 0..4000 {
+                assert_eq!(index.get(&WideKey(i)).await.unwrap(), vec![i]);
+            }
+            assert!(index.get(&WideKey(4000)).await.unwrap().is_empty());
+
+            let root = index.root();
+            let pages = index
+                .load_dir(&page_directory.fetch_page(root).await.unwrap())
+                .await
+                .unwrap()
+                .pages;
+            assert!(pages.len() >= 2);
+            index.destroy().await.unwrap();
+            for page in pages {
+                assert!(!page_directory.is_page_allocated(page).await);
+            }
+        })
+    }
+}
@@ -0,0 +1,240 @@
+use std::ops::Bound;
+
+use bytes::{Buf, BufMut};
+
+use super::Codec;
+use crate::{
+    datatypes::{array::scalar::ScalarImpl, record::RecordId},
+    error::{Error, Result},
+};
+
+/// The marker of a `NULL` value, which is ordered before any other value.
+const NULL: u8 = 0;
+/// The marker of a non-null value.
+const NOT_NULL: u8 = 1;
+
+/// The key of an index entry, which is the values of indexed columns followed
+/// by the id of record, so that records with the same values have distinct
+/// keys.
+///
+/// The key is encoded so that comparing the bytes of two keys is the same as
+/// comparing their columns in lexicographic order (then the record ids):
+/// - Every value starts with a marker, `NULL` is ordered first.
+/// - Integers are big-endian with the sign bit flipped.
+/// - Strings are terminated by `0x00 0x00`, and every `0x00` byte within a
+///   string is escaped as `0x00 0xff`, so a string is ordered before the
+///   strings it's a prefix of.
+///
+/// So the keys whose first n columns are equal to some values are a range of
+/// keys, as are the keys whose next column is in some range (see
+/// [`key_range`]).
+///
+/// The keys of a unique index leave out the record id (see
+/// [`IndexKey::unique`]), so that records with the same values collide.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexKey(Vec<u8>);
+
+impl IndexKey {
+    /// Returns the key of record `rid` of which the indexed columns are
+    /// `values`.
+    pub fn new(values: &[Option<&ScalarImpl>], rid: RecordId) -> Self {
+        let mut key = Self::unique(values);
+        key.0.put_u64(rid.0);
+        key.0.put_u64(rid.1 as u64);
+        key
+    }
+
+    /// Returns the key of the indexed columns `values` without record id.
+    pub fn unique(values: &[Option<&ScalarImpl>]) -> Self {
+        let mut buf = vec![];
+        for value in values {
+            encode_value(&mut buf, *value);
+        }
+        Self(buf)
+    }
+}
+
+impl Codec for IndexKey {
+    fn encoded_size(&self) -> usize {
+        2 + self.0.len()
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u16(self.0.len() as u16);
+        buf.put_slice(&self.0);
+    }
+
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self> {
+        let len = u16::decode(buf)? as usize;
+        if buf.remaining() < len {
+            return Err(Error::Corrupted(format!(
+                "index entry: {} bytes left",
+                buf.remaining()
+            )));
+        }
+        let mut key = vec![0u8; len];
+        buf.copy_to_slice(&mut key);
+        Ok(Self(key))
+    }
+}
+
+/// Returns the range of keys of which the first columns are equal to
+/// `prefix`, and the next column is in `range`. An unbounded start of `range`
+/// excludes `NULL`, which isn't comparable.
+pub fn key_range(
+    prefix: &[ScalarImpl],
+    range: (Bound<&ScalarImpl>, Bound<&ScalarImpl>),
+) -> (Bound<IndexKey>, Bound<IndexKey>) {
+    let mut buf = vec![];
+    for value in prefix {
+        encode_value(&mut buf, Some(value));
+    }
+    let with = |value: &ScalarImpl| {
+        let mut buf = buf.clone();
+        encode_value(&mut buf, Some(value));
+        buf
+    };
+
+    let start = match range.0 {
+        Bound::Included(value) => Bound::Included(IndexKey(with(value))),
+        Bound::Excluded(value) => prefix_end(with(value)),
+        Bound::Unbounded => {
+            let mut start = buf.clone();
+            start.push(NOT_NULL);
+            Bound::Included(IndexKey(start))
+        }
+    };
+    let end = match range.1 {
+        Bound::Included(value) => prefix_end(with(value)),
+        Bound::Excluded(value) => Bound::Excluded(IndexKey(with(value))),
+        Bound::Unbounded => prefix_end(buf),
+    };
+    (start, end)
+}
+
+/// Returns the range of keys of which the first columns are equal to
+/// `prefix`, including `NULL` in the rest columns.
+pub fn prefix_range(prefix: &[ScalarImpl]) -> (Bound<IndexKey>, Bound<IndexKey>) {
+    let mut buf = vec![];
+    for value in prefix {
+        encode_value(&mut buf, Some(value));
+    }
+    (Bound::Included(IndexKey(buf.clone())), prefix_end(buf))
+}
+
+/// Returns the bound which excludes all keys starting with `prefix`, i.e.
+/// the smallest key greater than them.
+fn prefix_end(mut prefix: Vec<u8>) -> Bound<IndexKey> {
+    while let Some(last) = prefix.pop() {
+        if last < u8::MAX {
+            prefix.push(last + 1);
+            return Bound::Excluded(IndexKey(prefix));
+        }
+    }
+    Bound::Unbounded
+}
+
+fn encode_value(buf: &mut Vec<u8>, value: Option<&ScalarImpl>) {
+    let value = match value {
+        Some(value) => value,
+        None => {
+            buf.put_u8(NULL);
+            return;
+        }
+    };
+
+    buf.put_u8(NOT_NULL);
+    match value {
+        ScalarImpl::Int16(v) => buf.put_u16(*v as u16 ^ (1 << 15)),
+        ScalarImpl::Int32(v) => buf.put_u32(*v as u32 ^ (1 << 31)),
+        ScalarImpl::Int64(v) => buf.put_u64(*v as u64 ^ (1 << 63)),
+        ScalarImpl::Bool(v) => buf.put_u8(*v as u8),
+        ScalarImpl::String(v) => {
+            for &b in v.as_bytes() {
+                buf.put_u8(b);
+                if b == 0 {
+                    buf.put_u8(u8::MAX);
+                }
+            }
+            buf.put_slice(&[0, 0]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeBounds;
+
+    use super::*;
+
+    fn key(values: &[Option<ScalarImpl>]) -> IndexKey {
+        IndexKey::new(
+            &values.iter().map(Option::as_ref).collect::<Vec<_>>(),
+            RecordId(1, 2),
+        )
+    }
+
+    #[test]
+    fn test_key_order() {
+        let str = |s: &str| Some(ScalarImpl::String(s.to_owned()));
+        let ordered = [
+            vec![None, str("")],
+            vec![Some(ScalarImpl::Int64(i64::MIN)), None],
+            vec![Some(ScalarImpl::Int64(-1)), str("b")],
+            vec![Some(ScalarImpl::Int64(0)), str("")],
+            vec![Some(ScalarImpl::Int64(0)), str("a")],
+            vec![Some(ScalarImpl::Int64(0)), str("a\0")],
+            vec![Some(ScalarImpl::Int64(0)), str("a\0b")],
+            vec![Some(ScalarImpl::Int64(0)), str("ab")],
+            vec![Some(ScalarImpl::Int64(1)), str("")],
+            vec![Some(ScalarImpl::Int64(i64::MAX)), None],
+        ];
+        for pair in ordered.windows(2) {
+            assert!(key(&pair[0]) < key(&pair[1]), "{:?}", pair);
+        }
+
+        let key = IndexKey::new(&[Some(&ScalarImpl::Int16(-3))], RecordId(1, 2));
+        assert!(key < IndexKey::new(&[Some(&ScalarImpl::Int16(-3))], RecordId(1, 3)));
+        let mut buf = vec![];
+        key.encode(&mut buf);
+        assert_eq!(buf.len(), key.encoded_size());
+        assert_eq!(IndexKey::decode(&mut buf.as_slice()).unwrap(), key);
+        assert!(IndexKey::decode(&mut [0u8, 3, 1].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_key_range() {
+        let int = |v: i32| ScalarImpl::Int32(v);
+        let key = |a: i32, b: Option<i32>| {
+            IndexKey::new(&[Some(&int(a)), b.map(int).as_ref()], RecordId(0, 0))
+        };
+        let contains =
+            |range: &(Bound<IndexKey>, Bound<IndexKey>), key: &IndexKey| range.contains(key);
+
+        let range = key_range(
+            &[int(1)],
+            (Bound::Excluded(&int(2)), Bound::Included(&int(4))),
+        );
+        assert!(!contains(&range, &key(1, Some(2))));
+        assert!(contains(&range, &key(1, Some(3))));
+        assert!(contains(&range, &key(1, Some(4))));
+        assert!(!contains(&range, &key(1, Some(5))));
+        assert!(!contains(&range, &key(2, Some(3))));
+
+        let range = key_range(&[int(1)], (Bound::Unbounded, Bound::Excluded(&int(0))));
+        assert!(!contains(&range, &key(1, None)));
+        assert!(contains(&range, &key(1, Some(i32::MIN))));
+        assert!(!contains(&range, &key(1, Some(0))));
+
+        let range = key_range(&[], (Bound::Included(&int(1)), Bound::Unbounded));
+        assert!(!contains(&range, &key(0, Some(i32::MAX))));
+        assert!(contains(&range, &key(1, None)));
+        assert!(contains(&range, &key(i32::MAX, Some(i32::MAX))));
+
+        let range = prefix_range(&[int(1)]);
+        assert!(contains(&range, &key(1, None)));
+        assert!(contains(&range, &key(1, Some(i32::MAX))));
+        assert!(!contains(&range, &key(2, None)));
+        assert_eq!(prefix_end(vec![u8::MAX]), Bound::Unbounded);
+    }
+}
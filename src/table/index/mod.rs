@@ -1,3 +1,302 @@
 pub mod btree;
 pub mod btree_builder;
-pub mod node;
\ No newline at end of file
+pub mod cursor;
+pub mod filter;
+pub mod hash;
+pub mod key;
+pub mod node;
+pub mod sorter;
+
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut};
+
+use self::{btree::BTree, hash::HashIndex, key::IndexKey};
+use crate::{
+    datatypes::record::RecordId,
+    error::{Error, Result},
+    table::{
+        manifest::{IndexChange, Lsn, ManifestEntry, TxnId},
+        page::page_directory::{PageDirectory, INDEX_PARTITION},
+    },
+    utils::checksum::crc32,
+};
+
+/// Allocates the root page of a new index.
+///
+/// All indices (`BTree`, `HashIndex` and `PageFilters`) take pages of
+/// `INDEX_PARTITION`, and changes to their pages are logged like changes to
+/// data pages (see `PageDirectory::log_page_update`). The root page of an
+/// index never moves, so an index is found by the page of root alone (see
+/// `TableIndex::root`).
+pub async fn alloc_root(page_directory: &PageDirectory) -> Result<u64> {
+    page_directory.alloc_part_with_num(INDEX_PARTITION).await?;
+    page_directory.alloc_page_with_part(INDEX_PARTITION).await
+}
+
+/// Opens `INDEX_PARTITION` for the index whose root is at page `root`.
+pub async fn open_root(page_directory: &PageDirectory, root: u64) -> Result<()> {
+    page_directory.alloc_part_with_num(INDEX_PARTITION).await?;
+    if !page_directory.is_page_allocated(root).await {
+        return Err(Error::NotFound(format!("index root page {}", root)));
+    }
+    Ok(())
+}
+
+/// The log of an operation on the entries of an index by transaction, which
+/// is a nested top action: the changes to pages are logged as usual, then the
+/// operation ends with its changes of entries (see
+/// `ManifestEntry::UpdateIndex`) while its pages are still latched.
+///
+/// So the changes to pages are only undone if the operation doesn't end, e.g.
+/// it fails in the middle, and no other transaction has seen them. Once it
+/// ends, the changes of entries are undone by the opposite changes instead
+/// (see `undo`), and the changes to the structure of index (e.g. splits) are
+/// kept, as other transactions may have changed the same pages since.
+pub struct EntryLog<'a> {
+    page_directory: &'a PageDirectory,
+    txn: TxnId,
+    /// The last LSN of transaction before the operation.
+    begin_lsn: Lsn,
+    /// The LSN to resume the rollback at if the operation undoes a change,
+    /// then it ends with a compensation record instead of its changes.
+    undo_next_lsn: Option<Lsn>,
+    changes: Vec<(IndexChange, Vec<u8>, Vec<u8>)>,
+}
+
+impl<'a> EntryLog<'a> {
+    /// Begins an operation of transaction.
+    pub async fn begin(page_directory: &'a PageDirectory, txn: TxnId) -> EntryLog<'a> {
+        let begin_lsn = page_directory
+            .manifest()
+            .await
+            .last_lsn_of(txn)
+            .unwrap_or(0);
+        Self {
+            page_directory,
+            txn,
+            begin_lsn,
+            undo_next_lsn: None,
+            changes: vec![],
+        }
+    }
+
+    /// Begins an operation which undoes a change of transaction, the rollback
+    /// resumes at `undo_next_lsn` once it ends.
+    async fn compensate(
+        page_directory: &'a PageDirectory,
+        txn: TxnId,
+        undo_next_lsn: Lsn,
+    ) -> EntryLog<'a> {
+        let mut log = Self::begin(page_directory, txn).await;
+        log.undo_next_lsn = Some(undo_next_lsn);
+        log
+    }
+
+    /// Returns the transaction of operation.
+    pub fn txn(&self) -> TxnId {
+        self.txn
+    }
+
+    /// Records a change of entry, which is logged once the operation ends.
+    pub fn change<K: Codec, V: Codec>(&mut self, change: IndexChange, key: &K, value: &V) {
+        let (mut key_buf, mut value_buf) = (vec![], vec![]);
+        key.encode(&mut key_buf);
+        value.encode(&mut value_buf);
+        self.changes.push((change, key_buf, value_buf));
+    }
+
+    /// Ends the operation on the index of `root`, whose pages changed must be
+    /// still latched. An operation which only changes the structure of index
+    /// ends without any change of entries, so it's never undone.
+    pub async fn end(self, root: u64) -> Result<()> {
+        let mut manifest = self.page_directory.manifest().await;
+        if let Some(undo_next_lsn) = self.undo_next_lsn {
+            let entry = ManifestEntry::EndNestedTopAction { undo_next_lsn };
+            manifest.append(self.txn, entry).await?;
+            return Ok(());
+        }
+        if self.changes.is_empty() {
+            if manifest.last_lsn_of(self.txn).unwrap_or(0) != self.begin_lsn {
+                let entry = ManifestEntry::EndNestedTopAction {
+                    undo_next_lsn: self.begin_lsn,
+                };
+                manifest.append(self.txn, entry).await?;
+            }
+            return Ok(());
+        }
+
+        // A change logged after another is undone first, then the rollback
+        // resumes at the other one.
+        let mut begin_lsn = self.begin_lsn;
+        for (change, key, value) in self.changes {
+            let entry = ManifestEntry::UpdateIndex {
+                change,
+                root,
+                key,
+                value,
+                begin_lsn,
+            };
+            begin_lsn = manifest.append(self.txn, entry).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Undoes the change of an index entry logged by `ManifestEntry::UpdateIndex`
+/// with the opposite change, i.e. removes the entry inserted or inserts the
+/// entry removed wherever it is now, which ends with a compensation record.
+/// The entries are those of the indices of tables, i.e. `IndexKey` to
+/// `RecordId`.
+pub async fn undo(
+    page_directory: &Arc<PageDirectory>,
+    txn: TxnId,
+    entry: &ManifestEntry,
+) -> Result<()> {
+    let (change, root, key, value, begin_lsn) = match entry {
+        ManifestEntry::UpdateIndex {
+            change,
+            root,
+            key,
+            value,
+            begin_lsn,
+        } => (*change, *root, key, value, *begin_lsn),
+        entry => return Err(Error::Invalid(format!("undo {:?} of index", entry))),
+    };
+    let key = IndexKey::decode(&mut &key[..])?;
+    let value = RecordId::decode(&mut &value[..])?;
+    let log = EntryLog::compensate(page_directory, txn, begin_lsn).await;
+    match change {
+        IndexChange::BTreeInsert => {
+            let btree = BTree::<IndexKey, RecordId>::open(page_directory.clone(), root).await?;
+            btree.remove_entry(log, &key).await?;
+        }
+        IndexChange::BTreeRemove => {
+            let btree = BTree::<IndexKey, RecordId>::open(page_directory.clone(), root).await?;
+            btree.insert_entry(log, key, value, false).await?;
+        }
+        IndexChange::HashInsert => {
+            let hash = HashIndex::<IndexKey, RecordId>::open(page_directory.clone(), root).await?;
+            hash.remove_entry(log, &key, &value).await?;
+        }
+        IndexChange::HashRemove => {
+            let hash = HashIndex::<IndexKey, RecordId>::open(page_directory.clone(), root).await?;
+            hash.insert_entry(log, key, value, false).await?;
+        }
+    }
+    Ok(())
+}
+
+/// A key or value of index entries, which is encoded into the nodes of index
+/// in big-endian.
+pub trait Codec: Sized {
+    /// Returns the size (in bytes) of the encoded value.
+    fn encoded_size(&self) -> usize;
+
+    fn encode<B: BufMut>(&self, buf: &mut B);
+
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self>;
+}
+
+/// Returns the hash of key, which is the CRC32 of the encoded key, so it's
+/// stable across runs.
+pub fn key_hash<K: Codec>(key: &K) -> u32 {
+    let mut buf = Vec::with_capacity(key.encoded_size());
+    key.encode(&mut buf);
+    crc32(&buf)
+}
+
+macro_rules! impl_codec {
+    ($($ty:ty, $put:ident, $get:ident);*) => {
+        $(
+            impl Codec for $ty {
+                fn encoded_size(&self) -> usize {
+                    std::mem::size_of::<$ty>()
+                }
+
+                fn encode<B: BufMut>(&self, buf: &mut B) {
+                    buf.$put(*self);
+                }
+
+                fn decode<B: Buf>(buf: &mut B) -> Result<Self> {
+                    if buf.remaining() < std::mem::size_of::<$ty>() {
+                        return Err(Error::Corrupted(format!(
+                            "index entry: {} bytes left",
+                            buf.remaining()
+                        )));
+                    }
+                    Ok(buf.$get())
+                }
+            }
+        )*
+    };
+}
+
+impl_codec!(u16, put_u16, get_u16; i32, put_i32, get_i32; u32, put_u32, get_u32; i64, put_i64, get_i64; u64, put_u64, get_u64);
+
+impl Codec for RecordId {
+    fn encoded_size(&self) -> usize {
+        16
+    }
+
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        self.0.encode(buf);
+        (self.1 as u64).encode(buf);
+    }
+
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self> {
+        Ok(RecordId(u64::decode(buf)?, u64::decode(buf)? as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytes::{Buf, BufMut};
+    use tempfile::TempDir;
+
+    use super::Codec;
+    use crate::{
+        error::Result,
+        options::Options,
+        table::{page::page_directory::PageDirectory, recover},
+    };
+
+    /// A key padded to 250 bytes, so that a node (or a bucket) holds about 16
+    /// entries.
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    pub(super) struct WideKey(pub u32);
+
+    impl Codec for WideKey {
+        fn encoded_size(&self) -> usize {
+            250
+        }
+
+        fn encode<B: BufMut>(&self, buf: &mut B) {
+            buf.put_u32(self.0);
+            buf.put_bytes(0, 246);
+        }
+
+        fn decode<B: Buf>(buf: &mut B) -> Result<Self> {
+            let key = buf.get_u32();
+            buf.advance(246);
+            Ok(WideKey(key))
+        }
+    }
+
+    /// Opens and recovers the page directory of the tests of indices, which
+    /// only caches 8 pages, so that indices span more pages than the cache.
+    pub(super) async fn open_page_directory(dir: &TempDir) -> Arc<PageDirectory> {
+        let page_directory = PageDirectory::open(&Options {
+            path: dir.path().join("db").to_string_lossy().to_string(),
+            cache_frames: 8,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let page_directory = Arc::new(page_directory);
+        recover::recover(&page_directory).await.unwrap();
+        page_directory
+    }
+}
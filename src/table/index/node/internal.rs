@@ -1,269 +1,115 @@
-use std::{borrow::Borrow, mem::MaybeUninit, slice::SliceIndex};
+use std::borrow::Borrow;
 
-use super::{move_to_slice, slice_remove, BoxedNode, Node, CAPACITY};
-use crate::table::index::node::{slice_insert, slice_shl, slice_shr};
+use bytes::{Buf, BufMut};
 
-pub struct InternalNode<K, V> {
-    pub len: u16,
-    pub keys: [MaybeUninit<K>; CAPACITY],
-    pub edges: [MaybeUninit<BoxedNode<K, V>>; CAPACITY],
-}
-
-/// pritive methods.
-impl<K, V> InternalNode<K, V> {
-    fn insert_internal_fit(&mut self, key: K, edge: BoxedNode<K, V>)
-    where
-        K: Ord,
-    {
-        let len = self.len();
-
-        assert!(len < CAPACITY);
+use super::{decode_len, split_point, POINTER_SIZE};
+use crate::{error::Result, table::index::Codec};
 
-        let keys = self.keys();
-        let mut left_idx_in_parent = 0;
-        while left_idx_in_parent < len && key > keys[left_idx_in_parent] {
-            left_idx_in_parent += 1;
-        }
-
-        if left_idx_in_parent < len && key == keys[left_idx_in_parent] {
-            return;
-        }
-
-        unsafe {
-            slice_insert(self.key_area_mut(..len + 1), left_idx_in_parent, key);
-            slice_insert(self.edge_area_mut(..len + 1), left_idx_in_parent, edge);
-        }
-    }
-
-    fn split_internal(&mut self) -> Box<InternalNode<K, V>> {
-        let mut new_internal = Self::new_boxed();
-        let splitpoint = CAPACITY / 2;
-
-        let len = self.len();
-
-        move_to_slice(
-            self.key_area_mut(splitpoint..),
-            new_internal.key_area_mut(..len - splitpoint),
-        );
-        move_to_slice(
-            self.edge_area_mut(splitpoint..),
-            new_internal.edge_area_mut(..len - splitpoint),
-        );
-
-        new_internal.len = (len - splitpoint) as u16;
-        self.len = splitpoint as u16;
-
-        new_internal
-    }
-
-    pub fn merge(&mut self, right_internal: &mut InternalNode<K, V>, discriminator_key: K) {
-        assert!(self.len() + right_internal.len() < CAPACITY);
-        let len = self.len();
-        let r_len = right_internal.len();
-        unsafe {
-            slice_insert(self.key_area_mut(..len + 1), len, discriminator_key);
-        }
-
-        move_to_slice(
-            right_internal.key_area_mut(..r_len),
-            self.key_area_mut(len + 1..len + r_len + 1),
-        );
+/// [`InternalNode`] represent a internal node in b+tree, used for routing
+/// keys to the leaves. `children[i]` holds the keys in `[keys[i - 1],
+/// keys[i])`, so it has one more child than keys.
+pub struct InternalNode<K> {
+    pub keys: Vec<K>,
+    /// The pages of children.
+    pub children: Vec<u64>,
+}
 
-        move_to_slice(
-            right_internal.edge_area_mut(..r_len),
-            self.edge_area_mut(len + 1..len + r_len + 1),
+impl<K> InternalNode<K> {
+    pub fn new(keys: Vec<K>, children: Vec<u64>) -> Self {
+        assert!(
+            keys.len() + 1 == children.len(),
+            "internal node has {} keys but {} children.",
+            keys.len(),
+            children.len()
         );
-
-        self.len += r_len as u16;
-    }
-
-    fn len(&self) -> usize {
-        usize::from(self.len)
+        Self { keys, children }
     }
 
-    fn keys(&self) -> &[K] {
-        unsafe { MaybeUninit::slice_assume_init_ref(self.keys.get_unchecked(..self.len())) }
+    pub fn len(&self) -> usize {
+        self.keys.len()
     }
 
-    fn key_area_mut<I, Output: ?Sized>(&mut self, index: I) -> &mut Output
-    where
-        I: SliceIndex<[MaybeUninit<K>], Output = Output>,
-    {
-        unsafe { self.keys.as_mut_slice().get_unchecked_mut(index) }
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
     }
 
-    fn edge_area_mut<I, Output: ?Sized>(&mut self, index: I) -> &mut Output
+    /// Returns the index of child which may contain `key`.
+    pub fn search_internal<Q>(&self, key: &Q) -> usize
     where
-        I: SliceIndex<[MaybeUninit<BoxedNode<K, V>>], Output = Output>,
+        K: Borrow<Q>,
+        Q: ?Sized + Ord,
     {
-        unsafe { self.edges.as_mut_slice().get_unchecked_mut(index) }
-    }
-
-    fn get_key_by_index(&self, idx: usize) -> &K {
-        assert!(idx < self.keys.len());
-        unsafe { self.keys.get_unchecked(idx).assume_init_ref() }
+        self.keys.partition_point(|k| k.borrow() <= key)
     }
 
-    fn get_mut_key_by_index(&mut self, idx: usize) -> &mut K {
-        assert!(idx < self.keys.len());
-        unsafe { self.keys.get_unchecked_mut(idx).assume_init_mut() }
+    /// Inserts the separator key of a child split at `idx` and the new right
+    /// child of the split.
+    pub fn insert_internal(&mut self, idx: usize, key: K, right: u64) {
+        self.keys.insert(idx, key);
+        self.children.insert(idx + 1, right);
     }
 
-    fn get_edge_by_index(&self, idx: usize) -> BoxedNode<K, V> {
-        assert!(idx < self.edges.len());
-        unsafe { self.edges.get_unchecked(idx).assume_init_read() }
+    /// Removes the separator key at `idx` and the right child of it, which
+    /// has been merged into the left one.
+    pub fn remove_internal(&mut self, idx: usize) -> K {
+        self.children.remove(idx + 1);
+        self.keys.remove(idx)
     }
 
-    fn get_mut_edge_by_index(&mut self, idx: usize) -> &mut BoxedNode<K, V> {
-        assert!(idx < self.edges.len());
-        unsafe { self.edges.get_unchecked_mut(idx).assume_init_mut() }
-    }
-
-    fn as_raw_ptr(&mut self) -> *mut InternalNode<K, V> {
-        self as *mut InternalNode<K, V>
+    /// Moves all keys and children of the right sibling `right` into this
+    /// node, with the separator key `key` between them pulled down.
+    pub fn merge_internal(&mut self, key: K, mut right: InternalNode<K>) {
+        self.keys.push(key);
+        self.keys.append(&mut right.keys);
+        self.children.append(&mut right.children);
     }
 }
 
-impl<K, V> InternalNode<K, V> {
-    pub fn new_boxed() -> Box<Self> {
-        let internal = Box::new_uninit();
-        unsafe { internal.assume_init() }
-    }
-
-    pub fn downcast(self) -> Box<Node<K, V>> {
-        Box::new(Node::Internal(self))
-    }
-
-    pub fn search_internal<Q: ?Sized>(&self, key: &Q) -> BoxedNode<K, V>
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord,
-    {
-        let mut idx = 0;
-        while idx < self.len() && key >= self.get_key_by_index(idx).borrow() {
-            idx += 1;
+impl<K: Codec> InternalNode<K> {
+    /// Moves the upper half (in bytes) of keys and children into a new node,
+    /// returns the middle key, which separates the two nodes, and the new node.
+    pub fn split_internal(&mut self) -> (K, InternalNode<K>) {
+        let sizes = self
+            .keys
+            .iter()
+            .map(|k| k.encoded_size() + POINTER_SIZE)
+            .collect::<Vec<_>>();
+        // Both nodes keep at least one key.
+        let splitpoint = split_point(&sizes).min(self.len() - 2);
+
+        let keys = self.keys.split_off(splitpoint + 1);
+        let children = self.children.split_off(splitpoint + 1);
+        let key = self.keys.pop().unwrap();
+        (key, InternalNode::new(keys, children))
+    }
+
+    pub fn encoded_size(&self) -> usize {
+        2 + POINTER_SIZE
+            + self
+                .keys
+                .iter()
+                .map(|k| k.encoded_size() + POINTER_SIZE)
+                .sum::<usize>()
+    }
+
+    pub fn encode<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u16(self.len() as u16);
+        buf.put_u64(self.children[0]);
+        for (k, child) in self.keys.iter().zip(&self.children[1..]) {
+            k.encode(buf);
+            buf.put_u64(*child);
         }
-        self.get_edge_by_index(idx)
     }
 
-    pub fn insert_internal(
-        &mut self,
-        key: K,
-        edge: BoxedNode<K, V>,
-    ) -> (Option<K>, Option<Box<InternalNode<K, V>>>)
-    where
-        K: Ord,
-    {
-        let len = self.len();
-        if len < CAPACITY {
-            self.insert_internal_fit(key, edge);
-            self.len += 1;
-            return (None, None);
+    pub fn decode<B: Buf>(buf: &mut B) -> Result<Self> {
+        let len = decode_len(buf)?;
+        let mut keys = Vec::with_capacity(len);
+        let mut children = Vec::with_capacity(len + 1);
+        children.push(u64::decode(buf)?);
+        for _ in 0..len {
+            keys.push(K::decode(buf)?);
+            children.push(u64::decode(buf)?);
         }
-
-        let mut split_node = self.split_internal();
-        split_node.as_mut().insert_internal_fit(key, edge);
-        self.len += 1;
-
-        let splitkey = unsafe { split_node.keys.get_unchecked(0).assume_init_read() };
-        (Some(splitkey), Some(split_node))
-    }
-
-    pub fn remove_internal(&mut self, edge: BoxedNode<K, V>) {
-        let len = self.len();
-
-        let mut remove_idx = 0;
-
-        while remove_idx <= self.len() && self.get_edge_by_index(remove_idx) != edge {
-            remove_idx += 1;
-        }
-
-        if remove_idx == len {
-            return;
-        }
-
-        unsafe {
-            slice_remove(self.key_area_mut(..len), remove_idx);
-            slice_remove(self.edge_area_mut(..len), remove_idx);
-        }
-
-        self.len -= 1;
-    }
-
-    pub fn steal_left(
-        &mut self,
-        left_internal: &mut InternalNode<K, V>,
-        discriminator_key: K,
-    ) -> Option<K> {
-        let len = self.len();
-
-        let l_len = left_internal.len();
-        assert!(len + 1 < CAPACITY);
-
-        unsafe {
-            slice_shr(self.key_area_mut(..len), 1);
-            slice_shr(self.edge_area_mut(..len), 1);
-        }
-
-        let replacement_key = unsafe {
-            let uninit_key = left_internal.keys.get_unchecked_mut(l_len - 1);
-
-            let key = uninit_key.assume_init_read();
-            uninit_key.assume_init_drop();
-
-            Some(key)
-        };
-
-        unsafe {
-            self.keys.get_unchecked_mut(0).write(discriminator_key);
-        }
-
-        std::mem::swap(
-            self.get_mut_edge_by_index(0),
-            left_internal.get_mut_edge_by_index(left_internal.len()),
-        );
-
-        self.len += 1;
-        left_internal.len -= 1;
-
-        replacement_key
-    }
-
-    pub fn steal_right(
-        &mut self,
-        right_internal: &mut InternalNode<K, V>,
-        discriminator_key: K,
-    ) -> Option<K> {
-        let len = self.len();
-
-        assert!(len + 1 < CAPACITY);
-
-        unsafe {
-            self.keys.get_unchecked_mut(len).write(discriminator_key);
-        }
-
-        std::mem::swap(
-            self.get_mut_edge_by_index(len + 1),
-            right_internal.get_mut_edge_by_index(0),
-        );
-
-        let replacement_key = unsafe {
-            let uninit_key = right_internal.keys.get_unchecked_mut(0);
-
-            let key = uninit_key.assume_init_read();
-            uninit_key.assume_init_drop();
-
-            Some(key)
-        };
-
-        unsafe {
-            slice_shl(right_internal.key_area_mut(..self.len()), 1);
-            slice_shl(right_internal.edge_area_mut(..self.len()), 1);
-        }
-
-        self.len += 1;
-        right_internal.len -= 1;
-
-        replacement_key
+        Ok(Self::new(keys, children))
     }
 }
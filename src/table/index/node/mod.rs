@@ -1,15 +1,33 @@
 pub mod internal;
 pub mod leaf;
 
-use std::{borrow::Borrow, mem::MaybeUninit, ptr::NonNull};
+use bytes::{Buf, BufMut};
 
 use self::{internal::InternalNode, leaf::LeafNode};
-
-const B: usize = 6;
-pub const CAPACITY: usize = 2 * B - 1;
-
-pub type Root<K, V> = NodeRef<K, V>;
-pub type BoxedNode<K, V> = NonNull<Node<K, V>>;
+use super::Codec;
+use crate::{
+    error::{Error, Result},
+    table::page::{
+        page_directory::{DATA_HEADER_SIZE, DEFAULT_PAGE_SIZE, RESERVED_SIZE},
+        DataPage,
+    },
+};
+
+/// The size (in bytes) of a node, which takes the data area of a page.
+pub const NODE_SIZE: usize = DEFAULT_PAGE_SIZE - RESERVED_SIZE - DATA_HEADER_SIZE;
+
+/// The max size (in bytes) of an encoded entry, so that both halves of a split
+/// node fit in a page.
+pub const MAX_ENTRY_SIZE: usize = NODE_SIZE / 4;
+
+/// The type tag of leaf nodes, which is 0 so that a newly allocated (zeroed)
+/// page is an empty leaf.
+const LEAF: u8 = 0;
+const INTERNAL: u8 = 1;
+
+/// The size (in bytes) of a child or sibling pointer, i.e. a virtual page
+/// number, 0 is a null pointer.
+pub const POINTER_SIZE: usize = 8;
 
 macro_rules! create_node_get_fn {
     ($name:ident, $param:ty, $ret:ty, $match:ident) => {
@@ -22,9 +40,16 @@ macro_rules! create_node_get_fn {
     };
 }
 
+/// A node of B+ tree, which is decoded from and encoded into the data area of
+/// a page (the reserved bytes and the data page header are not used):
+///  - 1-byte node type.
+///  - leaf nodes: 8-byte previous and next sibling leaves, 2-byte number of
+///    entries, followed by the entries (key then value) in key order.
+///  - internal nodes: 2-byte number of keys, 8-byte first child, followed by
+///    the keys in order, each one followed by its right child.
 pub enum Node<K, V> {
     Leaf(LeafNode<K, V>),
-    Internal(InternalNode<K, V>),
+    Internal(InternalNode<K>),
 }
 
 impl<K, V> Node<K, V> {
@@ -32,46 +57,9 @@ impl<K, V> Node<K, V> {
 
     create_node_get_fn!(get_leaf_mut, &mut Self, &mut LeafNode<K,V>, Leaf);
 
-    create_node_get_fn!(get_internal, &Self, &InternalNode<K,V>, Internal);
-
-    create_node_get_fn!(get_internal_mut, &mut Self, &mut InternalNode<K,V>, Internal);
-
-    pub fn search_node(&self, k: K) {}
+    create_node_get_fn!(get_internal, &Self, &InternalNode<K>, Internal);
 
-    pub fn new_leaf_boxed() -> Box<LeafNode<K, V>> {
-        LeafNode::new_boxed()
-    }
-
-    pub fn new_internal_boxed() -> Box<InternalNode<K, V>> {
-        InternalNode::new_boxed()
-    }
-
-    pub fn steal_left(&mut self, left_node: &mut Node<K, V>, discriminator_key: K) -> Option<K> {
-        match &mut *self {
-            Node::Leaf(leaf) => leaf.steal_left(left_node.get_leaf_mut().unwrap()),
-            Node::Internal(internal) => {
-                internal.steal_left(left_node.get_internal_mut().unwrap(), discriminator_key)
-            }
-        }
-    }
-
-    pub fn steal_right(&mut self, right_node: &mut Node<K, V>, discriminator_key: K) -> Option<K> {
-        match &mut *self {
-            Node::Leaf(leaf) => leaf.steal_right(right_node.get_leaf_mut().unwrap()),
-            Node::Internal(internal) => {
-                internal.steal_right(right_node.get_internal_mut().unwrap(), discriminator_key)
-            }
-        }
-    }
-
-    pub fn merge(&mut self, right_node: &mut Node<K, V>, discriminator_key: K) {
-        match &mut *self {
-            Node::Leaf(leaf) => leaf.merge(right_node.get_leaf_mut().unwrap()),
-            Node::Internal(internal) => {
-                internal.merge(right_node.get_internal_mut().unwrap(), discriminator_key)
-            }
-        }
-    }
+    create_node_get_fn!(get_internal_mut, &mut Self, &mut InternalNode<K>, Internal);
 
     /// Returns node whether leaf node.
     pub fn is_leaf(&self) -> bool {
@@ -80,218 +68,111 @@ impl<K, V> Node<K, V> {
         }
         false
     }
-
-    pub fn is_underfull(&self) -> bool {
-        match self {
-            Node::Leaf(leaf) => leaf.len < ((CAPACITY - 1) / 2) as u16,
-            Node::Internal(internal) => internal.len < (CAPACITY / 2) as u16,
-        }
-    }
-
-    pub fn has_spcae_for_insert(&self) -> bool {
-        match self {
-            Node::Leaf(leaf) => leaf.len < (CAPACITY - 1) as u16,
-            Node::Internal(internal) => internal.len < (CAPACITY - 1) as u16,
-        }
-    }
-
-    pub fn has_space_for_removal(&self) -> bool {
-        match self {
-            Node::Leaf(leaf) => leaf.len > ((CAPACITY - 1) / 2) as u16,
-            Node::Internal(internal) => internal.len > (CAPACITY / 2) as u16,
-        }
-    }
-
-    pub unsafe fn drop_key_val(&self) {
-        todo!()
-    }
 }
 
-pub struct NodeRef<K, V> {
-    node: NonNull<Node<K, V>>,
-}
-
-/// private methods.
-impl<K, V> NodeRef<K, V> {
-    fn reborrow(&self) -> Self {
-        NodeRef { node: self.node }
-    }
-
-    fn descend_to_leaf<Q: ?Sized>(&self, key: &Q) -> Option<&LeafNode<K, V>>
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord,
-    {
-        let mut node = self.reborrow().node;
-        loop {
-            let node_ref = unsafe { node.as_ref() };
-            if node_ref.is_leaf() {
-                return node_ref.get_leaf();
-            }
-            let internal = node_ref.get_internal()?;
-            node = internal.search_internal(key);
+impl<K: Codec, V: Codec> Node<K, V> {
+    /// Decodes the node stored in page.
+    pub fn decode(page: &DataPage) -> Result<Self> {
+        let mut buf = page.data_area();
+        match buf.get_u8() {
+            LEAF => Ok(Node::Leaf(LeafNode::decode(&mut buf)?)),
+            INTERNAL => Ok(Node::Internal(InternalNode::decode(&mut buf)?)),
+            ty => Err(Error::Corrupted(format!("index node: type {}", ty))),
         }
     }
 
-    fn descend_to_internal_leaf<Q: ?Sized>(
-        &self,
-        key: &Q,
-    ) -> (Option<&mut InternalNode<K, V>>, Option<&mut LeafNode<K, V>>)
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord,
-    {
-        let mut parent = None;
-        let mut node = self.reborrow().node;
-        loop {
-            let node_ref = unsafe { node.as_mut() };
-            if node_ref.is_leaf() {
-                return (parent, node_ref.get_leaf_mut());
+    /// Encodes the node into page, the node must fit in a page.
+    pub fn encode(&self, page: &mut DataPage) {
+        assert!(
+            !self.is_overflow(),
+            "node of {} bytes doesn't fit in a page.",
+            self.encoded_size()
+        );
+        let mut buf = page.data_area_mut();
+        match self {
+            Node::Leaf(leaf) => {
+                buf.put_u8(LEAF);
+                leaf.encode(&mut buf);
             }
-            let internal = node_ref.get_internal_mut();
-            match internal {
-                Some(internal_node) => {
-                    node = internal_node.search_internal(key);
-                    parent = Some(internal_node);
-                }
-                None => return (None, None),
+            Node::Internal(internal) => {
+                buf.put_u8(INTERNAL);
+                internal.encode(&mut buf);
             }
         }
     }
 
-    fn insert_into_parent(
-        &self,
-        key: K,
-        right: Box<InternalNode<K, V>>,
-        parent_node: Option<&mut InternalNode<K, V>>,
-    ) where
-        K: Ord,
-    {
-        match parent_node {
-            Some(parent) => {
-                if let (splitkey, Some(splitnode)) =
-                    parent.insert_internal(key, NonNull::from(Box::leak(right.downcast())))
-                {
-                    assert!(splitkey.is_some());
-                    // self.insert_into_parent(splitkey.unwrap(), splitnode, parent_node);
-                }
-            }
-            None => todo!(),
+    /// Returns the size (in bytes) of the encoded node.
+    pub fn encoded_size(&self) -> usize {
+        1 + match self {
+            Node::Leaf(leaf) => leaf.encoded_size(),
+            Node::Internal(internal) => internal.encoded_size(),
         }
     }
-}
 
-impl<K, V> NodeRef<K, V> {
-    pub fn from_node(node: Box<Node<K, V>>) -> Self {
-        Self {
-            node: NonNull::from(Box::leak(node)),
-        }
+    /// Returns whether the node doesn't fit in a page, so it has to be split.
+    pub fn is_overflow(&self) -> bool {
+        self.encoded_size() > NODE_SIZE
     }
 
-    pub fn search_node<Q: ?Sized>(&self, key: &Q) -> Option<&V>
-    where
-        K: Borrow<Q> + Ord,
-        Q: Ord,
-    {
-        if let Some(leaf) = self.descend_to_leaf(key) {
-            return leaf.search_leaf(key);
-        }
-        None
+    /// Returns whether the node takes another entry (or the separator key of
+    /// a split child) without splitting.
+    pub fn is_safe(&self) -> bool {
+        self.encoded_size() + MAX_ENTRY_SIZE <= NODE_SIZE
     }
 
-    pub fn insert_node(&mut self, key: K, val: V) -> Option<V>
-    where
-        K: Ord + Clone,
-    {
-        let (parent, leaf) = self.descend_to_internal_leaf(&key);
-        match leaf {
-            Some(leaf) => {
-                let (replaced, splitkey, splitnode) = leaf.insert_leaf(key, val);
-                if let Some(splitnode) = splitnode {
-                    assert!(splitkey.is_some());
-                    // self.insert_into_parent(splitkey.unwrap(), splitnode, parent);
-                }
-                replaced
-            }
-            None => {
-                assert!(parent.is_some());
-
-                let mut leaf = Node::new_leaf_boxed();
-                leaf.as_mut().insert_leaf(key.clone(), val);
-
-                parent
-                    .unwrap()
-                    .insert_internal(key, NonNull::from(Box::leak(leaf.downcast())));
-
-                None
-            }
-        }
+    /// Returns whether the node is less than a quarter full, so it's merged
+    /// with (or takes entries from) a sibling.
+    pub fn is_underflow(&self) -> bool {
+        self.encoded_size() < NODE_SIZE / 4
     }
 
-    pub fn remove_node(&mut self, key: K) -> Option<V> {
-        todo!()
+    /// Returns whether the node neither underflows when an entry (or the
+    /// separator key of merged children) is removed, nor splits when the
+    /// separator key of a child is replaced.
+    pub fn is_safe_for_remove(&self) -> bool {
+        self.encoded_size() >= NODE_SIZE / 4 + MAX_ENTRY_SIZE && self.is_safe()
     }
 }
 
-/// Inserts a value into a slice of initialized elements followed by on
-/// uninitialized element.
-///
-/// # Safety
-/// The slice has more than `idx` elements.
-pub unsafe fn slice_insert<T>(slice: &mut [MaybeUninit<T>], idx: usize, val: T) {
-    unsafe {
-        let len = slice.len();
-        assert!(idx < len);
-        let slice_ptr = slice.as_mut_ptr();
-        if len > idx + 1 {
-            std::ptr::copy(slice_ptr.add(idx), slice_ptr.add(idx + 1), len - idx - 1);
-        }
-        (*slice_ptr.add(idx)).write(val);
+/// Checks whether the entry is small enough to be stored in nodes (the key is
+/// also stored in internal nodes with a child pointer), returns
+/// `Error::TooLargeSize` if not.
+pub fn check_entry_size<K: Codec, V: Codec>(key: &K, val: &V) -> Result<()> {
+    if key.encoded_size() + val.encoded_size().max(POINTER_SIZE) > MAX_ENTRY_SIZE {
+        return Err(Error::TooLargeSize);
     }
+    Ok(())
 }
 
-/// Removes and returns a value from a slice of all initialized elements,
-/// leaving behind one trailing uninitialized element.
-///
-/// # Safety
-/// The slice has more than `idx` elements.
-pub unsafe fn slice_remove<T>(slice: &mut [MaybeUninit<T>], idx: usize) -> T {
-    unsafe {
-        let len = slice.len();
-        assert!(idx < len);
-        let slice_ptr = slice.as_mut_ptr();
-        let ret = (*slice_ptr.add(idx)).assume_init_read();
-        std::ptr::copy(slice_ptr.add(idx + 1), slice_ptr.add(idx), len - idx - 1);
-        ret
+/// Returns the index at which entries of `sizes` are split into two halves of
+/// about the same size (in bytes), both halves are not empty.
+pub fn split_point(sizes: &[usize]) -> usize {
+    assert!(sizes.len() >= 2, "can't split less than 2 entries.");
+    let total = sizes.iter().sum::<usize>();
+    let mut left = 0;
+    for (i, size) in sizes.iter().enumerate() {
+        if left >= total / 2 {
+            return i.clamp(1, sizes.len() - 1);
+        }
+        left += size;
     }
+    sizes.len() - 1
 }
 
-/// Shifts the elements in a slice `distance` posititions to the left.
-///
-/// # Satefy
-/// The slice has at least `distance` elements.
-pub unsafe fn slice_shl<T>(slice: &mut [MaybeUninit<T>], distance: usize) {
-    unsafe {
-        let slice_ptr = slice.as_mut_ptr();
-        std::ptr::copy(slice_ptr.add(distance), slice_ptr, slice.len() - distance);
-    }
+/// Decodes a child or sibling pointer.
+fn decode_pointer<B: Buf>(buf: &mut B) -> Result<Option<u64>> {
+    let page = u64::decode(buf)?;
+    Ok((page != 0).then_some(page))
 }
 
-/// Shifts the elements in a slice `distance` posititions to the right.
-///
-/// # Satefy
-/// The slice has at least `distance` elements.
-pub unsafe fn slice_shr<T>(slice: &mut [MaybeUninit<T>], distance: usize) {
-    unsafe {
-        let slice_ptr = slice.as_mut_ptr();
-        std::ptr::copy(slice_ptr, slice_ptr.add(distance), slice.len() - distance);
-    }
+fn encode_pointer<B: BufMut>(buf: &mut B, page: Option<u64>) {
+    buf.put_u64(page.unwrap_or(0));
 }
 
-/// Moves all values from a slice of initialized elements to a slice
-/// of uninitialized elements, leaving behind `src` as all uninitialized.
-/// Works like `dst.copy_from_slice(src)` but does not require `T` to be `Copy`.
-pub fn move_to_slice<T>(src: &mut [MaybeUninit<T>], dst: &mut [MaybeUninit<T>]) {
-    assert!(src.len() == dst.len());
-    unsafe { std::ptr::copy(src.as_ptr(), dst.as_mut_ptr(), src.len()) }
+/// Decodes the 2-byte number of entries.
+fn decode_len<B: Buf>(buf: &mut B) -> Result<usize> {
+    if buf.remaining() < 2 {
+        return Err(Error::Corrupted("index node: length".to_owned()));
+    }
+    Ok(buf.get_u16() as usize)
 }
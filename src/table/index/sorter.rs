@@ -0,0 +1,234 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::ErrorKind,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use async_fs::File;
+use bytes::BufMut;
+use futures::io::BufReader;
+use futures_lite::{AsyncReadExt, AsyncWriteExt};
+
+use super::Codec;
+use crate::{
+    error::{Error, Result},
+    utils::fs,
+};
+
+/// The default amount (in bytes) of entries sorted in memory.
+pub const DEFAULT_SORT_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Counter to generate the file names of runs.
+static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Sorts the entries of an index by key, e.g. for bulk loading. Entries are
+/// sorted in memory up to `buffer_size` bytes, beyond which the sorted entries
+/// are spilled as a run into file `{path}.sort.{n}`, and the runs are merged
+/// when all entries are pushed. The files are removed once the sorter or its
+/// output is dropped.
+///
+/// Entries in a run are encoded as the 2-byte size of the entry followed by
+/// the key and the value.
+pub struct ExternalSorter<K, V> {
+    path: String,
+
+    buffer_size: usize,
+
+    /// The entries not spilled yet, and their size.
+    buffer: Vec<(K, V)>,
+    buffered: usize,
+
+    /// The files of spilled runs.
+    runs: Vec<String>,
+}
+
+impl<K: Codec + Ord, V: Codec> ExternalSorter<K, V> {
+    pub fn new(path: impl Into<String>, buffer_size: usize) -> Self {
+        Self {
+            path: path.into(),
+            buffer_size,
+            buffer: vec![],
+            buffered: 0,
+            runs: vec![],
+        }
+    }
+
+    pub async fn push(&mut self, key: K, val: V) -> Result<()> {
+        let size = key.encoded_size() + val.encoded_size();
+        if size > u16::MAX as usize {
+            return Err(Error::TooLargeSize);
+        }
+
+        self.buffered += size;
+        self.buffer.push((key, val));
+        if self.buffered >= self.buffer_size {
+            self.spill().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether no entry is pushed.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty() && self.runs.is_empty()
+    }
+
+    /// Returns all pushed entries in key order.
+    pub async fn finish(mut self) -> Result<SortedEntries<K, V>> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut runs = vec![Run::Memory(buffer.into_iter())];
+
+        let paths = std::mem::take(&mut self.runs);
+        for path in &paths {
+            runs.push(Run::File(BufReader::new(fs::open(path).await?)));
+        }
+        let mut entries = SortedEntries {
+            runs,
+            heap: BinaryHeap::new(),
+            vals: vec![],
+            paths,
+        };
+        for run in 0..entries.runs.len() {
+            entries.vals.push(None);
+            entries.advance(run).await?;
+        }
+        Ok(entries)
+    }
+
+    /// Sorts the buffered entries and writes them into a new run.
+    async fn spill(&mut self) -> Result<()> {
+        self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut buf = Vec::with_capacity(self.buffered + 2 * self.buffer.len());
+        for (key, val) in self.buffer.drain(..) {
+            buf.put_u16((key.encoded_size() + val.encoded_size()) as u16);
+            key.encode(&mut buf);
+            val.encode(&mut buf);
+        }
+        self.buffered = 0;
+
+        let path = format!(
+            "{}.sort.{}",
+            self.path,
+            RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        // The file is removed on drop even if it's not completely written.
+        self.runs.push(path.clone());
+        let mut file = fs::create_file(&path).await?;
+        file.write_all(&buf).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+impl<K, V> Drop for ExternalSorter<K, V> {
+    fn drop(&mut self) {
+        remove_runs(&self.runs);
+    }
+}
+
+/// A sorted run, the last one is kept in memory.
+enum Run<K, V> {
+    Memory(std::vec::IntoIter<(K, V)>),
+    File(BufReader<File>),
+}
+
+impl<K: Codec, V: Codec> Run<K, V> {
+    async fn next(&mut self) -> Result<Option<(K, V)>> {
+        let file = match self {
+            Run::Memory(entries) => return Ok(entries.next()),
+            Run::File(file) => file,
+        };
+
+        let mut size = [0u8; 2];
+        match file.read_exact(&mut size).await {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        let mut entry = vec![0u8; u16::from_be_bytes(size) as usize];
+        file.read_exact(&mut entry).await?;
+
+        let mut buf = entry.as_slice();
+        Ok(Some((K::decode(&mut buf)?, V::decode(&mut buf)?)))
+    }
+}
+
+/// The entries of `ExternalSorter` in key order, which are merged from the
+/// sorted runs.
+pub struct SortedEntries<K, V> {
+    runs: Vec<Run<K, V>>,
+
+    /// The next key of every run which isn't exhausted, the smallest first.
+    heap: BinaryHeap<Reverse<(K, usize)>>,
+
+    /// The value of the next key of every run.
+    vals: Vec<Option<V>>,
+
+    paths: Vec<String>,
+}
+
+impl<K: Codec + Ord, V: Codec> SortedEntries<K, V> {
+    pub async fn next(&mut self) -> Result<Option<(K, V)>> {
+        let Reverse((key, run)) = match self.heap.pop() {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        let val = self.vals[run].take().unwrap();
+        self.advance(run).await?;
+        Ok(Some((key, val)))
+    }
+
+    /// Reads the next entry of run.
+    async fn advance(&mut self, run: usize) -> Result<()> {
+        if let Some((key, val)) = self.runs[run].next().await? {
+            self.heap.push(Reverse((key, run)));
+            self.vals[run] = Some(val);
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> Drop for SortedEntries<K, V> {
+    fn drop(&mut self) {
+        remove_runs(&self.paths);
+    }
+}
+
+fn remove_runs(paths: &[String]) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_external_sort() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("db").to_string_lossy().to_string();
+            // Every run holds 100 entries.
+            let mut sorter = ExternalSorter::new(path, 100 * 12);
+            for i in 0..1050u64 {
+                sorter.push(i * 7919 % 1050, i as u32).await.unwrap();
+            }
+            assert_eq!(fs::read_dir(dir.path()).await.unwrap().len(), 10);
+
+            let mut entries = sorter.finish().await.unwrap();
+            for i in 0..1050 {
+                let (key, val) = entries.next().await.unwrap().unwrap();
+                assert_eq!(key, i);
+                assert_eq!(val as u64 * 7919 % 1050, i);
+            }
+            assert!(entries.next().await.unwrap().is_none());
+
+            drop(entries);
+            assert!(fs::read_dir(dir.path()).await.unwrap().is_empty());
+        })
+    }
+}
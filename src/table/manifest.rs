@@ -1,35 +1,1183 @@
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use crate::error::Result;
+use bytes::{Buf, BufMut};
 
+use super::page::{
+    page_directory::{calculate_part_num, DATA_HEADER_SIZE, DEFAULT_PAGE_SIZE, LOG_PARTITION},
+    partition::PartitionHandle,
+};
+use crate::{
+    error::{Error, Result},
+    utils::checksum,
+};
+
+/// Log sequence number, the position of a log record in the log: the sequence
+/// number of log page in the upper bits and the offset in page in the lower 16
+/// bits. LSNs increase as records are appended and 0 is never a valid LSN.
+pub type Lsn = u64;
+
+/// Transaction id, 0 is never a valid transaction id, it's used by the records
+/// which don't belong to a transaction, e.g. checkpoints.
+pub type TxnId = u64;
+
+/// The size of header of a log page, the 8-byte sequence number of page (0 if
+/// the page is never written) followed by 2-byte size of used space.
+const LOG_PAGE_HEADER_SIZE: usize = 10;
+
+/// The data page of log partition which stores the master record, the 4-byte
+/// crc32 checksum followed by 8-byte LSN of the last checkpoint. The other data
+/// pages are log pages, which are recycled once the log is truncated.
+const MASTER_PAGE_NUM: usize = 0;
+
+/// The max size of payload of an `EndCheckpoint`, larger tables are split into
+/// multiple records.
+const MAX_CHECKPOINT_SIZE: usize =
+    DEFAULT_PAGE_SIZE - LOG_PAGE_HEADER_SIZE - RECORD_HEADER_SIZE - 4;
+
+/// The size of header of an encoded log record: 4-byte crc32 checksum, 2-byte
+/// length of body, followed by body of 8-byte transaction id, 8-byte previous
+/// LSN of the transaction and 1-byte type tag.
+const RECORD_HEADER_SIZE: usize = 4 + 2 + 8 + 8 + 1;
+
+/// The max length of before/after images of an `UpdatePage`, so that the
+/// record (8-byte page, 2-byte offset, 2-byte length and both images) fits on
+/// one log page. Larger changes are logged as multiple records.
+pub const MAX_UPDATE_SIZE: usize =
+    (DEFAULT_PAGE_SIZE - LOG_PAGE_HEADER_SIZE - RECORD_HEADER_SIZE - 12) / 2;
+
+#[inline]
+fn make_lsn(seq: u64, offset: usize) -> Lsn {
+    (seq << 16) | offset as u64
+}
+
+#[inline]
+fn lsn_seq(lsn: Lsn) -> u64 {
+    lsn >> 16
+}
+
+#[inline]
+fn lsn_offset(lsn: Lsn) -> usize {
+    (lsn & 0xffff) as usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxnStatus {
+    Running,
+    Committing,
+    Aborting,
+}
+
+/// An entry of the transaction table, which tracks the transactions not ended
+/// yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxnEntry {
+    pub status: TxnStatus,
+    /// The LSN of the first record of transaction, the log before it may be
+    /// truncated once the transaction ends.
+    pub first_lsn: Lsn,
+    /// The LSN of the last record of transaction, which is the previous LSN of
+    /// its next record.
+    pub last_lsn: Lsn,
+}
+
+/// A change to an entry of index, which is undone logically by the opposite
+/// change (see `ManifestEntry::UpdateIndex`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexChange {
+    BTreeInsert,
+    BTreeRemove,
+    HashInsert,
+    HashRemove,
+}
+
+/// The kinds of log record. Pages are identified by virtual page number, and
+/// the `Undo*` entries are compensation log records (CLRs) written while
+/// rolling back, which carry the LSN of the next record to undo.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ManifestEntry {
     BeginTxn,
     EndTnx,
     CommitTxn,
+    AbortTxn,
+
+    /// The start of a checkpoint, with the largest transaction id ever used.
+    BeginCheckpoint(TxnId),
+    /// The dirty page table (the page and the LSN of the first change since it
+    /// was last synced) and the transaction table at the checkpoint.
+    EndCheckpoint {
+        dirty_pages: Vec<(u64, Lsn)>,
+        txns: Vec<(TxnId, TxnEntry)>,
+    },
+
+    AllocPage(u64),
+    FreePage(u64),
+
+    AllocParition(usize),
+    FreePartition(usize),
+
+    UndoAllocPage {
+        page: u64,
+        undo_next_lsn: Lsn,
+    },
+    UndoFreePage {
+        page: u64,
+        undo_next_lsn: Lsn,
+    },
+
+    UndoAllocParition {
+        part_num: usize,
+        undo_next_lsn: Lsn,
+    },
+    UndoFreeParition {
+        part_num: usize,
+        undo_next_lsn: Lsn,
+    },
+
+    /// The bytes at `offset` of page are changed from `before` to `after`.
+    UpdatePage {
+        page: u64,
+        offset: u16,
+        before: Vec<u8>,
+        after: Vec<u8>,
+    },
+    UndoUpdatPage {
+        page: u64,
+        offset: u16,
+        after: Vec<u8>,
+        undo_next_lsn: Lsn,
+    },
+
+    /// The end of a nested top action, e.g. the split of an index node, whose
+    /// changes since `undo_next_lsn` are kept even if the transaction rolls
+    /// back. It's a compensation record which undoes nothing.
+    EndNestedTopAction {
+        undo_next_lsn: Lsn,
+    },
+    /// The entry (the encoded key and value) of the index of `root` is changed,
+    /// which ends the nested top action of the changes to the pages of index
+    /// since `begin_lsn`: those are never undone, as the entry may have moved
+    /// to other pages since, the change is undone logically instead (see
+    /// `index::undo`).
+    UpdateIndex {
+        change: IndexChange,
+        root: u64,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        begin_lsn: Lsn,
+    },
+}
+
+impl ManifestEntry {
+    /// Returns whether the entry is undone when its transaction rolls back.
+    pub fn is_undoable(&self) -> bool {
+        matches!(
+            self,
+            ManifestEntry::AllocPage(_)
+                | ManifestEntry::FreePage(_)
+                | ManifestEntry::AllocParition(_)
+                | ManifestEntry::FreePartition(_)
+                | ManifestEntry::UpdatePage { .. }
+                | ManifestEntry::UpdateIndex { .. }
+        )
+    }
+
+    /// Returns the compensation entry which undoes this entry, `undo_next_lsn`
+    /// is the previous LSN of the undone record. The logical changes of
+    /// `UpdateIndex` are undone by the index instead.
+    pub fn undo(&self, undo_next_lsn: Lsn) -> Option<ManifestEntry> {
+        let entry = match self {
+            ManifestEntry::AllocPage(page) => ManifestEntry::UndoAllocPage {
+                page: *page,
+                undo_next_lsn,
+            },
+            ManifestEntry::FreePage(page) => ManifestEntry::UndoFreePage {
+                page: *page,
+                undo_next_lsn,
+            },
+            ManifestEntry::AllocParition(part_num) => ManifestEntry::UndoAllocParition {
+                part_num: *part_num,
+                undo_next_lsn,
+            },
+            ManifestEntry::FreePartition(part_num) => ManifestEntry::UndoFreeParition {
+                part_num: *part_num,
+                undo_next_lsn,
+            },
+            ManifestEntry::UpdatePage {
+                page,
+                offset,
+                before,
+                ..
+            } => ManifestEntry::UndoUpdatPage {
+                page: *page,
+                offset: *offset,
+                after: before.clone(),
+                undo_next_lsn,
+            },
+            _ => return None,
+        };
+        Some(entry)
+    }
 
-    BeginCheckpoint,
-    EndCheckpoint,
+    /// Returns the LSN of the next record to undo if this is a compensation
+    /// entry.
+    pub fn undo_next_lsn(&self) -> Option<Lsn> {
+        match self {
+            ManifestEntry::UndoAllocPage { undo_next_lsn, .. }
+            | ManifestEntry::UndoFreePage { undo_next_lsn, .. }
+            | ManifestEntry::UndoAllocParition { undo_next_lsn, .. }
+            | ManifestEntry::UndoFreeParition { undo_next_lsn, .. }
+            | ManifestEntry::UndoUpdatPage { undo_next_lsn, .. }
+            | ManifestEntry::EndNestedTopAction { undo_next_lsn } => Some(*undo_next_lsn),
+            _ => None,
+        }
+    }
+
+    /// Returns the partition the entry modifies.
+    pub fn part_num(&self) -> Option<usize> {
+        match self {
+            ManifestEntry::AllocParition(part_num)
+            | ManifestEntry::FreePartition(part_num)
+            | ManifestEntry::UndoAllocParition { part_num, .. }
+            | ManifestEntry::UndoFreeParition { part_num, .. } => Some(*part_num),
+            _ => self
+                .page()
+                .map(super::page::page_directory::calculate_part_num),
+        }
+    }
+
+    /// Returns the page the entry modifies.
+    pub fn page(&self) -> Option<u64> {
+        match self {
+            ManifestEntry::AllocPage(page)
+            | ManifestEntry::FreePage(page)
+            | ManifestEntry::UndoAllocPage { page, .. }
+            | ManifestEntry::UndoFreePage { page, .. }
+            | ManifestEntry::UpdatePage { page, .. }
+            | ManifestEntry::UndoUpdatPage { page, .. } => Some(*page),
+            _ => None,
+        }
+    }
+
+    /// Encodes the type tag (in declaration order) followed by the payload.
+    fn encode<B: BufMut>(&self, buf: &mut B) {
+        match self {
+            ManifestEntry::BeginTxn => buf.put_u8(0),
+            ManifestEntry::EndTnx => buf.put_u8(1),
+            ManifestEntry::CommitTxn => buf.put_u8(2),
+            ManifestEntry::AbortTxn => buf.put_u8(3),
+            ManifestEntry::BeginCheckpoint(max_txn) => {
+                buf.put_u8(4);
+                buf.put_u64(*max_txn);
+            }
+            ManifestEntry::EndCheckpoint { dirty_pages, txns } => {
+                buf.put_u8(5);
+                buf.put_u16(dirty_pages.len() as u16);
+                for (page, rec_lsn) in dirty_pages {
+                    buf.put_u64(*page);
+                    buf.put_u64(*rec_lsn);
+                }
+                buf.put_u16(txns.len() as u16);
+                for (txn, entry) in txns {
+                    buf.put_u64(*txn);
+                    buf.put_u8(entry.status as u8);
+                    buf.put_u64(entry.first_lsn);
+                    buf.put_u64(entry.last_lsn);
+                }
+            }
+            ManifestEntry::AllocPage(page) => {
+                buf.put_u8(6);
+                buf.put_u64(*page);
+            }
+            ManifestEntry::FreePage(page) => {
+                buf.put_u8(7);
+                buf.put_u64(*page);
+            }
+            ManifestEntry::AllocParition(part_num) => {
+                buf.put_u8(8);
+                buf.put_u64(*part_num as u64);
+            }
+            ManifestEntry::FreePartition(part_num) => {
+                buf.put_u8(9);
+                buf.put_u64(*part_num as u64);
+            }
+            ManifestEntry::UndoAllocPage {
+                page,
+                undo_next_lsn,
+            } => {
+                buf.put_u8(10);
+                buf.put_u64(*page);
+                buf.put_u64(*undo_next_lsn);
+            }
+            ManifestEntry::UndoFreePage {
+                page,
+                undo_next_lsn,
+            } => {
+                buf.put_u8(11);
+                buf.put_u64(*page);
+                buf.put_u64(*undo_next_lsn);
+            }
+            ManifestEntry::UndoAllocParition {
+                part_num,
+                undo_next_lsn,
+            } => {
+                buf.put_u8(12);
+                buf.put_u64(*part_num as u64);
+                buf.put_u64(*undo_next_lsn);
+            }
+            ManifestEntry::UndoFreeParition {
+                part_num,
+                undo_next_lsn,
+            } => {
+                buf.put_u8(13);
+                buf.put_u64(*part_num as u64);
+                buf.put_u64(*undo_next_lsn);
+            }
+            ManifestEntry::UpdatePage {
+                page,
+                offset,
+                before,
+                after,
+            } => {
+                buf.put_u8(14);
+                buf.put_u64(*page);
+                buf.put_u16(*offset);
+                buf.put_u16(after.len() as u16);
+                buf.put_slice(before);
+                buf.put_slice(after);
+            }
+            ManifestEntry::UndoUpdatPage {
+                page,
+                offset,
+                after,
+                undo_next_lsn,
+            } => {
+                buf.put_u8(15);
+                buf.put_u64(*page);
+                buf.put_u16(*offset);
+                buf.put_u16(after.len() as u16);
+                buf.put_slice(after);
+                buf.put_u64(*undo_next_lsn);
+            }
+            ManifestEntry::EndNestedTopAction { undo_next_lsn } => {
+                buf.put_u8(16);
+                buf.put_u64(*undo_next_lsn);
+            }
+            ManifestEntry::UpdateIndex {
+                change,
+                root,
+                key,
+                value,
+                begin_lsn,
+            } => {
+                buf.put_u8(17);
+                buf.put_u8(*change as u8);
+                buf.put_u64(*root);
+                buf.put_u64(*begin_lsn);
+                buf.put_u16(key.len() as u16);
+                buf.put_slice(key);
+                buf.put_u16(value.len() as u16);
+                buf.put_slice(value);
+            }
+        }
+    }
+
+    /// Decodes an entry from the verified body of a log record.
+    fn decode<B: Buf>(buf: &mut B) -> Result<Self> {
+        let corrupted = || Error::Corrupted("log record: unexpected end of buffer".to_owned());
+        let get_u64 = |buf: &mut B| {
+            if buf.remaining() < 8 {
+                return Err(corrupted());
+            }
+            Ok(buf.get_u64())
+        };
+        // The page, offset and length of images.
+        let get_image = |buf: &mut B| {
+            if buf.remaining() < 12 {
+                return Err(corrupted());
+            }
+            Ok((buf.get_u64(), buf.get_u16(), buf.get_u16() as usize))
+        };
+        let get_bytes = |buf: &mut B, len: usize| {
+            if buf.remaining() < len {
+                return Err(corrupted());
+            }
+            let mut bytes = vec![0u8; len];
+            buf.copy_to_slice(&mut bytes);
+            Ok(bytes)
+        };
 
-    AllocPage,
-    FreePage,
+        if !buf.has_remaining() {
+            return Err(corrupted());
+        }
+        let entry = match buf.get_u8() {
+            0 => ManifestEntry::BeginTxn,
+            1 => ManifestEntry::EndTnx,
+            2 => ManifestEntry::CommitTxn,
+            3 => ManifestEntry::AbortTxn,
+            4 => ManifestEntry::BeginCheckpoint(get_u64(buf)?),
+            5 => {
+                let mut dirty_pages = vec![];
+                for _ in 0..get_bytes(buf, 2).map(|n| u16::from_be_bytes([n[0], n[1]]))? {
+                    dirty_pages.push((get_u64(buf)?, get_u64(buf)?));
+                }
+                let mut txns = vec![];
+                for _ in 0..get_bytes(buf, 2).map(|n| u16::from_be_bytes([n[0], n[1]]))? {
+                    let txn = get_u64(buf)?;
+                    let status = match get_bytes(buf, 1)?[0] {
+                        0 => TxnStatus::Running,
+                        1 => TxnStatus::Committing,
+                        2 => TxnStatus::Aborting,
+                        status => {
+                            return Err(Error::Corrupted(format!(
+                                "checkpoint: transaction status {}",
+                                status
+                            )))
+                        }
+                    };
+                    let entry = TxnEntry {
+                        status,
+                        first_lsn: get_u64(buf)?,
+                        last_lsn: get_u64(buf)?,
+                    };
+                    txns.push((txn, entry));
+                }
+                ManifestEntry::EndCheckpoint { dirty_pages, txns }
+            }
+            6 => ManifestEntry::AllocPage(get_u64(buf)?),
+            7 => ManifestEntry::FreePage(get_u64(buf)?),
+            8 => ManifestEntry::AllocParition(get_u64(buf)? as usize),
+            9 => ManifestEntry::FreePartition(get_u64(buf)? as usize),
+            10 => ManifestEntry::UndoAllocPage {
+                page: get_u64(buf)?,
+                undo_next_lsn: get_u64(buf)?,
+            },
+            11 => ManifestEntry::UndoFreePage {
+                page: get_u64(buf)?,
+                undo_next_lsn: get_u64(buf)?,
+            },
+            12 => ManifestEntry::UndoAllocParition {
+                part_num: get_u64(buf)? as usize,
+                undo_next_lsn: get_u64(buf)?,
+            },
+            13 => ManifestEntry::UndoFreeParition {
+                part_num: get_u64(buf)? as usize,
+                undo_next_lsn: get_u64(buf)?,
+            },
+            14 => {
+                let (page, offset, len) = get_image(buf)?;
+                ManifestEntry::UpdatePage {
+                    page,
+                    offset,
+                    before: get_bytes(buf, len)?,
+                    after: get_bytes(buf, len)?,
+                }
+            }
+            15 => {
+                let (page, offset, len) = get_image(buf)?;
+                ManifestEntry::UndoUpdatPage {
+                    page,
+                    offset,
+                    after: get_bytes(buf, len)?,
+                    undo_next_lsn: get_u64(buf)?,
+                }
+            }
+            16 => ManifestEntry::EndNestedTopAction {
+                undo_next_lsn: get_u64(buf)?,
+            },
+            17 => {
+                let change = match get_bytes(buf, 1)?[0] {
+                    0 => IndexChange::BTreeInsert,
+                    1 => IndexChange::BTreeRemove,
+                    2 => IndexChange::HashInsert,
+                    3 => IndexChange::HashRemove,
+                    change => {
+                        return Err(Error::Corrupted(format!(
+                            "log record: index change {}",
+                            change
+                        )))
+                    }
+                };
+                let (root, begin_lsn) = (get_u64(buf)?, get_u64(buf)?);
+                let get_len = |buf: &mut B| {
+                    get_bytes(buf, 2).map(|n| u16::from_be_bytes([n[0], n[1]]) as usize)
+                };
+                let len = get_len(buf)?;
+                let key = get_bytes(buf, len)?;
+                let len = get_len(buf)?;
+                ManifestEntry::UpdateIndex {
+                    change,
+                    root,
+                    key,
+                    value: get_bytes(buf, len)?,
+                    begin_lsn,
+                }
+            }
+            tag => return Err(Error::Corrupted(format!("manifest entry tag {}", tag))),
+        };
+        Ok(entry)
+    }
+}
+
+/// A record of the write-ahead log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub lsn: Lsn,
+    pub txn: TxnId,
+    /// The LSN of the previous record of the transaction, 0 if this is the
+    /// first one.
+    pub prev_lsn: Lsn,
+    pub entry: ManifestEntry,
+}
 
-    AllocParition,
-    FreePartition,
+impl LogRecord {
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(RECORD_HEADER_SIZE);
+        body.put_u64(self.txn);
+        body.put_u64(self.prev_lsn);
+        self.entry.encode(&mut body);
 
-    UndoAllocPage,
-    UndoFreePage,
+        let mut buf = Vec::with_capacity(6 + body.len());
+        buf.put_u32(checksum::crc32(&body));
+        buf.put_u16(body.len() as u16);
+        buf.put_slice(&body);
+        buf
+    }
 
-    UndoAllocParition,
-    UndoFreeParition,
+    /// Decodes the record at `lsn` from `buf`, returns `None` if the record is
+    /// torn, i.e. the checksum mismatches.
+    fn decode(lsn: Lsn, buf: &mut &[u8]) -> Result<Option<Self>> {
+        if buf.remaining() < 6 {
+            return Ok(None);
+        }
+        let crc = buf.get_u32();
+        let len = buf.get_u16() as usize;
+        if buf.remaining() < len || !checksum::is_same(&buf[..len], crc) {
+            return Ok(None);
+        }
 
-    UpdatePage,
-    UndoUpdatPage,
+        let mut body = &buf[..len];
+        buf.advance(len);
+        if body.remaining() < 16 {
+            return Ok(None);
+        }
+        let txn = body.get_u64();
+        let prev_lsn = body.get_u64();
+        let entry = ManifestEntry::decode(&mut body)?;
+        Ok(Some(LogRecord {
+            lsn,
+            txn,
+            prev_lsn,
+            entry,
+        }))
+    }
 }
 
-pub struct Manifest {}
+/// Manifest is the write-ahead log (WAL) stored in the data pages of
+/// `LOG_PARTITION`. Every change to a page is logged before the page is written
+/// back, and the LSN of the last change is stored in the reserved bytes of page
+/// (the page LSN), so recovery can tell which logged changes a page has.
+///
+/// A log page starts with its sequence number and the size of used space,
+/// followed by records. Records never span pages. Appended records are kept in
+/// memory until `flush_to`, which writes and syncs the log.
+///
+/// The manifest also tracks the transaction table and the dirty page table,
+/// which are saved by checkpoints (see `checkpoint`), so that recovery starts
+/// from the last checkpoint, and the log before the oldest LSN still needed is
+/// truncated.
+pub struct Manifest {
+    partition: PartitionHandle,
+
+    /// The data page numbers of log pages, by their sequence numbers.
+    pages: BTreeMap<u64, usize>,
+
+    /// The log page being appended.
+    tail: Vec<u8>,
+    tail_seq: u64,
+
+    /// The LSN of the last appended record.
+    last_lsn: Lsn,
+    /// Records up to this LSN are durable.
+    flushed_lsn: Lsn,
+
+    /// The transactions which are not ended.
+    txns: HashMap<TxnId, TxnEntry>,
+    /// The pages changed since they were last synced, with the LSNs of the
+    /// first and the last change.
+    dirty_pages: HashMap<u64, (Lsn, Lsn)>,
+    /// The largest transaction id ever used.
+    max_txn: TxnId,
+
+    /// The number of log pages appended since the last checkpoint.
+    pages_since_checkpoint: usize,
+}
 
 impl Manifest {
-    pub async fn open(_path: &Path) -> Result<Manifest> {
-        todo!()
+    /// Opens the log partition of database at `path`, appending continues after
+    /// the last valid record. A torn record at the end of log, left by a crash,
+    /// is discarded.
+    pub async fn open(path: &str) -> Result<Self> {
+        let mut partition = PartitionHandle::open(LOG_PARTITION, path, DATA_HEADER_SIZE).await?;
+        if partition.is_not_allocated_page(MASTER_PAGE_NUM) {
+            partition.alloc_page().await?;
+        }
+
+        // A log page which is allocated but never written is released.
+        let mut pages = BTreeMap::new();
+        let mut page = vec![0u8; DEFAULT_PAGE_SIZE];
+        for page_num in partition.data_page_nums() {
+            if page_num == MASTER_PAGE_NUM {
+                continue;
+            }
+            partition.read_page(page_num, &mut page).await?;
+            match (&page[..8]).get_u64() {
+                0 => partition.release_page(page_num).await?,
+                seq => {
+                    pages.insert(seq, page_num);
+                }
+            }
+        }
+
+        let mut manifest = Self {
+            partition,
+            pages,
+            tail: vec![0u8; DEFAULT_PAGE_SIZE],
+            tail_seq: 0,
+            last_lsn: 0,
+            flushed_lsn: 0,
+            txns: HashMap::new(),
+            dirty_pages: HashMap::new(),
+            max_txn: 0,
+            pages_since_checkpoint: 0,
+        };
+
+        let tail_seq = match manifest.pages.keys().last() {
+            Some(seq) => *seq,
+            None => {
+                manifest.new_tail(1).await?;
+                return Ok(manifest);
+            }
+        };
+
+        for record in manifest.records_from(0).await? {
+            if let ManifestEntry::BeginCheckpoint(max_txn) = record.entry {
+                manifest.max_txn = manifest.max_txn.max(max_txn);
+            }
+            manifest.max_txn = manifest.max_txn.max(record.txn);
+            manifest.last_lsn = record.lsn;
+        }
+        manifest.flushed_lsn = manifest.last_lsn;
+
+        let mut tail = vec![0u8; DEFAULT_PAGE_SIZE];
+        manifest
+            .partition
+            .read_page(manifest.pages[&tail_seq], &mut tail)
+            .await?;
+        let (_, used) = Self::decode_page(tail_seq, &tail)?;
+        tail[used..].fill(0);
+        (&mut tail[8..LOG_PAGE_HEADER_SIZE]).put_u16(used as u16);
+        manifest.tail = tail;
+        manifest.tail_seq = tail_seq;
+        Ok(manifest)
+    }
+
+    /// Starts a new log page with sequence number `seq`, the page is allocated
+    /// from the recycled pages if any.
+    async fn new_tail(&mut self, seq: u64) -> Result<()> {
+        let page_num = self.partition.alloc_page().await?;
+        self.pages.insert(seq, page_num);
+        self.tail.fill(0);
+        (&mut self.tail[..LOG_PAGE_HEADER_SIZE]).put_u64(seq);
+        (&mut self.tail[8..LOG_PAGE_HEADER_SIZE]).put_u16(LOG_PAGE_HEADER_SIZE as u16);
+        self.tail_seq = seq;
+        Ok(())
+    }
+
+    /// Decodes the records of a log page, returns the records and the size of
+    /// the valid part of page.
+    fn decode_page(seq: u64, page: &[u8]) -> Result<(Vec<LogRecord>, usize)> {
+        let used = (u16::from_be_bytes([page[8], page[9]]) as usize)
+            .clamp(LOG_PAGE_HEADER_SIZE, DEFAULT_PAGE_SIZE);
+        let mut records = vec![];
+        let mut offset = LOG_PAGE_HEADER_SIZE;
+        while offset < used {
+            let mut buf = &page[offset..used];
+            match LogRecord::decode(make_lsn(seq, offset), &mut buf)? {
+                Some(record) => records.push(record),
+                None => break,
+            }
+            offset = used - buf.len();
+        }
+        Ok((records, offset))
+    }
+
+    /// Reads the log page of sequence number `seq`.
+    async fn read_log_page(&mut self, seq: u64) -> Result<Option<Vec<u8>>> {
+        if seq == self.tail_seq {
+            return Ok(Some(self.tail.clone()));
+        }
+        let page_num = match self.pages.get(&seq) {
+            Some(page_num) => *page_num,
+            None => return Ok(None),
+        };
+        let mut page = vec![0u8; DEFAULT_PAGE_SIZE];
+        self.partition.read_page(page_num, &mut page).await?;
+        Ok(Some(page))
+    }
+
+    /// Returns the records of the log from `lsn` in LSN order.
+    pub async fn records_from(&mut self, lsn: Lsn) -> Result<Vec<LogRecord>> {
+        let seqs = self
+            .pages
+            .range(lsn_seq(lsn)..)
+            .map(|(seq, _)| *seq)
+            .collect::<Vec<_>>();
+        let mut records = vec![];
+        for seq in seqs {
+            if let Some(page) = self.read_log_page(seq).await? {
+                let page_records = Self::decode_page(seq, &page)?.0;
+                records.extend(page_records.into_iter().filter(|r| r.lsn >= lsn));
+            }
+        }
+        Ok(records)
+    }
+
+    /// Reads the record at `lsn`.
+    pub async fn read(&mut self, lsn: Lsn) -> Result<LogRecord> {
+        let offset = lsn_offset(lsn);
+        let not_found = || Error::NotFound(format!("log record {}", lsn));
+        if offset < LOG_PAGE_HEADER_SIZE {
+            return Err(not_found());
+        }
+
+        let page = self
+            .read_log_page(lsn_seq(lsn))
+            .await?
+            .ok_or_else(not_found)?;
+        LogRecord::decode(lsn, &mut &page[offset..])?.ok_or_else(not_found)
+    }
+
+    /// Returns a new transaction id.
+    pub fn begin(&mut self) -> TxnId {
+        self.max_txn += 1;
+        self.max_txn
+    }
+
+    /// Appends a record of transaction `txn`, returns its LSN. The record is
+    /// not durable until `flush_to`.
+    pub async fn append(&mut self, txn: TxnId, entry: ManifestEntry) -> Result<Lsn> {
+        let record = LogRecord {
+            lsn: 0,
+            txn,
+            prev_lsn: self.txns.get(&txn).map_or(0, |t| t.last_lsn),
+            entry,
+        };
+        let buf = record.encode();
+        if LOG_PAGE_HEADER_SIZE + buf.len() > DEFAULT_PAGE_SIZE {
+            return Err(Error::TooLargeSize);
+        }
+
+        let mut used = u16::from_be_bytes([self.tail[8], self.tail[9]]) as usize;
+        if used + buf.len() > DEFAULT_PAGE_SIZE {
+            self.partition
+                .write_page(self.pages[&self.tail_seq], &self.tail)
+                .await?;
+            self.new_tail(self.tail_seq + 1).await?;
+            self.pages_since_checkpoint += 1;
+            used = LOG_PAGE_HEADER_SIZE;
+        }
+
+        let lsn = make_lsn(self.tail_seq, used);
+        self.tail[used..used + buf.len()].copy_from_slice(&buf);
+        (&mut self.tail[8..LOG_PAGE_HEADER_SIZE]).put_u16((used + buf.len()) as u16);
+        self.last_lsn = lsn;
+
+        if let Some(page) = record.entry.page() {
+            self.dirty_pages.entry(page).or_insert((lsn, lsn)).1 = lsn;
+        }
+        if txn != 0 {
+            self.max_txn = self.max_txn.max(txn);
+            if record.entry == ManifestEntry::EndTnx {
+                self.txns.remove(&txn);
+            } else {
+                let entry = self.txns.entry(txn).or_insert(TxnEntry {
+                    status: TxnStatus::Running,
+                    first_lsn: lsn,
+                    last_lsn: lsn,
+                });
+                entry.last_lsn = lsn;
+                match record.entry {
+                    ManifestEntry::CommitTxn => entry.status = TxnStatus::Committing,
+                    ManifestEntry::AbortTxn => entry.status = TxnStatus::Aborting,
+                    _ => {}
+                }
+            }
+        }
+        Ok(lsn)
+    }
+
+    /// Makes the records up to `lsn` durable.
+    pub async fn flush_to(&mut self, lsn: Lsn) -> Result<()> {
+        if lsn <= self.flushed_lsn {
+            return Ok(());
+        }
+
+        self.partition
+            .write_page(self.pages[&self.tail_seq], &self.tail)
+            .await?;
+        self.partition.sync().await?;
+        self.flushed_lsn = self.last_lsn;
+        Ok(())
+    }
+
+    /// Logs the commit of transaction and flushes the log, the transaction is
+    /// committed once this returns.
+    pub async fn commit(&mut self, txn: TxnId) -> Result<Lsn> {
+        let lsn = self.append(txn, ManifestEntry::CommitTxn).await?;
+        self.flush_to(lsn).await?;
+        Ok(lsn)
+    }
+
+    /// Logs the end of transaction, after which it's forgotten by recovery.
+    pub async fn end(&mut self, txn: TxnId) -> Result<Lsn> {
+        self.append(txn, ManifestEntry::EndTnx).await
+    }
+
+    /// Returns the LSN of the last record of a running transaction.
+    pub fn last_lsn_of(&self, txn: TxnId) -> Option<Lsn> {
+        self.txns.get(&txn).map(|t| t.last_lsn)
+    }
+
+    /// Restores a transaction which was not ended at the crash, found by
+    /// analysis of recovery.
+    pub(crate) fn restore_txn(&mut self, txn: TxnId, entry: TxnEntry) {
+        self.max_txn = self.max_txn.max(txn);
+        self.txns.insert(txn, entry);
+    }
+
+    /// Forgets the dirty pages which are durable, i.e. their last change is
+    /// not after `synced_lsn` and their partitions are synced, or their
+    /// partitions are deleted.
+    pub fn clean_pages(&mut self, synced_lsn: Lsn, synced_parts: &HashSet<usize>) {
+        self.dirty_pages.retain(|&page, (_, last_lsn)| {
+            synced_parts.contains(&calculate_part_num(page)) && *last_lsn > synced_lsn
+        });
+    }
+
+    /// Returns the number of log pages appended since the last checkpoint.
+    pub fn pages_since_checkpoint(&self) -> usize {
+        self.pages_since_checkpoint
+    }
+
+    /// Takes a checkpoint, the transaction table and the dirty page table are
+    /// logged between `BeginCheckpoint` and `EndCheckpoint`, and the LSN of
+    /// `BeginCheckpoint` is saved in the master record once the checkpoint is
+    /// durable. Returns the LSN of `BeginCheckpoint`.
+    ///
+    /// The checkpoint is fuzzy, the dirty pages are not written back, so the
+    /// log is only truncated up to the oldest LSN which may be needed by
+    /// recovery: the first change of dirty pages and the first record of
+    /// transactions not ended.
+    pub async fn checkpoint(&mut self) -> Result<Lsn> {
+        let begin_lsn = self
+            .append(0, ManifestEntry::BeginCheckpoint(self.max_txn))
+            .await?;
+
+        let mut dirty_pages = self
+            .dirty_pages
+            .iter()
+            .map(|(page, (rec_lsn, _))| (*page, *rec_lsn))
+            .collect::<Vec<_>>();
+        let mut txns = self
+            .txns
+            .iter()
+            .map(|(txn, entry)| (*txn, *entry))
+            .collect::<Vec<_>>();
+        let end_lsn = loop {
+            // 2-byte counts, 16 bytes per page and 25 bytes per transaction.
+            let num_pages = dirty_pages.len().min((MAX_CHECKPOINT_SIZE - 4) / 16);
+            let num_txns = txns
+                .len()
+                .min((MAX_CHECKPOINT_SIZE - 4 - num_pages * 16) / 25);
+            let entry = ManifestEntry::EndCheckpoint {
+                dirty_pages: dirty_pages.split_off(dirty_pages.len() - num_pages),
+                txns: txns.split_off(txns.len() - num_txns),
+            };
+            let lsn = self.append(0, entry).await?;
+            if dirty_pages.is_empty() && txns.is_empty() {
+                break lsn;
+            }
+        };
+        self.flush_to(end_lsn).await?;
+        self.write_master(begin_lsn).await?;
+
+        let oldest_lsn = self
+            .dirty_pages
+            .values()
+            .map(|(rec_lsn, _)| *rec_lsn)
+            .chain(self.txns.values().map(|t| t.first_lsn))
+            .fold(begin_lsn, Lsn::min);
+        self.truncate(oldest_lsn).await?;
+
+        self.pages_since_checkpoint = 0;
+        Ok(begin_lsn)
+    }
+
+    /// Returns the LSN of the last checkpoint saved in the master record.
+    pub async fn checkpoint_lsn(&mut self) -> Result<Option<Lsn>> {
+        let mut page = vec![0u8; DEFAULT_PAGE_SIZE];
+        self.partition.read_page(MASTER_PAGE_NUM, &mut page).await?;
+        let crc = (&page[..4]).get_u32();
+        if !checksum::is_same(&page[4..12], crc) {
+            return Ok(None);
+        }
+        Ok(Some((&page[4..12]).get_u64()).filter(|lsn| *lsn != 0))
+    }
+
+    async fn write_master(&mut self, checkpoint_lsn: Lsn) -> Result<()> {
+        let mut page = vec![0u8; DEFAULT_PAGE_SIZE];
+        (&mut page[4..12]).put_u64(checkpoint_lsn);
+        let crc = checksum::crc32(&page[4..12]);
+        (&mut page[..4]).put_u32(crc);
+        self.partition.write_page(MASTER_PAGE_NUM, &page).await?;
+        self.partition.sync().await
+    }
+
+    /// Releases the log pages whose records are all before `lsn`, the released
+    /// pages are reused by new log pages.
+    async fn truncate(&mut self, lsn: Lsn) -> Result<()> {
+        let seqs = self
+            .pages
+            .range(..lsn_seq(lsn).min(self.tail_seq))
+            .map(|(seq, _)| *seq)
+            .collect::<Vec<_>>();
+        for seq in seqs {
+            let page_num = self.pages.remove(&seq).unwrap();
+            self.partition.release_page(page_num).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the LSN of the last appended record.
+    pub fn last_lsn(&self) -> Lsn {
+        self.last_lsn
+    }
+
+    pub fn flushed_lsn(&self) -> Lsn {
+        self.flushed_lsn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn update(page: u64, len: usize) -> ManifestEntry {
+        ManifestEntry::UpdatePage {
+            page,
+            offset: 100,
+            before: vec![0; len],
+            after: vec![7; len],
+        }
+    }
+
+    #[test]
+    fn test_manifest() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("db").to_string_lossy().to_string();
+
+            let mut manifest = Manifest::open(&path).await.unwrap();
+            assert!(manifest.records_from(0).await.unwrap().is_empty());
+            let txn = manifest.begin();
+            let first = manifest
+                .append(txn, ManifestEntry::AllocParition(3))
+                .await
+                .unwrap();
+            assert!(first > 0);
+            // Large updates roll over to new log pages.
+            let mut lsns = vec![first];
+            for _ in 0..5 {
+                lsns.push(
+                    manifest
+                        .append(txn, update(7, MAX_UPDATE_SIZE))
+                        .await
+                        .unwrap(),
+                );
+            }
+            assert!(lsns.windows(2).all(|w| w[0] < w[1]));
+            assert!(manifest
+                .append(txn, update(7, MAX_UPDATE_SIZE + 1))
+                .await
+                .is_err());
+            lsns.push(manifest.commit(txn).await.unwrap());
+
+            // Records which are not flushed are lost.
+            let other = manifest.begin();
+            manifest.append(other, update(8, 10)).await.unwrap();
+            drop(manifest);
+
+            let mut manifest = Manifest::open(&path).await.unwrap();
+            let records = manifest.records_from(0).await.unwrap();
+            assert_eq!(records.iter().map(|r| r.lsn).collect::<Vec<_>>(), lsns);
+            assert_eq!(records[0].entry, ManifestEntry::AllocParition(3));
+            assert_eq!(records[0].prev_lsn, 0);
+            assert_eq!(records[1].entry, update(7, MAX_UPDATE_SIZE));
+            assert_eq!(records[1].prev_lsn, first);
+            assert!(records.iter().all(|r| r.txn == txn));
+            assert_eq!(manifest.read(lsns[3]).await.unwrap(), records[3]);
+            assert!(manifest.read(lsns[3] + 1).await.is_err());
+            assert_eq!(manifest.records_from(lsns[3]).await.unwrap(), records[3..]);
+            assert!(manifest.begin() > txn);
+
+            // A torn record at the end is discarded.
+            let lsn = manifest.append(txn, ManifestEntry::EndTnx).await.unwrap();
+            manifest.flush_to(lsn).await.unwrap();
+            let page_num = manifest.pages[&lsn_seq(lsn)];
+            let mut page = vec![0u8; DEFAULT_PAGE_SIZE];
+            manifest
+                .partition
+                .read_page(page_num, &mut page)
+                .await
+                .unwrap();
+            page[lsn_offset(lsn) + 10] ^= 0xff;
+            manifest
+                .partition
+                .write_page(page_num, &page)
+                .await
+                .unwrap();
+            drop(manifest);
+
+            let mut manifest = Manifest::open(&path).await.unwrap();
+            assert_eq!(manifest.records_from(0).await.unwrap().len(), lsns.len());
+            let txn = manifest.begin();
+            let lsn = manifest.append(txn, ManifestEntry::AbortTxn).await.unwrap();
+            manifest.flush_to(lsn).await.unwrap();
+            drop(manifest);
+
+            let mut manifest = Manifest::open(&path).await.unwrap();
+            let records = manifest.records_from(0).await.unwrap();
+            assert_eq!(records.len(), lsns.len() + 1);
+            assert_eq!(records.last().unwrap().entry, ManifestEntry::AbortTxn);
+        })
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("db").to_string_lossy().to_string();
+
+            let mut manifest = Manifest::open(&path).await.unwrap();
+            assert_eq!(manifest.checkpoint_lsn().await.unwrap(), None);
+            let committed = manifest.begin();
+            let mut lsns = vec![];
+            for page in 0..5 {
+                lsns.push(
+                    manifest
+                        .append(committed, update(page, MAX_UPDATE_SIZE))
+                        .await
+                        .unwrap(),
+                );
+            }
+            manifest.commit(committed).await.unwrap();
+            manifest.end(committed).await.unwrap();
+            let synced_lsn = manifest.last_lsn();
+            let running = manifest.begin();
+            let first = manifest.append(running, update(9, 10)).await.unwrap();
+            assert_eq!(manifest.pages_since_checkpoint(), 5);
+
+            // Page 9 is dirty, and the running transaction needs its records.
+            manifest.clean_pages(synced_lsn, &HashSet::from([0]));
+            let begin_lsn = manifest.checkpoint().await.unwrap();
+            assert_eq!(manifest.checkpoint_lsn().await.unwrap(), Some(begin_lsn));
+            assert_eq!(manifest.pages_since_checkpoint(), 0);
+            assert!(manifest.read(lsns[0]).await.is_err());
+            assert!(manifest.read(first).await.is_ok());
+            let records = manifest.records_from(0).await.unwrap();
+            assert_eq!(
+                records.last().unwrap().entry,
+                ManifestEntry::EndCheckpoint {
+                    dirty_pages: vec![(9, first)],
+                    txns: vec![(
+                        running,
+                        TxnEntry {
+                            status: TxnStatus::Running,
+                            first_lsn: first,
+                            last_lsn: first,
+                        }
+                    )],
+                }
+            );
+
+            // Released log pages are reused, and LSNs keep increasing.
+            manifest.end(running).await.unwrap();
+            manifest.clean_pages(manifest.last_lsn(), &HashSet::from([0]));
+            let begin_lsn = manifest.checkpoint().await.unwrap();
+            assert_eq!(manifest.partition.get_num_data_pages(), 2);
+            manifest
+                .append(running + 1, update(1, MAX_UPDATE_SIZE))
+                .await
+                .unwrap();
+            let lsn = manifest
+                .append(running + 1, update(1, MAX_UPDATE_SIZE))
+                .await
+                .unwrap();
+            assert!(lsn > begin_lsn);
+            manifest.flush_to(lsn).await.unwrap();
+            drop(manifest);
+
+            let mut manifest = Manifest::open(&path).await.unwrap();
+            assert_eq!(manifest.checkpoint_lsn().await.unwrap(), Some(begin_lsn));
+            let records = manifest.records_from(begin_lsn).await.unwrap();
+            assert_eq!(records[0].entry, ManifestEntry::BeginCheckpoint(running));
+            assert_eq!(records.last().unwrap().lsn, lsn);
+            assert!(manifest.begin() > running + 1);
+        })
+    }
+
+    #[test]
+    fn test_undo_entry() {
+        let entry = update(7, 2);
+        let clr = entry.undo(42).unwrap();
+        assert_eq!(
+            clr,
+            ManifestEntry::UndoUpdatPage {
+                page: 7,
+                offset: 100,
+                after: vec![0; 2],
+                undo_next_lsn: 42,
+            }
+        );
+        assert!(!clr.is_undoable());
+        assert_eq!(clr.undo_next_lsn(), Some(42));
+        assert_eq!(clr.page(), Some(7));
+        assert!(ManifestEntry::CommitTxn.undo(42).is_none());
+
+        // Index changes are undone logically, and the end of a nested top
+        // action skips to the record before it.
+        let entry = ManifestEntry::UpdateIndex {
+            change: IndexChange::BTreeRemove,
+            root: 7,
+            key: vec![1, 2, 3],
+            value: vec![4],
+            begin_lsn: 42,
+        };
+        assert!(entry.is_undoable() && entry.undo(42).is_none());
+        let end = ManifestEntry::EndNestedTopAction { undo_next_lsn: 42 };
+        assert!(!end.is_undoable());
+        assert_eq!(end.undo_next_lsn(), Some(42));
+        for entry in [entry, end] {
+            let mut buf = vec![];
+            entry.encode(&mut buf);
+            assert_eq!(ManifestEntry::decode(&mut &buf[..]).unwrap(), entry);
+        }
+        assert_eq!(
+            ManifestEntry::FreePartition(5).undo(0).unwrap().part_num(),
+            Some(5)
+        );
     }
 }
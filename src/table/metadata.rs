@@ -1,19 +1,297 @@
-use crate::catalog::schema::Schema;
+use crate::{
+    catalog::schema::{Column, DataType, Schema, SchemaChange},
+    datatypes::{array::scalar::ScalarImpl, record::Record},
+    error::{Error, Result},
+    table::page::{
+        page_directory::{effective_page_size, DATA_HEADER_SIZE},
+        PageFormat, SCHEMA_VERSION_SIZE,
+    },
+};
 
+/// The max length (in bytes) of a table name.
+pub const MAX_TABLE_NAME_LEN: u16 = 255;
+
+/// TableMetadata describes a table, it's persisted as a row of the catalog.
+///
+/// The schema is versioned: version 0 is the schema which the table is created
+/// with, and every `SchemaChange` bumps the version. Records are written with
+/// the version of schema at the time, and upgraded to the latest version when
+/// they're read (see `upgrade_record`), so altering a table doesn't rewrite its
+/// partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TableMetadata {
-    schema: Schema,
+    name: String,
+    /// The partition which stores the pages of table.
+    part_num: usize,
+    /// The number of records on each fixed-size data page formatted with the
+    /// current schema, 0 for slotted pages.
+    num_records_per_page: usize,
+    /// The schema of every version, the last one is the current schema.
+    schemas: Vec<Schema>,
+    /// The changes between versions, `changes[v]` turns version `v` into `v +
+    /// 1`.
+    changes: Vec<SchemaChange>,
 }
 
 impl TableMetadata {
-    pub fn new(schema: Schema) -> Self {
-        Self { schema }
+    pub fn new(
+        name: impl Into<String>,
+        schema: Schema,
+        part_num: usize,
+        num_records_per_page: usize,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            part_num,
+            num_records_per_page,
+            schemas: vec![schema],
+            changes: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn part_num(&self) -> usize {
+        self.part_num
+    }
+
+    pub fn num_records_per_page(&self) -> usize {
+        self.num_records_per_page
+    }
+
+    pub(crate) fn set_num_records_per_page(&mut self, num_records_per_page: usize) {
+        self.num_records_per_page = num_records_per_page;
     }
 
     pub fn get_schema(&self) -> &Schema {
-        &self.schema
+        self.schemas.last().unwrap()
+    }
+
+    /// Sets the page of the root node of index `name`, in every version of
+    /// schema and in the change which adds the index.
+    pub(crate) fn set_index_root(&mut self, name: &str, root: u64) {
+        for schema in &mut self.schemas {
+            schema.set_index_root(name, root);
+        }
+        for change in &mut self.changes {
+            match change {
+                SchemaChange::AddIndex(index) if index.name == name => index.root = Some(root),
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the current schema version.
+    pub fn version(&self) -> u16 {
+        self.changes.len() as u16
+    }
+
+    /// Returns the size (in bytes) of an entry of fixed-size data pages
+    /// formatted with the current schema. A formatted page keeps its entry
+    /// size in its header (see `DataPage::entry_size`).
+    pub fn entry_size(&self) -> usize {
+        SCHEMA_VERSION_SIZE + self.get_schema().estimated_size()
+    }
+
+    /// Returns the metadata with the change applied as a new schema version.
+    pub fn alter(&self, change: SchemaChange) -> Result<TableMetadata> {
+        if self.changes.len() >= u16::MAX as usize {
+            return Err(Error::Invalid(format!(
+                "alter table {}: too many versions",
+                self.name
+            )));
+        }
+
+        let schema = self.get_schema().apply_change(&change)?;
+        let mut metadata = self.clone();
+        metadata.schemas.push(schema);
+        metadata.changes.push(change);
+
+        // The pages formatted afterwards hold fewer records if the entries
+        // grow.
+        if metadata.get_schema().page_format() == PageFormat::Fixed {
+            let max_records_per_page = max_records_per_page(metadata.entry_size());
+            if max_records_per_page == 0 {
+                return Err(Error::Invalid(format!(
+                    "alter table {}: records don't fit a page, use slotted pages instead",
+                    self.name
+                )));
+            }
+            metadata.num_records_per_page = self.num_records_per_page.min(max_records_per_page);
+        }
+        Ok(metadata)
+    }
+
+    /// Upgrades a record written with schema `version` to the current schema.
+    pub fn upgrade_record(&self, version: u16, mut record: Record) -> Result<Record> {
+        let version = version as usize;
+        if version > self.changes.len() {
+            return Err(Error::Corrupted(format!(
+                "record of table {}: schema version {}",
+                self.name, version
+            )));
+        }
+
+        for (change, schema) in self.changes[version..].iter().zip(&self.schemas[version..]) {
+            record = change.upgrade_record(schema, record)?;
+        }
+        Ok(record)
     }
 
     pub fn flush(&self) -> Self {
         todo!()
     }
+
+    /// Returns the schema of the catalog rows. The schema of version 0 and
+    /// the schema changes are stored as json, so the rows have variable length.
+    pub fn row_schema() -> Schema {
+        Schema::new()
+            .add(Column::new("table_name", DataType::String(MAX_TABLE_NAME_LEN)).not_null())
+            .add(Column::new("part_num", DataType::Int64).not_null())
+            .add(Column::new("num_records_per_page", DataType::Int32).not_null())
+            .add(Column::new("schema", DataType::String(u16::MAX)).not_null())
+            .add(Column::new("schema_changes", DataType::String(u16::MAX)).not_null())
+            .with_page_format(PageFormat::SlotDirectory)
+    }
+
+    /// Converts the metadata into a catalog row.
+    pub fn to_record(&self) -> Result<Record> {
+        let invalid =
+            |e: serde_json::Error| Error::Invalid(format!("schema of table {}: {}", self.name, e));
+        let schema = serde_json::to_string(&self.schemas[0]).map_err(invalid)?;
+        let changes = serde_json::to_string(&self.changes).map_err(invalid)?;
+
+        Ok(Record::from(vec![
+            ScalarImpl::String(self.name.clone()),
+            ScalarImpl::Int64(self.part_num as i64),
+            ScalarImpl::Int32(self.num_records_per_page as i32),
+            ScalarImpl::String(schema),
+            ScalarImpl::String(changes),
+        ]))
+    }
+
+    /// Parses the metadata from a catalog row.
+    pub fn from_record(record: &Record) -> Result<Self> {
+        match (
+            record.get(0),
+            record.get(1),
+            record.get(2),
+            record.get(3),
+            record.get(4),
+        ) {
+            (
+                Some(ScalarImpl::String(name)),
+                Some(ScalarImpl::Int64(part_num)),
+                Some(ScalarImpl::Int32(num_records_per_page)),
+                Some(ScalarImpl::String(schema)),
+                Some(ScalarImpl::String(changes)),
+            ) => {
+                let corrupted = |e: serde_json::Error| {
+                    Error::Corrupted(format!("schema of table {}: {}", name, e))
+                };
+                let schema = serde_json::from_str(schema).map_err(corrupted)?;
+                let changes: Vec<SchemaChange> =
+                    serde_json::from_str(changes).map_err(corrupted)?;
+
+                let mut metadata = Self::new(
+                    name.clone(),
+                    schema,
+                    *part_num as usize,
+                    *num_records_per_page as usize,
+                );
+                for change in changes {
+                    metadata = metadata.alter(change)?;
+                }
+                Ok(metadata)
+            }
+            _ => Err(Error::Corrupted(format!("catalog row {:?}", record))),
+        }
+    }
+}
+
+/// Returns the max number of fixed-size entries of `entry_size` bytes on a
+/// data page, 0 if no entry fits.
+pub fn max_records_per_page(entry_size: usize) -> usize {
+    // n records take ceil(n / 8) bytes of bitmap plus n * entry_size bytes.
+    let data_size = effective_page_size() - DATA_HEADER_SIZE;
+    8 * data_size / (1 + 8 * entry_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::schema::TableIndex;
+
+    #[test]
+    fn test_metadata_record() {
+        let schema = Schema::new()
+            .add(Column::new("id", DataType::Int32).not_null())
+            .add(
+                Column::new("name", DataType::String(16))
+                    .with_default(ScalarImpl::String("anonymous".to_owned())),
+            )
+            .add_index(TableIndex::new("idx_id", vec![0]))
+            .with_page_format(PageFormat::SlotDirectory);
+        let metadata = TableMetadata::new("users", schema, 3, 0)
+            .alter(SchemaChange::AddColumn(Column::new("age", DataType::Int16)))
+            .unwrap()
+            .alter(SchemaChange::RenameColumn(
+                "name".to_owned(),
+                "nickname".to_owned(),
+            ))
+            .unwrap();
+        assert_eq!(metadata.version(), 2);
+
+        let record = TableMetadata::row_schema()
+            .verify_record(metadata.to_record().unwrap())
+            .unwrap();
+        assert_eq!(TableMetadata::from_record(&record).unwrap(), metadata);
+
+        let record = Record::from(vec![ScalarImpl::String("users".to_owned())]);
+        assert!(TableMetadata::from_record(&record).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_record() {
+        let schema = Schema::new()
+            .add(Column::new("id", DataType::Int32).not_null())
+            .add(Column::new("name", DataType::String(16)));
+        let v0 = TableMetadata::new("users", schema, 3, 8);
+        let v1 = v0
+            .alter(SchemaChange::DropColumn("name".to_owned()))
+            .unwrap();
+        let v2 = v1
+            .alter(SchemaChange::AddColumn(
+                Column::new("active", DataType::Bool).with_default(ScalarImpl::Bool(true)),
+            ))
+            .unwrap();
+
+        let record = Record::from(vec![
+            ScalarImpl::Int32(1),
+            ScalarImpl::String("rookie".to_owned()),
+        ]);
+        let expected = Record::from(vec![ScalarImpl::Int32(1), ScalarImpl::Bool(true)]);
+        assert_eq!(v2.upgrade_record(0, record).unwrap(), expected);
+        assert_eq!(v2.upgrade_record(2, expected.clone()).unwrap(), expected);
+        assert!(v2.upgrade_record(3, expected).is_err());
+
+        // Fixed-size entries grow for the pages formatted afterwards, as long
+        // as an entry fits a page.
+        let v3 = v2
+            .alter(SchemaChange::AddColumn(Column::new(
+                "bio",
+                DataType::String(1024),
+            )))
+            .unwrap();
+        assert!(v3.entry_size() > v2.entry_size());
+        assert_eq!(v3.num_records_per_page(), 3);
+        assert!(v3
+            .alter(SchemaChange::AddColumn(Column::new(
+                "cover",
+                DataType::String(4096)
+            )))
+            .is_err());
+    }
 }
@@ -1,21 +1,43 @@
 mod bg;
-mod cache;
+pub mod cache;
 pub mod index;
-mod manifest;
+pub mod manifest;
 pub mod metadata;
 pub mod page;
 pub mod recover;
 mod stats;
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
 
 use self::{
-    index::{btree::BTree, btree_builder::BTreeBuilder}, metadata::TableMetadata, page::partition::PartitionHandle,
+    cache::PageGuard,
+    index::{
+        btree::BTree,
+        btree_builder::BTreeBuilder,
+        filter::PageFilters,
+        hash::HashIndex,
+        key::{key_range, prefix_range, IndexKey},
+        node::check_entry_size,
+    },
+    manifest::TxnId,
+    metadata::{max_records_per_page, TableMetadata},
+    page::{
+        page_directory::{effective_page_size, virtual_page_num, PageDirectory, DATA_HEADER_SIZE},
+        PageFormat, SCHEMA_VERSION_SIZE, SLOT_SIZE,
+    },
     stats::TableStats,
 };
 use crate::{
-    catalog::schema::Schema,
-    datatypes::record::{new_record_id, Record, RecordId},
+    catalog::schema::{IndexKind, Schema, TableIndex},
+    concurrency::{lock_manager::LockManager, lock_type::LockType, resource::ResourceName},
+    datatypes::{
+        array::scalar::ScalarImpl,
+        record::{new_record_id, Record, RecordId},
+    },
     error::{Error, Result},
 };
 
@@ -29,6 +51,15 @@ use crate::{
 /// A table can be loaded again by simply constructing it with the same
 /// parameters.
 ///
+/// Every change to a data page is logged by transaction `txn` before the page
+/// is written back (see `PageDirectory::log_page_update`), so that the change
+/// can be redone or undone by recovery.
+///
+/// A table with a lock manager (see `with_lock_manager`) locks a data page in
+/// X for transaction before it's changed, since the change is undone by
+/// restoring the page as logged. `insert` skips the pages locked by others,
+/// and never waits for a lock while it holds a latch.
+///
 /// # Storage Format
 /// All pages are data pages - there are no header pages, because all metadata
 /// is stored elsewhere (as rows in the _metadata.tables table). Every daa
@@ -38,6 +69,7 @@ use crate::{
 /// For example, here is a cartoon of what a table's file would look like if we
 /// had 5-byte pages and 1-byte records:
 ///
+/// ```text
 ///          +----------+----------+----------+----------+----------+ \
 ///   Page 0 | 1001xxxx | 01111010 | xxxxxxxx | xxxxxxxx | 01100001 |  |
 ///          +----------+----------+----------+----------+----------+  |
@@ -47,6 +79,7 @@ use crate::{
 ///          +----------+----------+----------+----------+----------+ /
 ///           \________/ \________/ \________/ \________/ \________/
 ///            bitmap     record 0   record 1   record 2   record 3
+/// ```
 /// - The first page (Page 0) is a data page. The first byte of this data page
 ///   is a bitmap, and the next four bytes are each records. The first and
 ///   fourth bytes are set indicating that record 0 and record 3 ar valid.
@@ -70,75 +103,537 @@ use crate::{
 /// tuple-level locks are necessary even at the cost of an I/O per tuple, a full
 /// page record may be desirable), and may be explicitly toggled on with the
 /// `set_full_page_records` methods.
+///
+/// Records of tables with wide string columns are usually much smaller than
+/// `Schema::estimated_size`, such tables can use `PageFormat::SlotDirectory`
+/// instead (see `Schema::with_page_format`), where each record takes only its
+/// encoded size plus a 4-byte slot. The entry number of record id is the slot
+/// number, which is stable across updates as long as the record still fits on
+/// its page.
+///
+/// # Indices
+/// Every index of the schema is a b+tree in the index partition, which maps
+/// the values of indexed columns (see `IndexKey`) to the record id, and is
+/// kept in sync by `insert`, `update` and `remove` within the same
+/// transaction. Records whose indexed values are all `NULL` are not indexed.
+/// An index added to a table with records (see `SchemaChange::AddIndex`) is
+/// bulk loaded from them instead (see `build_index`).
+///
+/// An index of `IndexKind::Hash` is a `HashIndex` instead, which maps the
+/// values to the ids of all records with them, and is only looked up by the
+/// values of all its columns. Records with any `NULL` value are not indexed,
+/// as they're never equal to the values.
+///
+/// An index of `IndexKind::Bloom` is a bloom filter of the values on every
+/// data page instead (see `PageFilters`), a lookup reads only the pages which
+/// may contain the values, and `may_contain` tells a join probe whether a page
+/// is worth reading.
+///
+/// A unique index (e.g. the primary key) rejects a record whose non-null
+/// indexed values are already in the index with `Error::ConstraintViolation`,
+/// which is checked before the record is written, and again atomically when
+/// the entry is inserted (see `BTree::try_insert`) in case another writer
+/// inserts the values in the meantime, then the transaction must be rolled
+/// back. Records with `NULL` values never collide.
+///
+/// Records can be looked up by the values of the first columns of an index
+/// (see `lookup`), plus a range of the next column (see `lookup_range`).
 pub struct Table {
     metadata: TableMetadata,
 
-    indices: HashMap<String, BTree<i32, i32>>,
-
-    /// The partition of table.
-    part_handle: Box<PartitionHandle>,
-
-    // The size (in bytes) of the bitmap found at the beginning of each data page.
-    bitmap_size: usize,
+    /// The trees of indices by name, which are changed concurrently.
+    indices: HashMap<String, Index>,
 
-    /// The number of records on each data page.
-    num_records_per_page: usize,
+    /// The page directory which the partition of table belongs to.
+    page_directory: Arc<PageDirectory>,
 
     /// Statistics about the contents of the database.
     table_stats: TableStats,
+
+    /// The lock manager of the data pages changed by transactions, if any.
+    lock_manager: Option<Arc<LockManager>>,
+}
+
+/// The tree, hash index or filters of a `TableIndex`.
+enum Index {
+    BTree(BTree<IndexKey, RecordId>),
+    Hash(HashIndex<IndexKey, RecordId>),
+    Bloom(PageFilters<IndexKey>),
+}
+
+impl Index {
+    fn root(&self) -> u64 {
+        match self {
+            Index::BTree(btree) => btree.root(),
+            Index::Hash(hash) => hash.root(),
+            Index::Bloom(filters) => filters.root(),
+        }
+    }
+
+    async fn contains_key(&self, key: &IndexKey) -> Result<bool> {
+        match self {
+            Index::BTree(btree) => btree.contains_key(key).await,
+            Index::Hash(hash) => hash.contains_key(key).await,
+            Index::Bloom(_) => unreachable!("filters are never unique."),
+        }
+    }
+
+    /// Adds the entry of record `rid`, returns the record which already has
+    /// the key if the index is unique.
+    async fn insert(
+        &self,
+        txn: TxnId,
+        key: IndexKey,
+        rid: RecordId,
+        unique: bool,
+    ) -> Result<Option<RecordId>> {
+        match self {
+            // The keys of a non-unique tree are distinct by record id.
+            Index::BTree(btree) => btree.try_insert(txn, key, rid).await,
+            Index::Hash(hash) if unique => hash.try_insert(txn, key, rid).await,
+            Index::Hash(hash) => hash.insert(txn, key, rid).await.map(|_| None),
+            Index::Bloom(filters) => filters.add(txn, rid.0 as usize, &key).await.map(|_| None),
+        }
+    }
+
+    async fn remove(&self, txn: TxnId, key: &IndexKey, rid: RecordId) -> Result<()> {
+        match self {
+            Index::BTree(btree) => btree.remove(txn, key).await.map(|_| ()),
+            Index::Hash(hash) => hash.remove(txn, key, &rid).await.map(|_| ()),
+            // The values stay in the filter of page.
+            Index::Bloom(_) => Ok(()),
+        }
+    }
+
+    async fn destroy(self) -> Result<()> {
+        match self {
+            Index::BTree(btree) => btree.destroy().await,
+            Index::Hash(hash) => hash.destroy().await,
+            Index::Bloom(filters) => filters.destroy().await,
+        }
+    }
 }
 
 /// Index associated method.
 impl Table {
+    /// Opens the indices of table which aren't open yet, the tree of an index
+    /// which has none yet is created and its root is recorded in the metadata,
+    /// which is persisted when the table is added to the catalog.
     async fn initialize_indices(&mut self) -> Result<()> {
-        let indices = self.get_schema().get_indcies();
-        let mut ans = HashMap::with_capacity(indices.len());
+        let indices = self.get_schema().get_indcies().to_vec();
+        for index in indices {
+            if self.indices.contains_key(&index.name) {
+                continue;
+            }
+            self.check_index(&index)?;
+            let tree = self.open_index(&index).await?;
+            if index.root.is_none() {
+                self.metadata.set_index_root(&index.name, tree.root());
+            }
+
+            self.indices.insert(index.name.clone(), tree);
+        }
+
+        Ok(())
+    }
+
+    /// Opens the tree of index, or creates an empty one if the index has none
+    /// yet.
+    async fn open_index(&self, index: &TableIndex) -> Result<Index> {
+        let page_directory = self.page_directory.clone();
+        let tree = match (index.kind, index.root) {
+            (IndexKind::BTree, _) => {
+                Index::BTree(BTreeBuilder::new(page_directory, index).finish().await?)
+            }
+            (IndexKind::Hash, Some(root)) => {
+                Index::Hash(HashIndex::open(page_directory, root).await?)
+            }
+            (IndexKind::Hash, None) => Index::Hash(HashIndex::create(page_directory).await?),
+            (IndexKind::Bloom, Some(root)) => {
+                Index::Bloom(PageFilters::open(page_directory, root, self.keys_per_page()).await?)
+            }
+            (IndexKind::Bloom, None) => {
+                Index::Bloom(PageFilters::create(page_directory, self.keys_per_page()).await?)
+            }
+        };
+        Ok(tree)
+    }
+
+    /// Drops the indices which are no longer in the schema, and opens the
+    /// indices added (see `build_index`). Dropping an indexed column is
+    /// rejected and the column ids of index are remapped by the schema, so the
+    /// remaining indices are still valid.
+    async fn alter_index(&mut self) -> Result<()> {
+        let indices = self.metadata.get_schema().get_indcies();
+        self.indices
+            .retain(|name, _| indices.iter().any(|index| &index.name == name));
+        self.initialize_indices().await
+    }
+
+    /// Builds the index added by `SchemaChange::AddIndex` from the records of
+    /// table, returns the page of its root, which is recorded in the catalog
+    /// with the altered metadata. A b+tree is bulk loaded (see
+    /// `BTreeBuilder`), the entries of a hash index or bloom filters are
+    /// inserted by transaction. Returns `Error::ConstraintViolation` if the
+    /// index is unique but records have the same values, then nothing is
+    /// written.
+    pub async fn build_index(&self, txn: TxnId, index: &TableIndex) -> Result<u64> {
+        self.check_index(index)?;
+        let records = self.scan().await?;
+        let mut entries = Vec::with_capacity(records.len());
+        for (rid, record) in &records {
+            if let Some(key) = Self::index_key(index, record, *rid) {
+                check_entry_size(&key, rid)?;
+                entries.push((key, *rid));
+            }
+        }
+        if index.unique {
+            let mut sorted = (0..entries.len()).collect::<Vec<_>>();
+            sorted.sort_by(|&a, &b| entries[a].0.cmp(&entries[b].0));
+            if let Some(pair) = sorted
+                .windows(2)
+                .find(|pair| entries[pair[0]].0 == entries[pair[1]].0)
+            {
+                return Err(Self::duplicate_key(index, &records[pair[1]].1));
+            }
+        }
+
+        let tree = match index.kind {
+            IndexKind::BTree => {
+                let mut builder = BTreeBuilder::new(self.page_directory.clone(), index);
+                for (key, rid) in entries {
+                    builder.add(key, rid).await?;
+                }
+                Index::BTree(builder.finish().await?)
+            }
+            IndexKind::Hash | IndexKind::Bloom => {
+                let tree = self.open_index(index).await?;
+                for (key, rid) in entries {
+                    tree.insert(txn, key, rid, index.unique).await?;
+                }
+                tree
+            }
+        };
+        Ok(tree.root())
+    }
+
+    /// Checks that the columns of index are in the table.
+    fn check_index(&self, index: &TableIndex) -> Result<()> {
+        let num_cols = self.get_schema().len();
+        if index.cols.is_empty() || index.cols.iter().any(|&col| col as usize >= num_cols) {
+            return Err(Error::Invalid(format!(
+                "index {} on columns {:?}",
+                index.name, index.cols
+            )));
+        }
+        if index.unique && index.kind == IndexKind::Bloom {
+            return Err(Error::Invalid(format!(
+                "bloom filter index {} can't be unique",
+                index.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the (estimated) number of records on a data page.
+    fn keys_per_page(&self) -> usize {
+        match self.num_records_per_page() {
+            0 => {
+                (effective_page_size() - DATA_HEADER_SIZE)
+                    / (self.metadata.entry_size() + SLOT_SIZE)
+            }
+            n => n,
+        }
+    }
+
+    /// Returns the key of record `rid` in index, `None` if the indexed values
+    /// are all `NULL` (or any is for a hash or bloom filter index).
+    fn index_key(index: &TableIndex, record: &Record, rid: RecordId) -> Option<IndexKey> {
+        let values = index
+            .cols
+            .iter()
+            .map(|&col| record.get(col as usize))
+            .collect::<Vec<_>>();
+        if index.kind != IndexKind::BTree {
+            if values.iter().any(Option::is_none) {
+                return None;
+            }
+            return Some(IndexKey::unique(&values));
+        }
+        if values.iter().all(Option::is_none) {
+            return None;
+        }
+        match Self::unique_key(index, record) {
+            Some(key) => Some(key),
+            None => Some(IndexKey::new(&values, rid)),
+        }
+    }
+
+    /// Returns the key of record in a unique index, which is the same for
+    /// records with the same values, `None` if the index isn't unique or any
+    /// indexed value is `NULL`.
+    fn unique_key(index: &TableIndex, record: &Record) -> Option<IndexKey> {
+        if !index.unique {
+            return None;
+        }
+        let values = index
+            .cols
+            .iter()
+            .map(|&col| record.get(col as usize).map(Some))
+            .collect::<Option<Vec<_>>>()?;
+        Some(IndexKey::unique(&values))
+    }
+
+    /// Checks that record doesn't violate the unique indices, `old` is the
+    /// record which is updated to `record`.
+    async fn check_unique(&self, record: &Record, old: Option<&Record>) -> Result<()> {
         for index in self.get_schema().get_indcies() {
-            let builder = BTreeBuilder::<i32, i32>::new(index);
-            let btree = builder.finish();
+            let key = match Self::unique_key(index, record) {
+                Some(key) => key,
+                None => continue,
+            };
+            if old.and_then(|old| Self::unique_key(index, old)).as_ref() == Some(&key) {
+                continue;
+            }
 
-            ans.insert(index.name.clone(), btree);
+            if self.indices[&index.name].contains_key(&key).await? {
+                return Err(Self::duplicate_key(index, record));
+            }
         }
+        Ok(())
+    }
 
-        self.indices = ans;
+    fn duplicate_key(index: &TableIndex, record: &Record) -> Error {
+        let values = index
+            .cols
+            .iter()
+            .map(|&col| record.get(col as usize))
+            .collect::<Vec<_>>();
+        Error::ConstraintViolation(format!(
+            "duplicate key {:?} of index {}",
+            values, index.name
+        ))
+    }
 
+    /// Adds the entry of record `rid` to every index.
+    async fn insert_index_entry(&self, txn: TxnId, record: &Record, rid: RecordId) -> Result<()> {
+        for index in self.get_schema().get_indcies() {
+            if let Some(key) = Self::index_key(index, record, rid) {
+                let tree = &self.indices[&index.name];
+                match tree.insert(txn, key, rid, index.unique).await? {
+                    Some(other) if other != rid => return Err(Self::duplicate_key(index, record)),
+                    _ => {}
+                }
+            }
+        }
         Ok(())
     }
 
-    async fn alter_index(&mut self) -> Result<()> {
-        todo!()
+    /// Removes the entry of record `rid` from every index.
+    async fn remove_index_entry(&self, txn: TxnId, record: &Record, rid: RecordId) -> Result<()> {
+        for index in self.get_schema().get_indcies() {
+            if let Some(key) = Self::index_key(index, record, rid) {
+                self.indices[&index.name].remove(txn, &key, rid).await?;
+            }
+        }
+        Ok(())
     }
 
-    async fn insert_index_entry(&self, record: &Record) -> Result<()> {
-        todo!()
+    /// Returns the ids of records whose first columns of index `name` are
+    /// equal to `prefix`, in key order. A hash or bloom filter index is looked
+    /// up by the values of all its columns, and the ids of a hash index are in
+    /// no particular order.
+    pub async fn lookup(&self, name: &str, prefix: &[ScalarImpl]) -> Result<Vec<RecordId>> {
+        let (index, prefix) = self.cast_prefix(name, prefix, 0)?;
+        if index.kind != IndexKind::BTree && prefix.len() < index.cols.len() {
+            return Err(Error::Invalid(format!(
+                "lookup {:?} index {} of {} columns by {} columns",
+                index.kind,
+                name,
+                index.cols.len(),
+                prefix.len()
+            )));
+        }
+
+        let values = prefix.iter().map(Some).collect::<Vec<_>>();
+        match &self.indices[&index.name] {
+            Index::BTree(btree) => btree.scan(prefix_range(&prefix)).await,
+            Index::Hash(hash) => hash.get(&IndexKey::unique(&values)).await,
+            Index::Bloom(filters) => {
+                let page_nums = self
+                    .page_directory
+                    .get_partition(self.get_part_num())?
+                    .data_page_nums();
+                let page_nums = filters
+                    .filter_pages(&page_nums, &IndexKey::unique(&values))
+                    .await?;
+
+                let mut ids = vec![];
+                for page_num in page_nums {
+                    let page = self.fetch_page(page_num as u64).await?;
+                    for entry_num in 0..page.as_data_page().capacity() {
+                        if !page.contains(entry_num) {
+                            continue;
+                        }
+                        let (version, record) = page.read_entry(entry_num).await?;
+                        let record = self.metadata.upgrade_record(version, record)?;
+                        let matches = index
+                            .cols
+                            .iter()
+                            .zip(&values)
+                            .all(|(&col, value)| record.get(col as usize) == *value);
+                        if matches {
+                            ids.push(new_record_id(page_num as u64, entry_num));
+                        }
+                    }
+                }
+                Ok(ids)
+            }
+        }
     }
 
-    async fn remove_index_entry(&self, record: &Record) -> Result<()> {
-        todo!()
+    /// Returns whether data page `page_num` may contain records whose columns
+    /// of bloom filter index `name` are equal to `values`, false if it
+    /// definitely doesn't, e.g. for a join probe to skip the page.
+    pub async fn may_contain(
+        &self,
+        name: &str,
+        page_num: u64,
+        values: &[ScalarImpl],
+    ) -> Result<bool> {
+        let (index, values) = self.cast_prefix(name, values, 0)?;
+        let filters = match &self.indices[&index.name] {
+            Index::Bloom(filters) if values.len() == index.cols.len() => filters,
+            _ => {
+                return Err(Error::Invalid(format!(
+                    "probe {:?} index {} of {} columns by {} columns",
+                    index.kind,
+                    name,
+                    index.cols.len(),
+                    values.len()
+                )))
+            }
+        };
+        let values = values.iter().map(Some).collect::<Vec<_>>();
+        filters
+            .may_contain(page_num as usize, &IndexKey::unique(&values))
+            .await
+    }
+
+    /// Returns the ids of records whose first columns of index `name` are
+    /// equal to `prefix` and whose next column is in `range`, in key order.
+    /// Records whose next column is `NULL` are not returned. A hash or bloom
+    /// filter index can't be looked up by range.
+    pub async fn lookup_range<R>(
+        &self,
+        name: &str,
+        prefix: &[ScalarImpl],
+        range: R,
+    ) -> Result<Vec<RecordId>>
+    where
+        R: RangeBounds<ScalarImpl>,
+    {
+        let (index, prefix) = self.cast_prefix(name, prefix, 1)?;
+        let data_type = &self.get_schema().columns()[index.cols[prefix.len()] as usize].data_type;
+        let cast = |bound: Bound<&ScalarImpl>| -> Result<Bound<ScalarImpl>> {
+            Ok(match bound {
+                Bound::Included(v) => Bound::Included(data_type.cast(v.clone())?),
+                Bound::Excluded(v) => Bound::Excluded(data_type.cast(v.clone())?),
+                Bound::Unbounded => Bound::Unbounded,
+            })
+        };
+        let start = cast(range.start_bound())?;
+        let end = cast(range.end_bound())?;
+
+        match &self.indices[&index.name] {
+            Index::BTree(btree) => {
+                btree
+                    .scan(key_range(&prefix, (start.as_ref(), end.as_ref())))
+                    .await
+            }
+            Index::Hash(_) | Index::Bloom(_) => Err(Error::Invalid(format!(
+                "lookup {:?} index {} by range",
+                index.kind, name
+            ))),
+        }
+    }
+
+    /// Casts `prefix` to the types of the first columns of index `name`, which
+    /// must have at least `extra` more columns.
+    fn cast_prefix(
+        &self,
+        name: &str,
+        prefix: &[ScalarImpl],
+        extra: usize,
+    ) -> Result<(&TableIndex, Vec<ScalarImpl>)> {
+        let index = self
+            .get_schema()
+            .get_indcies()
+            .iter()
+            .find(|index| index.name == name)
+            .ok_or_else(|| Error::NotFound(format!("index {}", name)))?;
+        if prefix.len() + extra > index.cols.len() {
+            return Err(Error::Invalid(format!(
+                "lookup index {} of {} columns by {} columns",
+                name,
+                index.cols.len(),
+                prefix.len() + extra
+            )));
+        }
+
+        let columns = self.get_schema().columns();
+        let prefix = prefix
+            .iter()
+            .zip(&index.cols)
+            .map(|(value, &col)| columns[col as usize].data_type.cast(value.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((index, prefix))
     }
 }
+
 impl Table {
-    /// Create a new table.
+    /// Create a new table in partition `metadata.part_num()`, the number of
+    /// records on each data page is at most `metadata.num_records_per_page()`
+    /// (1 means full page records).
     pub async fn create(
-        schema: Schema,
-        part_handle: Box<PartitionHandle>,
-        num_records_per_page: usize,
+        mut metadata: TableMetadata,
+        page_directory: Arc<PageDirectory>,
     ) -> Result<Self> {
         // todo enable cleanup and flush job.
 
+        let num_records_per_page = match metadata.get_schema().page_format() {
+            PageFormat::Fixed => {
+                let max_records_per_page = max_records_per_page(metadata.entry_size());
+                if max_records_per_page == 0 {
+                    return Err(Error::TooLargeSize);
+                }
+                metadata
+                    .num_records_per_page()
+                    .clamp(1, max_records_per_page)
+            }
+            // A slotted page holds as many records as fit.
+            PageFormat::SlotDirectory => 0,
+        };
+        metadata.set_num_records_per_page(num_records_per_page);
+
         let mut table = Table {
-            indices: HashMap::with_capacity(schema.get_indcies().len()),
-            metadata: TableMetadata::new(schema),
-            part_handle,
-            num_records_per_page,
+            indices: HashMap::with_capacity(metadata.get_schema().get_indcies().len()),
+            metadata,
+            page_directory,
             table_stats: TableStats::new(),
-            bitmap_size: 0,
+            lock_manager: None,
         };
 
         table.initialize_indices().await?;
         Ok(table)
     }
 
+    /// Locks the data pages in X for the transactions which change them.
+    pub fn with_lock_manager(mut self, lock_manager: Arc<LockManager>) -> Self {
+        self.lock_manager = Some(lock_manager);
+        self
+    }
+
     pub fn metadata(&self) -> &TableMetadata {
         &self.metadata
     }
@@ -147,8 +642,17 @@ impl Table {
         self.metadata.get_schema()
     }
 
+    pub fn name(&self) -> &str {
+        self.metadata.name()
+    }
+
+    /// Returns the number of records on each data page, 0 for slotted pages.
     pub fn num_records_per_page(&self) -> usize {
-        self.num_records_per_page
+        self.metadata.num_records_per_page()
+    }
+
+    pub fn page_format(&self) -> PageFormat {
+        self.get_schema().page_format()
     }
 
     // pub fn set_full_page_records(&mut self) {
@@ -163,7 +667,7 @@ impl Table {
     }
 
     pub fn get_part_num(&self) -> usize {
-        self.part_handle.part_num()
+        self.metadata.part_num()
     }
 
     /// Insert a record to this table and returns the record id of the newly
@@ -173,74 +677,143 @@ impl Table {
     /// if the first free page has  bitmao 0b11101000, then the record is
     /// inserted into the page with index 3 and the bitmap is update to
     /// 0b11111000.
-    pub async fn insert(&self, record: Record) -> Result<RecordId> {
+    pub async fn insert(&self, txn: TxnId, record: Record) -> Result<RecordId> {
         // Verify that the record whether valid. For example field value or field type.
-        let schema = self.get_schema();
-        let record = schema.verify_record(record)?;
+        let record = self.get_schema().verify_record(record)?;
+        self.check_unique(&record, None).await?;
+        let version = self.metadata.version();
+        let entry_size = SCHEMA_VERSION_SIZE + record.encoded_size();
 
-        let page = self
-            .part_handle
-            .get_page_with_space(schema.estimated_size())
-            .await;
+        let required_space = match self.page_format() {
+            // A page formatted before the table is altered may have smaller
+            // entries.
+            PageFormat::Fixed => entry_size,
+            PageFormat::SlotDirectory => {
+                let required_space = entry_size + SLOT_SIZE;
+                if required_space > effective_page_size() - DATA_HEADER_SIZE {
+                    return Err(Error::TooLargeSize);
+                }
+                required_space
+            }
+        };
+
+        // The page is locked in X at once while it's latched, a page locked by
+        // others is skipped.
+        let mut page = loop {
+            let page = self
+                .page_directory
+                .fetch_page_with(self.get_part_num(), required_space, |page| {
+                    let data_page = page.as_data_page();
+                    (data_page.format() != Some(PageFormat::Fixed)
+                        || data_page.entry_size() >= entry_size)
+                        && self.try_lock_page(txn, page.get_page_num())
+                })
+                .await?;
+            // A newly allocated page isn't checked by `accept`, it may still
+            // be locked by the transaction which released it.
+            let page_num = page.get_page_num();
+            if self.try_lock_page(txn, page_num) {
+                break page;
+            }
+            drop(page);
+            self.lock_page(txn, page_num).await?;
+        };
+        let before = page.as_data_page().vals().to_vec();
+
+        // A newly allocated page is formatted into the records of this table.
+        if !page.as_data_page().is_formatted() {
+            match self.page_format() {
+                PageFormat::Fixed => {
+                    page.format(self.num_records_per_page(), self.metadata.entry_size())?
+                }
+                PageFormat::SlotDirectory => page.format_slot_directory()?,
+            }
+        }
 
         // Find the first empty slot in the bitmap.
         // entry number of the first free slot and store it in entry number;
         // and(2) we count the total number of entries on this page.
-        let mut entry_num = page.get_idle_entry_num().await?;
+        let page_num = page.get_page_num();
+        let entry_num = page
+            .get_idle_entry_num()
+            .await
+            .ok_or_else(|| Error::Invalid(format!("insert into full page {}", page_num)))?;
 
-        if self.num_records_per_page == 1 {
-            entry_num = 0;
+        // Insert the record and update the bitmap, the slotted page maintains
+        // its free space itself.
+        page.insert_record(entry_num, version, &record).await?;
+        if self.page_format() == PageFormat::Fixed {
+            Self::update_fixed_free_space(&mut page).await?;
         }
 
-        assert!(entry_num < self.num_records_per_page);
-
-        // Insert the record and update the bitmap.
-        page.insert_record(entry_num, &record).await?;
+        self.page_directory
+            .log_page_update(txn, &mut page, &before)
+            .await?;
+        drop(page);
 
         // Insert the record to index.
-        self.insert_index_entry(&record).await?;
+        let rid = new_record_id(page_num, entry_num);
+        self.insert_index_entry(txn, &record, rid).await?;
 
         // Update the metadata.
         // todo stats ...
-        Ok(new_record_id(page.get_page_num(), entry_num))
+        Ok(rid)
     }
 
     /// Retrieves a record from the table, throwing an exception if no such
-    /// record exists.
+    /// record exists. A record written with an older schema version is
+    /// upgraded to the current schema.
     // todo needs get record from index of table.
-    pub async fn get(&mut self, id: RecordId) -> Result<Record> {
-        assert!(id.1 > 0 && id.1 < self.num_records_per_page);
+    pub async fn get(&self, id: RecordId) -> Result<Record> {
+        self.check_record_id(id)?;
 
-        let page = self.part_handle.get_page(id.0).await?;
+        let page = self.fetch_page(id.0).await?;
         if !page.contains(id.1) {
             return Err(Error::NotFound("record dose not exist.".to_owned()));
         }
-        let offset = self.bitmap_size + (id.1 * self.get_schema().estimated_size());
 
-        page.read_to_record(offset).await
+        let (version, record) = page.read_entry(id.1).await?;
+        self.metadata.upgrade_record(version, record)
     }
 
     /// Updates an existing record with new values and returns the existing
     /// record. stats is updated accordingly. An exception is thrown if
     /// recordId does not correspond to and existing record in the table.
+    ///
+    /// The updated record is written with the current schema version.
+    /// `Error::TooLargeSize` is returned if the updated record no longer fits
+    /// on its page, i.e. a slotted page, or a fixed-size page formatted before
+    /// the table is altered to larger entries.
     // todo needs get record from index of table.
-    pub async fn update(&mut self, old_record_id: RecordId, updated: Record) -> Result<Record> {
+    pub async fn update(
+        &self,
+        txn: TxnId,
+        old_record_id: RecordId,
+        updated: Record,
+    ) -> Result<Record> {
         let entry_num = old_record_id.1;
-        assert!(entry_num > 0 && entry_num < self.num_records_per_page);
+        self.check_record_id(old_record_id)?;
+        self.lock_page(txn, old_record_id.0).await?;
 
         let record = self.get_schema().verify_record(updated)?;
-        // If we're updating a record we'll need exclusive access to the page
-        // it's on.
-        // todo(project_part2): update the following line,
-
-        let page = self.part_handle.get_page(old_record_id.0).await?;
-
         let old_record = self.get(old_record_id).await?;
+        self.check_unique(&record, Some(&old_record)).await?;
+
+        // The page is locked in X, and latched while it's changed.
+        let mut page = self.fetch_page(old_record_id.0).await?;
+        let before = page.as_data_page().vals().to_vec();
 
-        page.insert_record(entry_num, &record).await?;
+        page.insert_record(entry_num, self.metadata.version(), &record)
+            .await?;
+        self.page_directory
+            .log_page_update(txn, &mut page, &before)
+            .await?;
+        drop(page);
 
         // Insert the record to index.
-        self.insert_index_entry(&record).await?;
+        self.remove_index_entry(txn, &old_record, old_record_id)
+            .await?;
+        self.insert_index_entry(txn, &record, old_record_id).await?;
 
         // Update the metadata.
         // todo stats ...
@@ -252,30 +825,843 @@ impl Table {
     /// updates stats, freePageNums and numRecords as necessary. An
     /// exception is thrown if recordId dose not correspond to an existing
     /// record in the table.
-    pub async fn remove(&mut self, id: RecordId) -> Result<Record> {
-        assert!(id.1 > 0 && id.1 < self.num_records_per_page);
+    pub async fn remove(&self, txn: TxnId, id: RecordId) -> Result<Record> {
+        self.check_record_id(id)?;
+        self.lock_page(txn, id.0).await?;
 
-        let mut page = self.part_handle.get_page(id.0).await?;
+        let mut page = self.fetch_page(id.0).await?;
+        let before = page.as_data_page().vals().to_vec();
 
-        let record = page.remove_record(id.1).await?;
+        let (version, record) = page.remove_record(id.1).await?;
+        let record = self.metadata.upgrade_record(version, record)?;
 
-        let freed_space = if self.num_records_per_page == 1 {
-            1
-        } else {
-            self.num_records_per_page - page.num_records() as usize
-        } * self.get_schema().estimated_size();
-
-        page.update_free_space(freed_space).await?;
+        if self.page_format() == PageFormat::Fixed {
+            Self::update_fixed_free_space(&mut page).await?;
+        }
+        self.page_directory
+            .log_page_update(txn, &mut page, &before)
+            .await?;
+        drop(page);
 
-        // Insert the record to index.
-        self.remove_index_entry(&record).await?;
+        // Remove the record from index.
+        self.remove_index_entry(txn, &record, id).await?;
 
         // Update the metadata.
         // todo stats ...
 
         Ok(record)
     }
+
+    /// Updates the free space of a fixed-size data page, i.e. the size of its
+    /// free entries.
+    async fn update_fixed_free_space(page: &mut PageGuard<'_>) -> Result<()> {
+        let data_page = page.as_data_page();
+        let free_space = (data_page.capacity() - data_page.len() as usize) * data_page.entry_size();
+        page.update_free_space(free_space).await
+    }
+
+    /// Returns the number of records of this table.
+    pub async fn num_records(&self) -> Result<usize> {
+        let mut pages = self.page_directory.scan_pages(self.get_part_num())?;
+        let mut num_records = 0;
+        while let Some(page) = pages.next().await? {
+            num_records += page.num_records() as usize;
+        }
+        Ok(num_records)
+    }
+
+    /// Alters the table to the new version of metadata, records are upgraded
+    /// lazily when they're read or updated.
+    pub async fn alter(&mut self, metadata: TableMetadata) -> Result<()> {
+        if metadata.version() <= self.metadata.version() {
+            return Err(Error::Invalid(format!(
+                "alter table {}: schema version {}",
+                self.name(),
+                metadata.version()
+            )));
+        }
+
+        self.metadata = metadata;
+        self.alter_index().await
+    }
+
+    /// Drops the indices of table and releases its partition, the table must
+    /// have been removed from the catalog.
+    pub async fn destroy(self) -> Result<()> {
+        let part_num = self.get_part_num();
+        for (_, tree) in self.indices {
+            tree.destroy().await?;
+        }
+        self.page_directory.release_part(part_num).await
+    }
+
+    /// Returns all records of this table with their record ids, ordered by
+    /// record id. The pages are read ahead without going through the buffer
+    /// pool (see `PageDirectory::scan_pages`).
+    pub async fn scan(&self) -> Result<Vec<(RecordId, Record)>> {
+        let mut pages = self.page_directory.scan_pages(self.get_part_num())?;
+        let mut records = vec![];
+        while let Some(page) = pages.next().await? {
+            let page_num = page.get_page_num();
+            for entry_num in 0..page.as_data_page().capacity() {
+                if page.contains(entry_num) {
+                    let (version, record) = page.read_entry(entry_num).await?;
+                    let record = self.metadata.upgrade_record(version, record)?;
+                    records.push((new_record_id(page_num, entry_num), record));
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Gets the data page `page_num` of the partition of table.
+    async fn fetch_page(&self, page_num: u64) -> Result<PageGuard<'_>> {
+        self.page_directory
+            .fetch_page(virtual_page_num(self.get_part_num(), page_num as usize))
+            .await
+    }
+
+    /// Locks the data page in X for transaction, and waits until it's
+    /// granted.
+    async fn lock_page(&self, txn: TxnId, page_num: u64) -> Result<()> {
+        match &self.lock_manager {
+            Some(lock_manager) => {
+                let name = ResourceName::Page(self.get_part_num(), page_num);
+                lock_manager.lock(txn, name, LockType::X).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Locks the data page in X for transaction if it's granted at once,
+    /// returns whether it's locked.
+    fn try_lock_page(&self, txn: TxnId, page_num: u64) -> bool {
+        match &self.lock_manager {
+            Some(lock_manager) => {
+                let name = ResourceName::Page(self.get_part_num(), page_num);
+                lock_manager.try_lock(txn, name, LockType::X)
+            }
+            None => true,
+        }
+    }
+
+    fn check_record_id(&self, id: RecordId) -> Result<()> {
+        if self.page_format() == PageFormat::Fixed && id.1 >= self.num_records_per_page() {
+            return Err(Error::Invalid(format!(
+                "record id {:?}, expect entry number less than {}",
+                id,
+                self.num_records_per_page()
+            )));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::collections::HashSet;
+
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        catalog::schema::{Column, DataType, SchemaChange, PRIMARY_KEY},
+        options::Options,
+        table::page::bitmap_size,
+    };
+
+    const TXN: TxnId = 1;
+
+    fn schema() -> Schema {
+        Schema::new()
+            .add(Column::new("id", DataType::Int32).not_null())
+            .add(Column::new("name", DataType::String(16)))
+    }
+
+    fn record(id: i32) -> Record {
+        Record::from(vec![
+            ScalarImpl::Int32(id),
+            ScalarImpl::String(format!("name-{}", id)),
+        ])
+    }
+
+    async fn create_table(dir: &TempDir, num_records_per_page: usize) -> Table {
+        create_table_with_schema(dir, schema(), num_records_per_page).await
+    }
+
+    async fn create_table_with_schema(
+        dir: &TempDir,
+        schema: Schema,
+        num_records_per_page: usize,
+    ) -> Table {
+        let page_directory = Arc::new(
+            PageDirectory::open(&Options {
+                path: dir.path().join("db").to_string_lossy().to_string(),
+                cache_frames: 16,
+                scan_ring_pages: 4,
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        let part_num = page_directory.alloc_part().await.unwrap();
+        let metadata = TableMetadata::new("test", schema, part_num, num_records_per_page);
+        Table::create(metadata, page_directory).await.unwrap()
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let table = create_table(&dir, 8).await;
+
+            let mut ids = vec![];
+            for i in 0..20 {
+                ids.push(table.insert(TXN, record(i)).await.unwrap());
+            }
+
+            // 8 records per page, so the records span 3 pages.
+            assert_eq!(ids[0], RecordId(0, 0));
+            assert_eq!(ids[8], RecordId(1, 0));
+            assert_eq!(ids[19], RecordId(2, 3));
+
+            for (i, id) in ids.into_iter().enumerate() {
+                assert_eq!(table.get(id).await.unwrap(), record(i as i32));
+            }
+            assert_eq!(table.num_records().await.unwrap(), 20);
+        })
+    }
+
+    #[test]
+    fn test_update_and_remove() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let table = create_table(&dir, 8).await;
+
+            let ids = [
+                table.insert(TXN, record(1)).await.unwrap(),
+                table.insert(TXN, record(2)).await.unwrap(),
+            ];
+
+            let old = table.update(TXN, ids[0], record(10)).await.unwrap();
+            assert_eq!(old, record(1));
+            assert_eq!(table.get(ids[0]).await.unwrap(), record(10));
+
+            let removed = table.remove(TXN, ids[1]).await.unwrap();
+            assert_eq!(removed, record(2));
+            assert!(table.get(ids[1]).await.is_err());
+            assert!(table.remove(TXN, ids[1]).await.is_err());
+
+            // The freed slot is reused.
+            assert_eq!(table.insert(TXN, record(3)).await.unwrap(), ids[1]);
+        })
+    }
+
+    #[test]
+    fn test_max_records_per_page() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let table = create_table(&dir, usize::MAX).await;
+
+            let record_size = table.metadata().entry_size();
+            let n = table.num_records_per_page();
+            let data_size = effective_page_size() - DATA_HEADER_SIZE;
+            assert!(bitmap_size(n) + n * record_size <= data_size);
+            assert!(bitmap_size(n + 1) + (n + 1) * record_size > data_size);
+
+            for i in 0..=n as i32 {
+                table.insert(TXN, record(i)).await.unwrap();
+            }
+            let num_data_pages = table
+                .page_directory
+                .get_partition(table.get_part_num())
+                .unwrap()
+                .get_num_data_pages();
+            assert_eq!(num_data_pages, 2);
+            assert_eq!(table.fetch_page(0).await.unwrap().num_records() as usize, n);
+        })
+    }
+
+    #[test]
+    fn test_alter_fixed_entries() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let mut table = create_table(&dir, 8).await;
+            let old = table.insert(TXN, record(0)).await.unwrap();
+
+            let metadata = table
+                .metadata()
+                .alter(SchemaChange::AddColumn(Column::new(
+                    "bio",
+                    DataType::String(200),
+                )))
+                .unwrap();
+            table.alter(metadata).await.unwrap();
+
+            // A record larger than the entries of the old page goes to a new
+            // page with larger entries, a small one still fits the old page.
+            let bio = |id: i32, bio: Option<&str>| {
+                Record::new(vec![
+                    Some(ScalarImpl::Int32(id)),
+                    Some(ScalarImpl::String(format!("name-{}", id))),
+                    bio.map(|bio| ScalarImpl::String(bio.to_owned())),
+                ])
+            };
+            let large = table
+                .insert(TXN, bio(1, Some(&"b".repeat(200))))
+                .await
+                .unwrap();
+            let small = table.insert(TXN, bio(2, None)).await.unwrap();
+            assert_eq!((old.0, large.0, small.0), (0, 1, 0));
+            let new_page = table.fetch_page(1).await.unwrap();
+            assert_eq!(
+                new_page.as_data_page().entry_size(),
+                table.metadata().entry_size()
+            );
+            drop(new_page);
+
+            assert_eq!(table.get(old).await.unwrap(), bio(0, None));
+            assert_eq!(
+                table.get(large).await.unwrap(),
+                bio(1, Some(&"b".repeat(200)))
+            );
+            // The old page can't hold the larger record.
+            assert!(matches!(
+                table.update(TXN, old, bio(0, Some(&"b".repeat(200)))).await,
+                Err(Error::TooLargeSize)
+            ));
+            assert_eq!(table.num_records().await.unwrap(), 3);
+        })
+    }
+
+    #[test]
+    fn test_scan() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            // Full page records, the table has more pages than the cache.
+            let table = create_table(&dir, 1).await;
+            let mut ids = vec![];
+            for i in 0..40 {
+                ids.push(table.insert(TXN, record(i)).await.unwrap());
+            }
+            // The changed page is cached but not written back.
+            table.update(TXN, ids[39], record(-1)).await.unwrap();
+
+            let stats = table.page_directory.cache_stats();
+            let records = table.scan().await.unwrap();
+            assert_eq!(table.num_records().await.unwrap(), 40);
+            // The scan doesn't go through the cache.
+            assert_eq!(table.page_directory.cache_stats(), stats);
+
+            let mut expected = (0..39).map(record).collect::<Vec<_>>();
+            expected.push(record(-1));
+            assert_eq!(records.iter().map(|(id, _)| *id).collect::<Vec<_>>(), ids);
+            assert_eq!(
+                records.into_iter().map(|(_, r)| r).collect::<Vec<_>>(),
+                expected
+            );
+        })
+    }
+
+    #[test]
+    fn test_slot_directory_table() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let schema = Schema::new()
+                .add(Column::new("id", DataType::Int32).not_null())
+                .add(Column::new("name", DataType::String(u16::MAX)))
+                .with_page_format(PageFormat::SlotDirectory);
+            // A fixed-size entry of the schema is larger than a page.
+            assert!(schema.estimated_size() > effective_page_size());
+            let table = create_table_with_schema(&dir, schema, 8).await;
+
+            let mut ids = vec![];
+            for i in 0..100 {
+                ids.push(table.insert(TXN, record(i)).await.unwrap());
+            }
+            // Small records share the page regardless of `num_records_per_page`.
+            assert!(ids.iter().all(|id| id.0 == 0));
+
+            let wide = Record::from(vec![
+                ScalarImpl::Int32(-1),
+                ScalarImpl::String("x".repeat(1500)),
+            ]);
+            let old = table.update(TXN, ids[10], wide.clone()).await.unwrap();
+            assert_eq!(old, record(10));
+            assert_eq!(table.get(ids[10]).await.unwrap(), wide);
+            assert_eq!(table.get(ids[11]).await.unwrap(), record(11));
+
+            assert_eq!(table.remove(TXN, ids[20]).await.unwrap(), record(20));
+            assert!(table.get(ids[20]).await.is_err());
+            assert_eq!(table.insert(TXN, record(200)).await.unwrap(), ids[20]);
+
+            // The record doesn't fit on the first page any more.
+            let id = table.insert(TXN, wide.clone()).await.unwrap();
+            assert_eq!(id.0, 1);
+            assert_eq!(table.get(id).await.unwrap(), wide);
+            assert_eq!(table.num_records().await.unwrap(), 101);
+
+            let too_large = Record::from(vec![
+                ScalarImpl::Int32(-1),
+                ScalarImpl::String("x".repeat(5000)),
+            ]);
+            assert!(matches!(
+                table.insert(TXN, too_large).await,
+                Err(Error::TooLargeSize)
+            ));
+        })
+    }
+
+    #[test]
+    fn test_index() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let schema = schema()
+                .add_index(TableIndex::new("idx_id", vec![0]))
+                .add_index(TableIndex::new("idx_name", vec![1]));
+            let table = create_table_with_schema(&dir, schema, 8).await;
+            let lookup = |name: &'static str, value: ScalarImpl| {
+                let table = &table;
+                async move { table.lookup(name, &[value]).await.unwrap() }
+            };
+
+            let mut ids = vec![];
+            for i in 0..300 {
+                ids.push(table.insert(TXN, record(i % 100)).await.unwrap());
+            }
+            let no_name = Record::new(vec![Some(ScalarImpl::Int32(7)), None]);
+            let id = table.insert(TXN, no_name).await.unwrap();
+
+            assert_eq!(
+                lookup("idx_id", ScalarImpl::Int32(7)).await,
+                vec![ids[7], ids[107], ids[207], id]
+            );
+            // NULL values are not indexed.
+            assert_eq!(
+                lookup("idx_name", ScalarImpl::String("name-7".to_owned())).await,
+                vec![ids[7], ids[107], ids[207]]
+            );
+            assert!(lookup("idx_id", ScalarImpl::Int32(100)).await.is_empty());
+
+            table.update(TXN, ids[107], record(100)).await.unwrap();
+            table.remove(TXN, ids[207]).await.unwrap();
+            assert_eq!(
+                lookup("idx_id", ScalarImpl::Int32(7)).await,
+                vec![ids[7], id]
+            );
+            assert_eq!(
+                lookup("idx_id", ScalarImpl::Int32(100)).await,
+                vec![ids[107]]
+            );
+            assert_eq!(
+                lookup("idx_name", ScalarImpl::String("name-100".to_owned())).await,
+                vec![ids[107]]
+            );
+            assert!(matches!(
+                table.lookup("idx_x", &[ScalarImpl::Int32(7)]).await,
+                Err(Error::NotFound(_))
+            ));
+        })
+    }
+
+    #[test]
+    fn test_invalid_index() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = Arc::new(
+                PageDirectory::open(&Options {
+                    path: dir.path().join("db").to_string_lossy().to_string(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap(),
+            );
+            let invalid = [
+                TableIndex::new("idx", vec![]),
+                TableIndex::new("idx", vec![2]),
+                TableIndex::new("idx", vec![0])
+                    .unique()
+                    .with_kind(IndexKind::Bloom),
+            ];
+            for index in invalid {
+                let schema = schema().add_index(index);
+                let metadata = TableMetadata::new("test", schema, 1, 8);
+                assert!(matches!(
+                    Table::create(metadata, page_directory.clone()).await,
+                    Err(Error::Invalid(_))
+                ));
+            }
+        })
+    }
+
+    #[test]
+    fn test_composite_index() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let schema = Schema::new()
+                .add(Column::new("id", DataType::Int32).not_null())
+                .add(Column::new("group", DataType::String(16)))
+                .add(Column::new("score", DataType::Int64))
+                .add_index(TableIndex::new("idx_group_score", vec![1, 2]));
+            let table = create_table_with_schema(&dir, schema, 8).await;
+            let record = |id: i32, group: Option<&str>, score: Option<i64>| {
+                Record::new(vec![
+                    Some(ScalarImpl::Int32(id)),
+                    group.map(|g| ScalarImpl::String(g.to_owned())),
+                    score.map(ScalarImpl::Int64),
+                ])
+            };
+
+            let mut ids = vec![];
+            for i in 0..60 {
+                let group = ["a", "b", "a\0"][i % 3];
+                let score = (i as i64 - 30) * 10;
+                ids.push(
+                    table
+                        .insert(TXN, record(i as i32, Some(group), Some(score)))
+                        .await
+                        .unwrap(),
+                );
+            }
+            let no_score = table
+                .insert(TXN, record(60, Some("b"), None))
+                .await
+                .unwrap();
+            let no_group = table.insert(TXN, record(61, None, Some(0))).await.unwrap();
+            // The indexed values are all NULL.
+            table.insert(TXN, record(62, None, None)).await.unwrap();
+
+            let group = |g: &str| ScalarImpl::String(g.to_owned());
+            // Records are ordered by score within a group.
+            let expected = (0..60).filter(|i| i % 3 == 1).map(|i| ids[i]);
+            let mut expected = expected.collect::<Vec<_>>();
+            expected.insert(0, no_score);
+            assert_eq!(
+                table
+                    .lookup("idx_group_score", &[group("b")])
+                    .await
+                    .unwrap(),
+                expected
+            );
+            assert_eq!(
+                table.lookup("idx_group_score", &[]).await.unwrap().len(),
+                62
+            );
+
+            // Scores are cast to the column type.
+            let range = ScalarImpl::Int32(-100)..=ScalarImpl::Int32(20);
+            assert_eq!(
+                table
+                    .lookup_range("idx_group_score", &[group("a")], range)
+                    .await
+                    .unwrap(),
+                vec![ids[21], ids[24], ids[27], ids[30]]
+            );
+            assert_eq!(
+                table
+                    .lookup_range("idx_group_score", &[group("b")], ..ScalarImpl::Int64(-250))
+                    .await
+                    .unwrap(),
+                vec![ids[1], ids[4]]
+            );
+            assert_eq!(
+                table
+                    .lookup_range("idx_group_score", &[], ScalarImpl::String("b".to_owned())..)
+                    .await
+                    .unwrap(),
+                table
+                    .lookup("idx_group_score", &[group("b")])
+                    .await
+                    .unwrap()
+            );
+            assert_eq!(
+                table
+                    .lookup("idx_group_score", &[group("a"), ScalarImpl::Int64(-300)])
+                    .await
+                    .unwrap(),
+                vec![ids[0]]
+            );
+            assert!(table
+                .lookup("idx_group_score", &[group("a\0"), ScalarImpl::Int64(-300)])
+                .await
+                .unwrap()
+                .is_empty());
+            assert_eq!(
+                table
+                    .lookup("idx_group_score", &[group("a\0"), ScalarImpl::Int64(-280)])
+                    .await
+                    .unwrap(),
+                vec![ids[2]]
+            );
+
+            // A range on a column beyond the index.
+            assert!(matches!(
+                table
+                    .lookup_range(
+                        "idx_group_score",
+                        &[group("a"), ScalarImpl::Int64(0)],
+                        ..ScalarImpl::Int32(0)
+                    )
+                    .await,
+                Err(Error::Invalid(_))
+            ));
+
+            table.remove(TXN, no_group).await.unwrap();
+            table
+                .update(TXN, ids[0], record(0, Some("c"), Some(1)))
+                .await
+                .unwrap();
+            assert_eq!(
+                table.lookup("idx_group_score", &[]).await.unwrap().len(),
+                61
+            );
+            assert_eq!(
+                table
+                    .lookup("idx_group_score", &[group("c")])
+                    .await
+                    .unwrap(),
+                vec![ids[0]]
+            );
+        })
+    }
+
+    #[test]
+    fn test_unique_index() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let schema = Schema::new()
+                .add(Column::new("id", DataType::Int32))
+                .add(Column::new("name", DataType::String(16)))
+                .with_primary_key(vec![0])
+                .unwrap()
+                .add_index(TableIndex::new("idx_name", vec![1]).unique());
+            let table = create_table_with_schema(&dir, schema, 8).await;
+            fn is_violation<T>(r: Result<T>) -> bool {
+                matches!(r, Err(Error::ConstraintViolation(_)))
+            }
+
+            let mut ids = vec![];
+            for i in 0..100 {
+                ids.push(table.insert(TXN, record(i)).await.unwrap());
+            }
+            assert!(is_violation(table.insert(TXN, record(7)).await));
+            let other_name = Record::from(vec![
+                ScalarImpl::Int32(7),
+                ScalarImpl::String("x".to_owned()),
+            ]);
+            assert!(is_violation(table.insert(TXN, other_name).await));
+            let other_id = Record::from(vec![
+                ScalarImpl::Int32(1000),
+                ScalarImpl::String("name-7".to_owned()),
+            ]);
+            assert!(is_violation(table.insert(TXN, other_id.clone()).await));
+            // The rejected records are not written.
+            assert_eq!(table.num_records().await.unwrap(), 100);
+
+            // Records with NULL values don't collide.
+            let no_name = |id: i32| Record::new(vec![Some(ScalarImpl::Int32(id)), None]);
+            let id = table.insert(TXN, no_name(1000)).await.unwrap();
+            table.insert(TXN, no_name(1001)).await.unwrap();
+            // The primary key is not null.
+            assert!(matches!(
+                table.insert(TXN, Record::new(vec![])).await,
+                Err(Error::Invalid(_))
+            ));
+
+            // Updating a record to its own values is not a violation.
+            table.update(TXN, ids[7], record(7)).await.unwrap();
+            assert!(is_violation(table.update(TXN, ids[8], record(7)).await));
+            assert!(is_violation(table.update(TXN, id, other_id.clone()).await));
+            assert_eq!(table.get(ids[8]).await.unwrap(), record(8));
+
+            // The values are free again once the record is removed or updated.
+            table.remove(TXN, ids[7]).await.unwrap();
+            table.update(TXN, id, other_id).await.unwrap();
+            assert!(is_violation(table.insert(TXN, record(7)).await));
+            table.update(TXN, id, no_name(1000)).await.unwrap();
+            let id = table.insert(TXN, record(7)).await.unwrap();
+            assert_eq!(
+                table
+                    .lookup(PRIMARY_KEY, &[ScalarImpl::Int32(7)])
+                    .await
+                    .unwrap(),
+                vec![id]
+            );
+        })
+    }
+
+    #[test]
+    fn test_hash_index() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let schema = schema()
+                .add(Column::new("group", DataType::Int64))
+                .add_index(TableIndex::new("idx_name", vec![1]).with_kind(IndexKind::Hash))
+                .add_index(
+                    TableIndex::new("idx_id", vec![0])
+                        .unique()
+                        .with_kind(IndexKind::Hash),
+                )
+                .add_index(TableIndex::new("idx_id_group", vec![0, 2]).with_kind(IndexKind::Hash));
+            let table = create_table_with_schema(&dir, schema, 8).await;
+            let row = |id: i32, name: &str, group: Option<i64>| {
+                Record::new(vec![
+                    Some(ScalarImpl::Int32(id)),
+                    Some(ScalarImpl::String(name.to_owned())),
+                    group.map(ScalarImpl::Int64),
+                ])
+            };
+            let lookup = |name: &'static str, values: Vec<ScalarImpl>| {
+                let table = &table;
+                async move {
+                    let mut ids = table.lookup(name, &values).await?;
+                    ids.sort();
+                    Ok::<_, Error>(ids)
+                }
+            };
+
+            let mut ids = vec![];
+            for i in 0..600 {
+                let name = format!("name-{}", i % 10);
+                ids.push(
+                    table
+                        .insert(TXN, row(i, &name, Some(i as i64 % 3)))
+                        .await
+                        .unwrap(),
+                );
+            }
+            let name = |s: &str| vec![ScalarImpl::String(s.to_owned())];
+            let expected = (0..600).filter(|i| i % 10 == 4).map(|i| ids[i]);
+            assert_eq!(
+                lookup("idx_name", name("name-4")).await.unwrap(),
+                expected.collect::<Vec<_>>()
+            );
+            assert!(lookup("idx_name", name("name-10"))
+                .await
+                .unwrap()
+                .is_empty());
+            // The values are cast to the types of columns.
+            assert_eq!(
+                lookup("idx_id", vec![ScalarImpl::Int64(42)]).await.unwrap(),
+                vec![ids[42]]
+            );
+            assert!(matches!(
+                table.insert(TXN, row(42, "x", None)).await,
+                Err(Error::ConstraintViolation(_))
+            ));
+
+            // Records with NULL values are not in a hash index.
+            let id = table.insert(TXN, row(600, "x", None)).await.unwrap();
+            assert_eq!(
+                lookup(
+                    "idx_id_group",
+                    vec![ScalarImpl::Int32(600), ScalarImpl::Int64(0)]
+                )
+                .await
+                .unwrap(),
+                vec![]
+            );
+            table.update(TXN, id, row(600, "x", Some(5))).await.unwrap();
+            assert_eq!(
+                lookup(
+                    "idx_id_group",
+                    vec![ScalarImpl::Int32(600), ScalarImpl::Int64(5)]
+                )
+                .await
+                .unwrap(),
+                vec![id]
+            );
+            table.remove(TXN, ids[4]).await.unwrap();
+            assert_eq!(lookup("idx_name", name("name-4")).await.unwrap().len(), 59);
+
+            // A hash index is only looked up by all its columns.
+            assert!(matches!(
+                lookup("idx_id_group", vec![ScalarImpl::Int32(600)]).await,
+                Err(Error::Invalid(_))
+            ));
+            assert!(matches!(
+                table
+                    .lookup_range("idx_id", &[], ScalarImpl::Int32(0)..)
+                    .await,
+                Err(Error::Invalid(_))
+            ));
+        })
+    }
+
+    #[test]
+    fn test_bloom_index() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let schema = schema()
+                .add_index(TableIndex::new("bloom_name", vec![1]).with_kind(IndexKind::Bloom));
+            let table = create_table_with_schema(&dir, schema, 8).await;
+
+            let mut ids = vec![];
+            for i in 0..400 {
+                ids.push(table.insert(TXN, record(i)).await.unwrap());
+            }
+            let name = |i: i32| vec![ScalarImpl::String(format!("name-{}", i))];
+            assert_eq!(
+                table.lookup("bloom_name", &name(123)).await.unwrap(),
+                vec![ids[123]]
+            );
+            assert!(table
+                .lookup("bloom_name", &name(400))
+                .await
+                .unwrap()
+                .is_empty());
+
+            // Only the page of record may contain it, except a few false
+            // positives.
+            let pages = ids.iter().map(|id| id.0).collect::<HashSet<_>>();
+            let mut candidates = 0;
+            for &page in &pages {
+                if table
+                    .may_contain("bloom_name", page, &name(123))
+                    .await
+                    .unwrap()
+                {
+                    candidates += 1;
+                }
+            }
+            assert!(table
+                .may_contain("bloom_name", ids[123].0, &name(123))
+                .await
+                .unwrap());
+            assert!(candidates <= 3, "{} of {} pages", candidates, pages.len());
+
+            // Updated and removed values may stay in the filters, but they're
+            // not returned.
+            table.update(TXN, ids[123], record(1000)).await.unwrap();
+            assert!(table
+                .lookup("bloom_name", &name(123))
+                .await
+                .unwrap()
+                .is_empty());
+            assert_eq!(
+                table.lookup("bloom_name", &name(1000)).await.unwrap(),
+                vec![ids[123]]
+            );
+            let no_name = Record::new(vec![Some(ScalarImpl::Int32(5)), None]);
+            table.insert(TXN, no_name).await.unwrap();
+            table.remove(TXN, ids[5]).await.unwrap();
+            assert!(table
+                .lookup("bloom_name", &name(5))
+                .await
+                .unwrap()
+                .is_empty());
+
+            assert!(matches!(
+                table.lookup("bloom_name", &[]).await,
+                Err(Error::Invalid(_))
+            ));
+            assert!(matches!(
+                table
+                    .lookup_range("bloom_name", &[], ScalarImpl::Int32(0)..)
+                    .await,
+                Err(Error::Invalid(_))
+            ));
+        })
+    }
+}
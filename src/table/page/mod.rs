@@ -6,8 +6,48 @@ pub mod reader;
 
 use std::{marker::PhantomData, mem::MaybeUninit, ptr::NonNull};
 
-use self::page_directory::DEFAULT_PAGE_SIZE;
-use crate::{datatypes::record::Record, error::Result};
+use bytes::{Buf, BufMut};
+use serde::{Deserialize, Serialize};
+
+use self::page_directory::{
+    effective_page_size, DATA_HEADER_SIZE, DEFAULT_PAGE_SIZE, RESERVED_SIZE,
+};
+use crate::{
+    datatypes::record::Record,
+    error::{Error, Result},
+};
+
+/// The format of the data pages of a table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PageFormat {
+    /// Fixed-size entries (sized by `TableMetadata::entry_size` when the page
+    /// is formatted, and kept in the page header) following a bitmap of valid
+    /// entries.
+    #[default]
+    Fixed,
+    /// A slot directory growing from the front of the data area and a heap of
+    /// variable-length records growing from the back. The heap is compacted
+    /// whenever a record is removed or resized, so the free space is always
+    /// contiguous.
+    SlotDirectory,
+}
+
+impl PageFormat {
+    fn from_u16(v: u16) -> Option<Self> {
+        match v {
+            FORMAT_FIXED => Some(PageFormat::Fixed),
+            FORMAT_SLOT_DIRECTORY => Some(PageFormat::SlotDirectory),
+            _ => None,
+        }
+    }
+
+    fn to_u16(self) -> u16 {
+        match self {
+            PageFormat::Fixed => FORMAT_FIXED,
+            PageFormat::SlotDirectory => FORMAT_SLOT_DIRECTORY,
+        }
+    }
+}
 
 pub mod marker {
     pub enum Header {}
@@ -33,34 +73,114 @@ impl<Type> Clone for PageRef<Type> {
 
 // Header/Data Page common methods.
 impl<Type> PageRef<Type> {
-    pub async fn get_idle_entry_num(&self) -> Result<usize> {
-        todo!()
+    /// Returns the first free entry of the page, `None` if the page is full. A
+    /// slotted page returns the first empty slot, or appends a new one if every
+    /// slot is used.
+    pub async fn get_idle_entry_num(&self) -> Option<usize> {
+        let page = self.as_data_page();
+        if page.format() == Some(PageFormat::SlotDirectory) {
+            return Some(
+                (0..page.capacity())
+                    .find(|&slot| !page.contains(slot))
+                    .unwrap_or_else(|| page.capacity()),
+            );
+        }
+        (0..page.capacity()).find(|&entry_num| !page.contains(entry_num))
     }
 
     pub fn get_page_num(&self) -> u64 {
-        todo!()
-    }
+        self.as_data_page().page_num
+    }
+
+    // Insert record of schema `version`, if record is exist then update value,
+    // otherwise insert directly.
+    pub async fn insert_record(
+        &self,
+        entry_num: usize,
+        version: u16,
+        record: &Record,
+    ) -> Result<()> {
+        let page = self.into_data_page_mut();
+        let mut entry = Vec::with_capacity(SCHEMA_VERSION_SIZE + record.encoded_size());
+        entry.put_u16(version);
+        record.encode(&mut entry)?;
+
+        if page.format() == Some(PageFormat::SlotDirectory) {
+            return page.put_slot_record(entry_num, &entry);
+        }
+        page.check_entry_num(entry_num)?;
 
-    // Insert record, if record is exist then update value, otherwise insert
-    // directly.
-    pub async fn insert_record(&self, _entry_num: usize, _record: &Record) -> Result<()> {
-        todo!()
+        if entry.len() > page.entry_size() {
+            return Err(Error::TooLargeSize);
+        }
+
+        let offset = page.entry_offset(entry_num);
+        page.data_area_mut()[offset..offset + entry.len()].copy_from_slice(&entry);
+
+        if !page.contains(entry_num) {
+            page.set_entry(entry_num, true);
+            page.set_len(page.len() + 1);
+        }
+        Ok(())
     }
 
-    pub async fn remove_record(&self, _id: usize) -> Result<Record> {
-        todo!()
+    /// Removes the entry, returns its schema version and record.
+    pub async fn remove_record(&self, id: usize) -> Result<(u16, Record)> {
+        let page = self.into_data_page_mut();
+        page.check_entry_num(id)?;
+
+        if !page.contains(id) {
+            return Err(Error::NotFound(format!(
+                "entry {} of page {}",
+                id, page.page_num
+            )));
+        }
+
+        let entry = decode_entry(&page.data_area()[page.entry_offset(id)..])?;
+        if page.format() == Some(PageFormat::SlotDirectory) {
+            page.remove_slot_record(id);
+        } else {
+            page.set_entry(id, false);
+        }
+        page.set_len(page.len() - 1);
+        Ok(entry)
+    }
+
+    /// Reads the schema version and record of entry `entry_num`.
+    pub async fn read_entry(&self, entry_num: usize) -> Result<(u16, Record)> {
+        let page = self.as_data_page();
+        page.check_entry_num(entry_num)?;
+        if !page.contains(entry_num) {
+            return Err(Error::NotFound(format!(
+                "entry {} of page {}",
+                entry_num, page.page_num
+            )));
+        }
+        decode_entry(&page.data_area()[page.entry_offset(entry_num)..])
     }
 
-    pub async fn read_to_record(&self, _offset: usize) -> Result<Record> {
-        todo!()
+    /// Reads the entry at `offset` of the data area (the bitmap of entries is
+    /// at offset 0).
+    pub async fn read_to_record(&self, offset: usize) -> Result<(u16, Record)> {
+        let data = self.as_data_page().data_area();
+        if offset >= data.len() {
+            return Err(Error::Invalid(format!("record offset {}", offset)));
+        }
+        decode_entry(&data[offset..])
     }
 
-    pub async fn update_free_space(&mut self, _freed_space: usize) -> Result<()> {
+    /// Updates the amount of free space of the page, it's picked up by the
+    /// partition when the page is written back.
+    pub async fn update_free_space(&mut self, freed_space: usize) -> Result<()> {
+        if freed_space > effective_page_size() - DATA_HEADER_SIZE {
+            return Err(Error::TooLargeSize);
+        }
+        self.as_data_page_mut().set_free_space(freed_space as u16);
         Ok(())
     }
 
     pub fn num_records(&self) -> u16 {
-        self.as_data_page().len
+        self.as_data_page().len()
     }
 }
 
@@ -114,6 +234,19 @@ impl PageRef<marker::HeaderOrData> {
         }
     }
 
+    /// Frees the page.
+    ///
+    /// # Safety
+    /// The page must be allocated by `new_header_page` or `new_data_page`, and
+    /// no other reference of the page is used afterwards.
+    pub unsafe fn dealloc(self) {
+        if self.is_header {
+            drop(Box::from_raw(self.page.as_ptr() as *mut HeaderPage));
+        } else {
+            drop(Box::from_raw(self.page.as_ptr()));
+        }
+    }
+
     /// Unsafely asserts to the compiler the static information that this page
     /// is a `Header`
     pub unsafe fn cast_to_header_page_unchecked(self) -> PageRef<marker::Header> {
@@ -217,8 +350,57 @@ impl PageRef<marker::Data> {
     }
 
     /// Checks whether page is contains entry.
-    pub fn contains(&self, _entry_num: usize) -> bool {
-        todo!()
+    pub fn contains(&self, entry_num: usize) -> bool {
+        let page = self.as_data_page();
+        entry_num < page.capacity() && page.contains(entry_num)
+    }
+
+    /// Formats an empty page into `capacity` entries of `entry_size` bytes.
+    pub fn format(&mut self, capacity: usize, entry_size: usize) -> Result<()> {
+        let page = self.as_data_page_mut();
+        if page.is_formatted() {
+            return Err(Error::Corrupted(format!(
+                "page {} is already formatted",
+                page.page_num
+            )));
+        }
+
+        if capacity == 0
+            || capacity > u16::MAX as usize
+            || entry_size > u16::MAX as usize
+            || bitmap_size(capacity) + capacity * entry_size > page.data_area().len()
+        {
+            return Err(Error::Invalid(format!(
+                "page format: {} entries of {} bytes",
+                capacity, entry_size
+            )));
+        }
+
+        page.vals_mut()[RESERVED_SIZE..RESERVED_SIZE + DATA_HEADER_SIZE].fill(0);
+        page.data_area_mut()[..bitmap_size(capacity)].fill(0);
+        page.put_u16(CAPACITY_OFFSET, capacity as u16);
+        page.put_u16(ENTRY_SIZE_OFFSET, entry_size as u16);
+        page.put_u16(FORMAT_OFFSET, PageFormat::Fixed.to_u16());
+        page.set_free_space((capacity * entry_size) as u16);
+        Ok(())
+    }
+
+    /// Formats an empty page into a slotted page without any slot.
+    pub fn format_slot_directory(&mut self) -> Result<()> {
+        let page = self.as_data_page_mut();
+        if page.is_formatted() {
+            return Err(Error::Corrupted(format!(
+                "page {} is already formatted",
+                page.page_num
+            )));
+        }
+
+        let heap_start = page.data_area().len();
+        page.vals_mut()[RESERVED_SIZE..RESERVED_SIZE + DATA_HEADER_SIZE].fill(0);
+        page.put_u16(HEAP_START_OFFSET, heap_start as u16);
+        page.put_u16(FORMAT_OFFSET, PageFormat::SlotDirectory.to_u16());
+        page.set_free_space(heap_start as u16);
+        Ok(())
     }
 }
 
@@ -235,19 +417,28 @@ impl PageRef<marker::Data> {
 ///    amount of free space
 ///  - repeat 10 byte entries
 ///
-/// Data pages contain a small header containing:
-///  - 4-byte page group id
-///  - 4-byte index of which header page manages it
-///  - 2-byte offset indicating which slot in the header page its data page
-///    entry resides
+/// Data pages reserve the first `RESERVED_SIZE` bytes for recovery, the first
+/// 8 bytes of which is the LSN of the last logged change to the page (see
+/// `Manifest`). The reserved bytes are followed by a small header containing:
+///  - 2-byte number of entries (slots of a slotted page)
+///  - 2-byte size of an entry (start of the record heap of a slotted page)
+///  - 2-byte number of records
+///  - 2-byte amount of free space
+///  - 2-byte page format (0 if the page is not formatted yet)
 ///
-/// This header is used to quickly locate and update the header page when the
-/// amount of free space on the data page changes, as well as ensure that we do
-/// not modify pages in other page groups by accident.
+/// This header is used to update the free space of the data page tracked by
+/// the partition when the page is written back. The rest of page is the data
+/// area, for `PageFormat::Fixed` it is a bitmap indicating which entries are
+/// valid, followed by fixed-size entries. Each entry is the 2-byte schema
+/// version of record followed by the record. For `PageFormat::SlotDirectory` it
+/// starts with 4-byte slots (2-byte offset of the record in data area, 0 if the
+/// slot is empty, followed by 2-byte length), records are packed at the end of
+/// data area:
 ///
-/// The page group id is a randomly generated 32-bit integer used to help
-/// detect bugs (where we attempt to write to a page that is not managed by the
-/// page group).
+///   +-------+-------+-----+----------------+----------+----------+
+///   | slot0 | slot1 | ... |   free space   | record 1 | record 0 |
+///   +-------+-------+-----+----------------+----------+----------+
+///                                          ^ heap start
 pub struct HeaderPage {
     next: Option<NonNull<HeaderPage>>,
     header_offset: MaybeUninit<u16>,
@@ -310,10 +501,45 @@ impl Iterator for HeaderPageIter {
     }
 }
 
+const PAGE_LSN_OFFSET: usize = 0;
+const CAPACITY_OFFSET: usize = RESERVED_SIZE;
+const ENTRY_SIZE_OFFSET: usize = RESERVED_SIZE + 2;
+const LEN_OFFSET: usize = RESERVED_SIZE + 4;
+const FREE_SPACE_OFFSET: usize = RESERVED_SIZE + 6;
+const FORMAT_OFFSET: usize = RESERVED_SIZE + 8;
+/// The start of the record heap of a slotted page, shares the field of entry
+/// size.
+const HEAP_START_OFFSET: usize = ENTRY_SIZE_OFFSET;
+
+const FORMAT_FIXED: u16 = 1;
+const FORMAT_SLOT_DIRECTORY: u16 = 2;
+
+/// The size (in bytes) of a slot of slotted page.
+pub const SLOT_SIZE: usize = 4;
+
+/// Every entry starts with 2-byte version of the schema which the record is
+/// written with, followed by the record.
+pub const SCHEMA_VERSION_SIZE: usize = 2;
+
+fn decode_entry(mut buf: &[u8]) -> Result<(u16, Record)> {
+    if buf.len() < SCHEMA_VERSION_SIZE {
+        return Err(Error::Corrupted("entry: unexpected end of page".to_owned()));
+    }
+    let version = buf.get_u16();
+    Ok((version, Record::decode(&mut buf)?))
+}
+
+/// Returns the size (in bytes) of the bitmap of `capacity` entries.
+#[inline]
+pub fn bitmap_size(capacity: usize) -> usize {
+    (capacity + 7) / 8
+}
+
 pub struct DataPage {
     parent: Option<NonNull<HeaderPage>>,
     parent_idx: MaybeUninit<u16>,
-    len: u16,
+    /// The page number in partition.
+    page_num: u64,
     vals: MaybeUninit<[u8; DEFAULT_PAGE_SIZE]>,
 }
 
@@ -325,7 +551,7 @@ impl DataPage {
 
         // So parent_idx, vals are all MaybeUninit.
         std::ptr::addr_of_mut!((*this).parent).write(None);
-        std::ptr::addr_of_mut!((*this).len).write(0);
+        std::ptr::addr_of_mut!((*this).page_num).write(0);
     }
 
     /// Creates a new boxed `DataPage`.
@@ -347,7 +573,304 @@ impl DataPage {
             );
         }
     }
+
+    pub fn set_page_num(&mut self, page_num: u64) {
+        self.page_num = page_num;
+    }
+
+    /// Returns the content of page, the page must be filled.
+    pub fn vals(&self) -> &[u8] {
+        unsafe { self.vals.assume_init_ref() }
+    }
+
+    pub fn vals_mut(&mut self) -> &mut [u8] {
+        unsafe { self.vals.assume_init_mut() }
+    }
+
+    /// Returns the LSN of the last logged change to the page, 0 if the page has
+    /// never been changed.
+    pub fn page_lsn(&self) -> u64 {
+        let mut lsn = [0u8; 8];
+        lsn.copy_from_slice(&self.vals()[PAGE_LSN_OFFSET..PAGE_LSN_OFFSET + 8]);
+        u64::from_be_bytes(lsn)
+    }
+
+    pub fn set_page_lsn(&mut self, lsn: u64) {
+        self.vals_mut()[PAGE_LSN_OFFSET..PAGE_LSN_OFFSET + 8].copy_from_slice(&lsn.to_be_bytes());
+    }
+
+    pub fn is_formatted(&self) -> bool {
+        self.format().is_some()
+    }
+
+    /// Returns the format of page, `None` if the page is not formatted yet.
+    pub fn format(&self) -> Option<PageFormat> {
+        PageFormat::from_u16(self.get_u16(FORMAT_OFFSET))
+    }
+
+    /// Returns the number of entries (or slots).
+    pub fn capacity(&self) -> usize {
+        self.get_u16(CAPACITY_OFFSET) as usize
+    }
+
+    /// Returns the size (in bytes) of an entry.
+    pub fn entry_size(&self) -> usize {
+        self.get_u16(ENTRY_SIZE_OFFSET) as usize
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> u16 {
+        self.get_u16(LEN_OFFSET)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the amount of free space, an unformatted page is entirely free.
+    pub fn free_space(&self) -> usize {
+        if !self.is_formatted() {
+            return effective_page_size() - DATA_HEADER_SIZE;
+        }
+        self.get_u16(FREE_SPACE_OFFSET) as usize
+    }
+
+    /// Returns the data area of page, which starts with the bitmap of entries.
+    pub fn data_area(&self) -> &[u8] {
+        &self.vals()[RESERVED_SIZE + DATA_HEADER_SIZE..]
+    }
+
+    pub fn data_area_mut(&mut self) -> &mut [u8] {
+        &mut self.vals_mut()[RESERVED_SIZE + DATA_HEADER_SIZE..]
+    }
+
+    /// Returns the offset of entry in the data area.
+    pub fn entry_offset(&self, entry_num: usize) -> usize {
+        match self.format() {
+            Some(PageFormat::SlotDirectory) => self.slot(entry_num).0,
+            _ => bitmap_size(self.capacity()) + entry_num * self.entry_size(),
+        }
+    }
+
+    fn contains(&self, entry_num: usize) -> bool {
+        match self.format() {
+            Some(PageFormat::SlotDirectory) => self.slot(entry_num).0 != 0,
+            _ => self.data_area()[entry_num / 8] & (1 << (entry_num % 8)) != 0,
+        }
+    }
+
+    /// Returns the offset and length of the record in slot.
+    fn slot(&self, slot: usize) -> (usize, usize) {
+        let offset = RESERVED_SIZE + DATA_HEADER_SIZE + slot * SLOT_SIZE;
+        (
+            self.get_u16(offset) as usize,
+            self.get_u16(offset + 2) as usize,
+        )
+    }
+
+    fn set_slot(&mut self, slot: usize, record_offset: usize, len: usize) {
+        let offset = RESERVED_SIZE + DATA_HEADER_SIZE + slot * SLOT_SIZE;
+        self.put_u16(offset, record_offset as u16);
+        self.put_u16(offset + 2, len as u16);
+    }
+
+    fn heap_start(&self) -> usize {
+        self.get_u16(HEAP_START_OFFSET) as usize
+    }
+
+    /// Puts the encoded record into slot, the slot is either an existing one
+    /// or the one right after the last slot. The record of an occupied slot is
+    /// replaced.
+    fn put_slot_record(&mut self, slot: usize, bytes: &[u8]) -> Result<()> {
+        let num_slots = self.capacity();
+        if slot > num_slots || (slot == num_slots && num_slots == u16::MAX as usize) {
+            return Err(Error::Invalid(format!(
+                "slot {} of page {} with {} slots",
+                slot, self.page_num, num_slots
+            )));
+        }
+
+        let (old_offset, old_len) = if slot < num_slots {
+            self.slot(slot)
+        } else {
+            (0, 0)
+        };
+        let new_slot_size = if slot == num_slots { SLOT_SIZE } else { 0 };
+        if bytes.len() + new_slot_size > self.free_space() + old_len {
+            return Err(Error::TooLargeSize);
+        }
+
+        if slot == num_slots {
+            self.put_u16(CAPACITY_OFFSET, num_slots as u16 + 1);
+        }
+        if old_offset != 0 {
+            self.set_slot(slot, 0, 0);
+            self.compact();
+        } else {
+            self.set_len(self.len() + 1);
+        }
+
+        let heap_start = self.heap_start() - bytes.len();
+        self.data_area_mut()[heap_start..heap_start + bytes.len()].copy_from_slice(bytes);
+        self.set_slot(slot, heap_start, bytes.len());
+        self.put_u16(HEAP_START_OFFSET, heap_start as u16);
+        self.update_slotted_free_space();
+        Ok(())
+    }
+
+    /// Empties the slot, trailing empty slots are dropped from the directory.
+    fn remove_slot_record(&mut self, slot: usize) {
+        self.set_slot(slot, 0, 0);
+
+        let mut num_slots = self.capacity();
+        while num_slots > 0 && !self.contains(num_slots - 1) {
+            num_slots -= 1;
+        }
+        self.put_u16(CAPACITY_OFFSET, num_slots as u16);
+
+        self.compact();
+        self.update_slotted_free_space();
+    }
+
+    /// Moves all records to the end of data area so that the free space
+    /// between the slot directory and the record heap is contiguous.
+    fn compact(&mut self) {
+        let mut records = (0..self.capacity())
+            .map(|slot| (slot, self.slot(slot)))
+            .filter(|(_, (offset, _))| *offset != 0)
+            .collect::<Vec<_>>();
+        records.sort_by_key(|&(_, (offset, _))| std::cmp::Reverse(offset));
+
+        let mut heap_start = self.data_area().len();
+        for (slot, (offset, len)) in records {
+            heap_start -= len;
+            self.data_area_mut()
+                .copy_within(offset..offset + len, heap_start);
+            self.set_slot(slot, heap_start, len);
+        }
+        self.put_u16(HEAP_START_OFFSET, heap_start as u16);
+    }
+
+    fn update_slotted_free_space(&mut self) {
+        let free_space = self.heap_start() - self.capacity() * SLOT_SIZE;
+        self.set_free_space(free_space as u16);
+    }
+
+    fn set_entry(&mut self, entry_num: usize, valid: bool) {
+        let byte = &mut self.data_area_mut()[entry_num / 8];
+        if valid {
+            *byte |= 1 << (entry_num % 8);
+        } else {
+            *byte &= !(1 << (entry_num % 8));
+        }
+    }
+
+    fn check_entry_num(&self, entry_num: usize) -> Result<()> {
+        if entry_num >= self.capacity() {
+            return Err(Error::Invalid(format!(
+                "entry {} of page {} with {} entries",
+                entry_num,
+                self.page_num,
+                self.capacity()
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_len(&mut self, len: u16) {
+        self.put_u16(LEN_OFFSET, len);
+    }
+
+    fn set_free_space(&mut self, free_space: u16) {
+        self.put_u16(FREE_SPACE_OFFSET, free_space);
+    }
+
+    #[inline]
+    fn get_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.vals()[offset], self.vals()[offset + 1]])
+    }
+
+    #[inline]
+    fn put_u16(&mut self, offset: usize, v: u16) {
+        self.vals_mut()[offset..offset + 2].copy_from_slice(&v.to_be_bytes());
+    }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::datatypes::array::scalar::ScalarImpl;
+
+    fn record(s: &str) -> Record {
+        Record::from(vec![ScalarImpl::String(s.to_owned())])
+    }
+
+    fn new_slotted_page() -> PageRef<marker::Data> {
+        let mut page = PageRef::new_data_page();
+        page.as_data_page_mut().fill(&[0; DEFAULT_PAGE_SIZE]);
+        page.format_slot_directory().unwrap();
+        page
+    }
+
+    #[test]
+    fn test_slot_directory() {
+        block_on(async {
+            let page = new_slotted_page();
+            let data_size = page.as_data_page().data_area().len();
+            assert_eq!(page.as_data_page().free_space(), data_size);
+
+            let records = [record("a"), record(&"b".repeat(100)), record("ccc")];
+            for (slot, r) in records.iter().enumerate() {
+                assert_eq!(page.get_idle_entry_num().await, Some(slot));
+                page.insert_record(slot, 0, r).await.unwrap();
+            }
+            let used: usize = records
+                .iter()
+                .map(|r| SCHEMA_VERSION_SIZE + r.encoded_size() + SLOT_SIZE)
+                .sum();
+            assert_eq!(page.as_data_page().free_space(), data_size - used);
+
+            // Removing a record compacts the heap and leaves the slot empty.
+            assert_eq!(
+                page.remove_record(1).await.unwrap(),
+                (0, records[1].clone())
+            );
+            assert!(!page.contains(1));
+            assert_eq!(
+                page.as_data_page().free_space(),
+                data_size - used + SCHEMA_VERSION_SIZE + records[1].encoded_size()
+            );
+            assert_eq!(page.read_entry(0).await.unwrap(), (0, records[0].clone()));
+            assert_eq!(page.read_entry(2).await.unwrap(), (0, records[2].clone()));
+            assert_eq!(page.get_idle_entry_num().await, Some(1));
+
+            // Updates resize the record in place.
+            page.insert_record(0, 1, &record(&"d".repeat(50)))
+                .await
+                .unwrap();
+            assert_eq!(
+                page.read_entry(0).await.unwrap(),
+                (1, record(&"d".repeat(50)))
+            );
+            assert_eq!(page.read_entry(2).await.unwrap(), (0, records[2].clone()));
+            assert_eq!(page.num_records(), 2);
+
+            // Trailing empty slots are dropped.
+            page.remove_record(2).await.unwrap();
+            assert_eq!(page.as_data_page().capacity(), 1);
+
+            // A record can not exceed the free space.
+            let free_space = page.as_data_page().free_space();
+            let too_large = record(&"e".repeat(free_space));
+            assert!(matches!(
+                page.insert_record(1, 0, &too_large).await,
+                Err(Error::TooLargeSize)
+            ));
+            assert_eq!(page.as_data_page().capacity(), 1);
+
+            unsafe { page.forget_type().dealloc() };
+        })
+    }
+}
@@ -1,13 +1,21 @@
 use std::{
+    collections::HashSet,
+    io::ErrorKind,
     sync::atomic::{AtomicUsize, Ordering},
     usize, vec,
 };
 
 use dashmap::{mapref::one::RefMut, DashMap};
+use futures::lock::{Mutex, MutexGuard};
 
-use super::partition::PartitionHandle;
+use super::{marker, partition::PartitionHandle, reader::PageReader, PageRef};
 use crate::{
     error::{Error, Result},
+    options::Options,
+    table::{
+        cache::{CacheStats, PageCache, PageGuard},
+        manifest::{Manifest, ManifestEntry, TxnId, MAX_UPDATE_SIZE},
+    },
     utils::fs,
 };
 
@@ -32,6 +40,21 @@ pub const DATA_HEADER_SIZE: usize = 10;
 /// 2-bytes : free space in current data page.
 pub const DATA_ENTRY_SIZE: usize = 10;
 
+/// The max number of pages written back at once.
+pub const MAX_WRITE_PAGES: usize = 32;
+
+/// The partition of redo or undo wal-log.
+pub const LOG_PARTITION: usize = 0;
+
+/// The partition of table metadata, i.e. the catalog.
+pub const METADATA_PARTITION: usize = 1;
+
+/// The partition of table indices.
+pub const INDEX_PARTITION: usize = 2;
+
+/// The first partition of table header/data pages.
+pub const FIRST_TABLE_PARTITION: usize = 3;
+
 /// Reserve 36 bytes on each page for bookkeeping for recovery
 /// (used to store the pageLSN, and to ensure that a redo-only/undo-only log
 /// record can fit on one page).
@@ -47,6 +70,18 @@ pub struct PageDirectory {
 
     partitions: DashMap<usize, PartitionHandle>,
 
+    /// The buffer pool shared by all partitions. Don't lock the cache while
+    /// holding a partition, as the cache may write back pages of any
+    /// partition.
+    cache: PageCache,
+
+    /// The number of frames of the private ring of a full scan.
+    scan_ring_pages: usize,
+
+    /// The write-ahead log in `LOG_PARTITION`, which is not one of
+    /// `partitions`.
+    manifest: Mutex<Manifest>,
+
     /// Counter to generate new partition numbers.
     partition_counter: AtomicUsize,
 
@@ -62,7 +97,7 @@ impl PageDirectory {
     }
 
     #[inline]
-    async fn inner_alloc_part(&mut self, part_num: usize) -> Result<usize> {
+    async fn inner_alloc_part(&self, part_num: usize) -> Result<usize> {
         if self.partitions.contains_key(&part_num) {
             return Err(Error::Corrupted(format!(
                 "allocate partition failed: partition number {} is exist.",
@@ -86,42 +121,392 @@ impl PageDirectory {
     /// - 1 represent table metadata.
     /// - 2 represent table indices.
     /// - .. represent table header/data page.
-    pub fn new(path: String) -> Self {
-        PageDirectory {
+    ///
+    /// The log partition is opened with the directory, other partitions are
+    /// opened on demand. Data pages are cached in `options.cache_frames`
+    /// frames. The directory must be recovered (see `recover::recover`) before
+    /// use.
+    pub async fn open(options: &Options) -> Result<Self> {
+        let path = options.path.clone();
+        Ok(PageDirectory {
             page_manager_id: 0,
-            empty_page_metadata_size: 0,
+            empty_page_metadata_size: DATA_HEADER_SIZE,
             partitions: DashMap::new(),
-            partition_counter: AtomicUsize::new(0),
+            cache: PageCache::new(options.cache_frames, options.eviction_policy),
+            scan_ring_pages: options.scan_ring_pages,
+            manifest: Mutex::new(Manifest::open(&path).await?),
+            partition_counter: AtomicUsize::new(FIRST_TABLE_PARTITION),
             path,
+        })
+    }
+
+    /// Returns the path prefix of partition files.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Locks and returns the write-ahead log. Don't get a partition while
+    /// holding the log, as the partition may be waiting for the log.
+    pub async fn manifest(&self) -> MutexGuard<'_, Manifest> {
+        self.manifest.lock().await
+    }
+
+    /// Starts a transaction, returns its id.
+    pub async fn begin_txn(&self) -> TxnId {
+        self.manifest().await.begin()
+    }
+
+    /// Commits and ends the transaction.
+    pub async fn commit_txn(&self, txn: TxnId) -> Result<()> {
+        let mut manifest = self.manifest().await;
+        manifest.commit(txn).await?;
+        manifest.end(txn).await?;
+        Ok(())
+    }
+
+    /// Logs an entry of transaction which isn't a page update, e.g. partition
+    /// allocation.
+    pub async fn log(&self, txn: TxnId, entry: ManifestEntry) -> Result<u64> {
+        self.manifest().await.append(txn, entry).await
+    }
+
+    /// Logs the change of cached data page since `before`, the image of page
+    /// before the change. The page LSN is set to the LSN of the last record and
+    /// the page is marked dirty, the log is flushed before the page is written
+    /// back.
+    pub async fn log_page_update(
+        &self,
+        txn: TxnId,
+        page: &mut PageGuard<'_>,
+        before: &[u8],
+    ) -> Result<()> {
+        let after = page.as_data_page().vals();
+        // The page LSN in reserved bytes is not logged.
+        let start = match (RESERVED_SIZE..DEFAULT_PAGE_SIZE).find(|&i| before[i] != after[i]) {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        let end = (start..DEFAULT_PAGE_SIZE)
+            .rev()
+            .find(|&i| before[i] != after[i])
+            .unwrap()
+            + 1;
+
+        // A checkpoint taken in the meantime must see the page dirty.
+        self.mark_dirty(page)?;
+        let mut manifest = self.manifest().await;
+        let mut lsn = 0;
+        for offset in (start..end).step_by(MAX_UPDATE_SIZE) {
+            let chunk = offset..end.min(offset + MAX_UPDATE_SIZE);
+            let entry = ManifestEntry::UpdatePage {
+                page: page.virtual_page_num(),
+                offset: offset as u16,
+                before: before[chunk.clone()].to_vec(),
+                after: after[chunk].to_vec(),
+            };
+            lsn = manifest.append(txn, entry).await?;
+        }
+        drop(manifest);
+
+        page.as_data_page_mut().set_page_lsn(lsn);
+        Ok(())
+    }
+
+    /// Gets a data page (page parameter is virtual offset) through the cache,
+    /// the page is read in if it's not cached, which may evict another page.
+    /// The cache is not locked during the I/O, only the frame of page is
+    /// latched.
+    pub async fn fetch_page(&self, page: u64) -> Result<PageGuard<'_>> {
+        let mut state = self.cache.lock().await;
+        while let Some(frame) = self.cache.pin(&mut state, page) {
+            self.cache.record_access(&mut state, frame, true);
+            drop(state);
+            // Waits for the page being read in if any.
+            let guard = self.cache.guard(frame, page).await;
+            if !guard.is_stale() {
+                return Ok(guard);
+            }
+            drop(guard);
+            state = self.cache.lock().await;
+        }
+        if !self.is_page_allocated(page).await {
+            return Err(Error::NotFound(format!("page {}", page)));
+        }
+
+        let frame = self.cache.victim(&mut state)?;
+        let (mut guard, evicted) = self.cache.reserve(&mut state, frame, page);
+        drop(state);
+
+        if let Some(evicted) = evicted {
+            if let Err(e) = self.write_back(evicted, &[*guard]).await {
+                let mut state = self.cache.lock().await;
+                self.cache.cancel_write_back(&mut state, guard, evicted);
+                return Err(e);
+            }
+            self.cache
+                .finish_write_back(&mut *self.cache.lock().await, frame, evicted);
+        }
+
+        let (part_num, page_num) = (calculate_part_num(page), calculate_page_num(page));
+        let read = match self.get_partition(part_num) {
+            Ok(mut part) => {
+                part.read_page(page_num, guard.as_data_page_mut().vals_mut())
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let mut state = self.cache.lock().await;
+        if let Err(e) = read {
+            self.cache.cancel_read(&mut state, guard);
+            return Err(e);
+        }
+        guard.as_data_page_mut().set_page_num(page_num as u64);
+        self.cache.finish_read(&mut state, &guard);
+        Ok(guard)
+    }
+
+    /// Gets a data page only if it's cached, which doesn't count as an access
+    /// of the page.
+    pub async fn peek_page(&self, page: u64) -> Option<PageGuard<'_>> {
+        let frame = self.cache.pin(&mut *self.cache.lock().await, page)?;
+        let guard = self.cache.guard(frame, page).await;
+        (!guard.is_stale()).then_some(guard)
+    }
+
+    /// Returns a reader of all data pages of partition for a full scan, which
+    /// doesn't read the pages into cache.
+    pub fn scan_pages(&self, part_num: usize) -> Result<PageReader<'_>> {
+        let page_nums = self.get_partition(part_num)?.data_page_nums();
+        Ok(PageReader::new(
+            self,
+            part_num,
+            page_nums,
+            self.scan_ring_pages,
+        ))
+    }
+
+    /// Gets a data page of partition with the required free space, allocates a
+    /// new page if not exists.
+    pub async fn fetch_page_with_space(
+        &self,
+        part_num: usize,
+        required_space: usize,
+    ) -> Result<PageGuard<'_>> {
+        self.fetch_page_with(part_num, required_space, |_| true)
+            .await
+    }
+
+    /// Gets a data page of partition with the required free space like
+    /// `fetch_page_with_space`, which is also accepted by `accept`, e.g. the
+    /// entries of a fixed-size page are large enough. A new page is always
+    /// accepted.
+    pub async fn fetch_page_with(
+        &self,
+        part_num: usize,
+        required_space: usize,
+        accept: impl Fn(&PageRef<marker::Data>) -> bool,
+    ) -> Result<PageGuard<'_>> {
+        assert!(
+            required_space > 0,
+            "can't request nonpositive amount of space."
+        );
+        assert!(
+            required_space <= effective_page_size() - self.empty_page_metadata_size,
+            "requesting page with more space than the size of page."
+        );
+
+        let free_spaces = {
+            let part = self.get_partition(part_num)?;
+            part.data_page_nums()
+                .into_iter()
+                .map(|page_num| (page_num, part.free_space(page_num)))
+                .collect::<Vec<_>>()
+        };
+        for (page_num, free_space) in free_spaces {
+            if free_space.map_or(true, |free_space| free_space >= required_space) {
+                let page = self
+                    .fetch_page(virtual_page_num(part_num, page_num))
+                    .await?;
+                // Picks up the free space of page, so that a page without
+                // enough space isn't read again.
+                let free_space = page.as_data_page().free_space();
+                self.get_partition(part_num)?
+                    .set_free_space(page_num, free_space);
+                if free_space >= required_space && accept(&page) {
+                    return Ok(page);
+                }
+            }
+        }
+
+        let page_num = self.get_partition(part_num)?.alloc_page().await?;
+        self.fetch_page(virtual_page_num(part_num, page_num)).await
+    }
+
+    /// Marks the cached page changed, so that it's written back before evicted,
+    /// and picks up the free space of page.
+    pub fn mark_dirty(&self, page: &PageGuard<'_>) -> Result<()> {
+        page.set_dirty();
+        let page_num = calculate_page_num(page.virtual_page_num());
+        self.get_partition(calculate_part_num(page.virtual_page_num()))?
+            .set_free_space(page_num, page.as_data_page().free_space());
+        Ok(())
+    }
+
+    /// Writes back all dirty pages in cache, adjacent dirty pages of a
+    /// partition are written together.
+    pub async fn flush_pages(&self) -> Result<()> {
+        let mut pages = {
+            let state = self.cache.lock().await;
+            self.cache.dirty_pages(&state)
+        };
+        pages.sort_unstable();
+
+        let mut run: Vec<PageGuard<'_>> = vec![];
+        for page in pages {
+            // The page may be evicted or written back in the meantime.
+            let frame = match self.cache.pin(&mut *self.cache.lock().await, page) {
+                Some(frame) => frame,
+                None => continue,
+            };
+            // Don't wait for a latch while holding the latches of run, the
+            // holder may be waiting for a page of run, e.g. the nodes of
+            // b+tree are latched from parent to child regardless of page
+            // order.
+            let guard = match self.cache.try_guard(frame, page) {
+                Some(guard) => guard,
+                None => {
+                    if !run.is_empty() {
+                        self.write_back_run(&run).await?;
+                        run.clear();
+                    }
+                    self.cache.guard(frame, page).await
+                }
+            };
+            if guard.is_stale() || !guard.is_dirty() {
+                continue;
+            }
+            if let Some(last) = run.last() {
+                if !is_next_page(last.virtual_page_num(), page) || run.len() == MAX_WRITE_PAGES {
+                    self.write_back_run(&run).await?;
+                    run.clear();
+                }
+            }
+            run.push(guard);
         }
+        if !run.is_empty() {
+            self.write_back_run(&run).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes back the consecutive pages of `run` at once.
+    async fn write_back_run(&self, run: &[PageGuard<'_>]) -> Result<()> {
+        let pages = run.iter().map(|guard| **guard).collect::<Vec<_>>();
+        self.write_back(run[0].virtual_page_num(), &pages).await?;
+        for guard in run {
+            guard.set_clean();
+        }
+        Ok(())
+    }
+
+    /// Returns the counters of page lookups of the cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Writes cached pages back to their partition from page `first`, after
+    /// the changes to the pages are logged.
+    async fn write_back(&self, first: u64, data_pages: &[PageRef<marker::Data>]) -> Result<()> {
+        let lsn = data_pages
+            .iter()
+            .map(|page| page.as_data_page().page_lsn())
+            .max()
+            .unwrap_or(0);
+        self.manifest().await.flush_to(lsn).await?;
+
+        let bufs = data_pages
+            .iter()
+            .map(|page| page.as_data_page().vals())
+            .collect::<Vec<_>>();
+        self.get_partition(calculate_part_num(first))?
+            .write_pages(calculate_page_num(first), &bufs)
+            .await
     }
 
     /// Allocates a new partition, Returns number of new partition.
-    pub async fn alloc_part(&mut self) -> Result<usize> {
+    pub async fn alloc_part(&self) -> Result<usize> {
         let part_num = self.partition_counter.fetch_add(1, Ordering::Release);
+
+        // The file may be left by a partition which was allocated but never
+        // recorded in the catalog before a crash, the new partition starts
+        // empty.
+        match fs::remove_file(format!("{}.{}", self.path, part_num)).await {
+            Err(Error::IO(e)) if e.kind() != ErrorKind::NotFound => return Err(Error::IO(e)),
+            _ => {}
+        }
+
         self.inner_alloc_part(part_num).await
     }
 
-    pub async fn alloc_part_with_num(&mut self, part_num: usize) -> Result<usize> {
-        let num = self.partition_counter.load(Ordering::Acquire);
-        let new_num = if part_num > num { part_num } else { num } + 1;
+    /// Opens the existing partition `part_num` (or creates it) if it's not
+    /// opened yet, partitions allocated afterwards never reuse the number.
+    pub async fn alloc_part_with_num(&self, part_num: usize) -> Result<usize> {
+        self.reserve_part_num(part_num);
+        if self.partitions.contains_key(&part_num) {
+            return Ok(part_num);
+        }
 
-        if self
-            .partition_counter
-            .compare_exchange(num, new_num, Ordering::AcqRel, Ordering::Acquire)
-            .is_err()
-        {
-            return Err(Error::Corrupted(format!(
-                "allocate partitiion {} failed",
-                part_num
-            )));
+        self.inner_alloc_part(part_num).await
+    }
+
+    /// Makes sure partitions allocated afterwards never reuse `part_num`.
+    pub fn reserve_part_num(&self, part_num: usize) {
+        self.partition_counter
+            .fetch_max(part_num + 1, Ordering::AcqRel);
+    }
+
+    /// Deletes the partition whether it's opened or not.
+    pub async fn delete_part(&self, part_num: usize) -> Result<()> {
+        if self.partitions.contains_key(&part_num) {
+            return self.release_part(part_num).await;
+        }
+
+        match fs::remove_file(format!("{}.{}", self.path, part_num)).await {
+            Err(Error::IO(e)) if e.kind() != ErrorKind::NotFound => Err(Error::IO(e)),
+            _ => Ok(()),
         }
+    }
 
-        self.inner_alloc_part(new_num).await
+    /// Syncs the open partitions to disk, returns their partition numbers.
+    pub async fn sync_parts(&self) -> Result<HashSet<usize>> {
+        let part_nums = self
+            .partitions
+            .iter()
+            .map(|part| *part.key())
+            .collect::<Vec<_>>();
+        let mut synced = HashSet::with_capacity(part_nums.len());
+        for part_num in part_nums {
+            // The partition may be released in the meantime.
+            if let Some(mut part) = self.partitions.get_mut(&part_num) {
+                part.sync().await?;
+                synced.insert(part_num);
+            }
+        }
+        Ok(synced)
     }
 
     /// Release a partition from use.
-    pub async fn release_part(&mut self, part_num: usize) -> Result<()> {
+    pub async fn release_part(&self, part_num: usize) -> Result<()> {
+        {
+            let mut state = self.cache.lock().await;
+            for page in self
+                .cache
+                .cached_pages(&state, |page| calculate_part_num(page) == part_num)
+            {
+                self.cache.discard(&mut state, page)?;
+            }
+        }
+
         let mut part = self
             .partitions
             .remove(&part_num)
@@ -163,6 +548,7 @@ impl PageDirectory {
     /// Release a page from use.
     pub async fn release_page(&self, page: u64) -> Result<()> {
         let (part_num, page_num) = (calculate_part_num(page), calculate_page_num(page));
+        self.cache.discard(&mut *self.cache.lock().await, page)?;
 
         let mut ph = self.get_partition(part_num)?;
         ph.release_page(page_num).await?;
@@ -174,7 +560,7 @@ impl PageDirectory {
         let (part_num, page_num) = (calculate_part_num(page), calculate_page_num(page));
 
         match self.partitions.get(&part_num) {
-            Some(handle) => handle.is_not_allocated_page(page_num),
+            Some(handle) => !handle.is_not_allocated_page(page_num),
             None => false,
         }
     }
@@ -186,9 +572,10 @@ impl PageDirectory {
             .ok_or(Error::NotFound(format!("partition number {}", part_num)))
     }
 
-    /// Reads a page(page parameters is virtual offset).
+    /// Reads a page(page parameters is virtual offset) from disk, bypassing
+    /// the cache.
     pub async fn read_page(&self, page: u64) -> Result<Vec<u8>> {
-        let mut data = vec![];
+        let mut data = vec![0u8; DEFAULT_PAGE_SIZE];
         self.read_page_to(page, &mut data).await?;
         Ok(data)
     }
@@ -206,7 +593,7 @@ impl PageDirectory {
         Ok(())
     }
 
-    /// Writes to a page.
+    /// Writes to a page on disk, bypassing the cache.
     pub async fn write_page(&self, page: u64, data: &[u8]) -> Result<()> {
         assert!(
             data.len() == DEFAULT_PAGE_SIZE,
@@ -219,6 +606,23 @@ impl PageDirectory {
         ph.write_page(page_num, data).await?;
         Ok(())
     }
+
+    /// Writes consecutive pages on disk from page `first`, bypassing the cache
+    /// (see `is_next_page`).
+    pub async fn write_pages(&self, first: u64, data: &[&[u8]]) -> Result<()> {
+        self.get_partition(calculate_part_num(first))?
+            .write_pages(calculate_page_num(first), data)
+            .await
+    }
+}
+
+/// Checks whether virtual page `next` follows `page` in the partition file,
+/// i.e. they're data pages of the same partition under the same header page.
+#[inline]
+pub fn is_next_page(page: u64, next: u64) -> bool {
+    next == page + 1
+        && calculate_part_num(page) == calculate_part_num(next)
+        && calculate_page_num(next) % DATA_PAGES_PER_HEADER != 0
 }
 
 /// Returns the number of data page entries in a header page.
@@ -281,3 +685,68 @@ pub fn calculate_page_num(virtial_page_num: u64) -> usize {
 pub fn virtual_page_num(part_num: usize, page_num: usize) -> u64 {
     (part_num * 10000000000 + page_num) as u64
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::datatypes::{array::scalar::ScalarImpl, record::Record};
+
+    async fn open(dir: &TempDir) -> PageDirectory {
+        let options = Options {
+            path: dir.path().join("db").to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        PageDirectory::open(&options).await.unwrap()
+    }
+
+    #[test]
+    fn test_fetch_page_with_space() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let page_directory = open(&dir).await;
+            let part_num = FIRST_TABLE_PARTITION;
+            page_directory.alloc_part_with_num(part_num).await.unwrap();
+
+            // Fill the first page up with a large record.
+            let record = Record::from(vec![ScalarImpl::String("a".repeat(3500))]);
+            let mut page = page_directory
+                .fetch_page_with_space(part_num, 1)
+                .await
+                .unwrap();
+            page.format_slot_directory().unwrap();
+            page.insert_record(0, 0, &record).await.unwrap();
+            page_directory.mark_dirty(&page).unwrap();
+            let free_space = page.as_data_page().free_space();
+            drop(page);
+            page_directory.flush_pages().await.unwrap();
+            drop(page_directory);
+
+            // The free space is unknown after reopen, and picked up when the
+            // page is rejected.
+            let page_directory = open(&dir).await;
+            page_directory.alloc_part_with_num(part_num).await.unwrap();
+            assert_eq!(
+                page_directory
+                    .get_partition(part_num)
+                    .unwrap()
+                    .free_space(0),
+                None
+            );
+            let page = page_directory
+                .fetch_page_with_space(part_num, free_space + 1)
+                .await
+                .unwrap();
+            assert_eq!(page.virtual_page_num(), virtual_page_num(part_num, 1));
+            assert_eq!(
+                page_directory
+                    .get_partition(part_num)
+                    .unwrap()
+                    .free_space(0),
+                Some(free_space)
+            );
+        })
+    }
+}
@@ -1,32 +1,74 @@
-use std::io::SeekFrom;
+use std::io::{self, ErrorKind, IoSlice, IoSliceMut, SeekFrom};
 
 use async_fs::File;
 use futures_lite::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 use crate::error::Result;
 
+/// The OS file of a partition. Pages are read and written at their offsets,
+/// consecutive pages with a single vectored call, which is retried until all
+/// bytes are transferred. A read past the end of file fails with
+/// `ErrorKind::UnexpectedEof`.
+///
+/// `async_fs::File` buffers writes, so every write is flushed to the OS before
+/// returning, but it's not durable until `sync` returns.
 pub struct PageFile(pub File);
 
 impl PageFile {
     #[inline]
-    pub async fn read(&mut self, ouput: &mut [u8]) -> Result<()> {
-        self.0.read(ouput).await?;
-        self.0.seek(SeekFrom::Start(0)).await?;
-        Ok(())
+    pub async fn read_from(&mut self, offset: u64, output: &mut [u8]) -> Result<()> {
+        self.read_pages_from(offset, &mut [output]).await
     }
 
-    #[inline]
-    pub async fn read_from(&mut self, offset: u64, ouput: &mut [u8]) -> Result<()> {
+    /// Reads consecutive pages from `offset` into `outputs`.
+    pub async fn read_pages_from(&mut self, offset: u64, outputs: &mut [&mut [u8]]) -> Result<()> {
         self.0.seek(SeekFrom::Start(offset)).await?;
-        self.0.read(ouput).await?;
-        self.0.seek(SeekFrom::Start(0)).await?;
+
+        let lens = outputs
+            .iter()
+            .map(|output| output.len())
+            .collect::<Vec<_>>();
+        let mut pos = (0, 0);
+        while skip_empty(&lens, &mut pos) {
+            let (first, rest) = outputs[pos.0..].split_first_mut().unwrap();
+            let mut slices = Vec::with_capacity(rest.len() + 1);
+            slices.push(IoSliceMut::new(&mut first[pos.1..]));
+            slices.extend(rest.iter_mut().map(|output| IoSliceMut::new(output)));
+
+            match self.0.read_vectored(&mut slices).await {
+                Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(n) => advance(&lens, &mut pos, n),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
         Ok(())
     }
 
     #[inline]
     pub async fn write_to(&mut self, offset: u64, buf: &[u8]) -> Result<()> {
+        self.write_pages_to(offset, &[buf]).await
+    }
+
+    /// Writes consecutive pages from `offset`.
+    pub async fn write_pages_to(&mut self, offset: u64, bufs: &[&[u8]]) -> Result<()> {
         self.0.seek(SeekFrom::Start(offset)).await?;
-        self.0.write(buf).await?;
+
+        let lens = bufs.iter().map(|buf| buf.len()).collect::<Vec<_>>();
+        let mut pos = (0, 0);
+        while skip_empty(&lens, &mut pos) {
+            let mut slices = Vec::with_capacity(bufs.len() - pos.0);
+            slices.push(IoSlice::new(&bufs[pos.0][pos.1..]));
+            slices.extend(bufs[pos.0 + 1..].iter().map(|buf| IoSlice::new(buf)));
+
+            match self.0.write_vectored(&slices).await {
+                Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero).into()),
+                Ok(n) => advance(&lens, &mut pos, n),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.0.flush().await?;
         Ok(())
     }
 
@@ -35,17 +77,94 @@ impl PageFile {
     where
         F: FnOnce() -> Vec<u8>,
     {
-        self.0.seek(SeekFrom::Start(offset)).await?;
-        self.0.write(&f()).await?;
-        Ok(())
+        self.write_to(offset, &f()).await
     }
 
+    /// Syncs the written data to disk (`fdatasync`), the pages written before
+    /// are durable once this returns. The write-ahead log is synced before a
+    /// commit returns, and partitions before a checkpoint.
     #[inline]
-    pub async fn write_f<F>(&mut self, f: F) -> Result<()>
-    where
-        F: FnOnce() -> Vec<u8>,
-    {
-        self.0.write(&f()).await?;
+    pub async fn sync(&mut self) -> Result<()> {
+        self.0.sync_data().await?;
         Ok(())
     }
 }
+
+/// Skips the empty buffers from `pos`, which is the buffer index and the offset
+/// in the buffer, returns whether any byte is left.
+fn skip_empty(lens: &[usize], pos: &mut (usize, usize)) -> bool {
+    while pos.0 < lens.len() && pos.1 == lens[pos.0] {
+        *pos = (pos.0 + 1, 0);
+    }
+    pos.0 < lens.len()
+}
+
+/// Advances `pos` over `n` transferred bytes.
+fn advance(lens: &[usize], pos: &mut (usize, usize), mut n: usize) {
+    while n > 0 {
+        let left = lens[pos.0] - pos.1;
+        if n < left {
+            pos.1 += n;
+            return;
+        }
+        n -= left;
+        *pos = (pos.0 + 1, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{error::Error, utils::fs};
+
+    #[test]
+    fn test_page_file() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("db.3").to_string_lossy().to_string();
+            let mut file = PageFile(fs::create_file(path).await.unwrap());
+
+            let pages = (1..=3u8).map(|i| vec![i; 16]).collect::<Vec<_>>();
+            let bufs = pages.iter().map(|page| page.as_slice()).collect::<Vec<_>>();
+            file.write_pages_to(16, &bufs).await.unwrap();
+            file.write_to(0, &[9; 16]).await.unwrap();
+            file.sync().await.unwrap();
+
+            let mut outputs = [vec![0u8; 16], vec![], vec![0u8; 40], vec![0u8; 8]];
+            let mut slices = outputs
+                .iter_mut()
+                .map(|output| output.as_mut_slice())
+                .collect::<Vec<_>>();
+            file.read_pages_from(0, &mut slices).await.unwrap();
+            assert_eq!(outputs[0], vec![9; 16]);
+            assert_eq!(outputs[2][..16], vec![1; 16]);
+            assert_eq!(outputs[2][16..32], vec![2; 16]);
+            assert_eq!(outputs[2][32..], vec![3; 8]);
+            assert_eq!(outputs[3], vec![3; 8]);
+
+            // Reads past the end of file.
+            let mut output = vec![0u8; 16];
+            match file.read_from(56, &mut output).await {
+                Err(Error::IO(e)) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+                r => panic!("unexpected {:?}", r),
+            }
+        })
+    }
+
+    #[test]
+    fn test_advance() {
+        let lens = [4, 0, 2, 3];
+        let mut pos = (0, 0);
+        advance(&lens, &mut pos, 3);
+        assert_eq!(pos, (0, 3));
+        advance(&lens, &mut pos, 2);
+        assert_eq!(pos, (2, 1));
+        assert!(skip_empty(&lens, &mut pos));
+        advance(&lens, &mut pos, 4);
+        assert_eq!(pos, (4, 0));
+        assert!(!skip_empty(&lens, &mut pos));
+    }
+}
@@ -1,20 +1,17 @@
-use std::{io::ErrorKind, ptr::NonNull, usize, vec};
+use std::{collections::HashMap, io::ErrorKind, usize, vec};
 
 use bytes::BufMut;
 
 use super::{
-    marker,
     page_directory::{
         virtual_header_page_offset, DATA_PAGES_PER_HEADER, DEFAULT_PAGE_SIZE, MAX_HEADER_PAGES,
     },
     page_file::PageFile,
-    PageRef,
 };
 use crate::{
     error::{Error, Result},
-    table::{
-        cache::PageCache,
-        page::page_directory::{effective_page_size, virtual_data_page_offset, virtual_page_num},
+    table::page::page_directory::{
+        effective_page_size, virtual_data_page_offset, virtual_page_num,
     },
     utils::{bitmap::Bitmap, fs},
 };
@@ -24,21 +21,19 @@ pub struct PartitionHandle {
     /// partition from data pages.
     part_num: usize,
 
-    /// Page cache.
-    cache: PageCache,
-
     page_file: PageFile,
 
     /// The bitmap of master page.
     m_bitmap: Bitmap,
 
-    /// The bitmap of header page.
+    /// The bitmap of header page, grows as header pages are allocated.
     h_bitmaps: Vec<Bitmap>,
 
+    /// The amount of free space of data pages, which is loaded lazily.
+    free_spaces: HashMap<usize, usize>,
+
     /// The size of metadata of an empty data page.
     empty_page_metadata_size: usize,
-
-    first_header: PageRef<marker::Header>,
 }
 
 impl PartitionHandle {
@@ -62,7 +57,7 @@ impl PartitionHandle {
             }
         };
 
-        let mut header_pages = Vec::with_capacity(MAX_HEADER_PAGES);
+        let mut h_bitmaps = Vec::new();
 
         let mut m_bitmap = Bitmap::new(MAX_HEADER_PAGES as u32);
 
@@ -70,24 +65,25 @@ impl PartitionHandle {
         if page_len == 0 {
             // New file, write empty master page.
             page_file
-                .write_f(|| Self::write_master_page(&m_bitmap))
+                .write_to_f(0, || Self::write_master_page(&m_bitmap))
                 .await?;
         } else {
             // Old file, read in master page and header pages.
             let mut m_buf = vec![0u8; DEFAULT_PAGE_SIZE];
-            page_file.read(&mut m_buf).await?;
+            page_file.read_from(0, &mut m_buf).await?;
 
-            for (index, bits) in m_buf.splitn(2, |v| v.is_ascii_digit()).enumerate() {
+            for index in 0..MAX_HEADER_PAGES {
                 // fill master page.
-                if u16::from_be_bytes([bits[0], bits[1]]) == 1 {
+                if u16::from_be_bytes([m_buf[2 * index], m_buf[2 * index + 1]]) == 1 {
                     m_bitmap.set(index as u32);
 
                     // fill header page.
+                    h_bitmaps.resize(index + 1, Bitmap::new(DATA_PAGES_PER_HEADER as u32));
                     let offset = virtual_header_page_offset(index);
                     if offset < page_len {
                         let mut h_buf = vec![0u8; DEFAULT_PAGE_SIZE];
                         page_file.read_from(offset, &mut h_buf).await?;
-                        header_pages.insert(index, h_buf);
+                        h_bitmaps[index] = Self::read_header_page(&h_buf);
                     }
                 }
             }
@@ -95,28 +91,29 @@ impl PartitionHandle {
 
         Ok(PartitionHandle {
             part_num,
-            cache: PageCache::with_capacity(10),
             page_file,
             m_bitmap,
-            h_bitmaps: Vec::with_capacity(DATA_PAGES_PER_HEADER),
+            h_bitmaps,
+            free_spaces: HashMap::new(),
             empty_page_metadata_size,
-            first_header: PageRef::new_header_page(0, 0, true),
         })
     }
 
     /// Allocates a new page in the partition.
     pub async fn alloc_page(&mut self) -> Result<usize> {
-        match self.m_bitmap.vacance() {
-            None => Err(Error::Corrupted(
-                "partition has reached max size.".to_owned(),
-            )),
-            Some(h) => match self.h_bitmaps[h as usize].vacance() {
-                None => Err(Error::Corrupted(
-                    "header page not has free space.".to_owned(),
-                )),
-                Some(p) => self.alloc_page_with_index(h as usize, p as usize).await,
-            },
-        }
+        let header_index = (0..MAX_HEADER_PAGES)
+            .find(|&h| h >= self.h_bitmaps.len() || !self.h_bitmaps[h].is_full())
+            .ok_or_else(|| Error::Corrupted("partition has reached max size.".to_owned()))?;
+
+        let page_index = match self.h_bitmaps.get(header_index) {
+            None => 0,
+            Some(bitmap) => bitmap
+                .vacance()
+                .ok_or_else(|| Error::Corrupted("header page not has free space.".to_owned()))?,
+        };
+
+        self.alloc_page_with_index(header_index, page_index as usize)
+            .await
     }
 
     pub async fn alloc_page_with_index(
@@ -124,11 +121,21 @@ impl PartitionHandle {
         header_index: usize,
         page_index: usize,
     ) -> Result<usize> {
+        assert!(
+            header_index < MAX_HEADER_PAGES && page_index < DATA_PAGES_PER_HEADER,
+            "page {} in header {} out of range.",
+            page_index,
+            header_index,
+        );
+        if self.h_bitmaps.len() <= header_index {
+            self.h_bitmaps
+                .resize(header_index + 1, Bitmap::new(DATA_PAGES_PER_HEADER as u32));
+        }
         assert!(
             !self.h_bitmaps[header_index].exist(page_index as u32),
             "page {} in header {} already allocated.",
-            header_index,
             page_index,
+            header_index,
         );
         self.h_bitmaps[header_index].set(page_index as u32);
         self.m_bitmap.set(header_index as u32);
@@ -137,6 +144,9 @@ impl PartitionHandle {
 
         let _vpn = virtual_page_num(self.part_num, page_num);
 
+        // Write an empty data page, so that the page can be read in.
+        self.write_page(page_num, &vec![0u8; DEFAULT_PAGE_SIZE])
+            .await?;
         self.page_file
             .write_to_f(0, || Self::write_master_page(&self.m_bitmap))
             .await?;
@@ -145,6 +155,11 @@ impl PartitionHandle {
                 Self::write_header_page(&self.h_bitmaps[header_index])
             })
             .await?;
+
+        self.free_spaces.insert(
+            page_num,
+            effective_page_size() - self.empty_page_metadata_size,
+        );
         Ok(page_num)
     }
 
@@ -167,6 +182,9 @@ impl PartitionHandle {
             self.m_bitmap.clear(idx);
         }
 
+        self.page_file
+            .write_to_f(0, || Self::write_master_page(&self.m_bitmap))
+            .await?;
         Ok(())
     }
 
@@ -178,7 +196,7 @@ impl PartitionHandle {
         );
 
         assert!(
-            self.h_bitmaps[header_index].exist(page_index as u32),
+            !self.is_not_allocated_page(page_num),
             "can't release unallocated page."
         );
 
@@ -191,6 +209,7 @@ impl PartitionHandle {
             .await?;
 
         self.h_bitmaps[header_index].clear(page_index as u32);
+        self.free_spaces.remove(&page_num);
 
         self.page_file
             .write_to_f(0, || Self::write_master_page(&self.m_bitmap))
@@ -217,6 +236,40 @@ impl PartitionHandle {
         Ok(())
     }
 
+    /// Reads in consecutive data pages from `page_num`, one page into each
+    /// output.
+    pub async fn read_pages(&mut self, page_num: usize, outputs: &mut [&mut [u8]]) -> Result<()> {
+        self.check_contiguous(page_num, outputs.len())?;
+        self.page_file
+            .read_pages_from(virtual_data_page_offset(page_num), outputs)
+            .await
+    }
+
+    /// Writes consecutive data pages from `page_num`.
+    pub async fn write_pages(&mut self, page_num: usize, bufs: &[&[u8]]) -> Result<()> {
+        self.check_contiguous(page_num, bufs.len())?;
+        self.page_file
+            .write_pages_to(virtual_data_page_offset(page_num), bufs)
+            .await
+    }
+
+    /// Checks the `len` data pages from `page_num` are allocated and under the
+    /// same header page, so that they're contiguous in the file.
+    fn check_contiguous(&self, page_num: usize, len: usize) -> Result<()> {
+        let end = page_num + len;
+        assert!(
+            len > 0 && page_num / DATA_PAGES_PER_HEADER == (end - 1) / DATA_PAGES_PER_HEADER,
+            "pages {}..{} are not contiguous",
+            page_num,
+            end
+        );
+        // The pages may be released after they're listed.
+        match (page_num..end).find(|&p| self.is_not_allocated_page(p)) {
+            Some(page_num) => Err(Error::NotFound(format!("page {}", page_num))),
+            None => Ok(()),
+        }
+    }
+
     /// Writes to a data page. Assumes that the partition lock is held.
     pub async fn write_page(&mut self, page_num: usize, buf: &[u8]) -> Result<()> {
         assert!(
@@ -232,10 +285,15 @@ impl PartitionHandle {
         Ok(())
     }
 
+    /// Syncs the pages written to the partition to disk.
+    pub async fn sync(&mut self) -> Result<()> {
+        self.page_file.sync().await
+    }
+
     /// Writes the master page to disk, because the default page size of 4kb, so
     /// we put 1bit of bitmap as 2bits.
     pub fn write_master_page(bitmap: &Bitmap) -> Vec<u8> {
-        let mut buf = vec![0u8; DEFAULT_PAGE_SIZE];
+        let mut buf = Vec::with_capacity(DEFAULT_PAGE_SIZE);
         (0..MAX_HEADER_PAGES).for_each(|index| {
             let v = if bitmap.exist(index as u32) {
                 1u16
@@ -247,16 +305,28 @@ impl PartitionHandle {
         buf
     }
 
-    /// Writes the header page to disk.
+    /// Writes the header page to disk, 1bit for each data page.
     pub fn write_header_page(bitmap: &Bitmap) -> Vec<u8> {
         let mut buf = vec![0u8; DEFAULT_PAGE_SIZE];
-        (0..DEFAULT_PAGE_SIZE).for_each(|index| {
-            let v = if bitmap.exist(index as u32) { 1u8 } else { 0u8 };
-            buf.put_u8(v);
-        });
+        for index in bitmap.iter() {
+            buf[index as usize / 8] |= 1 << (index % 8);
+        }
         buf
     }
 
+    /// Reads the header page written by `write_header_page`.
+    pub fn read_header_page(buf: &[u8]) -> Bitmap {
+        let mut bitmap = Bitmap::new(DATA_PAGES_PER_HEADER as u32);
+        for (idx, byte) in buf.iter().enumerate().filter(|(_, byte)| **byte != 0) {
+            for bit in 0..8 {
+                if byte & (1 << bit) != 0 {
+                    bitmap.set((idx * 8 + bit) as u32);
+                }
+            }
+        }
+        bitmap
+    }
+
     /// Checks if page number is for an unallocated data.
     pub fn is_not_allocated_page(&self, page_num: usize) -> bool {
         let (header_index, page_index) = (
@@ -264,7 +334,7 @@ impl PartitionHandle {
             (page_num % DATA_PAGES_PER_HEADER),
         );
 
-        if header_index >= MAX_HEADER_PAGES
+        if header_index >= self.h_bitmaps.len()
             || !self.m_bitmap.exist(header_index as u32)
             || !self.h_bitmaps[header_index].exist(page_index as u32)
         {
@@ -274,64 +344,37 @@ impl PartitionHandle {
         false
     }
 
-    /// Gets page.
-    pub async fn get_page(&mut self, page_num: u64) -> Result<PageRef<marker::Data>> {
-        // Return header page if exist in page cache.
-        if let Some(entry) = self.cache.lookup(page_num) {
-            // return page;
-            assert!(
-                entry.num() == page_num,
-                "get page failure, expect {} but got {}",
-                page_num,
-                entry.num()
-            );
-
-            return Ok(unsafe { entry.page().cast_to_data_page_unchecked() });
-        }
-
-        assert!(
-            !self.is_not_allocated_page(page_num as usize),
-            "page {} is not allocated",
-            page_num
-        );
-
-        let mut buf = Vec::with_capacity(DEFAULT_PAGE_SIZE);
-        self.read_page(page_num as usize, &mut buf).await?;
-
-        let mut data_page = PageRef::new_data_page();
-        data_page.as_data_page_mut().fill(&buf);
-        Ok(data_page)
+    /// Returns the free space of data page picked up when the page is
+    /// allocated, looked up by `PageDirectory::fetch_page_with_space` or
+    /// changed (see `PageDirectory::mark_dirty`), `None` if it's not known
+    /// yet.
+    pub fn free_space(&self, page_num: usize) -> Option<usize> {
+        self.free_spaces.get(&page_num).copied()
     }
 
-    pub async fn get_page_with_space(&self, required_space: usize) -> PageRef<marker::Data> {
-        assert!(
-            required_space > 0,
-            "can't request nonpositive amount of space."
-        );
-
-        assert!(
-            required_space < effective_page_size() - self.empty_page_metadata_size,
-            "requesting page with more space than the size of page."
-        );
-
-        // todo (project4_part2): Update the following line.
-
-        let page = Box::new(
-            self.first_header
-                .as_header_page()
-                .load_page_with_space(required_space)
-                .await,
-        );
+    pub fn set_free_space(&mut self, page_num: usize, free_space: usize) {
+        self.free_spaces.insert(page_num, free_space);
+    }
 
-        PageRef::from_data_page(unsafe { NonNull::new_unchecked(Box::leak(page)) })
+    /// Returns the page numbers of all allocated data pages.
+    pub fn data_page_nums(&self) -> Vec<usize> {
+        self.m_bitmap
+            .iter()
+            .filter(|&h| (h as usize) < self.h_bitmaps.len())
+            .flat_map(|h| {
+                self.h_bitmaps[h as usize]
+                    .iter()
+                    .map(move |d| h as usize * DATA_PAGES_PER_HEADER + d as usize)
+            })
+            .collect()
     }
 
     /// Returns how many data pages in current partition.
-    pub fn get_num_data_pages(self) -> usize {
-        self.first_header
-            .as_header_page()
+    pub fn get_num_data_pages(&self) -> usize {
+        self.m_bitmap
             .iter()
-            .map(|page| page.data_page_nums as usize)
+            .filter(|&h| (h as usize) < self.h_bitmaps.len())
+            .map(|h| self.h_bitmaps[h as usize].len() as usize)
             .sum()
     }
 
@@ -1,2 +1,300 @@
-pub struct PageReader {
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    marker::PhantomData,
+    sync::mpsc as std_mpsc,
+    thread,
+};
+
+use futures::{channel::mpsc, StreamExt};
+
+use super::{
+    marker,
+    page_directory::{
+        virtual_data_page_offset, virtual_page_num, PageDirectory, DATA_PAGES_PER_HEADER,
+        DEFAULT_PAGE_SIZE,
+    },
+    DataPage, PageRef,
+};
+use crate::{
+    datatypes::record::Record,
+    error::{Error, Result},
+};
+
+/// A run of consecutive data pages read at once, i.e. the first page and the
+/// content of pages.
+type Run = (usize, Vec<u8>);
+
+/// Reads all data pages of a partition in order for a full scan, see
+/// `rfcs/storage-sequential-read.md`.
+///
+/// Pages are read ahead into a small ring of buffers private to the reader
+/// instead of the page cache, so that a scan doesn't evict the pages used by
+/// others. Consecutive data pages are contiguous in the partition file (see
+/// `virtual_data_page_offset`), so a run of them is read in at once. The ring
+/// is split into two runs, the next run is read by a read-ahead thread while
+/// the pages of the current one are returned. A page which is cached is read
+/// from the cache instead, as it may be newer than the page on disk.
+pub struct PageReader<'a> {
+    page_directory: &'a PageDirectory,
+
+    part_num: usize,
+
+    /// The frame holding the page returned last.
+    frame: PageRef<marker::Data>,
+
+    /// The runs read by the read-ahead thread, in order.
+    runs: mpsc::UnboundedReceiver<Result<Run>>,
+
+    /// The buffers of runs consumed, which are given back to the read-ahead
+    /// thread.
+    free_bufs: std_mpsc::Sender<Vec<u8>>,
+
+    /// The run being consumed.
+    run: Option<Run>,
+
+    /// The index of next page in the run.
+    pos: usize,
+}
+
+/// A page returned by `PageReader::next`, which borrows the frame of reader,
+/// so it can't be used after the next call or once the reader is dropped.
+pub struct ScannedPage<'r> {
+    page: PageRef<marker::Data>,
+    _reader: PhantomData<&'r mut ()>,
+}
+
+impl ScannedPage<'_> {
+    pub fn get_page_num(&self) -> u64 {
+        self.page.get_page_num()
+    }
+
+    pub fn num_records(&self) -> u16 {
+        self.page.num_records()
+    }
+
+    /// Checks whether page is contains entry.
+    pub fn contains(&self, entry_num: usize) -> bool {
+        self.page.contains(entry_num)
+    }
+
+    /// Reads the schema version and record of entry `entry_num`.
+    pub async fn read_entry(&self, entry_num: usize) -> Result<(u16, Record)> {
+        self.page.read_entry(entry_num).await
+    }
+
+    pub fn as_data_page(&self) -> &DataPage {
+        self.page.as_data_page()
+    }
+}
+
+impl<'a> PageReader<'a> {
+    /// Creates a reader of `page_nums` of partition with `ring_size` pages of
+    /// buffers, and starts reading ahead.
+    pub fn new(
+        page_directory: &'a PageDirectory,
+        part_num: usize,
+        page_nums: Vec<usize>,
+        ring_size: usize,
+    ) -> Self {
+        assert!(ring_size > 0, "page reader needs at least one frame.");
+        let runs = split_runs(&page_nums, (ring_size / 2).max(1));
+
+        let (free_bufs, free_bufs_rx) = std_mpsc::channel();
+        for _ in 0..2 {
+            free_bufs.send(vec![]).unwrap();
+        }
+        let (runs_tx, runs_rx) = mpsc::unbounded();
+        let path = format!("{}.{}", page_directory.path(), part_num);
+        thread::Builder::new()
+            .name("page-reader".to_owned())
+            .spawn(move || read_ahead(path, runs, free_bufs_rx, runs_tx))
+            .expect("failed to spawn page reader");
+
+        let mut frame = PageRef::new_data_page();
+        frame.as_data_page_mut().fill(&[0u8; DEFAULT_PAGE_SIZE]);
+        Self {
+            page_directory,
+            part_num,
+            frame,
+            runs: runs_rx,
+            free_bufs,
+            run: None,
+            pos: 0,
+        }
+    }
+
+    /// Returns the next page, which is valid until the next call.
+    pub async fn next(&mut self) -> Result<Option<ScannedPage<'_>>> {
+        let page_num = loop {
+            if let Some((first, buf)) = &self.run {
+                let offset = self.pos * DEFAULT_PAGE_SIZE;
+                if offset < buf.len() {
+                    self.frame
+                        .as_data_page_mut()
+                        .fill(&buf[offset..offset + DEFAULT_PAGE_SIZE]);
+                    self.pos += 1;
+                    break first + self.pos - 1;
+                }
+
+                // The read-ahead thread is gone if the send fails, then the
+                // runs left are never received either.
+                let (_, buf) = self.run.take().unwrap();
+                let _ = self.free_bufs.send(buf);
+            }
+
+            match self.runs.next().await {
+                Some(run) => {
+                    self.run = Some(run?);
+                    self.pos = 0;
+                }
+                None => return Ok(None),
+            }
+        };
+
+        // The page may be released after it's listed.
+        let vpn = virtual_page_num(self.part_num, page_num);
+        if !self.page_directory.is_page_allocated(vpn).await {
+            return Err(Error::NotFound(format!("page {}", page_num)));
+        }
+
+        let page = self.frame.as_data_page_mut();
+        page.set_page_num(page_num as u64);
+        if let Some(cached) = self.page_directory.peek_page(vpn).await {
+            page.fill(cached.as_data_page().vals());
+        }
+        Ok(Some(ScannedPage {
+            page: self.frame,
+            _reader: PhantomData,
+        }))
+    }
+}
+
+impl<'a> Drop for PageReader<'a> {
+    fn drop(&mut self) {
+        // The read-ahead thread stops once the channels are closed.
+        unsafe { self.frame.forget_type().dealloc() };
+    }
+}
+
+/// Splits the pages into runs of consecutive pages of at most `max_len`
+/// pages, which don't cross a header page, returns the first page and the
+/// length of every run.
+fn split_runs(page_nums: &[usize], max_len: usize) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = vec![];
+    for &page_num in page_nums {
+        match runs.last_mut() {
+            Some((first, len))
+                if *len < max_len
+                    && *first + *len == page_num
+                    && page_num % DATA_PAGES_PER_HEADER != 0 =>
+            {
+                *len += 1
+            }
+            _ => runs.push((page_num, 1)),
+        }
+    }
+    runs
+}
+
+/// Reads the runs of partition file at `path` in order, into the buffers given
+/// back by the reader. It stops at the first error, or once the reader is
+/// dropped.
+fn read_ahead(
+    path: String,
+    runs: Vec<(usize, usize)>,
+    free_bufs: std_mpsc::Receiver<Vec<u8>>,
+    sender: mpsc::UnboundedSender<Result<Run>>,
+) {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = sender.unbounded_send(Err(e.into()));
+            return;
+        }
+    };
+
+    for (first, len) in runs {
+        let mut buf = match free_bufs.recv() {
+            Ok(buf) => buf,
+            Err(_) => return,
+        };
+        buf.resize(len * DEFAULT_PAGE_SIZE, 0);
+        let read = file
+            .seek(SeekFrom::Start(virtual_data_page_offset(first)))
+            .and_then(|_| file.read_exact(&mut buf));
+        let failed = read.is_err();
+        let run = read.map(|_| (first, buf)).map_err(Error::from);
+        if sender.unbounded_send(run).is_err() || failed {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::options::Options;
+
+    #[test]
+    fn test_split_runs() {
+        assert_eq!(split_runs(&[], 4), vec![]);
+        assert_eq!(
+            split_runs(&[0, 1, 2, 3, 4, 6, 7], 4),
+            vec![(0, 4), (4, 1), (6, 2)]
+        );
+        let header = DATA_PAGES_PER_HEADER;
+        assert_eq!(
+            split_runs(&[header - 1, header, header + 1], 4),
+            vec![(header - 1, 1), (header, 2)]
+        );
+    }
+
+    #[test]
+    fn test_page_reader() {
+        block_on(async {
+            let dir = TempDir::new().unwrap();
+            let options = Options {
+                path: dir.path().join("db").to_string_lossy().to_string(),
+                scan_ring_pages: 4,
+                ..Default::default()
+            };
+            let page_directory = PageDirectory::open(&options).await.unwrap();
+            let part_num = page_directory.alloc_part().await.unwrap();
+            let mut pages = vec![];
+            for i in 0..7u8 {
+                let page = page_directory.alloc_page_with_part(part_num).await.unwrap();
+                let mut guard = page_directory.fetch_page(page).await.unwrap();
+                guard.as_data_page_mut().vals_mut()[100] = i;
+                page_directory.mark_dirty(&guard).unwrap();
+                pages.push(page);
+            }
+            page_directory.flush_pages().await.unwrap();
+
+            // A cached page is newer than the one on disk.
+            let mut guard = page_directory.fetch_page(pages[5]).await.unwrap();
+            guard.as_data_page_mut().vals_mut()[100] = 50;
+            page_directory.mark_dirty(&guard).unwrap();
+            drop(guard);
+
+            let mut reader = page_directory.scan_pages(part_num).unwrap();
+            for i in 0..7 {
+                let page = reader.next().await.unwrap().unwrap();
+                let expected = if i == 5 { 50 } else { i as u8 };
+                assert_eq!(page.get_page_num(), i);
+                assert_eq!(page.as_data_page().vals()[100], expected);
+            }
+            assert!(reader.next().await.unwrap().is_none());
+            drop(reader);
+
+            // A page released after it's listed is not found.
+            let mut reader = page_directory.scan_pages(part_num).unwrap();
+            page_directory.release_page(pages[1]).await.unwrap();
+            assert!(reader.next().await.unwrap().is_some());
+            assert!(matches!(reader.next().await, Err(Error::NotFound(_))));
+        })
+    }
 }
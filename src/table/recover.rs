@@ -1,16 +1,306 @@
-use std::path::Path;
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    sync::Arc,
+};
 
-use super::{manifest::Manifest, Table};
+use super::{
+    index,
+    manifest::{Lsn, ManifestEntry, TxnEntry, TxnId, TxnStatus},
+    page::page_directory::{calculate_part_num, PageDirectory},
+};
 use crate::error::Result;
 
-impl Table {
-    pub async fn recover<P: AsRef<Path>>(path: P) -> Result<()> {
-        let mut _manifest = Manifest::open(path.as_ref()).await?;
+/// Recovers the database after a crash with ARIES, which is done in three
+/// passes over the write-ahead log:
+///  - analysis starts from the last checkpoint, it rebuilds the transactions
+///    which were not ended at the crash and the pages which may not have their
+///    changes on disk (the dirty page table), and finds the partitions dropped
+///    by committed transactions.
+///  - redo repeats history from the oldest change of dirty pages, every logged
+///    change is applied to the pages whose page LSN is older than the change,
+///    including the changes of uncommitted transactions and compensation
+///    records.
+///  - undo rolls back the transactions which were neither committed nor rolled
+///    back, writing compensation records so that a crash during recovery
+///    doesn't undo a change twice. The changes of index entries are undone
+///    logically (see `ManifestEntry::UpdateIndex`).
+///
+/// Committed transactions which were not ended finish the partition drops
+/// deferred to their commit, then all transactions are ended and a checkpoint
+/// is taken.
+pub async fn recover(page_directory: &Arc<PageDirectory>) -> Result<()> {
+    let (checkpoint_lsn, records) = {
+        let mut manifest = page_directory.manifest().await;
+        let checkpoint_lsn = manifest.checkpoint_lsn().await?.unwrap_or(0);
+        (checkpoint_lsn, manifest.records_from(0).await?)
+    };
 
-        Ok(())
+    // Analysis.
+    let mut txns: HashMap<TxnId, TxnEntry> = HashMap::new();
+    let mut dirty_pages: HashMap<u64, Lsn> = HashMap::new();
+    let mut committed = HashSet::new();
+    for record in &records {
+        if let Some(part_num) = record.entry.part_num() {
+            page_directory.reserve_part_num(part_num);
+        }
+        if record.entry == ManifestEntry::CommitTxn {
+            committed.insert(record.txn);
+        }
+        if record.lsn < checkpoint_lsn {
+            continue;
+        }
+
+        if let Some(page) = record.entry.page() {
+            dirty_pages.entry(page).or_insert(record.lsn);
+        }
+        match &record.entry {
+            ManifestEntry::EndCheckpoint {
+                dirty_pages: checkpoint_pages,
+                txns: checkpoint_txns,
+            } => {
+                for (page, rec_lsn) in checkpoint_pages {
+                    let lsn = dirty_pages.entry(*page).or_insert(*rec_lsn);
+                    *lsn = (*lsn).min(*rec_lsn);
+                }
+                for (txn, entry) in checkpoint_txns {
+                    txns.entry(*txn).or_insert(*entry);
+                }
+            }
+            ManifestEntry::BeginCheckpoint(_) => {}
+            ManifestEntry::EndTnx => {
+                txns.remove(&record.txn);
+            }
+            entry => {
+                let txn = txns.entry(record.txn).or_insert(TxnEntry {
+                    status: TxnStatus::Running,
+                    first_lsn: record.lsn,
+                    last_lsn: record.lsn,
+                });
+                txn.last_lsn = record.lsn;
+                match entry {
+                    ManifestEntry::CommitTxn => txn.status = TxnStatus::Committing,
+                    ManifestEntry::AbortTxn => txn.status = TxnStatus::Aborting,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // The file of a partition is deleted once the transaction which drops it
+    // commits, changes logged before are not redone, otherwise the file is
+    // created again. The drop of a committing transaction may be logged before
+    // the records read.
+    let mut dropped: HashMap<usize, Lsn> = HashMap::new();
+    for (&txn, entry) in &txns {
+        if entry.status != TxnStatus::Committing {
+            continue;
+        }
+        let mut manifest = page_directory.manifest().await;
+        let mut lsn = entry.last_lsn;
+        while lsn != 0 {
+            let record = manifest.read(lsn).await?;
+            if let ManifestEntry::FreePartition(part_num) = record.entry {
+                dropped.insert(part_num, record.lsn);
+            }
+            lsn = record.prev_lsn;
+        }
+        committed.insert(txn);
+    }
+    for record in &records {
+        match record.entry {
+            ManifestEntry::FreePartition(part_num) if committed.contains(&record.txn) => {
+                dropped.insert(part_num, record.lsn);
+            }
+            ManifestEntry::AllocParition(part_num) => {
+                dropped.remove(&part_num);
+            }
+            _ => {}
+        }
+    }
+
+    // Redo.
+    let redo_lsn = dirty_pages.values().copied().fold(checkpoint_lsn, Lsn::min);
+    for record in records.iter().filter(|r| r.lsn >= redo_lsn) {
+        let is_dropped = record
+            .entry
+            .part_num()
+            .and_then(|part_num| dropped.get(&part_num))
+            .map_or(false, |&lsn| record.lsn < lsn);
+        let is_clean = record.entry.page().map_or(false, |page| {
+            dirty_pages.get(&page).map_or(true, |&lsn| record.lsn < lsn)
+        });
+        if !is_dropped && !is_clean {
+            redo(page_directory, record.lsn, &record.entry).await?;
+        }
+    }
+    for &part_num in dropped.keys() {
+        page_directory.delete_part(part_num).await?;
+    }
+
+    // Undo.
+    let mut losers = vec![];
+    {
+        let mut manifest = page_directory.manifest().await;
+        for (&txn, &entry) in &txns {
+            manifest.restore_txn(txn, entry);
+            match entry.status {
+                TxnStatus::Committing => {
+                    manifest.end(txn).await?;
+                }
+                TxnStatus::Aborting => losers.push((txn, entry.last_lsn)),
+                TxnStatus::Running => {
+                    let lsn = manifest.append(txn, ManifestEntry::AbortTxn).await?;
+                    losers.push((txn, lsn));
+                }
+            }
+        }
+    }
+    undo(page_directory, losers).await?;
+
+    checkpoint(page_directory).await
+}
+
+/// Takes a fuzzy checkpoint. The cached pages are written back and the open
+/// partitions are synced first, so that the pages whose changes are all logged
+/// before are no longer dirty, then the log before the oldest LSN needed by
+/// recovery is truncated (see `Manifest::checkpoint`). Transactions keep
+/// running during the checkpoint.
+pub async fn checkpoint(page_directory: &PageDirectory) -> Result<()> {
+    let synced_lsn = page_directory.manifest().await.last_lsn();
+    page_directory.flush_pages().await?;
+    let synced_parts = page_directory.sync_parts().await?;
+
+    let mut manifest = page_directory.manifest().await;
+    manifest.clean_pages(synced_lsn, &synced_parts);
+    manifest.checkpoint().await?;
+    Ok(())
+}
+
+/// Rolls back a running transaction, its changes are undone in reverse order.
+pub async fn rollback(page_directory: &Arc<PageDirectory>, txn: TxnId) -> Result<()> {
+    let lsn = page_directory
+        .manifest()
+        .await
+        .append(txn, ManifestEntry::AbortTxn)
+        .await?;
+    undo(page_directory, vec![(txn, lsn)]).await
+}
+
+/// Undoes the transactions from their last LSNs, the records of all
+/// transactions are undone together in LSN descending order. Every undone
+/// change is logged as a compensation record, whose undo-next LSN tells where
+/// to resume if the undo is interrupted by a crash.
+async fn undo(page_directory: &Arc<PageDirectory>, txns: Vec<(TxnId, Lsn)>) -> Result<()> {
+    let mut queue = txns
+        .into_iter()
+        .map(|(txn, lsn)| (lsn, txn))
+        .collect::<BinaryHeap<_>>();
+
+    while let Some((lsn, txn)) = queue.pop() {
+        let next_lsn = undo_record(page_directory, txn, lsn).await?;
+        if next_lsn == 0 {
+            page_directory.manifest().await.end(txn).await?;
+        } else {
+            queue.push((next_lsn, txn));
+        }
     }
+    Ok(())
+}
+
+/// Rolls back the changes of a running transaction logged after `lsn`, e.g.
+/// to a savepoint. The transaction keeps running, and the compensation records
+/// make a later rollback skip the undone changes.
+pub async fn rollback_to(page_directory: &Arc<PageDirectory>, txn: TxnId, lsn: Lsn) -> Result<()> {
+    let mut next_lsn = page_directory
+        .manifest()
+        .await
+        .last_lsn_of(txn)
+        .unwrap_or(0);
+    while next_lsn > lsn {
+        next_lsn = undo_record(page_directory, txn, next_lsn).await?;
+    }
+    Ok(())
+}
+
+/// Undoes the record of transaction at `lsn` if it's undoable, returns the
+/// LSN of the next record to undo, 0 if none.
+async fn undo_record(page_directory: &Arc<PageDirectory>, txn: TxnId, lsn: Lsn) -> Result<Lsn> {
+    let mut manifest = page_directory.manifest().await;
+    let record = manifest.read(lsn).await?;
+    if let Some(undo_next_lsn) = record.entry.undo_next_lsn() {
+        return Ok(undo_next_lsn);
+    }
+    if let ManifestEntry::UpdateIndex { begin_lsn, .. } = record.entry {
+        // The opposite change ends with a compensation record, which skips
+        // the changes to pages before.
+        drop(manifest);
+        index::undo(page_directory, txn, &record.entry).await?;
+        return Ok(begin_lsn);
+    }
+
+    if let Some(clr) = record.entry.undo(record.prev_lsn) {
+        let clr_lsn = manifest.append(txn, clr.clone()).await?;
+        manifest.flush_to(clr_lsn).await?;
+        // The partition may be waiting for the log.
+        drop(manifest);
+        redo(page_directory, clr_lsn, &clr).await?;
+    }
+    Ok(record.prev_lsn)
+}
+
+/// Applies a logged change, page changes are skipped if the page LSN shows the
+/// page already has it.
+async fn redo(page_directory: &PageDirectory, lsn: Lsn, entry: &ManifestEntry) -> Result<()> {
+    match entry {
+        ManifestEntry::AllocParition(part_num) => {
+            page_directory.alloc_part_with_num(*part_num).await?;
+        }
+        ManifestEntry::UndoAllocParition { part_num, .. } => {
+            page_directory.delete_part(*part_num).await?;
+        }
+        ManifestEntry::AllocPage(page) | ManifestEntry::UndoFreePage { page, .. } => {
+            page_directory
+                .alloc_part_with_num(calculate_part_num(*page))
+                .await?;
+            if !page_directory.is_page_allocated(*page).await {
+                page_directory.alloc_page(*page).await?;
+            }
+        }
+        ManifestEntry::FreePage(page) | ManifestEntry::UndoAllocPage { page, .. } => {
+            if page_directory.is_page_allocated(*page).await {
+                page_directory.release_page(*page).await?;
+            }
+        }
+        ManifestEntry::UpdatePage {
+            page,
+            offset,
+            after,
+            ..
+        }
+        | ManifestEntry::UndoUpdatPage {
+            page,
+            offset,
+            after,
+            ..
+        } => {
+            let part_num = calculate_part_num(*page);
+            page_directory.alloc_part_with_num(part_num).await?;
+            if !page_directory.is_page_allocated(*page).await {
+                page_directory.alloc_page(*page).await?;
+            }
 
-    pub async fn apply() -> Result<()> {
-        Ok(())
+            let mut data_page = page_directory.fetch_page(*page).await?;
+            if data_page.as_data_page().page_lsn() >= lsn {
+                return Ok(());
+            }
+            let offset = *offset as usize;
+            let data_page_mut = data_page.as_data_page_mut();
+            data_page_mut.vals_mut()[offset..offset + after.len()].copy_from_slice(after);
+            data_page_mut.set_page_lsn(lsn);
+            page_directory.mark_dirty(&data_page)?;
+        }
+        // Partitions are deleted once the transaction which drops it commits.
+        _ => {}
     }
+    Ok(())
 }
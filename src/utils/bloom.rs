@@ -58,32 +58,44 @@ impl<'a> Bloom<'a> {
         locs as usize
     }
 
-    /// Build bloom filter from key hashes
-    pub fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Bytes {
+    /// Get the number of hash functions from bloom filter bits per key
+    pub fn num_hashes(bits_per_key: usize) -> u8 {
         // 0.69 is approximately ln(2)
         let k = ((bits_per_key as f64) * 0.69) as u32;
         // limit k in [1, 30]
-        let k = k.clamp(1, 30);
+        k.clamp(1, 30) as u8
+    }
+
+    /// Build bloom filter from key hashes
+    pub fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Bytes {
+        let k = Self::num_hashes(bits_per_key);
         // For small len(keys), we set a minimum bloom filter length to avoid high FPR
         let nbits = (keys.len() * bits_per_key).max(64);
         let nbytes = (nbits + 7) / 8;
-        // nbits is always multiplication of 8
-        let nbits = nbytes * 8;
         let mut filter = BytesMut::with_capacity(nbytes + 1);
         filter.resize(nbytes, 0);
+        filter.put_u8(k);
         for h in keys {
-            let mut h = *h;
-            let delta = (h >> 17) | (h << 15);
-            for _ in 0..k {
-                let bit_pos = (h as usize) % nbits;
-                filter.set_bit(bit_pos, true);
-                h = h.wrapping_add(delta);
-            }
+            Self::add_key_hash(&mut filter, *h);
         }
-        filter.put_u8(k as u8);
         filter.freeze()
     }
 
+    /// Add a key hash to the bloom filter in a byte slice (the filter bits
+    /// followed by the number of hash functions), so that a filter of fixed
+    /// size can be built incrementally
+    pub fn add_key_hash(buf: &mut [u8], mut h: u32) {
+        let (mut filter, k) = buf.split_at_mut(buf.len() - 1);
+        // nbits is always multiplication of 8
+        let nbits = filter.bit_len();
+        let delta = (h >> 17) | (h << 15);
+        for _ in 0..k[0] {
+            let bit_pos = (h as usize) % nbits;
+            filter.set_bit(bit_pos, true);
+            h = h.wrapping_add(delta);
+        }
+    }
+
     /// Check if a bloom filter may contain some data
     pub fn may_contain(&self, mut h: u32) -> bool {
         if self.k > 30 {
@@ -9,7 +9,6 @@ pub async fn open<P: AsRef<Path>>(path: P) -> std::io::Result<File> {
     async_fs::OpenOptions::new()
         .read(true)
         .write(true)
-        .truncate(true)
         .open(path)
         .await
 }
@@ -27,7 +26,13 @@ pub async fn remove_dir<P: AsRef<Path>>(path: P) -> Result<()> {
 }
 
 pub async fn create_file<P: AsRef<Path>>(path: P) -> Result<File> {
-    Ok(File::create(path).await?)
+    Ok(async_fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .await?)
 }
 
 pub async fn create_dir<P: AsRef<Path>>(path: P) -> Result<()> {